@@ -0,0 +1,154 @@
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Algorithm, Argon2, Params, Version,
+};
+use color_eyre::eyre::{eyre, Result};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+
+/// Start of the PHC strings written by `hash_password`. Passwords were stored in plaintext before
+/// they were hashed, and such rows are told apart by not starting with it.
+const HASH_PREFIX: &str = "$argon2id$";
+
+/// The Argon2id hasher, with the recommended parameters.
+fn argon2() -> Argon2<'static> {
+    // the recommended parameters take seconds per hash in unoptimized builds, so unit tests use
+    // the cheapest ones allowed. Verifying reads the parameters from the hash either way.
+    #[cfg(test)]
+    let params = Params::new(
+        Params::MIN_M_COST,
+        Params::MIN_T_COST,
+        Params::MIN_P_COST,
+        None,
+    )
+    .expect("minimum Argon2 parameters are valid");
+    #[cfg(not(test))]
+    let params = Params::default();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+/// Hash a password with Argon2id and a random salt. This is slow by design, so async code should
+/// call it from a blocking task.
+/// # Arguments
+/// * `password` - The password to hash
+/// # Returns
+/// The hash as a PHC string, which includes the salt and parameters
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    argon2()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| eyre!("Failed to hash password: {}", e))
+}
+
+/// Whether a stored password is a hash, rather than plaintext from before hashing.
+pub fn is_hash(stored: &str) -> bool {
+    stored.starts_with(HASH_PREFIX)
+}
+
+/// Check a password against the stored one, which is either a hash from `hash_password` or
+/// plaintext. Either way, the comparison takes the same time wherever the first difference is.
+/// # Arguments
+/// * `password` - The password given by the user
+/// * `stored` - The stored hash or plaintext password
+/// # Returns
+/// Whether the password matches, or an error if the stored hash is malformed
+pub fn verify_password(password: &str, stored: &str) -> Result<bool> {
+    if !is_hash(stored) {
+        return Ok(constant_time_eq(password.as_bytes(), stored.as_bytes()));
+    }
+    let hash = PasswordHash::new(stored).map_err(|e| eyre!("Malformed password hash: {}", e))?;
+    if hash.hash.is_none() {
+        return Err(eyre!("Malformed password hash: no output"));
+    }
+    Ok(argon2().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+/// Check a password against a fixed hash and discard the result. Called for users that do not
+/// exist, so that rejecting them takes as long as rejecting a wrong password, and response times
+/// do not tell which usernames exist. Slow like `hash_password`.
+pub fn verify_dummy(password: &str) {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    let hash = DUMMY.get_or_init(|| hash_password("").expect("hashing a password failed"));
+    let _ = verify_password(password, hash);
+}
+
+/// Compare two byte strings in time that depends only on their lengths.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Start of every API token, so that they are recognizable, such as by secret scanners.
+const TOKEN_PREFIX: &str = "crataegus_";
+
+/// Generate a random API token.
+/// # Returns
+/// The token, 256 random bits in hex after `TOKEN_PREFIX`
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, hex(&bytes))
+}
+
+/// Hash an API token for storage and lookup. Tokens are random rather than chosen by people, so
+/// a fast hash is enough, unlike for passwords.
+/// # Returns
+/// The SHA-256 of the token, in hex
+pub fn hash_token(token: &str) -> String {
+    hex(&Sha256::digest(token.as_bytes()))
+}
+
+/// Lowercase hex encoding of bytes.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_password() {
+        let hash = hash_password("correct horse battery staple").unwrap();
+        assert!(is_hash(&hash));
+        assert!(verify_password("correct horse battery staple", &hash).unwrap());
+        assert!(!verify_password("correct horse battery stapler", &hash).unwrap());
+        // salted, so the same password hashes differently
+        assert_ne!(hash, hash_password("correct horse battery staple").unwrap());
+        // no limit on length
+        let long = "x".repeat(1000);
+        assert!(verify_password(&long, &hash_password(&long).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_verify_plaintext() {
+        assert!(!is_hash("pass"));
+        assert!(verify_password("pass", "pass").unwrap());
+        assert!(!verify_password("pass", "pas").unwrap());
+        assert!(!verify_password("pass", "past").unwrap());
+        assert!(verify_password("pass", "$argon2id$garbage").is_err());
+    }
+
+    #[test]
+    fn test_token() {
+        let token = generate_token();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + 64);
+        assert_ne!(token, generate_token());
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hash_token(&token), hash_token(&token));
+    }
+}
@@ -1,17 +1,37 @@
+use std::collections::HashMap;
+use std::io::Read;
 use std::path::Path;
 use std::sync::Arc;
 
+use chrono::Utc;
 use chrono_english::parse_date_string;
 use clap::ValueEnum;
 use color_eyre::eyre::{eyre, Result, WrapErr};
 use futures::StreamExt;
-use inquire::{Password, Text};
+use inquire::{Confirm, Password, Text};
 use log::info;
 use serde::Deserialize;
 
-use crate::db::{Config as DbConfig, Db};
+use crate::db::{
+    Config as DbConfig, ConflictPolicy, Db, InsertCounts, LocationKey, LocationStatus,
+};
 use crate::export::{create_exporter, Format as ExportFormat};
+use crate::google::{records::read_records, timeline::read_timeline};
 use crate::gpslogger::csv::read_csv;
+use crate::import::{
+    exif::read_exif,
+    find_files,
+    fit::read_fit,
+    geojson::read_geojson,
+    gpx::read_gpx,
+    kml::read_kml,
+    nmea::{read_nmea, CorruptLine},
+    tcx::read_tcx,
+    video::read_video,
+    FoundFile,
+};
+use crate::inbox::{Config as InboxConfig, Inbox};
+use crate::schema::{Location, Scope};
 use crate::server::{Config as ServerConfig, Server};
 
 /// Configuration for the server, obtained from main.rs::Args
@@ -19,13 +39,88 @@ use crate::server::{Config as ServerConfig, Server};
 pub struct Config {
     https: ServerConfig,
     db: DbConfig,
+    /// Optional drop directory to import files from while serving
+    inbox: Option<InboxConfig>,
 }
 
 /// Types of supported imports
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
 pub enum ImportFormat {
+    /// Detect the format of each file from its name and contents
+    Auto,
     /// GPSLogger CSV format
     GpsLoggerCsv,
+    /// Google Takeout Location History (Records.json)
+    GoogleRecords,
+    /// Google on-device Timeline export (Timeline.json)
+    GoogleTimeline,
+    /// GPX 1.0/1.1 tracks, routes and waypoints
+    Gpx,
+    /// KML or zipped KMZ placemarks and gx:Tracks
+    Kml,
+    /// GeoJSON FeatureCollection of timestamped Points or LineStrings
+    #[value(name = "geojson")]
+    GeoJson,
+    /// Geotags of JPEG, HEIC and TIFF photos in a directory
+    Exif,
+    /// MP4/MOV video: phone location metadata or GoPro GPMF telemetry
+    Video,
+    /// Garmin FIT activity
+    Fit,
+    /// Garmin Training Center XML activity or course
+    Tcx,
+    /// NMEA 0183 log of GGA, RMC and GSA sentences
+    Nmea,
+}
+
+impl ImportFormat {
+    /// Guess the format of a file from its extension, and for JSON files from the keys near the
+    /// start of the file.
+    ///
+    /// # Arguments
+    /// * `path`: path to the file
+    ///
+    /// # Returns
+    /// The format, or None if the file is not one that can be imported
+    pub fn detect(path: &Path) -> Option<ImportFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "csv" => Some(ImportFormat::GpsLoggerCsv),
+            "json" => Self::detect_json(path),
+            "gpx" => Some(ImportFormat::Gpx),
+            "kml" | "kmz" => Some(ImportFormat::Kml),
+            "geojson" => Some(ImportFormat::GeoJson),
+            "jpg" | "jpeg" | "heic" | "heif" | "tif" | "tiff" => Some(ImportFormat::Exif),
+            "mp4" | "mov" => Some(ImportFormat::Video),
+            "fit" => Some(ImportFormat::Fit),
+            "tcx" => Some(ImportFormat::Tcx),
+            "nmea" | "nma" => Some(ImportFormat::Nmea),
+            _ => None,
+        }
+    }
+
+    /// Tell the JSON formats apart by the keys in the first 64 KiB.
+    fn detect_json(path: &Path) -> Option<ImportFormat> {
+        let mut head = Vec::new();
+        std::fs::File::open(path)
+            .ok()?
+            .take(64 * 1024)
+            .read_to_end(&mut head)
+            .ok()?;
+        let head = String::from_utf8_lossy(&head);
+        // iOS exports of the Timeline are a bare array of segments
+        let ios_timeline = head.trim_start().starts_with('[') && head.contains("\"startTime\"");
+        if head.contains("\"semanticSegments\"") || head.contains("\"rawSignals\"") || ios_timeline
+        {
+            Some(ImportFormat::GoogleTimeline)
+        } else if head.contains("\"FeatureCollection\"") {
+            Some(ImportFormat::GeoJson)
+        } else if head.contains("\"locations\"") {
+            Some(ImportFormat::GoogleRecords)
+        } else {
+            None
+        }
+    }
 }
 
 /// Implementation of the Config struct
@@ -56,6 +151,9 @@ pub async fn serve(config: Config) -> Result<()> {
             .await
             .map_err(|e| eyre!("Failed to connect to database: {}", e))?,
     );
+    if let Some(inbox_config) = config.inbox {
+        tokio::spawn(Inbox::new(inbox_config, db.clone()).run());
+    }
     let server =
         Server::new(config.https, db).map_err(|e| eyre!("Failed to create server: {}", e))?;
     server
@@ -65,20 +163,242 @@ pub async fn serve(config: Config) -> Result<()> {
     Ok(())
 }
 
-pub async fn useradd(config: Config) -> Result<()> {
-    println!("Adding a user to the database");
-    let db = Arc::new(
-        Db::new(&config.db)
-            .await
-            .map_err(|e| eyre!("Failed to connect to database: {}", e))?,
-    );
-    println!("Connected to the database. Enter the user information:");
-    let username = Text::new("Username").prompt()?;
-    let password = Password::new("Password").prompt()?;
-    db.user_insert(username, password)
+/// Where to read a password from, so that users can be provisioned without a terminal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PasswordSource {
+    /// Given on the command line or in an environment variable
+    Value(String),
+    /// The first line of standard input
+    Stdin,
+    /// Prompted for, with confirmation
+    Prompt,
+}
+
+impl PasswordSource {
+    /// Read the password from its source.
+    fn read(self) -> Result<String> {
+        self.read_from(std::io::stdin().lock())
+    }
+
+    /// Read the password, taking standard input from `input`.
+    fn read_from(self, mut input: impl std::io::BufRead) -> Result<String> {
+        let password = match self {
+            PasswordSource::Value(password) => password,
+            PasswordSource::Stdin => {
+                let mut line = String::new();
+                input
+                    .read_line(&mut line)
+                    .wrap_err("Failed to read password from stdin")?;
+                line.trim_end_matches(['\r', '\n']).to_string()
+            }
+            PasswordSource::Prompt => Password::new("Password").prompt()?,
+        };
+        if password.is_empty() {
+            return Err(eyre!("Password must not be empty"));
+        }
+        Ok(password)
+    }
+}
+
+/// Add a user to the database. The username is prompted for if it is not given.
+pub async fn useradd(
+    config: Config,
+    username: Option<String>,
+    password: PasswordSource,
+) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    let username = match username {
+        Some(username) => username,
+        None => Text::new("Username").prompt()?,
+    };
+    let password = password.read()?;
+    db.user_insert(username.clone(), password)
         .await
         .map_err(|e| eyre!("Failed to add user: {}", e))?;
-    println!("User added successfully");
+    println!("Added user {}", username);
+    Ok(())
+}
+
+/// Print every username, one per line.
+pub async fn user_list(config: Config) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    for username in db.user_vec().await? {
+        println!("{}", username);
+    }
+    Ok(())
+}
+
+/// Delete a user and everything recorded for them.
+/// # Arguments
+/// * `config` - The configuration
+/// * `username` - The user to delete
+/// * `yes` - Skip the confirmation prompt
+/// * `backup` - Back up the database first
+pub async fn user_del(config: Config, username: &str, yes: bool, backup: bool) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    if !db.user_vec().await?.iter().any(|user| user == username) {
+        return Err(eyre!("No such user: {}", username));
+    }
+    let count = db.location_count(Some(username), None).await?;
+    if !yes {
+        let confirmed = Confirm::new(&format!(
+            "Delete user {} and their {} locations?",
+            username, count
+        ))
+        .with_default(false)
+        .prompt()?;
+        if !confirmed {
+            println!("Nothing deleted");
+            return Ok(());
+        }
+    }
+    if backup {
+        db.backup()
+            .await
+            .map_err(|e| eyre!("Failed to back up database: {}", e))?;
+    }
+    db.user_delete(username).await?;
+    println!("Deleted user {} and their {} locations", username, count);
+    Ok(())
+}
+
+/// Rename a user, along with everything recorded for them.
+pub async fn user_rename(config: Config, username: &str, new_username: &str) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    db.user_rename(username, new_username).await?;
+    println!("Renamed user {} to {}", username, new_username);
+    Ok(())
+}
+
+/// Replace a user's password.
+pub async fn user_passwd(config: Config, username: &str, password: PasswordSource) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    let password = password.read()?;
+    db.user_set_password(username, &password).await?;
+    println!("Changed the password of {}", username);
+    Ok(())
+}
+
+/// Create an API token and print it. It cannot be shown again.
+/// # Arguments
+/// * `config` - The configuration
+/// * `username` - The user the token authenticates as
+/// * `name` - Name of the token, such as the device it is for
+/// * `scope` - What the token may be used for
+/// * `expires_str` - When the token stops working, in any format `export` accepts, if ever
+pub async fn token_create(
+    config: Config,
+    username: &str,
+    name: &str,
+    scope: Scope,
+    expires_str: Option<&str>,
+) -> Result<()> {
+    let expires = match expires_str {
+        Some(expires_str) => {
+            let now = chrono::offset::Local::now().fixed_offset();
+            let expires = parse_date_string(expires_str, now, chrono_english::Dialect::Us)
+                .map_err(|_| eyre!("Failed to parse expiry date"))?;
+            Some(expires.to_utc())
+        }
+        None => None,
+    };
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    let token = db.token_insert(username, name, scope, expires).await?;
+    println!("{}", token);
+    eprintln!("Store this token now, it cannot be shown again");
+    Ok(())
+}
+
+/// List API tokens, without the tokens themselves.
+pub async fn token_list(config: Config, username: Option<&str>) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    let now = Utc::now();
+    for token in db.token_vec(username).await? {
+        let expires = match token.expires_utc {
+            Some(expires) if token.is_expired(now) => format!("expired {}", expires.to_rfc3339()),
+            Some(expires) => format!("expires {}", expires.to_rfc3339()),
+            None => "never expires".to_string(),
+        };
+        println!(
+            "{:<12} {:<16} {:<6}  created {}  {}",
+            token.username,
+            token.name,
+            token.scope,
+            token.created_utc.to_rfc3339(),
+            expires
+        );
+    }
+    Ok(())
+}
+
+/// Revoke an API token, so it is refused from now on.
+pub async fn token_revoke(config: Config, username: &str, name: &str) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    db.token_delete(username, name).await?;
+    println!("Revoked token {} of {}", name, username);
+    Ok(())
+}
+
+/// Show the schema version of the database and the migrations it needs.
+pub async fn migrate_status(config: Config) -> Result<()> {
+    let db = Db::open(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    let status = db.migration_status().await?;
+    match status.current {
+        Some(version) => println!("Schema version: {}", version),
+        None => println!("Schema version: none"),
+    }
+    println!("Latest version: {}", status.latest);
+    for migration in status.pending.iter() {
+        println!("  pending {}: {}", migration.version, migration.description);
+    }
+    Ok(())
+}
+
+/// Bring the database schema up to date, backing it up first.
+/// # Arguments
+/// * `config`: the configuration
+/// * `dry_run`: print the statements that would run instead of running them
+pub async fn migrate(config: Config, dry_run: bool) -> Result<()> {
+    let db = Db::open(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    if dry_run {
+        let status = db.migration_status().await?;
+        for migration in status.pending.iter() {
+            println!("-- {}: {}", migration.version, migration.description);
+            for (step, pending) in db.migration_plan(migration).await? {
+                match pending {
+                    true => println!("{};", step.sql),
+                    false => println!("-- already in effect, skipped:\n-- {};", step.sql),
+                }
+            }
+        }
+        println!(
+            "{} migrations pending, nothing written",
+            status.pending.len()
+        );
+        return Ok(());
+    }
+    let count = db.migrate().await?;
+    println!("Applied {} migrations", count);
     Ok(())
 }
 
@@ -101,6 +421,7 @@ pub async fn export(
     format: ExportFormat,
     path: &Path,
     username: &str,
+    device: Option<&str>,
     start_str: &str,
     stop_str: &str,
 ) -> Result<()> {
@@ -129,7 +450,7 @@ pub async fn export(
     let mut exporter = create_exporter(format, &name, path)
         .map_err(|e| eyre!("Failed to create exporter: {}", e))?;
     let mut location_stream = db
-        .location_stream(username, start.to_utc(), stop.to_utc())
+        .location_stream(username, device, start.to_utc(), stop.to_utc())
         .await
         .map_err(|e| eyre!("Failed to get location stream: {}", e))?;
     let mut count = 0;
@@ -145,55 +466,602 @@ pub async fn export(
     Ok(())
 }
 
-async fn import_gps_logger_csv(db: Arc<Db>, path: &Path, username: &str) -> Result<(usize, usize)> {
-    let mut added_count = 0;
-    let mut skipped_count = 0;
+/// Insert every location produced by an import reader in batched transactions, stopping at the
+/// first error.
+/// # Arguments
+/// * `db`: the database to insert into
+/// * `iter`: the locations to insert
+/// * `policy`: what to do with locations that conflict with stored ones
+/// # Returns
+/// The number of locations that met each outcome
+async fn import_locations(
+    db: Arc<Db>,
+    iter: impl Iterator<Item = Result<Location>>,
+    policy: ConflictPolicy,
+) -> Result<InsertCounts> {
+    let iter = iter.map(|location| location.map_err(|e| eyre!("Failed to read location: {}", e)));
+    db.location_insert_many(iter, policy)
+        .await
+        .map_err(|e| eyre!("Failed to insert locations: {}", e))
+}
+
+async fn import_gps_logger_csv(
+    db: Arc<Db>,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<InsertCounts> {
     let iter = read_csv(path, username).map_err(|e| eyre!("Failed to read CSV file: {}", e))?;
-    for location in iter {
-        let location = location.map_err(|e| eyre!("Failed to read location: {}", e))?;
-        match db
-            .location_insert(location)
+    import_locations(db, iter, policy).await
+}
+
+async fn import_google_records(
+    db: Arc<Db>,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<InsertCounts> {
+    let iter = read_records(path, username)
+        .map_err(|e| eyre!("Failed to read Records.json file: {}", e))?;
+    import_locations(db, iter, policy).await
+}
+
+async fn import_google_timeline(
+    db: Arc<Db>,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<InsertCounts> {
+    let timeline = read_timeline(path, username)
+        .map_err(|e| eyre!("Failed to read Timeline.json file: {}", e))?;
+    let counts =
+        import_locations(db.clone(), timeline.locations.into_iter().map(Ok), policy).await?;
+    let mut segment_count = 0;
+    for segment in timeline.segments {
+        if db
+            .segment_insert(segment)
             .await
-            .map_err(|e| eyre!("Failed to insert location: {}", e))?
+            .map_err(|e| eyre!("Failed to insert segment: {}", e))?
         {
-            true => added_count += 1,
-            false => skipped_count += 1,
+            segment_count += 1;
+        }
+    }
+    println!("Added {} visit/activity segments", segment_count);
+    Ok(counts)
+}
+
+async fn import_gpx(
+    db: Arc<Db>,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<InsertCounts> {
+    let iter = read_gpx(path, username).map_err(|e| eyre!("Failed to read GPX file: {}", e))?;
+    import_locations(db, iter, policy).await
+}
+
+async fn import_kml(
+    db: Arc<Db>,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<InsertCounts> {
+    let iter = read_kml(path, username).map_err(|e| eyre!("Failed to read KML file: {}", e))?;
+    import_locations(db, iter, policy).await
+}
+
+async fn import_geojson(
+    db: Arc<Db>,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<InsertCounts> {
+    let iter =
+        read_geojson(path, username).map_err(|e| eyre!("Failed to read GeoJSON file: {}", e))?;
+    import_locations(db, iter, policy).await
+}
+
+async fn import_exif(
+    db: Arc<Db>,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<InsertCounts> {
+    let iter = read_exif(path, username).map_err(|e| eyre!("Failed to read photos: {}", e))?;
+    import_locations(db, iter, policy).await
+}
+
+async fn import_video(
+    db: Arc<Db>,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<InsertCounts> {
+    let iter = read_video(path, username).map_err(|e| eyre!("Failed to read video: {}", e))?;
+    import_locations(db, iter, policy).await
+}
+
+async fn import_fit(
+    db: Arc<Db>,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<InsertCounts> {
+    let iter = read_fit(path, username).map_err(|e| eyre!("Failed to read FIT file: {}", e))?;
+    import_locations(db, iter, policy).await
+}
+
+async fn import_tcx(
+    db: Arc<Db>,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<InsertCounts> {
+    let iter = read_tcx(path, username).map_err(|e| eyre!("Failed to read TCX file: {}", e))?;
+    import_locations(db, iter, policy).await
+}
+
+async fn import_nmea(
+    db: Arc<Db>,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<FileImport> {
+    let mut reader =
+        read_nmea(path, username).map_err(|e| eyre!("Failed to read NMEA log: {}", e))?;
+    let counts = import_locations(db, reader.by_ref(), policy).await?;
+    Ok(FileImport {
+        counts,
+        corrupt: reader.corrupt_lines().to_vec(),
+    })
+}
+
+/// What came of importing a single file.
+#[derive(Debug, Default)]
+pub(crate) struct FileImport {
+    /// The number of locations that met each outcome
+    pub counts: InsertCounts,
+    /// Lines skipped because they were corrupt, for the formats that skip rather than fail
+    pub corrupt: Vec<CorruptLine>,
+}
+
+/// Import a single file in a known format.
+pub(crate) async fn import_file(
+    db: Arc<Db>,
+    format: ImportFormat,
+    path: &Path,
+    username: &str,
+    policy: ConflictPolicy,
+) -> Result<FileImport> {
+    let counts = match format {
+        ImportFormat::Auto => Err(eyre!("Could not detect the format of the file")),
+        ImportFormat::GpsLoggerCsv => import_gps_logger_csv(db, path, username, policy)
+            .await
+            .map_err(|e| eyre!("Failed to import GPSLogger CSV: {}", e)),
+        ImportFormat::GoogleRecords => import_google_records(db, path, username, policy)
+            .await
+            .map_err(|e| eyre!("Failed to import Google Records.json: {}", e)),
+        ImportFormat::GoogleTimeline => import_google_timeline(db, path, username, policy)
+            .await
+            .map_err(|e| eyre!("Failed to import Google Timeline.json: {}", e)),
+        ImportFormat::Gpx => import_gpx(db, path, username, policy)
+            .await
+            .map_err(|e| eyre!("Failed to import GPX: {}", e)),
+        ImportFormat::Kml => import_kml(db, path, username, policy)
+            .await
+            .map_err(|e| eyre!("Failed to import KML: {}", e)),
+        ImportFormat::GeoJson => import_geojson(db, path, username, policy)
+            .await
+            .map_err(|e| eyre!("Failed to import GeoJSON: {}", e)),
+        ImportFormat::Exif => import_exif(db, path, username, policy)
+            .await
+            .map_err(|e| eyre!("Failed to import photo geotags: {}", e)),
+        ImportFormat::Video => import_video(db, path, username, policy)
+            .await
+            .map_err(|e| eyre!("Failed to import video: {}", e)),
+        ImportFormat::Fit => import_fit(db, path, username, policy)
+            .await
+            .map_err(|e| eyre!("Failed to import FIT: {}", e)),
+        ImportFormat::Tcx => import_tcx(db, path, username, policy)
+            .await
+            .map_err(|e| eyre!("Failed to import TCX: {}", e)),
+        ImportFormat::Nmea => {
+            return import_nmea(db, path, username, policy)
+                .await
+                .map_err(|e| eyre!("Failed to import NMEA: {}", e))
+        }
+    }?;
+    Ok(FileImport {
+        counts,
+        corrupt: Vec::new(),
+    })
+}
+
+/// Decide which of the found files to import, and in which format. A single file given directly
+/// is imported in the requested format whatever its name. Otherwise files are detected, and with
+/// a format other than `Auto` only the files detected as that format are imported.
+pub(crate) fn plan_import(
+    format: ImportFormat,
+    path: &Path,
+    files: Vec<FoundFile>,
+) -> Vec<(FoundFile, ImportFormat)> {
+    let single = path.is_file() && files.len() == 1 && files[0].path == path;
+    files
+        .into_iter()
+        .filter_map(|file| {
+            let file_format = match (format, ImportFormat::detect(&file.path)) {
+                (ImportFormat::Auto, detected) => detected,
+                (format, _) if single => Some(format),
+                (format, detected) => detected.filter(|detected| *detected == format),
+            };
+            match file_format {
+                Some(file_format) => Some((file, file_format)),
+                None => {
+                    info!("Skipping {}", file.name.display());
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Open a file with the reader for its format, for a dry run. Google Timeline segments are not
+/// checked.
+fn read_locations(
+    format: ImportFormat,
+    path: &Path,
+    username: &str,
+) -> Result<Box<dyn Iterator<Item = Result<Location>>>> {
+    Ok(match format {
+        ImportFormat::Auto => return Err(eyre!("Could not detect the format of the file")),
+        ImportFormat::GpsLoggerCsv => Box::new(read_csv(path, username)?),
+        ImportFormat::GoogleRecords => Box::new(read_records(path, username)?),
+        ImportFormat::GoogleTimeline => {
+            Box::new(read_timeline(path, username)?.locations.into_iter().map(Ok))
         }
+        ImportFormat::Gpx => Box::new(read_gpx(path, username)?),
+        ImportFormat::Kml => Box::new(read_kml(path, username)?),
+        ImportFormat::GeoJson => Box::new(read_geojson(path, username)?),
+        ImportFormat::Exif => Box::new(read_exif(path, username)?),
+        ImportFormat::Video => Box::new(read_video(path, username)?),
+        ImportFormat::Fit => Box::new(read_fit(path, username)?),
+        ImportFormat::Tcx => Box::new(read_tcx(path, username)?),
+        ImportFormat::Nmea => Box::new(read_nmea(path, username)?),
+    })
+}
+
+/// What importing a file would do, as found by a dry run.
+#[derive(Debug, Default)]
+struct DryRunReport {
+    /// Locations that would be added
+    ok: usize,
+    /// Perfect duplicates that would be skipped
+    duplicate: usize,
+    /// Where and why locations conflict with ones already stored or earlier in the file
+    conflicts: Vec<(String, String)>,
+    /// Where and why records fail to parse
+    invalid: Vec<(String, String)>,
+    /// Where and why locations fail the sanity check, and would be quarantined
+    quarantine: Vec<(String, String)>,
+}
+
+impl DryRunReport {
+    fn total(&self) -> usize {
+        self.ok + self.duplicate + self.conflicts.len() + self.invalid.len() + self.quarantine.len()
     }
-    Ok((added_count, skipped_count))
+
+    fn summary(&self) -> String {
+        format!(
+            "{} records: {} ok, {} would be skipped, {} conflicting, {} invalid, {} would be quarantined",
+            self.total(),
+            self.ok,
+            self.duplicate,
+            self.conflicts.len(),
+            self.invalid.len(),
+            self.quarantine.len()
+        )
+    }
+}
+
+/// Check every location a reader produces against the sanity checks, the database, and the
+/// locations before it, without writing anything. Unlike an import, a bad record does not stop
+/// the check.
+/// # Arguments
+/// * `db`: the database to check against
+/// * `iter`: the locations to check
+/// * `label`: names the position of a record in the file, given its 1-based index
+/// # Returns
+/// The report of what an import would do
+async fn dry_run_locations(
+    db: &Db,
+    iter: impl Iterator<Item = Result<Location>>,
+    label: impl Fn(usize) -> String,
+) -> Result<DryRunReport> {
+    let mut report = DryRunReport::default();
+    let mut seen: HashMap<LocationKey, (usize, Location)> = HashMap::new();
+    for (i, location) in iter.enumerate() {
+        let n = i + 1;
+        let location = match location {
+            Ok(location) => location,
+            Err(e) => {
+                report.invalid.push((label(n), e.to_string()));
+                continue;
+            }
+        };
+        if let Err(e) = location.sanity_check_bounds(db.bounds()) {
+            report.quarantine.push((label(n), e.to_string()));
+            continue;
+        }
+        let key = LocationKey::of(&location);
+        if let Some((first, earlier)) = seen.get(&key) {
+            match *earlier == location {
+                true => report.duplicate += 1,
+                false => report.conflicts.push((
+                    label(n),
+                    format!("Differs from {} at the same time", label(*first)),
+                )),
+            }
+            continue;
+        }
+        match db.location_check(&location).await? {
+            LocationStatus::New => report.ok += 1,
+            LocationStatus::Duplicate => report.duplicate += 1,
+            LocationStatus::Conflict(orig) => report.conflicts.push((
+                label(n),
+                format!("Differs from the stored location: {:?}", orig),
+            )),
+        }
+        seen.insert(key, (n, location));
+    }
+    Ok(report)
+}
+
+/// Dry run the import of a single file in a known format.
+async fn dry_run_file(
+    db: &Db,
+    format: ImportFormat,
+    path: &Path,
+    username: &str,
+) -> Result<DryRunReport> {
+    match format {
+        // CSV records are one per line, after the header
+        ImportFormat::GpsLoggerCsv => {
+            let iter = read_locations(format, path, username)?;
+            dry_run_locations(db, iter, |n| format!("line {}", n + 1)).await
+        }
+        // corrupt lines never become records, so are added separately
+        ImportFormat::Nmea => {
+            let mut reader = read_nmea(path, username)?;
+            let mut report =
+                dry_run_locations(db, reader.by_ref(), |n| format!("fix {}", n)).await?;
+            for line in reader.corrupt_lines() {
+                report
+                    .invalid
+                    .push((format!("line {}", line.line), line.reason.clone()));
+            }
+            Ok(report)
+        }
+        _ => {
+            let iter = read_locations(format, path, username)?;
+            dry_run_locations(db, iter, |n| format!("record {}", n)).await
+        }
+    }
+}
+
+/// Report what importing the planned files would do, without writing anything.
+async fn dry_run_import(db: &Db, plan: &[(FoundFile, ImportFormat)], username: &str) -> Result<()> {
+    if !db.user_vec().await?.iter().any(|user| user == username) {
+        return Err(eyre!("User `{}` does not exist in the database", username));
+    }
+    let mut total = DryRunReport::default();
+    let mut failed_count = 0;
+    for (file, file_format) in plan.iter() {
+        match dry_run_file(db, *file_format, &file.path, username).await {
+            Ok(report) => {
+                println!(
+                    "{} ({:?}): {}",
+                    file.name.display(),
+                    file_format,
+                    report.summary()
+                );
+                for (place, reason) in report.conflicts.iter() {
+                    println!("  {}: conflicting: {}", place, reason);
+                }
+                for (place, reason) in report.invalid.iter() {
+                    println!("  {}: invalid: {}", place, reason);
+                }
+                for (place, reason) in report.quarantine.iter() {
+                    println!("  {}: quarantine: {}", place, reason);
+                }
+                total.ok += report.ok;
+                total.duplicate += report.duplicate;
+                total.conflicts.extend(report.conflicts);
+                total.invalid.extend(report.invalid);
+                total.quarantine.extend(report.quarantine);
+            }
+            Err(e) => {
+                println!("{} ({:?}): failed: {}", file.name.display(), file_format, e);
+                failed_count += 1;
+            }
+        }
+    }
+    println!(
+        "Dry run of {} files, nothing was written. {}",
+        plan.len(),
+        total.summary()
+    );
+    if failed_count > 0 {
+        return Err(eyre!(
+            "Failed to read {} of {} files",
+            failed_count,
+            plan.len()
+        ));
+    }
+    Ok(())
 }
 
+/// Import a file, a directory, or a ZIP archive. Directories and archives are searched
+/// recursively, and every file in them is imported on its own, with a summary for each file and
+/// a total at the end. A file that fails to import does not stop the others. Locations that
+/// conflict with stored ones are resolved by `policy`. A dry run reads and checks every file,
+/// reporting what would happen, but writes nothing. Archives may extract to `max_zip_bytes` in
+/// all.
 pub async fn import(
     config: Config,
     format: ImportFormat,
     path: &Path,
     username: &str,
+    policy: ConflictPolicy,
+    dry_run: bool,
+    max_zip_bytes: u64,
 ) -> Result<()> {
     println!(
-        "Importing\n  format: {:?}\n  path: {}",
+        "Importing\n  format: {:?}\n  path: {}\n  conflicts: {:?}\n  dry run: {}",
         format,
-        path.display()
+        path.display(),
+        policy,
+        dry_run
     );
     let db = Arc::new(
         Db::new(&config.db)
             .await
             .map_err(|e| eyre!("Failed to connect to database: {}", e))?,
     );
-    let (added_count, skipped_count) = match format {
-        ImportFormat::GpsLoggerCsv => import_gps_logger_csv(db, path, username)
-            .await
-            .map_err(|e| eyre!("Failed to import GPSLogger CSV: {}", e))?,
-    };
+    let mut temp_dirs = Vec::new();
+    let files = find_files(path, max_zip_bytes, &mut temp_dirs)
+        .wrap_err("Failed to find files to import")?;
+    let plan = plan_import(format, path, files);
+    if plan.is_empty() {
+        return Err(eyre!("No files to import found in {}", path.display()));
+    }
+    if dry_run {
+        return dry_run_import(&db, &plan, username).await;
+    }
+    let mut total = InsertCounts::default();
+    let mut failed_count = 0;
+    for (file, file_format) in plan.iter() {
+        match import_file(db.clone(), *file_format, &file.path, username, policy).await {
+            Ok(FileImport { counts, corrupt }) => {
+                println!(
+                    "{} ({:?}): found {}, {}",
+                    file.name.display(),
+                    file_format,
+                    counts.total(),
+                    counts
+                );
+                if !corrupt.is_empty() {
+                    println!("  skipped {} corrupt lines:", corrupt.len());
+                    for line in corrupt {
+                        println!("    line {}: {}", line.line, line.reason);
+                    }
+                }
+                total += counts;
+            }
+            Err(e) => {
+                println!("{} ({:?}): failed: {}", file.name.display(), file_format, e);
+                failed_count += 1;
+            }
+        }
+    }
     println!(
-        "Found {} locations. Added {}, skipped {}",
-        added_count + skipped_count,
-        added_count,
-        skipped_count
+        "Found {} locations in {} files. {}",
+        total.total(),
+        plan.len() - failed_count,
+        total
     );
+    if failed_count > 0 {
+        return Err(eyre!(
+            "Failed to import {} of {} files",
+            failed_count,
+            plan.len()
+        ));
+    }
     Ok(())
 }
 
-pub async fn info(config: Config, username: Option<&str>) -> Result<()> {
+pub async fn quarantine_list(config: Config, username: Option<&str>) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    let quarantined = db.quarantine_vec(username).await?;
+    for q in quarantined.iter() {
+        println!(
+            "{:>6}  {:<12} {}  {}",
+            q.id,
+            q.username,
+            q.time_local.to_rfc3339(),
+            q.rule
+        );
+    }
+    println!("{} quarantined locations", quarantined.len());
+    Ok(())
+}
+
+pub async fn quarantine_show(config: Config, id: i64) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    let q = db
+        .quarantine_get(id)
+        .await?
+        .ok_or_else(|| eyre!("No quarantined location with id {}", id))?;
+    println!("{:#?}", q);
+    Ok(())
+}
+
+/// Record quarantined locations, each in its own transaction. One that fails to be recorded stays
+/// in quarantine and does not stop the others.
+pub async fn quarantine_promote(config: Config, ids: &[i64], policy: ConflictPolicy) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    let mut total = InsertCounts::default();
+    let mut failed_count = 0;
+    for id in ids {
+        match db.quarantine_promote(*id, policy).await {
+            Ok(counts) => total += counts,
+            Err(e) => {
+                println!("{}: failed: {:#}", id, e);
+                failed_count += 1;
+            }
+        }
+    }
+    println!("Promoted {} locations: {}", ids.len() - failed_count, total);
+    if failed_count > 0 {
+        return Err(eyre!(
+            "Failed to promote {} of {} locations",
+            failed_count,
+            ids.len()
+        ));
+    }
+    Ok(())
+}
+
+pub async fn quarantine_purge(config: Config, ids: &[i64]) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    let mut count = 0;
+    for id in ids {
+        match db.quarantine_delete(*id).await? {
+            true => count += 1,
+            false => println!("{}: not in quarantine", id),
+        }
+    }
+    println!("Purged {} quarantined locations", count);
+    Ok(())
+}
+
+pub async fn quarantine_clear(config: Config, username: Option<&str>) -> Result<()> {
+    let db = Db::new(&config.db)
+        .await
+        .map_err(|e| eyre!("Failed to connect to database: {}", e))?;
+    let count = db.quarantine_clear(username).await?;
+    println!("Purged {} quarantined locations", count);
+    Ok(())
+}
+
+pub async fn info(config: Config, username: Option<&str>, device: Option<&str>) -> Result<()> {
     use crate::db::UserInfo;
     use chrono::{DateTime, Local};
     let db = Arc::new(
@@ -202,7 +1070,7 @@ pub async fn info(config: Config, username: Option<&str>) -> Result<()> {
             .map_err(|e| eyre!("Failed to connect to database: {}", e))?,
     );
     let user_infos: Vec<UserInfo> = db
-        .info(username)
+        .info(username, device)
         .await
         .wrap_err("Failed to get user info")?;
     for user_info in user_infos {
@@ -214,6 +1082,19 @@ pub async fn info(config: Config, username: Option<&str>) -> Result<()> {
         } else {
             println!("  Last seen: Never");
         }
+        for device_info in user_info.devices {
+            let device = device_info.device;
+            match device.profile {
+                Some(profile) => println!("  Device {} ({})", device.id, profile),
+                None => println!("  Device {}", device.id),
+            }
+            println!("    Total: {}", device_info.location_count);
+            if let Some(last_seen) = device_info.last_seen.map(DateTime::<Local>::from) {
+                println!("    Last seen: {}", last_seen);
+            } else {
+                println!("    Last seen: Never");
+            }
+        }
     }
     Ok(())
 }
@@ -221,11 +1102,28 @@ pub async fn info(config: Config, username: Option<&str>) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::import::DEFAULT_MAX_ZIP_BYTES;
     use pretty_assertions::assert_eq;
     use std::fs::File;
     use std::io::Write;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_password_source() {
+        let read = |source: PasswordSource, input: &str| source.read_from(input.as_bytes());
+        assert_eq!(
+            read(PasswordSource::Value("secret".to_string()), "").unwrap(),
+            "secret"
+        );
+        assert_eq!(
+            read(PasswordSource::Stdin, "secret\r\nmore\n").unwrap(),
+            "secret"
+        );
+        assert_eq!(read(PasswordSource::Stdin, "secret").unwrap(), "secret");
+        assert!(read(PasswordSource::Stdin, "").is_err());
+        assert!(read(PasswordSource::Value(String::new()), "").is_err());
+    }
+
     #[tokio::test]
     async fn test_import_gps_logger_csv() {
         static CSV_DATA: &str = r#"time,lat,lon,elevation,accuracy,bearing,speed,satellites,provider,hdop,vdop,pdop,geoidheight,ageofdgpsdata,dgpsid,activity,battery,annotation,timestamp_ms,time_offset,distance,starttimestamp_ms,profile_name,battery_charging
@@ -245,6 +1143,7 @@ mod tests {
         let db_config = DbConfig {
             path: db_path,
             backups: 0,
+            bounds: Default::default(),
         };
         let db = Arc::new(Db::new(&db_config).await.unwrap());
         db.user_insert(USERNAME.to_string(), "password".to_string())
@@ -263,14 +1162,24 @@ mod tests {
             altitude: 1355.0,
             accuracy: Some(48.0),
             source: crate::schema::Source::GpsLogger,
+            speed: Some(0.0),
+            bearing: None,
+            satellites: Some(0),
+            provider: Some("gps".to_string()),
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: Some(62.0),
+            charging: Some(false),
+            device: String::new(),
         };
         db.location_insert(loc3.clone()).await.unwrap();
         // now import the CSV
-        let (added_count, skipped_count) = import_gps_logger_csv(db.clone(), &csv_path, USERNAME)
+        let counts = import_gps_logger_csv(db.clone(), &csv_path, USERNAME, ConflictPolicy::Error)
             .await
             .unwrap();
-        assert_eq!(added_count, 5);
-        assert_eq!(skipped_count, 1);
+        assert_eq!(counts.added, 5);
+        assert_eq!(counts.skipped, 1);
         let locs = db
             .location_vec(
                 USERNAME,
@@ -286,4 +1195,143 @@ mod tests {
         assert_eq!(locs.len(), 6);
         assert_eq!(locs[2], loc3);
     }
+
+    #[tokio::test]
+    async fn test_dry_run_file() {
+        static CSV_DATA: &str = r#"time,lat,lon,elevation,accuracy,bearing,speed,satellites,provider,hdop,vdop,pdop,geoidheight,ageofdgpsdata,dgpsid,activity,battery,annotation,timestamp_ms,time_offset,distance,starttimestamp_ms,profile_name,battery_charging
+2025-01-24T07:02:29.168Z,24.240779519081116,-11.84485614299774,1476.0,48.0,,0.0,0,gps,,,,,,,,64,,1737702149168,2025-01-24T00:02:29.168-07:00,14780.376051140634,1737686054899,Default Profile,false
+2025-01-24T07:23:55.551Z,24.241143584251404,-11.84490287303925,1411.0,48.0,,0.0,0,gps,,,,,,,,63,,1737703435551,2025-01-24T00:23:55.551-07:00,14821.04923758446,1737686054899,Default Profile,false
+2025-01-24T07:30:20.375Z,24.241090416908264,-11.84478521347046,1355.0,48.0,,0.0,0,gps,,,,,,,,62,,1737703820375,2025-01-24T00:30:20.375-07:00,14832.590979680575,1737686054899,Default Profile,false
+2025-01-24T07:36:54.148Z,124.24091112613678,-11.8446295261383,1414.0,48.0,,0.0,0,gps,,,,,,,,62,,1737704214148,2025-01-24T00:36:54.148-07:00,14856.455069256903,1737686054899,Default Profile,false
+2025-01-24T07:02:29.168Z,24.240779519081116,-11.84485614299774,1476.0,48.0,,0.0,0,gps,,,,,,,,64,,1737702149168,2025-01-24T00:02:29.168-07:00,14780.376051140634,1737686054899,Default Profile,false
+2025-01-24T07:02:29.168Z,24.240779519081116,-11.84485614299774,1000.0,48.0,,0.0,0,gps,,,,,,,,64,,1737702149168,2025-01-24T00:02:29.168-07:00,14780.376051140634,1737686054899,Default Profile,false"#;
+        static USERNAME: &str = "test";
+        let dir = tempdir().unwrap();
+        let csv_path = dir.path().join("test.csv");
+        std::fs::write(&csv_path, CSV_DATA).unwrap();
+        let db = Db::new(&DbConfig {
+            path: dir.path().join("test.db"),
+            backups: 0,
+            bounds: Default::default(),
+        })
+        .await
+        .unwrap();
+        db.user_insert(USERNAME.to_string(), "password".to_string())
+            .await
+            .unwrap();
+        // the 2nd line is already stored, and the 3rd is stored differently
+        let mut stored = read_csv(&csv_path, USERNAME)
+            .unwrap()
+            .skip(1)
+            .take(2)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        stored[1].altitude = 0.0;
+        for location in stored {
+            db.location_insert(location).await.unwrap();
+        }
+
+        let report = dry_run_file(&db, ImportFormat::GpsLoggerCsv, &csv_path, USERNAME)
+            .await
+            .unwrap();
+        assert_eq!(report.ok, 1);
+        // the 2nd line, and the 5th which repeats the 1st
+        assert_eq!(report.duplicate, 2);
+        let conflicts = report
+            .conflicts
+            .iter()
+            .map(|(place, _)| place.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(conflicts, vec!["line 4", "line 7"]);
+        assert!(report.invalid.is_empty());
+        let quarantine = report
+            .quarantine
+            .iter()
+            .map(|(place, _)| place.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(quarantine, vec!["line 5"]);
+        // nothing was written
+        assert_eq!(db.location_count(Some(USERNAME), None).await.unwrap(), 2);
+
+        // two devices may record the same time
+        let phone = read_csv(&csv_path, USERNAME)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let mut tablet = phone.clone();
+        tablet.device = "tablet".to_string();
+        let report = dry_run_locations(&db, [phone, tablet].into_iter().map(Ok), |n| {
+            format!("record {}", n)
+        })
+        .await
+        .unwrap();
+        assert_eq!(report.ok, 2);
+        assert!(report.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let dir = tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        };
+        let records = write("Records.json", r#"{"locations": []}"#);
+        let timeline = write("Timeline.json", r#"{"semanticSegments": []}"#);
+        let ios_timeline = write(
+            "location-history.json",
+            r#"[{"startTime": "2024-06-01T08:00:00.000+02:00"}]"#,
+        );
+        let geojson = write("export.json", r#"{"type": "FeatureCollection"}"#);
+        let other = write("other.json", r#"{"hello": "world"}"#);
+        assert_eq!(
+            ImportFormat::detect(&records),
+            Some(ImportFormat::GoogleRecords)
+        );
+        assert_eq!(
+            ImportFormat::detect(&timeline),
+            Some(ImportFormat::GoogleTimeline)
+        );
+        assert_eq!(
+            ImportFormat::detect(&ios_timeline),
+            Some(ImportFormat::GoogleTimeline)
+        );
+        assert_eq!(ImportFormat::detect(&geojson), Some(ImportFormat::GeoJson));
+        assert_eq!(ImportFormat::detect(&other), None);
+        assert_eq!(
+            ImportFormat::detect(Path::new("20250124.CSV")),
+            Some(ImportFormat::GpsLoggerCsv)
+        );
+        assert_eq!(
+            ImportFormat::detect(Path::new("IMG_0001.HEIC")),
+            Some(ImportFormat::Exif)
+        );
+        assert_eq!(ImportFormat::detect(Path::new("notes.txt")), None);
+        assert_eq!(ImportFormat::detect(Path::new("README")), None);
+    }
+
+    #[test]
+    fn test_plan_import() {
+        let dir = tempdir().unwrap();
+        for name in ["a.csv", "b.gpx", "c.txt"] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        let mut temp_dirs = Vec::new();
+        let files = find_files(dir.path(), DEFAULT_MAX_ZIP_BYTES, &mut temp_dirs).unwrap();
+        let plan = plan_import(ImportFormat::Auto, dir.path(), files.clone());
+        let formats: Vec<ImportFormat> = plan.iter().map(|(_, format)| *format).collect();
+        assert_eq!(formats, vec![ImportFormat::GpsLoggerCsv, ImportFormat::Gpx]);
+        // Only the files of the requested format
+        let plan = plan_import(ImportFormat::Gpx, dir.path(), files);
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].0.path, dir.path().join("b.gpx"));
+        // A single file in the requested format, whatever its name
+        let path = dir.path().join("c.txt");
+        let files = find_files(&path, DEFAULT_MAX_ZIP_BYTES, &mut temp_dirs).unwrap();
+        let plan = plan_import(ImportFormat::Nmea, &path, files);
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].1, ImportFormat::Nmea);
+    }
 }
@@ -1,20 +1,38 @@
 use chrono::{DateTime, Utc};
+use clap::ValueEnum;
 use color_eyre::eyre::{eyre, Result, WrapErr};
 use futures::Stream;
-use log::{debug, LevelFilter};
+use log::{debug, info, warn, LevelFilter};
 use sea_orm::{
-    error::DbErr, ActiveModelTrait, ColumnTrait, ConnectOptions, ConnectionTrait, Database,
-    DatabaseConnection, EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder,
-    Schema, SqlErr,
+    error::DbErr,
+    sea_query::{Expr, OnConflict},
+    ActiveModelTrait,
+    ActiveValue::NotSet,
+    ColumnTrait, ConnectOptions, ConnectionTrait, Database, DatabaseConnection, EntityTrait,
+    IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder, SqlErr, TransactionTrait,
 };
 use serde::Deserialize;
 
 use std::{
+    collections::HashMap,
+    fmt,
     iter::Iterator,
+    ops::AddAssign,
     path::{Path, PathBuf},
 };
 
-use crate::schema::{location, user, Location, SanityCheck};
+use crate::auth;
+use crate::migrate::{self, Migration, Step};
+use crate::schema::{
+    api_token, check_username, conflict, device, location, quarantine, segment, user, ApiToken,
+    Bounds, Conflict, Device, Location, Quarantined, SanityCheck, Scope, Segment,
+};
+
+/// Number of locations `Db::location_insert_many` records per transaction
+pub const LOCATION_BATCH_SIZE: usize = 10_000;
+/// Number of rows per multi-row insert. Each location binds 18 parameters, which keeps a
+/// statement under SQLite's limit of 32766 parameters.
+const INSERT_CHUNK_SIZE: usize = 1_000;
 
 /// Configuration for the database, obtained from main.rs::Args
 #[derive(Deserialize, Debug, Clone)]
@@ -23,6 +41,133 @@ pub struct Config {
     pub path: PathBuf,
     /// Keep this many most recent backups
     pub backups: usize,
+    /// Locations outside these limits are put in quarantine instead of being recorded
+    #[serde(default)]
+    pub bounds: Bounds,
+}
+
+/// How a location compares to what is already stored, as found by `Db::location_check`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LocationStatus {
+    /// Nothing is stored for this user, time and device, so the location would be recorded
+    New,
+    /// An identical location is stored, so the location would be skipped
+    Duplicate,
+    /// A different location is stored for this user, time and device, so recording it would be
+    /// up to the `ConflictPolicy`
+    Conflict(Location),
+}
+
+/// What to do with a location that shares its user, time and device with a stored location, but
+/// differs from it. Perfect duplicates are always skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// Fail, recording nothing from the batch
+    #[default]
+    Error,
+    /// Keep the stored location and drop the new one
+    KeepExisting,
+    /// Overwrite the stored location with the new one
+    Replace,
+    /// Keep whichever has the smaller accuracy radius. One without an accuracy is the less
+    /// accurate, and ties keep the stored location.
+    KeepMoreAccurate,
+    /// Keep the stored location, and set the new one aside in the conflicts table
+    StoreBoth,
+}
+
+/// Whether `loc` is more accurate than `orig`, for `ConflictPolicy::KeepMoreAccurate`.
+fn is_more_accurate(loc: &Location, orig: &Location) -> bool {
+    match (loc.accuracy, orig.accuracy) {
+        (Some(new), Some(old)) => new < old,
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+/// Whether `loc` is `orig` with the details added in version 5 of the schema filled in. Locations
+/// stored before then have none of them, so importing the same file again backfills them rather
+/// than conflicting.
+fn fills_in_details(loc: &Location, orig: &Location) -> bool {
+    *orig
+        == Location {
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            ..loc.clone()
+        }
+}
+
+/// What identifies a stored location: its user, time and device.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LocationKey {
+    username: String,
+    time_utc: DateTime<Utc>,
+    device: String,
+}
+
+impl LocationKey {
+    /// The key of a location.
+    pub fn of(loc: &Location) -> Self {
+        LocationKey {
+            username: loc.username.clone(),
+            time_utc: loc.time_utc,
+            device: loc.device.clone(),
+        }
+    }
+}
+
+/// The number of locations that met each outcome when recording them.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct InsertCounts {
+    /// Recorded as new locations
+    pub added: usize,
+    /// Perfect duplicates of stored locations, or of conflicts already set aside. Includes
+    /// duplicates that fill in the details of locations stored before there were any
+    pub skipped: usize,
+    /// Conflicting, and dropped in favor of the stored location
+    pub kept: usize,
+    /// Conflicting, and recorded in place of the stored location
+    pub replaced: usize,
+    /// Conflicting, and set aside in the conflicts table
+    pub stored: usize,
+    /// Failed the sanity check, and put in quarantine
+    pub quarantined: usize,
+}
+
+impl InsertCounts {
+    /// The number of locations counted
+    pub fn total(&self) -> usize {
+        self.added + self.skipped + self.kept + self.replaced + self.stored + self.quarantined
+    }
+}
+
+impl AddAssign for InsertCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.added += other.added;
+        self.skipped += other.skipped;
+        self.kept += other.kept;
+        self.replaced += other.replaced;
+        self.stored += other.stored;
+        self.quarantined += other.quarantined;
+    }
+}
+
+impl fmt::Display for InsertCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "added {}, skipped {}, kept existing {}, replaced {}, set aside {}, quarantined {}",
+            self.added, self.skipped, self.kept, self.replaced, self.stored, self.quarantined
+        )
+    }
 }
 
 /// Struct to hold user information
@@ -34,6 +179,30 @@ pub struct UserInfo {
     pub location_count: u64,
     /// Last time the user was seen
     pub last_seen: Option<DateTime<Utc>>,
+    /// The same information for each of the user's devices
+    pub devices: Vec<DeviceInfo>,
+}
+
+/// Struct to hold device information
+#[derive(Debug)]
+pub struct DeviceInfo {
+    /// The device
+    pub device: Device,
+    /// Number of locations recorded by the device
+    pub location_count: u64,
+    /// Last time the device recorded a location
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// Where the database schema stands, as found by `Db::migration_status`.
+#[derive(Debug)]
+pub struct MigrationStatus {
+    /// Version of the schema, or None if migrations have never been applied
+    pub current: Option<i64>,
+    /// Version this build of crataegus works with
+    pub latest: i64,
+    /// Migrations still to be applied, in order
+    pub pending: Vec<Migration>,
 }
 
 /// The database struct used by the server and the app. SQLite is used as the database backend, and
@@ -47,12 +216,45 @@ pub struct Db {
 
 impl Db {
     /// Create a new database connection. If the database does not exist, it will be created and
-    /// the necessary tables will be added.
+    /// the necessary tables will be added. An existing database must already be at the schema
+    /// version of this build; see `migrate`.
     /// # Arguments
     /// * `config` - The configuration for the database
     /// # Returns
     /// The database struct
     pub async fn new(config: &Config) -> Result<Self> {
+        let db = Self::open(config).await?;
+        if migrate::is_empty(&db.conn).await? {
+            for migration in migrate::migrations() {
+                migrate::apply(&db.conn, &migration).await?;
+            }
+            return Ok(db);
+        }
+        let status = db.migration_status().await?;
+        let current = status.current.unwrap_or(0);
+        if current > status.latest {
+            return Err(eyre!(
+                "Database schema is at version {}, newer than version {} of this build of crataegus",
+                current,
+                status.latest
+            ));
+        }
+        if !status.pending.is_empty() {
+            return Err(eyre!(
+                "Database schema is at version {}, but version {} is required. Run `crataegus migrate`, which backs up the database first.",
+                current,
+                status.latest
+            ));
+        }
+        Ok(db)
+    }
+
+    /// Connect to the database without checking its schema, so that it can be migrated.
+    /// # Arguments
+    /// * `config` - The configuration for the database
+    /// # Returns
+    /// The database struct
+    pub async fn open(config: &Config) -> Result<Self> {
         // connecting with `c` option will create the file if it doesn't exist
         let url = format!("sqlite://{}?mode=rwc", config.path.display());
         let mut options = ConnectOptions::new(url);
@@ -60,32 +262,79 @@ impl Db {
         let conn = Database::connect(options)
             .await
             .wrap_err("Failed to connect to the database")?;
-        let schema = Schema::new(conn.get_database_backend());
-        // add all the tables
-        conn.execute(
-            conn.get_database_backend().build(
-                schema
-                    .create_table_from_entity(user::Entity)
-                    .if_not_exists(),
-            ),
-        )
-        .await
-        .wrap_err("Failed to create the users table")?;
-        conn.execute(
-            conn.get_database_backend().build(
-                schema
-                    .create_table_from_entity(location::Entity)
-                    .if_not_exists(),
-            ),
-        )
-        .await
-        .wrap_err("Failed to create the locations table")?;
         Ok(Db {
             config: config.clone(),
             conn,
         })
     }
 
+    /////////////////////////
+    // Migration Functions //
+    /////////////////////////
+
+    /// Find out which migrations the database needs.
+    /// # Returns
+    /// The current and latest schema versions, and the migrations to get from one to the other
+    pub async fn migration_status(&self) -> Result<MigrationStatus> {
+        let current = migrate::current_version(&self.conn).await?;
+        let pending = migrate::migrations()
+            .into_iter()
+            .filter(|migration| migration.version > current.unwrap_or(0))
+            .collect();
+        Ok(MigrationStatus {
+            current,
+            latest: migrate::latest_version(),
+            pending,
+        })
+    }
+
+    /// Whether each step of a pending migration would run against the current schema, or be
+    /// skipped because it has already taken effect. Nothing is written.
+    /// # Arguments
+    /// * `migration` - The migration to check
+    /// # Returns
+    /// Each step, with whether it would run
+    pub async fn migration_plan(&self, migration: &Migration) -> Result<Vec<(Step, bool)>> {
+        let mut plan = Vec::new();
+        for step in migration.steps.iter() {
+            plan.push((step.clone(), migrate::step_pending(&self.conn, step).await?));
+        }
+        Ok(plan)
+    }
+
+    /// Apply every pending migration, each in its own transaction. A database that has tables is
+    /// backed up first, next to it as `<path>.pre-v<latest>.<ts>.bak`, which is not deleted with
+    /// the regular backups.
+    /// # Returns
+    /// The number of migrations applied
+    pub async fn migrate(&self) -> Result<usize> {
+        let status = self.migration_status().await?;
+        if status.pending.is_empty() {
+            return Ok(0);
+        }
+        if !migrate::is_empty(&self.conn).await? {
+            let path = std::path::absolute(&self.config.path)
+                .wrap_err("Failed to find the absolute path of the database")?;
+            let backup_path = PathBuf::from(format!(
+                "{}.pre-v{}.{}.bak",
+                path.display(),
+                status.latest,
+                chrono::Utc::now().timestamp()
+            ));
+            info!(
+                "Backing up the database before migrating, to {:?}",
+                backup_path
+            );
+            self.backup_to(&backup_path)
+                .await
+                .wrap_err("Failed to back up the database before migrating")?;
+        }
+        for migration in status.pending.iter() {
+            migrate::apply(&self.conn, migration).await?;
+        }
+        Ok(status.pending.len())
+    }
+
     //////////////////////
     // Backup Functions //
     //////////////////////
@@ -190,10 +439,12 @@ impl Db {
     /// an error.
     /// # Arguments
     /// * `username` - The username to insert
-    /// * `password` - The password to insert
+    /// * `password` - The password to insert, which is stored as an Argon2id hash
     /// # Returns
     /// `Ok(())` if the user was successfully inserted, an error otherwise
     pub async fn user_insert(&self, username: String, password: String) -> Result<()> {
+        check_username(&username)?;
+        let password = hash_password(password).await?;
         let user = user::Model { username, password };
         let active_user = user.into_active_model();
         active_user
@@ -203,8 +454,29 @@ impl Db {
         Ok(())
     }
 
+    /// Replace the password of an existing user.
+    /// # Arguments
+    /// * `username` - The user whose password to replace
+    /// * `password` - The new password, which is stored as an Argon2id hash
+    /// # Returns
+    /// `Ok(())` if the password was replaced, an error if the user does not exist
+    pub async fn user_set_password(&self, username: &str, password: &str) -> Result<()> {
+        let password = hash_password(password.to_string()).await?;
+        let result = user::Entity::update_many()
+            .col_expr(user::Column::Password, Expr::value(password))
+            .filter(user::Column::Username.eq(username))
+            .exec(&self.conn)
+            .await
+            .wrap_err("Failed to update user password")?;
+        if result.rows_affected == 0 {
+            return Err(eyre!("No such user: {}", username));
+        }
+        Ok(())
+    }
+
     /// Check if the user exists in the database and if the password matches. Returns false if
     /// either the user does not exist or the user does exist, but the password does not match.
+    /// Passwords stored in plaintext by older versions are replaced by a hash once they match.
     /// # Arguments
     /// * `username` - The username to check
     /// * `password` - The password to check
@@ -216,13 +488,66 @@ impl Db {
             .one(&self.conn)
             .await
             .wrap_err("Failed to query user from database")?;
-        match user {
-            Some(user) => {
-                user.sanity_check()?;
-                Ok(user.password == *password)
-            }
-            None => Ok(false),
+        let Some(user) = user else {
+            let given = password.to_string();
+            tokio::task::spawn_blocking(move || auth::verify_dummy(&given))
+                .await
+                .wrap_err("Password verification panicked")?;
+            return Ok(false);
+        };
+        let stored = user.password.clone();
+        let given = password.to_string();
+        let good = tokio::task::spawn_blocking(move || auth::verify_password(&given, &stored))
+            .await
+            .wrap_err("Password verification panicked")??;
+        if good && !auth::is_hash(&user.password) {
+            info!("Replacing plaintext password of {} with a hash", username);
+            self.user_set_password(username, password).await?;
+        }
+        Ok(good)
+    }
+
+    /// Delete a user, along with everything recorded for them: locations, devices, segments,
+    /// conflicts and quarantined locations.
+    /// # Arguments
+    /// * `username` - The user to delete
+    /// # Returns
+    /// `Ok(())` if the user was deleted, an error if the user does not exist
+    pub async fn user_delete(&self, username: &str) -> Result<()> {
+        let result = user::Entity::delete_by_id(username)
+            .exec(&self.conn)
+            .await
+            .wrap_err("Failed to delete user from database")?;
+        if result.rows_affected == 0 {
+            return Err(eyre!("No such user: {}", username));
+        }
+        Ok(())
+    }
+
+    /// Rename a user. Everything recorded for them follows, since every table references the user
+    /// with `ON UPDATE CASCADE`.
+    /// # Arguments
+    /// * `username` - The current username
+    /// * `new_username` - The username to change it to, which must not be taken
+    /// # Returns
+    /// `Ok(())` if the user was renamed, an error otherwise
+    pub async fn user_rename(&self, username: &str, new_username: &str) -> Result<()> {
+        check_username(new_username)?;
+        let result = user::Entity::update_many()
+            .col_expr(user::Column::Username, Expr::value(new_username))
+            .filter(user::Column::Username.eq(username))
+            .exec(&self.conn)
+            .await
+            .map_err(|e| match e.sql_err() {
+                Some(SqlErr::UniqueConstraintViolation(_)) => {
+                    eyre!("User already exists: {}", new_username)
+                }
+                _ => eyre!("Failed to rename user: {}", e),
+            })?;
+        if result.rows_affected == 0 {
+            return Err(eyre!("No such user: {}", username));
         }
+        Ok(())
     }
 
     /// Get a list of all usernames in the database.
@@ -241,186 +566,988 @@ impl Db {
         Ok(users)
     }
 
-    ////////////////////////////////
-    // Location-Related Functions //
-    ////////////////////////////////
+    //////////////////////////////
+    // Device-Related Functions //
+    //////////////////////////////
 
-    /// Record a new location in the database. Silently ignore entries that are perfect duplicates,
-    /// which may occur as a result of manual uploads. Duplicated user/time info with different
-    /// location data will return an error.
+    /// Register a device, or update the serial number and profile of a registered one. Devices
+    /// are also registered, without these details, when their first location is recorded.
     /// # Arguments
-    /// * `loc` - The location to record
+    /// * `device` - The device to register
     /// # Returns
-    /// `Ok(true)` if the location was successfully recorded, Ok(false) if the locations already exists in the database. An
-    /// error otherwise.
-    pub async fn location_insert(&self, loc: Location) -> Result<bool> {
-        loc.sanity_check()?;
-        let active_loc = loc.clone().into_active_model();
-        match active_loc.insert(&self.conn).await {
-            Ok(_) => Ok(true),
+    /// `Ok(())` if the device was registered, an error otherwise
+    pub async fn device_insert(&self, device: Device) -> Result<()> {
+        device.sanity_check()?;
+        let username = device.username.clone();
+        let result = device::Entity::insert(device.into_active_model())
+            .on_conflict(
+                OnConflict::columns([device::Column::Username, device::Column::Id])
+                    .update_columns([device::Column::Serial, device::Column::Profile])
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.conn)
+            .await;
+        match result {
+            Ok(_) => Ok(()),
             Err(e) => {
-                if let Some(SqlErr::UniqueConstraintViolation(_)) = e.sql_err() {
-                    let orig = location::Entity::find()
-                        .filter(location::Column::Username.eq(loc.username.clone()))
-                        .filter(location::Column::TimeUtc.eq(loc.time_utc))
-                        .one(&self.conn)
-                        .await
-                        .wrap_err("Failed to query original location when investigating duplicate")?
-                        .ok_or_else(|| eyre!("Got unique constraint violation but couldn't find the original:\n{:?}", loc))?;
-                    if loc == orig {
-                        debug!("Ignoring duplicate location entry: {:?}", loc);
-                        Ok(false)
-                    } else {
-                        Err(e).wrap_err(format!("Received user/time info that is duplicated, but other fields differ.\nOriginal: {:?}\nReceived: {:?}", orig, loc))
-                    }
-                } else if let Some(SqlErr::ForeignKeyConstraintViolation(_)) = e.sql_err() {
-                    Err(e).wrap_err(format!(
-                        "User `{}` does not exist in the database. Cannot insert location.",
-                        loc.username
-                    ))
-                } else {
-                    Err(e).wrap_err(format!(
-                        "Failed to insert location into database for unknown reason: {:?}",
-                        loc
-                    ))
+                if let Some(SqlErr::ForeignKeyConstraintViolation(_)) = e.sql_err() {
+                    return Err(e).wrap_err(format!(
+                        "User `{}` does not exist in the database. Cannot register device.",
+                        username
+                    ));
                 }
+                Err(e).wrap_err("Failed to register device")
             }
         }
     }
 
-    /// Generator function that returns all locations in the database that fall between the
-    /// specified time bounds. Avoids loading all locations into memory at once. Lifetime is tied
-    /// to the database connection.
+    /// Get a list of a user's devices.
     /// # Arguments
-    /// * `start` - The start time of the range, inclusive.
-    /// * `stop` - The stop time of the range, exclusive.
+    /// * `username` - The owner of the devices
     /// # Returns
-    /// Locations that fall within the specified time range, in ascending order of time.
-    pub async fn location_stream(
-        &self,
-        username: &str,
-        start: DateTime<Utc>,
-        stop: DateTime<Utc>,
-    ) -> Result<impl Stream<Item = Result<Location, DbErr>> + use<'_>, DbErr> {
-        let stream = location::Entity::find()
-            .filter(location::Column::Username.eq(username))
-            .filter(location::Column::TimeUtc.between(start, stop))
-            .order_by_asc(location::Column::TimeUtc)
-            .stream(&self.conn)
-            .await?;
-        Ok(stream)
+    /// The devices, sorted by id
+    pub async fn device_vec(&self, username: &str) -> Result<Vec<Device>> {
+        device::Entity::find()
+            .filter(device::Column::Username.eq(username))
+            .order_by_asc(device::Column::Id)
+            .all(&self.conn)
+            .await
+            .wrap_err("Failed to query devices from database")
     }
 
-    /// Get a user location closest to, but not after, the specified time.
+    /////////////////////////////////
+    // API Token-Related Functions //
+    /////////////////////////////////
+
+    /// Create an API token for a user. The token itself is not stored, so this is the only time
+    /// it is available.
     /// # Arguments
-    /// * `username` - The username to get the location for
-    /// * `time` - The time to get the location for. Time of returned location will be less than or
-    ///   equal to this time.
+    /// * `username` - The user the token authenticates as
+    /// * `name` - Name of the token, unique per user
+    /// * `scope` - What the token may be used for
+    /// * `expires_utc` - When the token stops working, if ever
     /// # Returns
-    /// The location closest to, but not after, the specified time, if it exists.
-    pub async fn location_at(
+    /// The token, or an error if the user does not exist or already has a token of that name
+    pub async fn token_insert(
         &self,
         username: &str,
-        time: &DateTime<Utc>,
-    ) -> Result<Option<Location>> {
-        let loc = location::Entity::find()
-            .filter(location::Column::Username.eq(username))
-            .filter(location::Column::TimeUtc.lte(*time))
-            .order_by_desc(location::Column::TimeUtc)
-            .one(&self.conn)
-            .await
-            .wrap_err("Failed to query location from database")?;
-        Ok(loc)
+        name: &str,
+        scope: Scope,
+        expires_utc: Option<DateTime<Utc>>,
+    ) -> Result<String> {
+        let token = auth::generate_token();
+        let model = ApiToken {
+            id: 0,
+            username: username.to_string(),
+            name: name.to_string(),
+            scope,
+            hash: auth::hash_token(&token),
+            created_utc: Utc::now(),
+            expires_utc,
+        };
+        model.sanity_check()?;
+        let mut active = model.into_active_model();
+        active.id = NotSet;
+        match active.insert(&self.conn).await {
+            Ok(_) => Ok(token),
+            Err(e) => match e.sql_err() {
+                Some(SqlErr::ForeignKeyConstraintViolation(_)) => Err(e).wrap_err(format!(
+                    "User `{}` does not exist in the database. Cannot create token.",
+                    username
+                )),
+                Some(SqlErr::UniqueConstraintViolation(_)) => Err(e).wrap_err(format!(
+                    "User `{}` already has a token named `{}`",
+                    username, name
+                )),
+                _ => Err(e).wrap_err("Failed to insert token into database"),
+            },
+        }
     }
 
-    #[cfg(test)]
-    pub(crate) async fn location_vec(
-        &self,
-        username: &str,
-        start: DateTime<Utc>,
-        stop: DateTime<Utc>,
-    ) -> Result<Vec<Location>> {
-        use futures::StreamExt;
-        let mut stream = self.location_stream(username, start, stop).await?;
-        let mut vec = Vec::new();
-        while let Some(loc) = stream.next().await {
-            vec.push(loc?);
+    /// Get a list of API tokens.
+    /// # Arguments
+    /// * `username` - Only list this user's, if set
+    /// # Returns
+    /// The tokens, sorted by user and name
+    pub async fn token_vec(&self, username: Option<&str>) -> Result<Vec<ApiToken>> {
+        let mut query = api_token::Entity::find();
+        if let Some(username) = username {
+            query = query.filter(api_token::Column::Username.eq(username));
         }
-        Ok(vec)
+        query
+            .order_by_asc(api_token::Column::Username)
+            .order_by_asc(api_token::Column::Name)
+            .all(&self.conn)
+            .await
+            .wrap_err("Failed to query tokens from database")
     }
 
-    /// Count the number of locations in the database. If username is provided, count only the
-    /// locations for that user.
+    /// Revoke an API token, deleting it.
     /// # Arguments
-    /// * `username` - The username to count locations for. If None, count all locations.
+    /// * `username` - The user the token belongs to
+    /// * `name` - Name of the token
     /// # Returns
-    /// The number of locations in the database if the query was successful, an error otherwise.
-    pub async fn location_count(&self, username: Option<&str>) -> Result<u64> {
-        match username {
-            Some(username) => {
-                // ensure user exists
-                if user::Entity::find()
-                    .filter(user::Column::Username.eq(username))
-                    .one(&self.conn)
-                    .await
-                    .wrap_err(format!("Failed to query user {} from database", username))?
-                    .is_none()
-                {
-                    return Err(eyre!("User {} does not exist in the database", username));
-                }
-                Ok(location::Entity::find()
-                    .filter(location::Column::Username.eq(username))
-                    .count(&self.conn)
-                    .await
-                    .wrap_err(format!("Failed to count locations for user {}", username))?)
-            }
-            None => Ok(location::Entity::find()
-                .count(&self.conn)
-                .await
-                .wrap_err("Failed to count locations for all users")?),
+    /// `Ok(())` if the token was revoked, an error if there is no such token
+    pub async fn token_delete(&self, username: &str, name: &str) -> Result<()> {
+        let result = api_token::Entity::delete_many()
+            .filter(api_token::Column::Username.eq(username))
+            .filter(api_token::Column::Name.eq(name))
+            .exec(&self.conn)
+            .await
+            .wrap_err("Failed to delete token from database")?;
+        if result.rows_affected == 0 {
+            return Err(eyre!("User `{}` has no token named `{}`", username, name));
         }
+        Ok(())
     }
 
-    //////////////////////////
-    // High Level Functions //
-    //////////////////////////
-
-    /// Get information about all users in the database.
+    /// Look up the API token presented by a client.
     /// # Arguments
-    /// * `username` - The username to get information for. If None, get information for all users.
+    /// * `token` - The token
     /// # Returns
-    /// A vector of user information structs.
-    pub async fn info(&self, username: Option<&str>) -> Result<Vec<UserInfo>> {
-        let users: Vec<String> = match username {
-            Some(username) => vec![username.to_string()],
-            None => self
-                .user_vec()
-                .await
-                .wrap_err("Failed to query user list from database")?,
-        };
-        let mut user_infos = Vec::new();
-        for username in users {
-            let count = self.location_count(Some(&username)).await?;
-            let last_seen = location::Entity::find()
-                .filter(location::Column::Username.eq(&username))
-                .order_by_desc(location::Column::TimeUtc)
-                .one(&self.conn)
-                .await
-                .wrap_err("Failed to query last seen location from database")?
-                .map(|loc| loc.time_utc);
-            user_infos.push(UserInfo {
-                username,
-                location_count: count,
-                last_seen,
-            });
-        }
-        Ok(user_infos)
+    /// The token's record, or None if it does not exist or has expired
+    pub async fn token_check(&self, token: &str) -> Result<Option<ApiToken>> {
+        let found = api_token::Entity::find()
+            .filter(api_token::Column::Hash.eq(auth::hash_token(token)))
+            .one(&self.conn)
+            .await
+            .wrap_err("Failed to query token from database")?;
+        Ok(found.filter(|found| !found.is_expired(Utc::now())))
     }
-}
 
-////////////////
-// Unit Tests //
-////////////////
+    ////////////////////////////////
+    // Location-Related Functions //
+    ////////////////////////////////
+
+    /// Record a new location in the database. Silently ignore entries that are perfect duplicates,
+    /// which may occur as a result of manual uploads. Duplicated user/time info with different
+    /// location data will return an error.
+    /// # Arguments
+    /// * `loc` - The location to record
+    /// # Returns
+    /// `Ok(true)` if the location was successfully recorded, Ok(false) if the locations already exists in the database. An
+    /// error otherwise.
+    pub async fn location_insert(&self, loc: Location) -> Result<bool> {
+        loc.sanity_check_bounds(&self.config.bounds)?;
+        Self::location_insert_with(&self.conn, loc).await
+    }
+
+    /// Record a location, resolving a conflict with a stored location by the given policy.
+    /// # Arguments
+    /// * `loc` - The location to record
+    /// * `policy` - What to do if a different location is stored for the same user, time and device
+    /// # Returns
+    /// The outcome, as counts of one, or an error.
+    pub async fn location_insert_with_policy(
+        &self,
+        loc: Location,
+        policy: ConflictPolicy,
+    ) -> Result<InsertCounts> {
+        self.location_insert_all(vec![loc], policy).await
+    }
+
+    /// Record a batch of locations in a single transaction. Perfect duplicates are skipped, and
+    /// locations that differ from a stored one, or from an earlier one in the batch, with the same
+    /// user, time and device are resolved by `policy`. Either the whole batch is applied, or nothing is
+    /// written. Stored locations in the batch's time range are fetched up front, so that
+    /// duplicates and conflicts are found without a query per location, and new locations are
+    /// written with multi-row inserts.
+    /// # Arguments
+    /// * `locs` - The locations to record
+    /// * `policy` - What to do with conflicting locations
+    /// # Returns
+    /// The number of locations that met each outcome, once the transaction is committed. An error
+    /// otherwise.
+    pub async fn location_insert_all(
+        &self,
+        locs: Vec<Location>,
+        policy: ConflictPolicy,
+    ) -> Result<InsertCounts> {
+        let mut accepted = Vec::with_capacity(locs.len());
+        let mut rejected = Vec::new();
+        for loc in locs {
+            match loc.sanity_check_bounds(&self.config.bounds) {
+                Ok(()) => accepted.push(loc),
+                Err(e) => rejected.push((loc, e.to_string())),
+            }
+        }
+        let txn = self
+            .conn
+            .begin()
+            .await
+            .wrap_err("Failed to begin location batch transaction")?;
+        // returning early drops the transaction, which rolls it back
+        let mut counts = Self::location_write(&txn, accepted, policy).await?;
+        counts += Self::quarantine_insert(&txn, rejected).await?;
+        txn.commit()
+            .await
+            .wrap_err("Failed to commit location batch transaction")?;
+        Ok(counts)
+    }
+
+    /// Write locations that passed the sanity check, as `location_insert_all` does, on a
+    /// connection that should be a transaction.
+    async fn location_write<C: ConnectionTrait>(
+        conn: &C,
+        locs: Vec<Location>,
+        policy: ConflictPolicy,
+    ) -> Result<InsertCounts> {
+        let (writes, conflicts, mut counts) = Self::location_resolve(conn, locs, policy).await?;
+        let (inserts, updates): (Vec<_>, Vec<_>) =
+            writes.into_iter().partition(|(_, replaces)| !replaces);
+        let inserts = inserts.into_iter().map(|(loc, _)| loc).collect::<Vec<_>>();
+        for chunk in inserts.chunks(INSERT_CHUNK_SIZE) {
+            let models = chunk
+                .iter()
+                .cloned()
+                .map(IntoActiveModel::into_active_model);
+            let result = location::Entity::insert_many(models)
+                .on_conflict(
+                    OnConflict::columns([
+                        location::Column::Username,
+                        location::Column::TimeUtc,
+                        location::Column::Device,
+                    ])
+                    .do_nothing()
+                    .to_owned(),
+                )
+                .exec_without_returning(conn)
+                .await;
+            match result {
+                Ok(rows) => {
+                    counts.added += rows as usize;
+                    // only possible if another writer got there first
+                    counts.skipped += chunk.len() - rows as usize;
+                }
+                Err(e) => {
+                    if let Some(SqlErr::ForeignKeyConstraintViolation(_)) = e.sql_err() {
+                        return Err(e).wrap_err(format!(
+                            "User `{}` does not exist in the database. Cannot insert locations.",
+                            chunk[0].username
+                        ));
+                    }
+                    return Err(e).wrap_err("Failed to insert location batch into database");
+                }
+            }
+        }
+        Self::device_register(conn, &inserts).await?;
+        let updates = updates.into_iter().map(|(loc, _)| loc).collect::<Vec<_>>();
+        for chunk in updates.chunks(INSERT_CHUNK_SIZE) {
+            let models = chunk
+                .iter()
+                .cloned()
+                .map(IntoActiveModel::into_active_model);
+            // a model converted from a location has every column unchanged, so `update` would
+            // write nothing. Upserting overwrites all but the key instead.
+            location::Entity::insert_many(models)
+                .on_conflict(
+                    OnConflict::columns([
+                        location::Column::Username,
+                        location::Column::TimeUtc,
+                        location::Column::Device,
+                    ])
+                    .update_columns([
+                        location::Column::TimeLocal,
+                        location::Column::Latitude,
+                        location::Column::Longitude,
+                        location::Column::Altitude,
+                        location::Column::Accuracy,
+                        location::Column::Source,
+                        location::Column::Speed,
+                        location::Column::Bearing,
+                        location::Column::Satellites,
+                        location::Column::Provider,
+                        location::Column::Hdop,
+                        location::Column::Vdop,
+                        location::Column::Pdop,
+                        location::Column::Battery,
+                        location::Column::Charging,
+                    ])
+                    .to_owned(),
+                )
+                .exec_without_returning(conn)
+                .await
+                .wrap_err("Failed to replace locations in database")?;
+        }
+        for chunk in conflicts.chunks(INSERT_CHUNK_SIZE) {
+            let models = chunk.iter().cloned().map(conflict::ActiveModel::from);
+            conflict::Entity::insert_many(models)
+                .exec_without_returning(conn)
+                .await
+                .wrap_err("Failed to store conflicting locations in database")?;
+        }
+        Ok(counts)
+    }
+
+    /// Record every location an iterator produces, committing a transaction per
+    /// `LOCATION_BATCH_SIZE` locations with `location_insert_all`. Stops at the first error,
+    /// whether from the iterator or the database, in which case the batches before it stay
+    /// recorded and the one in progress is rolled back.
+    /// # Arguments
+    /// * `locs` - The locations to record
+    /// * `policy` - What to do with conflicting locations
+    /// # Returns
+    /// The number of locations that met each outcome. An error otherwise.
+    pub async fn location_insert_many(
+        &self,
+        locs: impl Iterator<Item = Result<Location>>,
+        policy: ConflictPolicy,
+    ) -> Result<InsertCounts> {
+        let mut counts = InsertCounts::default();
+        let mut batch = Vec::with_capacity(LOCATION_BATCH_SIZE);
+        for loc in locs {
+            batch.push(loc?);
+            if batch.len() == LOCATION_BATCH_SIZE {
+                counts += self
+                    .location_insert_all(std::mem::take(&mut batch), policy)
+                    .await?;
+            }
+        }
+        if !batch.is_empty() {
+            counts += self.location_insert_all(batch, policy).await?;
+        }
+        Ok(counts)
+    }
+
+    /// Work out what to write for a batch of locations, comparing them with the stored locations
+    /// and kept-aside conflicts of the same users at the same times, and with each other. Only
+    /// those exact times are looked up, in chunks, so a batch spanning years does not load every
+    /// location in between.
+    /// # Returns
+    /// The locations to write, each with whether it replaces a stored location, the conflicting
+    /// locations to keep aside, and the counts of the locations that need no writing. An error
+    /// for the first conflict if the policy is `ConflictPolicy::Error`.
+    async fn location_resolve<C: ConnectionTrait>(
+        conn: &C,
+        locs: Vec<Location>,
+        policy: ConflictPolicy,
+    ) -> Result<(Vec<(Location, bool)>, Vec<Location>, InsertCounts)> {
+        let mut times: HashMap<String, Vec<DateTime<Utc>>> = HashMap::new();
+        for loc in locs.iter() {
+            times
+                .entry(loc.username.clone())
+                .or_default()
+                .push(loc.time_utc);
+        }
+        // the current location for each user, time and device, with its index in `writes` if it
+        // is to be written
+        let mut seen: HashMap<LocationKey, (Location, Option<usize>)> = HashMap::new();
+        let mut kept_aside: HashMap<LocationKey, Vec<Location>> = HashMap::new();
+        for (username, mut times) in times {
+            times.sort();
+            times.dedup();
+            for chunk in times.chunks(INSERT_CHUNK_SIZE) {
+                let stored = location::Entity::find()
+                    .filter(location::Column::Username.eq(username.clone()))
+                    .filter(location::Column::TimeUtc.is_in(chunk.iter().copied()))
+                    .all(conn)
+                    .await
+                    .wrap_err("Failed to query stored locations when checking for duplicates")?;
+                for orig in stored {
+                    seen.insert(LocationKey::of(&orig), (orig, None));
+                }
+                if policy == ConflictPolicy::StoreBoth {
+                    let stored = conflict::Entity::find()
+                        .filter(conflict::Column::Username.eq(username.clone()))
+                        .filter(conflict::Column::TimeUtc.is_in(chunk.iter().copied()))
+                        .all(conn)
+                        .await
+                        .wrap_err("Failed to query stored conflicts")?;
+                    for orig in stored {
+                        let orig = orig.to_location();
+                        kept_aside
+                            .entry(LocationKey::of(&orig))
+                            .or_default()
+                            .push(orig);
+                    }
+                }
+            }
+        }
+        let mut writes: Vec<(Location, bool)> = Vec::new();
+        let mut conflicts = Vec::new();
+        let mut counts = InsertCounts::default();
+        for loc in locs {
+            let key = LocationKey::of(&loc);
+            let Some((current, write)) = seen.get_mut(&key) else {
+                writes.push((loc.clone(), false));
+                seen.insert(key, (loc, Some(writes.len() - 1)));
+                continue;
+            };
+            if *current == loc {
+                debug!("Ignoring duplicate location entry: {:?}", loc);
+                counts.skipped += 1;
+                continue;
+            }
+            if fills_in_details(&loc, current) {
+                debug!("Filling in the details of duplicate location: {:?}", loc);
+                counts.skipped += 1;
+            } else {
+                let replace = match policy {
+                    ConflictPolicy::Error => {
+                        return Err(eyre!("Received user/time info that is duplicated, but other fields differ.\nOriginal: {:?}\nReceived: {:?}", current, loc));
+                    }
+                    ConflictPolicy::KeepExisting => false,
+                    ConflictPolicy::Replace => true,
+                    ConflictPolicy::KeepMoreAccurate => is_more_accurate(&loc, current),
+                    ConflictPolicy::StoreBoth => {
+                        let aside = kept_aside.entry(key).or_default();
+                        if aside.contains(&loc) {
+                            counts.skipped += 1;
+                        } else {
+                            aside.push(loc.clone());
+                            conflicts.push(loc);
+                            counts.stored += 1;
+                        }
+                        continue;
+                    }
+                };
+                if !replace {
+                    debug!("Keeping existing location over conflicting: {:?}", loc);
+                    counts.kept += 1;
+                    continue;
+                }
+                counts.replaced += 1;
+            }
+            match write {
+                Some(i) => writes[*i].0 = loc.clone(),
+                None => {
+                    writes.push((loc.clone(), true));
+                    *write = Some(writes.len() - 1);
+                }
+            }
+            *current = loc;
+        }
+        Ok((writes, conflicts, counts))
+    }
+
+    /// Register the devices of newly recorded locations, skipping those already registered.
+    /// Locations without a device are ignored.
+    async fn device_register<C: ConnectionTrait>(conn: &C, locs: &[Location]) -> Result<()> {
+        let mut devices = locs
+            .iter()
+            .filter(|loc| !loc.device.is_empty())
+            .map(|loc| (loc.username.clone(), loc.device.clone()))
+            .collect::<Vec<_>>();
+        devices.sort();
+        devices.dedup();
+        for chunk in devices.chunks(INSERT_CHUNK_SIZE) {
+            let models = chunk.iter().map(|(username, id)| {
+                Device {
+                    username: username.clone(),
+                    id: id.clone(),
+                    serial: None,
+                    profile: None,
+                }
+                .into_active_model()
+            });
+            device::Entity::insert_many(models)
+                .on_conflict(
+                    OnConflict::columns([device::Column::Username, device::Column::Id])
+                        .do_nothing()
+                        .to_owned(),
+                )
+                .exec_without_returning(conn)
+                .await
+                .wrap_err("Failed to register devices")?;
+        }
+        Ok(())
+    }
+
+    /// List the conflicting locations kept aside for a user, in order of time.
+    pub async fn conflict_vec(&self, username: &str) -> Result<Vec<Conflict>> {
+        conflict::Entity::find()
+            .filter(conflict::Column::Username.eq(username))
+            .order_by_asc(conflict::Column::TimeUtc)
+            .order_by_asc(conflict::Column::Id)
+            .all(&self.conn)
+            .await
+            .wrap_err("Failed to query conflicts")
+    }
+
+    //////////////////////////
+    // Quarantine Functions //
+    //////////////////////////
+
+    /// The limits locations must be within to be recorded rather than put in quarantine.
+    pub fn bounds(&self) -> &Bounds {
+        &self.config.bounds
+    }
+
+    /// Put locations that failed the sanity check in quarantine, skipping any that already are.
+    /// Like `location_write`, quarantined rows at the same times are looked up and new ones
+    /// inserted in chunks, and a single line is logged for the whole batch.
+    /// # Arguments
+    /// * `conn` - The connection, which should be a transaction
+    /// * `rejected` - The locations, each with the rule it failed
+    /// # Returns
+    /// The number of locations quarantined and skipped
+    async fn quarantine_insert<C: ConnectionTrait>(
+        conn: &C,
+        rejected: Vec<(Location, String)>,
+    ) -> Result<InsertCounts> {
+        let mut counts = InsertCounts::default();
+        if rejected.is_empty() {
+            return Ok(counts);
+        }
+        let mut times: HashMap<String, Vec<DateTime<Utc>>> = HashMap::new();
+        for (loc, _) in rejected.iter() {
+            times
+                .entry(loc.username.clone())
+                .or_default()
+                .push(loc.time_utc);
+        }
+        // the quarantined rows for each user and time, stored or about to be
+        let mut rows: HashMap<(String, DateTime<Utc>), Vec<Quarantined>> = HashMap::new();
+        for (username, mut times) in times {
+            times.sort();
+            times.dedup();
+            for chunk in times.chunks(INSERT_CHUNK_SIZE) {
+                let existing = quarantine::Entity::find()
+                    .filter(quarantine::Column::Username.eq(username.clone()))
+                    .filter(quarantine::Column::TimeUtc.is_in(chunk.iter().copied()))
+                    .all(conn)
+                    .await
+                    .wrap_err("Failed to query quarantine")?;
+                for orig in existing {
+                    rows.entry((orig.username.clone(), orig.time_utc))
+                        .or_default()
+                        .push(orig);
+                }
+            }
+        }
+        let mut inserts = Vec::new();
+        for (loc, rule) in rejected {
+            let same_time = rows
+                .entry((loc.username.clone(), loc.time_utc))
+                .or_default();
+            if same_time.iter().any(|orig| orig.is(&loc)) {
+                debug!("Ignoring duplicate quarantined location: {:?}", loc);
+                counts.skipped += 1;
+                continue;
+            }
+            debug!("Quarantining location ({}): {:?}", rule, loc);
+            let row = Quarantined::of(loc, rule);
+            same_time.push(row.clone());
+            inserts.push(row);
+        }
+        counts.quarantined = inserts.len();
+        if let Some(first) = inserts.first() {
+            warn!(
+                "Quarantining {} locations that failed the sanity check, the first at {} for {} ({})",
+                inserts.len(),
+                first.time_utc.to_rfc3339(),
+                first.username,
+                first.rule
+            );
+        }
+        for chunk in inserts.chunks(INSERT_CHUNK_SIZE) {
+            quarantine::Entity::insert_many(chunk.iter().cloned().map(Quarantined::into_insert))
+                .exec_without_returning(conn)
+                .await
+                .wrap_err("Failed to quarantine locations")?;
+        }
+        Ok(counts)
+    }
+
+    /// List quarantined locations, in order of time.
+    /// # Arguments
+    /// * `username` - Only list this user's, if given
+    pub async fn quarantine_vec(&self, username: Option<&str>) -> Result<Vec<Quarantined>> {
+        let mut query = quarantine::Entity::find();
+        if let Some(username) = username {
+            query = query.filter(quarantine::Column::Username.eq(username));
+        }
+        query
+            .order_by_asc(quarantine::Column::TimeUtc)
+            .order_by_asc(quarantine::Column::Id)
+            .all(&self.conn)
+            .await
+            .wrap_err("Failed to query quarantine")
+    }
+
+    /// Look up a quarantined location by its id.
+    pub async fn quarantine_get(&self, id: i64) -> Result<Option<Quarantined>> {
+        quarantine::Entity::find_by_id(id)
+            .one(&self.conn)
+            .await
+            .wrap_err("Failed to query quarantine")
+    }
+
+    /// Move a quarantined location to the locations table, without the limits on altitude and
+    /// accuracy, since it has been reviewed. It must still have finite values, a valid position
+    /// and consistent times.
+    /// # Arguments
+    /// * `id` - The id of the quarantined location
+    /// * `policy` - What to do if it conflicts with a stored location
+    /// # Returns
+    /// The outcome of recording it, or an error if it does not exist or cannot be recorded, in
+    /// which case it stays in quarantine.
+    pub async fn quarantine_promote(
+        &self,
+        id: i64,
+        policy: ConflictPolicy,
+    ) -> Result<InsertCounts> {
+        let txn = self
+            .conn
+            .begin()
+            .await
+            .wrap_err("Failed to begin promotion transaction")?;
+        let quarantined = quarantine::Entity::find_by_id(id)
+            .one(&txn)
+            .await
+            .wrap_err("Failed to query quarantine")?
+            .ok_or_else(|| eyre!("No quarantined location with id {}", id))?;
+        let loc = quarantined
+            .to_location()
+            .ok_or_else(|| eyre!("Quarantined location {} has values that are not finite", id))?;
+        loc.sanity_check_bounds(&Bounds::unbounded())
+            .wrap_err(format!("Quarantined location {} cannot be promoted", id))?;
+        let counts = Self::location_write(&txn, vec![loc], policy).await?;
+        quarantine::Entity::delete_by_id(id)
+            .exec(&txn)
+            .await
+            .wrap_err("Failed to remove promoted location from quarantine")?;
+        txn.commit()
+            .await
+            .wrap_err("Failed to commit promotion transaction")?;
+        Ok(counts)
+    }
+
+    /// Delete a quarantined location.
+    /// # Returns
+    /// Whether it existed
+    pub async fn quarantine_delete(&self, id: i64) -> Result<bool> {
+        let result = quarantine::Entity::delete_by_id(id)
+            .exec(&self.conn)
+            .await
+            .wrap_err("Failed to delete quarantined location")?;
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Delete every quarantined location.
+    /// # Arguments
+    /// * `username` - Only delete this user's, if given
+    /// # Returns
+    /// The number deleted
+    pub async fn quarantine_clear(&self, username: Option<&str>) -> Result<u64> {
+        let mut query = quarantine::Entity::delete_many();
+        if let Some(username) = username {
+            query = query.filter(quarantine::Column::Username.eq(username));
+        }
+        let result = query
+            .exec(&self.conn)
+            .await
+            .wrap_err("Failed to purge quarantine")?;
+        Ok(result.rows_affected)
+    }
+
+    /// Implementation of `location_insert` on any connection, so that it may also run inside a
+    /// transaction.
+    async fn location_insert_with<C: ConnectionTrait>(conn: &C, loc: Location) -> Result<bool> {
+        let active_loc = loc.clone().into_active_model();
+        match active_loc.insert(conn).await {
+            Ok(_) => {
+                Self::device_register(conn, &[loc]).await?;
+                Ok(true)
+            }
+            Err(e) => {
+                if let Some(SqlErr::UniqueConstraintViolation(_)) = e.sql_err() {
+                    let orig = location::Entity::find()
+                        .filter(location::Column::Username.eq(loc.username.clone()))
+                        .filter(location::Column::TimeUtc.eq(loc.time_utc))
+                        .filter(location::Column::Device.eq(loc.device.clone()))
+                        .one(conn)
+                        .await
+                        .wrap_err("Failed to query original location when investigating duplicate")?
+                        .ok_or_else(|| eyre!("Got unique constraint violation but couldn't find the original:\n{:?}", loc))?;
+                    if loc == orig {
+                        debug!("Ignoring duplicate location entry: {:?}", loc);
+                        Ok(false)
+                    } else {
+                        Err(e).wrap_err(format!("Received user/time info that is duplicated, but other fields differ.\nOriginal: {:?}\nReceived: {:?}", orig, loc))
+                    }
+                } else if let Some(SqlErr::ForeignKeyConstraintViolation(_)) = e.sql_err() {
+                    Err(e).wrap_err(format!(
+                        "User `{}` does not exist in the database. Cannot insert location.",
+                        loc.username
+                    ))
+                } else {
+                    Err(e).wrap_err(format!(
+                        "Failed to insert location into database for unknown reason: {:?}",
+                        loc
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Find out what `location_insert` would do with a location, without writing anything.
+    /// # Arguments
+    /// * `loc` - The location to check
+    /// # Returns
+    /// Whether the location is new, a perfect duplicate, or conflicts with a stored location
+    pub async fn location_check(&self, loc: &Location) -> Result<LocationStatus> {
+        let orig = location::Entity::find()
+            .filter(location::Column::Username.eq(loc.username.clone()))
+            .filter(location::Column::TimeUtc.eq(loc.time_utc))
+            .filter(location::Column::Device.eq(loc.device.clone()))
+            .one(&self.conn)
+            .await
+            .wrap_err("Failed to query location")?;
+        Ok(match orig {
+            None => LocationStatus::New,
+            Some(orig) if orig == *loc => LocationStatus::Duplicate,
+            Some(orig) => LocationStatus::Conflict(orig),
+        })
+    }
+
+    /// Generator function that returns all locations in the database that fall between the
+    /// specified time bounds. Avoids loading all locations into memory at once. Lifetime is tied
+    /// to the database connection.
+    /// # Arguments
+    /// * `username` - The user whose locations to return.
+    /// * `device` - Only return locations recorded by this device, if given.
+    /// * `start` - The start time of the range, inclusive.
+    /// * `stop` - The stop time of the range, exclusive.
+    /// # Returns
+    /// Locations that fall within the specified time range, in ascending order of time.
+    pub async fn location_stream(
+        &self,
+        username: &str,
+        device: Option<&str>,
+        start: DateTime<Utc>,
+        stop: DateTime<Utc>,
+    ) -> Result<impl Stream<Item = Result<Location, DbErr>> + use<'_>, DbErr> {
+        let mut query = location::Entity::find()
+            .filter(location::Column::Username.eq(username))
+            .filter(location::Column::TimeUtc.between(start, stop));
+        if let Some(device) = device {
+            query = query.filter(location::Column::Device.eq(device));
+        }
+        let stream = query
+            .order_by_asc(location::Column::TimeUtc)
+            .order_by_asc(location::Column::Device)
+            .stream(&self.conn)
+            .await?;
+        Ok(stream)
+    }
+
+    /// Get a user location closest to, but not after, the specified time.
+    /// # Arguments
+    /// * `username` - The username to get the location for
+    /// * `time` - The time to get the location for. Time of returned location will be less than or
+    ///   equal to this time.
+    /// # Returns
+    /// The location closest to, but not after, the specified time, if it exists.
+    pub async fn location_at(
+        &self,
+        username: &str,
+        time: &DateTime<Utc>,
+    ) -> Result<Option<Location>> {
+        let loc = location::Entity::find()
+            .filter(location::Column::Username.eq(username))
+            .filter(location::Column::TimeUtc.lte(*time))
+            .order_by_desc(location::Column::TimeUtc)
+            .one(&self.conn)
+            .await
+            .wrap_err("Failed to query location from database")?;
+        Ok(loc)
+    }
+
+    #[cfg(test)]
+    pub(crate) async fn location_vec(
+        &self,
+        username: &str,
+        start: DateTime<Utc>,
+        stop: DateTime<Utc>,
+    ) -> Result<Vec<Location>> {
+        use futures::StreamExt;
+        let mut stream = self.location_stream(username, None, start, stop).await?;
+        let mut vec = Vec::new();
+        while let Some(loc) = stream.next().await {
+            vec.push(loc?);
+        }
+        Ok(vec)
+    }
+
+    /// Count the number of locations in the database. If username is provided, count only the
+    /// locations for that user.
+    /// # Arguments
+    /// * `username` - The username to count locations for. If None, count all locations.
+    /// * `device` - Only count locations recorded by this device, if given.
+    /// # Returns
+    /// The number of locations in the database if the query was successful, an error otherwise.
+    pub async fn location_count(
+        &self,
+        username: Option<&str>,
+        device: Option<&str>,
+    ) -> Result<u64> {
+        let mut query = location::Entity::find();
+        if let Some(device) = device {
+            query = query.filter(location::Column::Device.eq(device));
+        }
+        match username {
+            Some(username) => {
+                // ensure user exists
+                if user::Entity::find()
+                    .filter(user::Column::Username.eq(username))
+                    .one(&self.conn)
+                    .await
+                    .wrap_err(format!("Failed to query user {} from database", username))?
+                    .is_none()
+                {
+                    return Err(eyre!("User {} does not exist in the database", username));
+                }
+                Ok(query
+                    .filter(location::Column::Username.eq(username))
+                    .count(&self.conn)
+                    .await
+                    .wrap_err(format!("Failed to count locations for user {}", username))?)
+            }
+            None => Ok(query
+                .count(&self.conn)
+                .await
+                .wrap_err("Failed to count locations for all users")?),
+        }
+    }
+
+    ///////////////////////////////
+    // Segment-Related Functions //
+    ///////////////////////////////
+
+    /// Record a new visit or activity segment. Perfect duplicates are ignored, while a segment
+    /// with the same user and time span but different contents returns an error.
+    /// # Arguments
+    /// * `seg` - The segment to record
+    /// # Returns
+    /// `Ok(true)` if the segment was recorded, `Ok(false)` if it already exists. An error
+    /// otherwise.
+    pub async fn segment_insert(&self, seg: Segment) -> Result<bool> {
+        seg.sanity_check()?;
+        let active_seg = seg.clone().into_active_model();
+        match active_seg.insert(&self.conn).await {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                if let Some(SqlErr::UniqueConstraintViolation(_)) = e.sql_err() {
+                    let orig = segment::Entity::find()
+                        .filter(segment::Column::Username.eq(seg.username.clone()))
+                        .filter(segment::Column::StartUtc.eq(seg.start_utc))
+                        .filter(segment::Column::EndUtc.eq(seg.end_utc))
+                        .one(&self.conn)
+                        .await
+                        .wrap_err("Failed to query original segment when investigating duplicate")?
+                        .ok_or_else(|| eyre!("Got unique constraint violation but couldn't find the original:\n{:?}", seg))?;
+                    if seg == orig {
+                        debug!("Ignoring duplicate segment entry: {:?}", seg);
+                        Ok(false)
+                    } else {
+                        Err(e).wrap_err(format!("Received user/time span that is duplicated, but other fields differ.\nOriginal: {:?}\nReceived: {:?}", orig, seg))
+                    }
+                } else {
+                    Err(e).wrap_err(format!("Failed to insert segment into database: {:?}", seg))
+                }
+            }
+        }
+    }
+
+    /// Get all segments for a user that start within the specified time bounds.
+    /// # Arguments
+    /// * `username` - The username to get segments for
+    /// * `start` - The start time of the range, inclusive.
+    /// * `stop` - The stop time of the range, inclusive.
+    /// # Returns
+    /// Segments in ascending order of start time.
+    pub async fn segment_vec(
+        &self,
+        username: &str,
+        start: DateTime<Utc>,
+        stop: DateTime<Utc>,
+    ) -> Result<Vec<Segment>> {
+        segment::Entity::find()
+            .filter(segment::Column::Username.eq(username))
+            .filter(segment::Column::StartUtc.between(start, stop))
+            .order_by_asc(segment::Column::StartUtc)
+            .all(&self.conn)
+            .await
+            .wrap_err("Failed to query segments from database")
+    }
+
+    //////////////////////////
+    // High Level Functions //
+    //////////////////////////
+
+    /// Get information about all users in the database.
+    /// # Arguments
+    /// * `username` - The username to get information for. If None, get information for all users.
+    /// * `device` - Only count locations recorded by this device, if given.
+    /// # Returns
+    /// A vector of user information structs.
+    pub async fn info(
+        &self,
+        username: Option<&str>,
+        device: Option<&str>,
+    ) -> Result<Vec<UserInfo>> {
+        let users: Vec<String> = match username {
+            Some(username) => vec![username.to_string()],
+            None => self
+                .user_vec()
+                .await
+                .wrap_err("Failed to query user list from database")?,
+        };
+        let mut user_infos = Vec::new();
+        for username in users {
+            let count = self.location_count(Some(&username), device).await?;
+            let last_seen = self.last_seen(&username, device).await?;
+            let mut devices = Vec::new();
+            for dev in self.device_vec(&username).await? {
+                if device.is_some_and(|device| device != dev.id) {
+                    continue;
+                }
+                devices.push(DeviceInfo {
+                    location_count: self.location_count(Some(&username), Some(&dev.id)).await?,
+                    last_seen: self.last_seen(&username, Some(&dev.id)).await?,
+                    device: dev,
+                });
+            }
+            user_infos.push(UserInfo {
+                username,
+                location_count: count,
+                last_seen,
+                devices,
+            });
+        }
+        Ok(user_infos)
+    }
+
+    /// Time of the most recent location of a user, optionally recorded by one device.
+    async fn last_seen(
+        &self,
+        username: &str,
+        device: Option<&str>,
+    ) -> Result<Option<DateTime<Utc>>> {
+        let mut query = location::Entity::find().filter(location::Column::Username.eq(username));
+        if let Some(device) = device {
+            query = query.filter(location::Column::Device.eq(device));
+        }
+        Ok(query
+            .order_by_desc(location::Column::TimeUtc)
+            .one(&self.conn)
+            .await
+            .wrap_err("Failed to query last seen location from database")?
+            .map(|loc| loc.time_utc))
+    }
+}
+
+/// Hash a password on a blocking thread, since hashing is slow by design.
+async fn hash_password(password: String) -> Result<String> {
+    tokio::task::spawn_blocking(move || auth::hash_password(&password))
+        .await
+        .wrap_err("Password hashing panicked")?
+}
+
+////////////////
+// Unit Tests //
+////////////////
 
 #[cfg(test)]
 mod tests {
@@ -429,64 +1556,692 @@ mod tests {
     use pretty_assertions::assert_eq;
     use tempfile::NamedTempFile;
 
-    /// Creates an ephemeral database for testing and tries to add identical entries to the
-    /// database. The second entry should fail due to the unique constraint on the primary key.
+    /// Creates an ephemeral database for testing and tries to add identical entries to the
+    /// database. The second entry should fail due to the unique constraint on the primary key.
+    #[tokio::test]
+    async fn test_unique_constraint() {
+        let db_file = NamedTempFile::new().unwrap();
+        // having a file that exists ensures that the schema existence is checked when determining
+        // whether to create the tables
+        let db = Db::new(&Config {
+            path: db_file.path().to_path_buf(),
+            backups: 1,
+            bounds: Default::default(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(db.location_count(None, None).await.unwrap(), 0);
+        // add user to the database
+        db.user_insert("test".to_string(), "pass".to_string())
+            .await
+            .unwrap();
+        let username = "test".to_string();
+        let time_utc = DateTime::parse_from_rfc3339("2025-01-16T03:54:51.000Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let time_local = time_utc.with_timezone(&chrono::FixedOffset::west_opt(3600).unwrap());
+        let loc = Location {
+            username: username.clone(),
+            time_utc,
+            time_local,
+            latitude: 0.0,
+            longitude: 0.0,
+            altitude: 0.0,
+            accuracy: Some(0.0),
+            source: location::Source::GpsLogger,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        };
+        db.location_insert(loc.clone()).await.unwrap();
+        assert_eq!(db.location_count(None, None).await.unwrap(), 1); // successfully added the first entry
+        db.location_insert(loc.clone()).await.unwrap(); // adding again does nothing
+        assert_eq!(db.location_count(None, None).await.unwrap(), 1);
+        let mut loc2 = loc.clone();
+        loc2.time_utc += chrono::Duration::seconds(1); // modify the time to make it unique
+        assert!(db.location_insert(loc2.clone()).await.is_err()); // but the 2 times don't match
+        loc2.time_local += chrono::Duration::seconds(1); // now the times are unique and match
+        db.location_insert(loc2.clone()).await.unwrap();
+        assert_eq!(db.location_count(None, None).await.unwrap(), 2); // successfully added the second entry
+        let loc3 = Location {
+            username,
+            time_utc,
+            time_local,
+            latitude: 1.0,
+            longitude: 1.0,
+            altitude: 1.0,
+            accuracy: Some(1.0),
+            source: location::Source::GpsLogger,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        };
+        let err = db.location_insert(loc3).await.unwrap_err(); // same user/time with different location
+        assert!(err
+            .to_string()
+            .contains("Received user/time info that is duplicated, but other fields differ."));
+        assert_eq!(db.location_count(None, None).await.unwrap(), 2); // failed to add the third entry
+    }
+
+    /// A batch containing a conflicting location must not write any of its locations.
+    #[tokio::test]
+    async fn test_location_insert_all() {
+        let db_file = NamedTempFile::new().unwrap();
+        let db = Db::new(&Config {
+            path: db_file.path().to_path_buf(),
+            backups: 1,
+            bounds: Default::default(),
+        })
+        .await
+        .unwrap();
+        db.user_insert("test".to_string(), "pass".to_string())
+            .await
+            .unwrap();
+        let time_utc = DateTime::parse_from_rfc3339("2025-01-16T03:54:51.000Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let loc = Location {
+            username: "test".to_string(),
+            time_utc,
+            time_local: time_utc.with_timezone(&chrono::FixedOffset::west_opt(3600).unwrap()),
+            latitude: 0.0,
+            longitude: 0.0,
+            altitude: 0.0,
+            accuracy: Some(0.0),
+            source: location::Source::Overland,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        };
+        let mut loc2 = loc.clone();
+        loc2.time_utc += chrono::Duration::seconds(1);
+        loc2.time_local += chrono::Duration::seconds(1);
+        let mut loc3 = loc2.clone();
+        loc3.time_utc += chrono::Duration::seconds(1);
+        loc3.time_local += chrono::Duration::seconds(1);
+        db.location_insert(loc.clone()).await.unwrap();
+        // the first location is a perfect duplicate and is skipped
+        assert_eq!(
+            db.location_insert_all(vec![loc.clone(), loc2.clone()], ConflictPolicy::Error)
+                .await
+                .unwrap(),
+            InsertCounts {
+                added: 1,
+                skipped: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(db.location_count(None, None).await.unwrap(), 2);
+        // loc3 is new, but the conflicting location rolls back the whole batch
+        let mut conflict = loc.clone();
+        conflict.latitude = 1.0;
+        assert!(db
+            .location_insert_all(vec![loc3.clone(), conflict], ConflictPolicy::Error)
+            .await
+            .is_err());
+        assert_eq!(db.location_count(None, None).await.unwrap(), 2);
+        assert_eq!(
+            db.location_insert_all(vec![loc3], ConflictPolicy::Error)
+                .await
+                .unwrap()
+                .added,
+            1
+        );
+        assert_eq!(db.location_count(None, None).await.unwrap(), 3);
+    }
+
+    /// Two devices may record the same second, devices are registered as their locations arrive,
+    /// and queries can be narrowed to one device.
+    #[tokio::test]
+    async fn test_devices() {
+        use futures::StreamExt;
+        let db_file = NamedTempFile::new().unwrap();
+        let db = Db::new(&Config {
+            path: db_file.path().to_path_buf(),
+            backups: 1,
+            bounds: Default::default(),
+        })
+        .await
+        .unwrap();
+        db.user_insert("test".to_string(), "pass".to_string())
+            .await
+            .unwrap();
+        let time_utc = DateTime::parse_from_rfc3339("2025-01-16T03:54:51.000Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let loc = Location {
+            username: "test".to_string(),
+            time_utc,
+            time_local: time_utc.with_timezone(&chrono::FixedOffset::west_opt(3600).unwrap()),
+            latitude: 0.0,
+            longitude: 0.0,
+            altitude: 0.0,
+            accuracy: Some(0.0),
+            source: location::Source::GpsLogger,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: "phone".to_string(),
+        };
+        let mut tablet = loc.clone();
+        tablet.device = "tablet".to_string();
+        tablet.latitude = 1.0;
+        let mut unknown = loc.clone();
+        unknown.device = String::new();
+        unknown.latitude = 2.0;
+        let mut later = loc.clone();
+        later.time_utc += chrono::Duration::seconds(1);
+        later.time_local += chrono::Duration::seconds(1);
+        assert_eq!(
+            db.location_insert_all(
+                vec![loc.clone(), tablet.clone(), unknown.clone()],
+                ConflictPolicy::Error
+            )
+            .await
+            .unwrap()
+            .added,
+            3
+        );
+        assert!(db.location_insert(later.clone()).await.unwrap());
+        // a different location from the same device still conflicts
+        let mut conflict = loc.clone();
+        conflict.latitude = 3.0;
+        assert!(db.location_insert(conflict.clone()).await.is_err());
+        assert_eq!(
+            db.location_check(&conflict).await.unwrap(),
+            LocationStatus::Conflict(loc.clone())
+        );
+
+        let devices = db.device_vec("test").await.unwrap();
+        assert_eq!(
+            devices.iter().map(|d| d.id.as_str()).collect::<Vec<_>>(),
+            vec!["phone", "tablet"]
+        );
+        assert_eq!(devices[0].profile, None);
+        db.device_insert(Device {
+            username: "test".to_string(),
+            id: "phone".to_string(),
+            serial: Some("1234".to_string()),
+            profile: Some("Default Profile".to_string()),
+        })
+        .await
+        .unwrap();
+        let devices = db.device_vec("test").await.unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].serial.as_deref(), Some("1234"));
+        assert_eq!(devices[0].profile.as_deref(), Some("Default Profile"));
+        assert!(db
+            .device_insert(Device {
+                username: "nobody".to_string(),
+                id: "phone".to_string(),
+                serial: None,
+                profile: None,
+            })
+            .await
+            .is_err());
+
+        assert_eq!(db.location_count(Some("test"), None).await.unwrap(), 4);
+        assert_eq!(
+            db.location_count(Some("test"), Some("phone"))
+                .await
+                .unwrap(),
+            2
+        );
+        let mut stream = db
+            .location_stream("test", Some("tablet"), time_utc, later.time_utc)
+            .await
+            .unwrap();
+        let mut streamed = Vec::new();
+        while let Some(loc) = stream.next().await {
+            streamed.push(loc.unwrap());
+        }
+        assert_eq!(streamed, vec![tablet]);
+        // the stream holds the in-memory database's only connection
+        drop(stream);
+
+        let info = db.info(Some("test"), None).await.unwrap();
+        assert_eq!(info[0].location_count, 4);
+        assert_eq!(info[0].devices.len(), 2);
+        assert_eq!(info[0].devices[0].location_count, 2);
+        assert_eq!(info[0].devices[0].last_seen, Some(later.time_utc));
+        assert_eq!(info[0].devices[1].location_count, 1);
+        let info = db.info(Some("test"), Some("tablet")).await.unwrap();
+        assert_eq!(info[0].location_count, 1);
+        assert_eq!(info[0].last_seen, Some(time_utc));
+        assert_eq!(info[0].devices.len(), 1);
+        assert_eq!(info[0].devices[0].device.id, "tablet");
+    }
+
+    /// Locations spanning several batches are all recorded, with duplicates within and across
+    /// batches skipped, and an error leaves the batches before it recorded.
     #[tokio::test]
-    async fn test_unique_constraint() {
+    async fn test_location_insert_many() {
         let db_file = NamedTempFile::new().unwrap();
-        // having a file that exists ensures that the schema existence is checked when determining
-        // whether to create the tables
         let db = Db::new(&Config {
             path: db_file.path().to_path_buf(),
             backups: 1,
+            bounds: Default::default(),
+        })
+        .await
+        .unwrap();
+        db.user_insert("test".to_string(), "pass".to_string())
+            .await
+            .unwrap();
+        let start = DateTime::parse_from_rfc3339("2025-01-16T03:54:51.000Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let make = |i: usize| Location {
+            username: "test".to_string(),
+            time_utc: start + chrono::Duration::seconds(i as i64),
+            time_local: (start + chrono::Duration::seconds(i as i64))
+                .with_timezone(&chrono::FixedOffset::west_opt(3600).unwrap()),
+            latitude: 0.0,
+            longitude: 0.0,
+            altitude: (i % 1000) as f64,
+            accuracy: Some(0.0),
+            source: location::Source::GpsLogger,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        };
+        let count = LOCATION_BATCH_SIZE + 10;
+        db.location_insert(make(5)).await.unwrap();
+        // the stored 5th, and a repeat of the 1st in the next batch, are skipped
+        let locs = (0..count).chain([0]).map(make).map(Ok);
+        let counts = db
+            .location_insert_many(locs, ConflictPolicy::Error)
+            .await
+            .unwrap();
+        assert_eq!((counts.added, counts.skipped), (count - 1, 2));
+        assert_eq!(db.location_count(None, None).await.unwrap(), count as u64);
+        // the first batch is recorded before the conflict in the second is found
+        let mut conflict = make(0);
+        conflict.latitude = 1.0;
+        let locs = (count..count + LOCATION_BATCH_SIZE + 1)
+            .map(make)
+            .chain([conflict])
+            .map(Ok);
+        assert!(db
+            .location_insert_many(locs, ConflictPolicy::Error)
+            .await
+            .is_err());
+        assert_eq!(
+            db.location_count(None, None).await.unwrap(),
+            (count + LOCATION_BATCH_SIZE) as u64
+        );
+        // a reader error stops the insert too
+        let locs = [Ok(make(0)), Err(eyre!("bad record"))].into_iter();
+        assert!(db
+            .location_insert_many(locs, ConflictPolicy::Error)
+            .await
+            .is_err());
+        // unknown users are reported
+        let mut stranger = make(0);
+        stranger.username = "stranger".to_string();
+        let err = db
+            .location_insert_all(vec![stranger], ConflictPolicy::Error)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+    }
+
+    /// Each policy resolves conflicts with stored locations and within a batch, and counts the
+    /// outcomes.
+    #[tokio::test]
+    async fn test_conflict_policy() {
+        let db_file = NamedTempFile::new().unwrap();
+        let db = Db::new(&Config {
+            path: db_file.path().to_path_buf(),
+            backups: 1,
+            bounds: Default::default(),
+        })
+        .await
+        .unwrap();
+        db.user_insert("test".to_string(), "pass".to_string())
+            .await
+            .unwrap();
+        let start = DateTime::parse_from_rfc3339("2025-01-16T03:54:51.000Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let make = |i: i64, accuracy: Option<f32>| Location {
+            username: "test".to_string(),
+            time_utc: start + chrono::Duration::seconds(i),
+            time_local: (start + chrono::Duration::seconds(i))
+                .with_timezone(&chrono::FixedOffset::west_opt(3600).unwrap()),
+            latitude: 0.0,
+            longitude: 0.0,
+            altitude: 0.0,
+            accuracy,
+            source: location::Source::GpsLogger,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        };
+        let stored = (0..4).map(|i| make(i, Some(10.0))).collect::<Vec<_>>();
+        db.location_insert_all(stored.clone(), ConflictPolicy::Error)
+            .await
+            .unwrap();
+        let stored_at = |i: usize| stored[i].time_utc;
+        let get = |i: usize| {
+            let db = &db;
+            async move {
+                db.location_at("test", &stored_at(i))
+                    .await
+                    .unwrap()
+                    .unwrap()
+            }
+        };
+
+        // keep existing
+        let counts = db
+            .location_insert_with_policy(make(0, Some(5.0)), ConflictPolicy::KeepExisting)
+            .await
+            .unwrap();
+        assert_eq!(counts.kept, 1);
+        assert_eq!(get(0).await, stored[0]);
+
+        // replace, including a later conflict in the same batch winning over an earlier one
+        let counts = db
+            .location_insert_all(
+                vec![make(1, Some(20.0)), make(1, Some(30.0)), make(4, None)],
+                ConflictPolicy::Replace,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            counts,
+            InsertCounts {
+                added: 1,
+                replaced: 2,
+                ..Default::default()
+            }
+        );
+        assert_eq!(get(1).await, make(1, Some(30.0)));
+
+        // keep more accurate
+        let counts = db
+            .location_insert_all(
+                vec![make(2, Some(20.0)), make(2, None), make(3, Some(1.0))],
+                ConflictPolicy::KeepMoreAccurate,
+            )
+            .await
+            .unwrap();
+        assert_eq!((counts.kept, counts.replaced), (2, 1));
+        assert_eq!(get(2).await, stored[2]);
+        assert_eq!(get(3).await, make(3, Some(1.0)));
+
+        // store both, once
+        for _ in 0..2 {
+            db.location_insert_with_policy(make(0, Some(7.0)), ConflictPolicy::StoreBoth)
+                .await
+                .unwrap();
+        }
+        let conflicts = db.conflict_vec("test").await.unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert!(conflicts[0].is(&make(0, Some(7.0))));
+        assert_eq!(get(0).await, stored[0]);
+
+        // error
+        assert!(db
+            .location_insert_with_policy(make(0, Some(7.0)), ConflictPolicy::Error)
+            .await
+            .is_err());
+        assert_eq!(db.location_count(None, None).await.unwrap(), 5);
+    }
+
+    /// Locations that fail the sanity check are quarantined with the rule they broke, once, and
+    /// can be promoted or purged.
+    #[tokio::test]
+    async fn test_quarantine() {
+        let db_file = NamedTempFile::new().unwrap();
+        let db = Db::new(&Config {
+            path: db_file.path().to_path_buf(),
+            backups: 1,
+            bounds: Bounds {
+                max_altitude: 12000.0,
+                ..Default::default()
+            },
+        })
+        .await
+        .unwrap();
+        db.user_insert("test".to_string(), "pass".to_string())
+            .await
+            .unwrap();
+        let start = DateTime::parse_from_rfc3339("2025-01-16T03:54:51.000Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let make = |i: i64, altitude: f64| Location {
+            username: "test".to_string(),
+            time_utc: start + chrono::Duration::seconds(i),
+            time_local: (start + chrono::Duration::seconds(i))
+                .with_timezone(&chrono::FixedOffset::west_opt(3600).unwrap()),
+            latitude: 0.0,
+            longitude: 0.0,
+            altitude,
+            accuracy: Some(5.0),
+            source: location::Source::GpsLogger,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        };
+        let mut not_finite = make(3, 0.0);
+        not_finite.latitude = f64::NAN;
+        let locs = vec![
+            make(0, 11000.0), // within the configured bounds
+            make(1, 13000.0),
+            make(2, 14000.0),
+            not_finite.clone(),
+            // repeated within the batch
+            make(2, 14000.0),
+            not_finite.clone(),
+        ];
+        let counts = db
+            .location_insert_all(locs.clone(), ConflictPolicy::Error)
+            .await
+            .unwrap();
+        assert_eq!(
+            (counts.added, counts.quarantined, counts.skipped),
+            (1, 3, 2)
+        );
+        // quarantining again skips them
+        let counts = db
+            .location_insert_all(locs, ConflictPolicy::Error)
+            .await
+            .unwrap();
+        assert_eq!((counts.skipped, counts.quarantined), (6, 0));
+
+        let quarantined = db.quarantine_vec(Some("test")).await.unwrap();
+        assert_eq!(quarantined.len(), 3);
+        assert_eq!(quarantined[0].rule, "Altitude out of bounds: 13000");
+        assert_eq!(quarantined[2].latitude, None);
+        assert!(db.quarantine_vec(Some("other")).await.unwrap().is_empty());
+        assert_eq!(
+            db.quarantine_get(quarantined[1].id).await.unwrap(),
+            Some(quarantined[1].clone())
+        );
+
+        // promoting records it, and takes it out of quarantine
+        let counts = db
+            .quarantine_promote(quarantined[0].id, ConflictPolicy::Error)
+            .await
+            .unwrap();
+        assert_eq!(counts.added, 1);
+        assert_eq!(
+            db.location_at("test", &make(1, 0.0).time_utc)
+                .await
+                .unwrap(),
+            Some(make(1, 13000.0))
+        );
+        assert!(db
+            .quarantine_get(quarantined[0].id)
+            .await
+            .unwrap()
+            .is_none());
+        // unless it can't be recorded
+        assert!(db
+            .quarantine_promote(quarantined[2].id, ConflictPolicy::Error)
+            .await
+            .is_err());
+        assert!(db
+            .quarantine_promote(quarantined[0].id, ConflictPolicy::Error)
+            .await
+            .is_err());
+        assert_eq!(db.quarantine_vec(None).await.unwrap().len(), 2);
+
+        // purging
+        assert!(db.quarantine_delete(quarantined[1].id).await.unwrap());
+        assert!(!db.quarantine_delete(quarantined[1].id).await.unwrap());
+        assert_eq!(db.quarantine_clear(Some("test")).await.unwrap(), 1);
+        assert!(db.quarantine_vec(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_location_check() {
+        let db_file = NamedTempFile::new().unwrap();
+        let db = Db::new(&Config {
+            path: db_file.path().to_path_buf(),
+            backups: 1,
+            bounds: Default::default(),
         })
         .await
         .unwrap();
-        assert_eq!(db.location_count(None).await.unwrap(), 0);
-        // add user to the database
         db.user_insert("test".to_string(), "pass".to_string())
             .await
             .unwrap();
-        let username = "test".to_string();
         let time_utc = DateTime::parse_from_rfc3339("2025-01-16T03:54:51.000Z")
             .unwrap()
             .with_timezone(&Utc);
-        let time_local = time_utc.with_timezone(&chrono::FixedOffset::west_opt(3600).unwrap());
         let loc = Location {
-            username: username.clone(),
+            username: "test".to_string(),
             time_utc,
-            time_local,
+            time_local: time_utc.fixed_offset(),
             latitude: 0.0,
             longitude: 0.0,
             altitude: 0.0,
-            accuracy: Some(0.0),
-            source: location::Source::GpsLogger,
+            accuracy: None,
+            source: location::Source::Gpx,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
         };
+        assert_eq!(db.location_check(&loc).await.unwrap(), LocationStatus::New);
         db.location_insert(loc.clone()).await.unwrap();
-        assert_eq!(db.location_count(None).await.unwrap(), 1); // successfully added the first entry
-        db.location_insert(loc.clone()).await.unwrap(); // adding again does nothing
-        assert_eq!(db.location_count(None).await.unwrap(), 1);
-        let mut loc2 = loc.clone();
-        loc2.time_utc += chrono::Duration::seconds(1); // modify the time to make it unique
-        assert!(db.location_insert(loc2.clone()).await.is_err()); // but the 2 times don't match
-        loc2.time_local += chrono::Duration::seconds(1); // now the times are unique and match
-        db.location_insert(loc2.clone()).await.unwrap();
-        assert_eq!(db.location_count(None).await.unwrap(), 2); // successfully added the second entry
-        let loc3 = Location {
-            username,
-            time_utc,
-            time_local,
-            latitude: 1.0,
-            longitude: 1.0,
-            altitude: 1.0,
-            accuracy: Some(1.0),
-            source: location::Source::GpsLogger,
+        assert_eq!(
+            db.location_check(&loc).await.unwrap(),
+            LocationStatus::Duplicate
+        );
+        let mut conflict = loc.clone();
+        conflict.altitude = 1.0;
+        assert_eq!(
+            db.location_check(&conflict).await.unwrap(),
+            LocationStatus::Conflict(loc)
+        );
+        // nothing was written by the checks
+        assert_eq!(db.location_count(None, None).await.unwrap(), 1);
+    }
+
+    /// Segments behave like locations with respect to duplicates.
+    #[tokio::test]
+    async fn test_segment_insert() {
+        let db_file = NamedTempFile::new().unwrap();
+        let db = Db::new(&Config {
+            path: db_file.path().to_path_buf(),
+            backups: 1,
+            bounds: Default::default(),
+        })
+        .await
+        .unwrap();
+        db.user_insert("test".to_string(), "pass".to_string())
+            .await
+            .unwrap();
+        let start_local = DateTime::parse_from_rfc3339("2025-01-16T03:54:51.000+01:00").unwrap();
+        let end_local = DateTime::parse_from_rfc3339("2025-01-16T05:54:51.000+01:00").unwrap();
+        let seg = Segment {
+            username: "test".to_string(),
+            start_utc: start_local.to_utc(),
+            end_utc: end_local.to_utc(),
+            start_local,
+            end_local,
+            kind: segment::Kind::Visit,
+            latitude: 48.1,
+            longitude: 11.5,
+            end_latitude: None,
+            end_longitude: None,
+            place_id: Some("ChIJ".to_string()),
+            semantic_type: Some("HOME".to_string()),
+            probability: Some(0.5),
+            distance: None,
         };
-        let err = db.location_insert(loc3).await.unwrap_err(); // same user/time with different location
-        assert!(err
-            .to_string()
-            .contains("Received user/time info that is duplicated, but other fields differ."));
-        assert_eq!(db.location_count(None).await.unwrap(), 2); // failed to add the third entry
+        assert!(db.segment_insert(seg.clone()).await.unwrap());
+        assert!(!db.segment_insert(seg.clone()).await.unwrap());
+        let mut conflict = seg.clone();
+        conflict.semantic_type = Some("WORK".to_string());
+        assert!(db.segment_insert(conflict).await.is_err());
+        let mut backwards = seg.clone();
+        backwards.start_utc = seg.end_utc;
+        backwards.end_utc = seg.start_utc;
+        assert!(db.segment_insert(backwards).await.is_err());
+        assert_eq!(
+            db.segment_vec("test", seg.start_utc, seg.end_utc)
+                .await
+                .unwrap(),
+            vec![seg]
+        );
     }
 
     /// Creates an ephemeral database and checks user table operations.
@@ -496,6 +2251,7 @@ mod tests {
         let db = Db::new(&Config {
             path: db_file.path().to_path_buf(),
             backups: 1,
+            bounds: Default::default(),
         })
         .await
         .unwrap();
@@ -511,6 +2267,193 @@ mod tests {
             .await
             .unwrap();
         assert_eq!(db.user_vec().await.unwrap(), vec!["another_user", "user"]);
+        // stored hashed, with no limit on the password length
+        let stored = user::Entity::find_by_id("user")
+            .one(&db.conn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(auth::is_hash(&stored.password));
+        let long = "p".repeat(200);
+        db.user_set_password("user", &long).await.unwrap();
+        assert_eq!(db.user_check("user", "pass").await.unwrap(), false);
+        assert_eq!(db.user_check("user", &long).await.unwrap(), true);
+        assert!(db.user_set_password("nonexistent", "pass").await.is_err());
+    }
+
+    /// Checks that a plaintext password from an older version is hashed on the first good login.
+    #[tokio::test]
+    async fn test_plaintext_password_upgrade() {
+        let db_file = NamedTempFile::new().unwrap();
+        let db = Db::new(&Config {
+            path: db_file.path().to_path_buf(),
+            backups: 1,
+            bounds: Default::default(),
+        })
+        .await
+        .unwrap();
+        user::Model {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        }
+        .into_active_model()
+        .insert(&db.conn)
+        .await
+        .unwrap();
+        let stored = || async {
+            user::Entity::find_by_id("user")
+                .one(&db.conn)
+                .await
+                .unwrap()
+                .unwrap()
+                .password
+        };
+        // a failed login leaves it alone
+        assert_eq!(db.user_check("user", "wrong").await.unwrap(), false);
+        assert_eq!(stored().await, "pass");
+        assert_eq!(db.user_check("user", "pass").await.unwrap(), true);
+        assert!(auth::is_hash(&stored().await));
+        assert_eq!(db.user_check("user", "pass").await.unwrap(), true);
+        assert_eq!(db.user_check("user", "wrong").await.unwrap(), false);
+    }
+
+    /// Checks that renaming and deleting a user carries their locations and devices along.
+    #[tokio::test]
+    async fn test_user_rename_delete() {
+        let db_file = NamedTempFile::new().unwrap();
+        let db = Db::new(&Config {
+            path: db_file.path().to_path_buf(),
+            backups: 1,
+            bounds: Default::default(),
+        })
+        .await
+        .unwrap();
+        for username in ["alice", "bob"] {
+            db.user_insert(username.to_string(), "pass".to_string())
+                .await
+                .unwrap();
+        }
+        let time_utc = DateTime::parse_from_rfc3339("2025-01-16T03:54:51.000Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let loc = Location {
+            username: "alice".to_string(),
+            time_utc,
+            time_local: time_utc.fixed_offset(),
+            latitude: 0.0,
+            longitude: 0.0,
+            altitude: 0.0,
+            accuracy: Some(0.0),
+            source: location::Source::GpsLogger,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: "phone".to_string(),
+        };
+        let mut bobs = loc.clone();
+        bobs.username = "bob".to_string();
+        db.location_insert(loc).await.unwrap();
+        db.location_insert(bobs).await.unwrap();
+
+        // taken, too long, empty, with a colon, or missing
+        assert!(db.user_rename("alice", "bob").await.is_err());
+        assert!(db.user_rename("alice", &"a".repeat(33)).await.is_err());
+        assert!(db.user_rename("alice", "").await.is_err());
+        assert!(db.user_rename("alice", "al:ice").await.is_err());
+        assert!(db.user_rename("carol", "dave").await.is_err());
+        assert!(db
+            .user_insert("a".repeat(33), "pass".to_string())
+            .await
+            .is_err());
+        assert!(db
+            .user_insert(String::new(), "pass".to_string())
+            .await
+            .is_err());
+        assert!(db
+            .user_insert("dave:x".to_string(), "pass".to_string())
+            .await
+            .is_err());
+
+        db.user_rename("alice", "carol").await.unwrap();
+        assert_eq!(db.user_vec().await.unwrap(), vec!["bob", "carol"]);
+        assert_eq!(db.user_check("carol", "pass").await.unwrap(), true);
+        assert!(db.location_count(Some("alice"), None).await.is_err());
+        assert_eq!(db.location_count(Some("carol"), None).await.unwrap(), 1);
+        assert_eq!(db.device_vec("carol").await.unwrap().len(), 1);
+
+        db.user_delete("carol").await.unwrap();
+        assert!(db.user_delete("carol").await.is_err());
+        assert_eq!(db.user_vec().await.unwrap(), vec!["bob"]);
+        assert_eq!(db.location_count(None, None).await.unwrap(), 1);
+        assert!(db.device_vec("carol").await.unwrap().is_empty());
+    }
+
+    /// Checks creating, checking, listing and revoking API tokens.
+    #[tokio::test]
+    async fn test_tokens() {
+        let db_file = NamedTempFile::new().unwrap();
+        let db = Db::new(&Config {
+            path: db_file.path().to_path_buf(),
+            backups: 1,
+            bounds: Default::default(),
+        })
+        .await
+        .unwrap();
+        db.user_insert("user".to_string(), "pass".to_string())
+            .await
+            .unwrap();
+        assert!(db
+            .token_insert("nonexistent", "phone", Scope::Ingest, None)
+            .await
+            .is_err());
+        let phone = db
+            .token_insert("user", "phone", Scope::Ingest, None)
+            .await
+            .unwrap();
+        assert!(db
+            .token_insert("user", "phone", Scope::Read, None)
+            .await
+            .is_err());
+        assert!(db
+            .token_insert("user", "", Scope::Read, None)
+            .await
+            .is_err());
+        let expired = db
+            .token_insert(
+                "user",
+                "old",
+                Scope::Read,
+                Some(Utc::now() - chrono::Duration::seconds(1)),
+            )
+            .await
+            .unwrap();
+
+        let found = db.token_check(&phone).await.unwrap().unwrap();
+        assert_eq!(found.username, "user");
+        assert_eq!(found.scope, Scope::Ingest);
+        assert!(!found.scope.allows(Scope::Read));
+        assert!(Scope::Read.allows(Scope::Ingest));
+        assert_ne!(found.hash, phone);
+        assert!(db.token_check(&expired).await.unwrap().is_none());
+        assert!(db.token_check("crataegus_wrong").await.unwrap().is_none());
+        let names = |tokens: Vec<ApiToken>| tokens.into_iter().map(|t| t.name).collect::<Vec<_>>();
+        assert_eq!(
+            names(db.token_vec(None).await.unwrap()),
+            vec!["old", "phone"]
+        );
+
+        db.token_delete("user", "phone").await.unwrap();
+        assert!(db.token_delete("user", "phone").await.is_err());
+        assert!(db.token_check(&phone).await.unwrap().is_none());
+        // tokens go with their user
+        db.user_delete("user").await.unwrap();
+        assert!(db.token_vec(Some("user")).await.unwrap().is_empty());
     }
 
     // creates an ephemeral database and checks the username relation
@@ -520,6 +2463,7 @@ mod tests {
         let db = Db::new(&Config {
             path: db_file.path().to_path_buf(),
             backups: 1,
+            bounds: Default::default(),
         })
         .await
         .unwrap();
@@ -538,6 +2482,16 @@ mod tests {
             altitude: 0.0,
             accuracy: Some(0.0),
             source: location::Source::GpsLogger,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
         };
         // insert the location should fail since no user exists
         assert!(db.location_insert(loc.clone()).await.is_err());
@@ -558,6 +2512,7 @@ mod tests {
         let db = Db::new(&Config {
             path: db_file.path().to_path_buf(),
             backups: 3,
+            bounds: Default::default(),
         })
         .await
         .unwrap();
@@ -602,6 +2557,7 @@ mod tests {
         let db = Db::new(&Config {
             path: db_file.path().to_path_buf(),
             backups: 1,
+            bounds: Default::default(),
         })
         .await
         .unwrap();
@@ -634,7 +2590,7 @@ mod tests {
                 .unwrap()
                 .with_timezone(&Utc),
         ];
-        let locs = vec![
+        let locs = [
             Location {
                 username: "user1".to_string(),
                 time_utc: times[1],
@@ -644,6 +2600,16 @@ mod tests {
                 altitude: 1.0,
                 accuracy: Some(1.0),
                 source: location::Source::GpsLogger,
+                speed: None,
+                bearing: None,
+                satellites: None,
+                provider: None,
+                hdop: None,
+                vdop: None,
+                pdop: None,
+                battery: None,
+                charging: None,
+                device: String::new(),
             },
             Location {
                 username: "user2".to_string(),
@@ -654,6 +2620,16 @@ mod tests {
                 altitude: 2.0,
                 accuracy: Some(2.0),
                 source: location::Source::GpsLogger,
+                speed: None,
+                bearing: None,
+                satellites: None,
+                provider: None,
+                hdop: None,
+                vdop: None,
+                pdop: None,
+                battery: None,
+                charging: None,
+                device: String::new(),
             },
             Location {
                 username: "user1".to_string(),
@@ -664,6 +2640,16 @@ mod tests {
                 altitude: 3.0,
                 accuracy: Some(3.0),
                 source: location::Source::GpsLogger,
+                speed: None,
+                bearing: None,
+                satellites: None,
+                provider: None,
+                hdop: None,
+                vdop: None,
+                pdop: None,
+                battery: None,
+                charging: None,
+                device: String::new(),
             },
             Location {
                 username: "user2".to_string(),
@@ -674,6 +2660,16 @@ mod tests {
                 altitude: 4.0,
                 accuracy: Some(4.0),
                 source: location::Source::GpsLogger,
+                speed: None,
+                bearing: None,
+                satellites: None,
+                provider: None,
+                hdop: None,
+                vdop: None,
+                pdop: None,
+                battery: None,
+                charging: None,
+                device: String::new(),
             },
             Location {
                 username: "user1".to_string(),
@@ -684,6 +2680,16 @@ mod tests {
                 altitude: 5.0,
                 accuracy: Some(5.0),
                 source: location::Source::GpsLogger,
+                speed: None,
+                bearing: None,
+                satellites: None,
+                provider: None,
+                hdop: None,
+                vdop: None,
+                pdop: None,
+                battery: None,
+                charging: None,
+                device: String::new(),
             },
         ];
         for loc in locs.iter() {
@@ -694,7 +2700,7 @@ mod tests {
         {
             let expected_idxs = [0, 2, 4];
             let mut stream = db
-                .location_stream("user1", times[0], times[6])
+                .location_stream("user1", None, times[0], times[6])
                 .await
                 .unwrap();
             let mut count = 0;
@@ -709,7 +2715,7 @@ mod tests {
         {
             let expected_idxs = [1, 3];
             let mut stream = db
-                .location_stream("user2", times[0], times[6])
+                .location_stream("user2", None, times[0], times[6])
                 .await
                 .unwrap();
             let mut count = 0;
@@ -725,7 +2731,7 @@ mod tests {
         {
             let expected_idx = 1;
             let mut stream = db
-                .location_stream("user2", times[1], times[3])
+                .location_stream("user2", None, times[1], times[3])
                 .await
                 .unwrap();
             let loc = stream.next().await.unwrap().unwrap();
@@ -740,22 +2746,23 @@ mod tests {
         let db = Db::new(&Config {
             path: db_file.path().to_path_buf(),
             backups: 1,
+            bounds: Default::default(),
         })
         .await
         .unwrap();
-        assert_eq!(db.location_count(None).await.unwrap(), 0);
+        assert_eq!(db.location_count(None, None).await.unwrap(), 0);
         // should err for nonexistent user
-        assert!(db.location_count(Some("user1")).await.is_err());
+        assert!(db.location_count(Some("user1"), None).await.is_err());
         db.user_insert("user1".to_string(), "pass".to_string())
             .await
             .unwrap();
-        assert_eq!(db.location_count(None).await.unwrap(), 0);
-        assert_eq!(db.location_count(Some("user1")).await.unwrap(), 0);
-        assert!(db.location_count(Some("user2")).await.is_err());
+        assert_eq!(db.location_count(None, None).await.unwrap(), 0);
+        assert_eq!(db.location_count(Some("user1"), None).await.unwrap(), 0);
+        assert!(db.location_count(Some("user2"), None).await.is_err());
         db.user_insert("user2".to_string(), "pass".to_string())
             .await
             .unwrap();
-        assert_eq!(db.location_count(None).await.unwrap(), 0);
+        assert_eq!(db.location_count(None, None).await.unwrap(), 0);
         db.location_insert(Location {
             username: "user1".to_string(),
             time_utc: DateTime::parse_from_rfc3339("2025-01-16T03:54:51.000Z")
@@ -769,13 +2776,23 @@ mod tests {
             altitude: 0.0,
             accuracy: Some(0.0),
             source: location::Source::GpsLogger,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
         })
         .await
         .unwrap();
-        assert_eq!(db.location_count(None).await.unwrap(), 1);
-        assert_eq!(db.location_count(Some("user1")).await.unwrap(), 1);
-        assert_eq!(db.location_count(Some("user2")).await.unwrap(), 0);
-        assert!(db.location_count(Some("user3")).await.is_err());
+        assert_eq!(db.location_count(None, None).await.unwrap(), 1);
+        assert_eq!(db.location_count(Some("user1"), None).await.unwrap(), 1);
+        assert_eq!(db.location_count(Some("user2"), None).await.unwrap(), 0);
+        assert!(db.location_count(Some("user3"), None).await.is_err());
         db.location_insert(Location {
             username: "user2".to_string(),
             time_utc: DateTime::parse_from_rfc3339("2025-01-16T03:54:51.000Z")
@@ -789,12 +2806,22 @@ mod tests {
             altitude: 0.0,
             accuracy: Some(0.0),
             source: location::Source::GpsLogger,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
         })
         .await
         .unwrap();
-        assert_eq!(db.location_count(None).await.unwrap(), 2);
-        assert_eq!(db.location_count(Some("user1")).await.unwrap(), 1);
-        assert_eq!(db.location_count(Some("user2")).await.unwrap(), 1);
+        assert_eq!(db.location_count(None, None).await.unwrap(), 2);
+        assert_eq!(db.location_count(Some("user1"), None).await.unwrap(), 1);
+        assert_eq!(db.location_count(Some("user2"), None).await.unwrap(), 1);
         db.location_insert(Location {
             username: "user1".to_string(),
             time_utc: DateTime::parse_from_rfc3339("2025-01-16T03:54:52.000Z")
@@ -808,12 +2835,22 @@ mod tests {
             altitude: 0.0,
             accuracy: Some(0.0),
             source: location::Source::GpsLogger,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
         })
         .await
         .unwrap();
-        assert_eq!(db.location_count(None).await.unwrap(), 3);
-        assert_eq!(db.location_count(Some("user1")).await.unwrap(), 2);
-        assert_eq!(db.location_count(Some("user2")).await.unwrap(), 1);
+        assert_eq!(db.location_count(None, None).await.unwrap(), 3);
+        assert_eq!(db.location_count(Some("user1"), None).await.unwrap(), 2);
+        assert_eq!(db.location_count(Some("user2"), None).await.unwrap(), 1);
     }
 
     #[tokio::test]
@@ -822,6 +2859,7 @@ mod tests {
         let db = Db::new(&Config {
             path: db_file.path().to_path_buf(),
             backups: 1,
+            bounds: Default::default(),
         })
         .await
         .unwrap();
@@ -850,6 +2888,16 @@ mod tests {
                     altitude: 0.0,
                     accuracy: Some(0.0),
                     source: location::Source::GpsLogger,
+                    speed: None,
+                    bearing: None,
+                    satellites: None,
+                    provider: None,
+                    hdop: None,
+                    vdop: None,
+                    pdop: None,
+                    battery: None,
+                    charging: None,
+                    device: String::new(),
                 })
                 .await
                 .unwrap();
@@ -929,6 +2977,7 @@ mod tests {
         let db = Db::new(&Config {
             path: db_file.path().to_path_buf(),
             backups: 1,
+            bounds: Default::default(),
         })
         .await
         .unwrap();
@@ -957,6 +3006,16 @@ mod tests {
                     altitude: 0.0,
                     accuracy: Some(0.0),
                     source: location::Source::GpsLogger,
+                    speed: None,
+                    bearing: None,
+                    satellites: None,
+                    provider: None,
+                    hdop: None,
+                    vdop: None,
+                    pdop: None,
+                    battery: None,
+                    charging: None,
+                    device: String::new(),
                 })
                 .await
                 .unwrap();
@@ -965,7 +3024,7 @@ mod tests {
         db.user_insert("user3".to_string(), "pass".to_string())
             .await
             .unwrap();
-        let info = db.info(None).await.unwrap();
+        let info = db.info(None, None).await.unwrap();
         assert_eq!(info.len(), 3);
         assert_eq!(info[0].username, "user1");
         assert_eq!(info[0].location_count, 2);
@@ -0,0 +1,399 @@
+/* This is an example of the point format.
+
+{"type":"FeatureCollection","name":"crataegus_export","features":[
+{"type":"Feature","geometry":{"type":"Point","coordinates":[11.5167,48.1173,545.4]},"properties":{"time":"2023-10-07T12:35:19+02:00","accuracy":4.0,"source":"GPSLogger"}},
+{"type":"Feature","geometry":{"type":"Point","coordinates":[11.5168,48.1172,546.0]},"properties":{"time":"2023-10-07T12:35:29+02:00","accuracy":null,"source":"GPSLogger","speed":1.5,"battery":80.0}}
+]}
+
+Speed, bearing, satellites, provider, DOPs, battery and charging state are only written when known.
+
+The daily format has one LineString per local calendar day, with per-vertex values as arrays in
+the properties. Days with a single location are written as a Point, since a LineString needs at
+least two positions. The optional values get an array, such as `speeds`, only if one of the day's
+locations has them.
+
+{"type":"Feature","geometry":{"type":"LineString","coordinates":[[11.5167,48.1173,545.4],[11.5168,48.1172,546.0]]},"properties":{"date":"2023-10-07","times":["2023-10-07T12:35:19+02:00","2023-10-07T12:35:29+02:00"],"accuracies":[4.0,null],"sources":["GPSLogger","GPSLogger"]}}
+
+*/
+use crate::{export::Exporter, schema::Location};
+use chrono::NaiveDate;
+use color_eyre::eyre::Result;
+use sea_orm::ActiveEnum;
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Writes a GeoJSON FeatureCollection one feature at a time, so the whole collection never has to
+/// be held in memory. Failure to call `finish` may result in a corrupted file.
+struct FeatureWriter {
+    writer: BufWriter<File>,
+    /// Whether no feature has been written yet, so no separator is needed.
+    first: bool,
+}
+
+impl FeatureWriter {
+    fn new(name: &str, path: &Path) -> Result<Self> {
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        write!(
+            writer,
+            r#"{{"type":"FeatureCollection","name":{},"features":["#,
+            serde_json::to_string(name)?
+        )?;
+        Ok(FeatureWriter {
+            writer,
+            first: true,
+        })
+    }
+
+    fn write_feature(&mut self, feature: &Value) -> Result<()> {
+        if !self.first {
+            self.writer.write_all(b",")?;
+        }
+        self.first = false;
+        self.writer.write_all(b"\n")?;
+        serde_json::to_writer(&mut self.writer, feature)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.write_all(b"\n]}\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// GeoJSON position of a location.
+fn coordinates(location: &Location) -> Value {
+    json!([location.longitude, location.latitude, location.altitude])
+}
+
+/// The optional values of a location that are only written when known, with the property names
+/// used for a Point and for the per-vertex arrays of a LineString.
+fn details(location: &Location) -> [(&'static str, &'static str, Value); 9] {
+    [
+        ("speed", "speeds", json!(location.speed)),
+        ("bearing", "bearings", json!(location.bearing)),
+        ("satellites", "satellite_counts", json!(location.satellites)),
+        ("provider", "providers", json!(location.provider)),
+        ("hdop", "hdops", json!(location.hdop)),
+        ("vdop", "vdops", json!(location.vdop)),
+        ("pdop", "pdops", json!(location.pdop)),
+        ("battery", "batteries", json!(location.battery)),
+        ("charging", "charging_states", json!(location.charging)),
+    ]
+}
+
+/// A Point feature for a single location.
+fn point_feature(location: &Location) -> Value {
+    let mut feature = json!({
+        "type": "Feature",
+        "geometry": {
+            "type": "Point",
+            "coordinates": coordinates(location),
+        },
+        "properties": {
+            "time": location.time_local.to_rfc3339(),
+            "accuracy": location.accuracy,
+            "source": location.source.to_value(),
+        },
+    });
+    for (name, _, value) in details(location) {
+        if !value.is_null() {
+            feature["properties"][name] = value;
+        }
+    }
+    feature
+}
+
+/// Writes every location as a Point feature.
+pub struct GeoJsonExporter {
+    writer: FeatureWriter,
+}
+
+impl GeoJsonExporter {
+    /// Create a new GeoJSON exporter and write the start of the collection to the file.
+    /// # Arguments
+    /// * `name`: The name of the collection
+    /// * `path`: The path to the file to write
+    /// # Returns
+    /// The exporter
+    pub fn new(name: &str, path: &Path) -> Result<Self> {
+        Ok(GeoJsonExporter {
+            writer: FeatureWriter::new(name, path)?,
+        })
+    }
+}
+
+impl Exporter for GeoJsonExporter {
+    fn write_location(&mut self, location: &Location) -> Result<()> {
+        self.writer.write_feature(&point_feature(location))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.finish()
+    }
+}
+
+/// Writes one LineString feature per local calendar day. Locations must arrive in ascending order
+/// of time, as they do from `Db::location_stream`, and only one day is buffered at a time.
+pub struct GeoJsonDailyExporter {
+    writer: FeatureWriter,
+    /// Local date of the buffered locations.
+    day: Option<NaiveDate>,
+    /// Locations of the current day.
+    locations: Vec<Location>,
+}
+
+impl GeoJsonDailyExporter {
+    /// Create a new daily GeoJSON exporter and write the start of the collection to the file.
+    /// # Arguments
+    /// * `name`: The name of the collection
+    /// * `path`: The path to the file to write
+    /// # Returns
+    /// The exporter
+    pub fn new(name: &str, path: &Path) -> Result<Self> {
+        Ok(GeoJsonDailyExporter {
+            writer: FeatureWriter::new(name, path)?,
+            day: None,
+            locations: Vec::new(),
+        })
+    }
+
+    /// Write the buffered day as a single feature.
+    fn flush_day(&mut self) -> Result<()> {
+        let locations = std::mem::take(&mut self.locations);
+        let mut feature = match (self.day, locations.as_slice()) {
+            (_, []) => return Ok(()),
+            (_, [location]) => point_feature(location),
+            (Some(day), locations) => json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": locations.iter().map(coordinates).collect::<Vec<_>>(),
+                },
+                "properties": {
+                    "date": day.to_string(),
+                    "times": locations
+                        .iter()
+                        .map(|location| location.time_local.to_rfc3339())
+                        .collect::<Vec<_>>(),
+                    "accuracies": locations
+                        .iter()
+                        .map(|location| location.accuracy)
+                        .collect::<Vec<_>>(),
+                    "sources": locations
+                        .iter()
+                        .map(|location| location.source.to_value())
+                        .collect::<Vec<_>>(),
+                },
+            }),
+            (None, _) => unreachable!("Locations are always buffered with their day"),
+        };
+        if locations.len() > 1 {
+            let details = locations.iter().map(details).collect::<Vec<_>>();
+            for i in 0..details[0].len() {
+                let values = details
+                    .iter()
+                    .map(|details| details[i].2.clone())
+                    .collect::<Vec<_>>();
+                if values.iter().any(|value| !value.is_null()) {
+                    feature["properties"][details[0][i].1] = Value::Array(values);
+                }
+            }
+        }
+        self.writer.write_feature(&feature)
+    }
+}
+
+impl Exporter for GeoJsonDailyExporter {
+    fn write_location(&mut self, location: &Location) -> Result<()> {
+        let day = location.time_local.date_naive();
+        if self.day != Some(day) {
+            self.flush_day()?;
+            self.day = Some(day);
+        }
+        self.locations.push(location.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.flush_day()?;
+        self.writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{Location, Source};
+    use chrono::DateTime;
+    use pretty_assertions::assert_eq;
+
+    fn locations() -> Vec<Location> {
+        vec![
+            Location {
+                username: "test".to_string(),
+                time_utc: DateTime::parse_from_rfc3339("2023-10-07T10:35:19Z")
+                    .unwrap()
+                    .into(),
+                time_local: DateTime::parse_from_rfc3339("2023-10-07T12:35:19+02:00").unwrap(),
+                latitude: 48.1173,
+                longitude: 11.5167,
+                altitude: 545.4,
+                accuracy: Some(4.0),
+                source: Source::GpsLogger,
+                speed: None,
+                bearing: None,
+                satellites: None,
+                provider: None,
+                hdop: None,
+                vdop: None,
+                pdop: None,
+                battery: None,
+                charging: None,
+                device: String::new(),
+            },
+            Location {
+                username: "test".to_string(),
+                time_utc: DateTime::parse_from_rfc3339("2023-10-07T10:35:29Z")
+                    .unwrap()
+                    .into(),
+                time_local: DateTime::parse_from_rfc3339("2023-10-07T12:35:29+02:00").unwrap(),
+                latitude: 48.1172,
+                longitude: 11.5168,
+                altitude: 546.0,
+                accuracy: None,
+                source: Source::GpsLogger,
+                speed: Some(1.5),
+                bearing: None,
+                satellites: None,
+                provider: None,
+                hdop: None,
+                vdop: None,
+                pdop: None,
+                battery: Some(80.0),
+                charging: None,
+                device: String::new(),
+            },
+            Location {
+                username: "test".to_string(),
+                time_utc: DateTime::parse_from_rfc3339("2023-10-08T10:35:39Z")
+                    .unwrap()
+                    .into(),
+                time_local: DateTime::parse_from_rfc3339("2023-10-08T12:35:39+02:00").unwrap(),
+                latitude: 48.1175,
+                longitude: 11.5166,
+                altitude: 547.5,
+                accuracy: None,
+                source: Source::Gpx,
+                speed: None,
+                bearing: None,
+                satellites: None,
+                provider: None,
+                hdop: None,
+                vdop: None,
+                pdop: None,
+                battery: None,
+                charging: None,
+                device: String::new(),
+            },
+        ]
+    }
+
+    fn export(mut exporter: Box<dyn Exporter>, path: &Path) -> String {
+        for location in locations().iter() {
+            exporter.write_location(location).unwrap();
+        }
+        exporter.finish().unwrap();
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_geojson_exporter() {
+        let tempfile = tempfile::NamedTempFile::new().unwrap();
+        let exporter = GeoJsonExporter::new("test", tempfile.path()).unwrap();
+        let contents = export(Box::new(exporter), tempfile.path());
+        println!("{}", contents);
+        // one feature per line
+        assert_eq!(contents.lines().count(), 5);
+        assert_eq!(
+            serde_json::from_str::<Value>(&contents).unwrap(),
+            json!({
+                "type": "FeatureCollection",
+                "name": "test",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "geometry": {"type": "Point", "coordinates": [11.5167, 48.1173, 545.4]},
+                        "properties": {"time": "2023-10-07T12:35:19+02:00", "accuracy": 4.0, "source": "GPSLogger"},
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": {"type": "Point", "coordinates": [11.5168, 48.1172, 546.0]},
+                        "properties": {"time": "2023-10-07T12:35:29+02:00", "accuracy": null, "source": "GPSLogger", "speed": 1.5, "battery": 80.0},
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": {"type": "Point", "coordinates": [11.5166, 48.1175, 547.5]},
+                        "properties": {"time": "2023-10-08T12:35:39+02:00", "accuracy": null, "source": "GPX"},
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_geojson_daily_exporter() {
+        let tempfile = tempfile::NamedTempFile::new().unwrap();
+        let exporter = GeoJsonDailyExporter::new("test", tempfile.path()).unwrap();
+        let contents = export(Box::new(exporter), tempfile.path());
+        println!("{}", contents);
+        assert_eq!(contents.lines().count(), 4);
+        assert_eq!(
+            serde_json::from_str::<Value>(&contents).unwrap(),
+            json!({
+                "type": "FeatureCollection",
+                "name": "test",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "LineString",
+                            "coordinates": [[11.5167, 48.1173, 545.4], [11.5168, 48.1172, 546.0]],
+                        },
+                        "properties": {
+                            "date": "2023-10-07",
+                            "times": ["2023-10-07T12:35:19+02:00", "2023-10-07T12:35:29+02:00"],
+                            "accuracies": [4.0, null],
+                            "sources": ["GPSLogger", "GPSLogger"],
+                            "speeds": [null, 1.5],
+                            "batteries": [null, 80.0],
+                        },
+                    },
+                    // a lone location in a day is a Point
+                    {
+                        "type": "Feature",
+                        "geometry": {"type": "Point", "coordinates": [11.5166, 48.1175, 547.5]},
+                        "properties": {"time": "2023-10-08T12:35:39+02:00", "accuracy": null, "source": "GPX"},
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_empty_export() {
+        let tempfile = tempfile::NamedTempFile::new().unwrap();
+        let mut exporter = GeoJsonDailyExporter::new("test", tempfile.path()).unwrap();
+        exporter.finish().unwrap();
+        let contents = std::fs::read_to_string(tempfile.path()).unwrap();
+        assert_eq!(
+            contents,
+            "{\"type\":\"FeatureCollection\",\"name\":\"test\",\"features\":[\n]}\n"
+        );
+    }
+}
@@ -24,6 +24,7 @@
 */
 use crate::{export::Exporter, schema::Location};
 use color_eyre::eyre::Result;
+use sea_orm::ActiveEnum;
 use std::{
     fs::File,
     io::{BufWriter, Write},
@@ -60,7 +61,8 @@ impl Exporter for GpxExporter {
             .replace("{latitude}", &location.latitude.to_string())
             .replace("{longitude}", &location.longitude.to_string())
             .replace("{altitude}", &location.altitude.to_string())
-            .replace("{time}", &location.time_local.to_rfc3339());
+            .replace("{time}", &location.time_local.to_rfc3339())
+            .replace("{details}", &details(location));
         self.writer.write_all(point.as_bytes())?;
         Ok(())
     }
@@ -72,8 +74,70 @@ impl Exporter for GpxExporter {
     }
 }
 
+/// Format the optional values of a location as GPX elements, in the order the GPX 1.1 schema
+/// requires. Satellites and DOPs are standard `trkpt` elements. Speed and bearing go into a Garmin
+/// TrackPointExtension, which most tools understand, and the accuracy, battery, provider, source
+/// and device into our own namespace, so that re-importing an export matches the stored
+/// locations. Values that are not known are left out.
+/// # Arguments
+/// * `location`: The location to format
+/// # Returns
+/// The elements, each on its own line, or an empty string
+fn details(location: &Location) -> String {
+    let mut details = String::new();
+    for (name, value) in [
+        ("sat", location.satellites.map(|v| v.to_string())),
+        ("hdop", location.hdop.map(|v| v.to_string())),
+        ("vdop", location.vdop.map(|v| v.to_string())),
+        ("pdop", location.pdop.map(|v| v.to_string())),
+    ] {
+        if let Some(value) = value {
+            details += &format!("\n        <{name}>{value}</{name}>");
+        }
+    }
+    let mut garmin = String::new();
+    for (name, value) in [("speed", location.speed), ("course", location.bearing)] {
+        if let Some(value) = value {
+            garmin += &format!("<gpxtpx:{name}>{value}</gpxtpx:{name}>");
+        }
+    }
+    let mut ours = String::new();
+    let device = Some(&location.device)
+        .filter(|device| !device.is_empty())
+        .map(|device| escape(device));
+    for (name, value) in [
+        ("accuracy", location.accuracy.map(|v| v.to_string())),
+        ("battery", location.battery.map(|v| v.to_string())),
+        ("charging", location.charging.map(|v| v.to_string())),
+        ("provider", location.provider.as_deref().map(escape)),
+        ("source", Some(location.source.to_value())),
+        ("device", device),
+    ] {
+        if let Some(value) = value {
+            ours += &format!("<crataegus:{name}>{value}</crataegus:{name}>");
+        }
+    }
+    if !garmin.is_empty() || !ours.is_empty() {
+        details += "\n        <extensions>";
+        if !garmin.is_empty() {
+            details +=
+                &format!("<gpxtpx:TrackPointExtension>{garmin}</gpxtpx:TrackPointExtension>");
+        }
+        details += &ours;
+        details += "</extensions>";
+    }
+    details
+}
+
+/// Escape the characters that may not appear in XML text.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
 static HEADER_FMT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
-<gpx version="1.1" creator="crataegus" xmlns="http://www.topografix.com/GPX/1/1">
+<gpx version="1.1" creator="crataegus" xmlns="http://www.topografix.com/GPX/1/1" xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v2" xmlns:crataegus="urn:crataegus:gpx:1">
   <trk>
     <name>{track_name}</name>
     <trkseg>
@@ -82,7 +146,7 @@ static HEADER_FMT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
 static POINT_FMT: &str = r#"
       <trkpt lat="{latitude}" lon="{longitude}">
         <ele>{altitude}</ele>
-        <time>{time}</time>
+        <time>{time}</time>{details}
       </trkpt>
 "#;
 
@@ -118,6 +182,16 @@ mod tests {
                     altitude: 545.4,
                     accuracy: None,
                     source: Source::GpsLogger,
+                    speed: None,
+                    bearing: None,
+                    satellites: None,
+                    provider: None,
+                    hdop: None,
+                    vdop: None,
+                    pdop: None,
+                    battery: None,
+                    charging: None,
+                    device: String::new(),
                 })
                 .unwrap();
             exporter
@@ -132,6 +206,16 @@ mod tests {
                     altitude: 546.0,
                     accuracy: None,
                     source: Source::GpsLogger,
+                    speed: Some(1.5),
+                    bearing: Some(90.0),
+                    satellites: Some(7),
+                    provider: Some("gps".to_string()),
+                    hdop: Some(0.75),
+                    vdop: None,
+                    pdop: Some(1.25),
+                    battery: Some(80.0),
+                    charging: Some(false),
+                    device: String::new(),
                 })
                 .unwrap();
             exporter
@@ -146,6 +230,16 @@ mod tests {
                     altitude: 547.5,
                     accuracy: None,
                     source: Source::GpsLogger,
+                    speed: None,
+                    bearing: None,
+                    satellites: None,
+                    provider: None,
+                    hdop: None,
+                    vdop: None,
+                    pdop: None,
+                    battery: None,
+                    charging: None,
+                    device: String::new(),
                 })
                 .unwrap();
             exporter.finish().unwrap();
@@ -158,7 +252,7 @@ mod tests {
         assert_eq!(
             contents,
             r#"<?xml version="1.0" encoding="UTF-8"?>
-<gpx version="1.1" creator="crataegus" xmlns="http://www.topografix.com/GPX/1/1">
+<gpx version="1.1" creator="crataegus" xmlns="http://www.topografix.com/GPX/1/1" xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v2" xmlns:crataegus="urn:crataegus:gpx:1">
   <trk>
     <name>test</name>
     <trkseg>
@@ -166,16 +260,22 @@ mod tests {
       <trkpt lat="48.1173" lon="11.5167">
         <ele>545.4</ele>
         <time>2023-10-07T12:35:19+02:00</time>
+        <extensions><crataegus:source>GPSLogger</crataegus:source></extensions>
       </trkpt>
 
       <trkpt lat="48.1172" lon="11.5168">
         <ele>546</ele>
         <time>2023-10-07T12:35:29+02:00</time>
+        <sat>7</sat>
+        <hdop>0.75</hdop>
+        <pdop>1.25</pdop>
+        <extensions><gpxtpx:TrackPointExtension><gpxtpx:speed>1.5</gpxtpx:speed><gpxtpx:course>90</gpxtpx:course></gpxtpx:TrackPointExtension><crataegus:battery>80</crataegus:battery><crataegus:charging>false</crataegus:charging><crataegus:provider>gps</crataegus:provider><crataegus:source>GPSLogger</crataegus:source></extensions>
       </trkpt>
 
       <trkpt lat="48.1175" lon="11.5166">
         <ele>547.5</ele>
         <time>2023-10-07T12:35:39+02:00</time>
+        <extensions><crataegus:source>GPSLogger</crataegus:source></extensions>
       </trkpt>
 
     </trkseg>
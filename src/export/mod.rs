@@ -1,14 +1,23 @@
+use crate::export::geojson::{GeoJsonDailyExporter, GeoJsonExporter};
 use crate::export::gpx::GpxExporter;
 use crate::schema::Location;
 use clap::ValueEnum;
 use color_eyre::eyre::Result;
 use std::path::Path;
+mod geojson;
 mod gpx;
 
 /// Filtypes that can be exported
 #[derive(Debug, Clone, Copy, ValueEnum)]
 pub enum Format {
+    /// GPX track with a single segment
     Gpx,
+    /// GeoJSON FeatureCollection of Points
+    #[value(name = "geojson")]
+    GeoJson,
+    /// GeoJSON FeatureCollection with a LineString per day
+    #[value(name = "geojson-daily")]
+    GeoJsonDaily,
 }
 
 /// Trait for exporting locations to a file.
@@ -38,5 +47,7 @@ pub trait Exporter {
 pub fn create_exporter(format: Format, name: &str, path: &Path) -> Result<Box<dyn Exporter>> {
     match format {
         Format::Gpx => Ok(Box::new(GpxExporter::new(name, path)?)),
+        Format::GeoJson => Ok(Box::new(GeoJsonExporter::new(name, path)?)),
+        Format::GeoJsonDaily => Ok(Box::new(GeoJsonDailyExporter::new(name, path)?)),
     }
 }
@@ -0,0 +1,339 @@
+use chrono::{DateTime, FixedOffset};
+use color_eyre::eyre::{eyre, Result};
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::mpsc::{sync_channel, SyncSender},
+    thread,
+};
+
+use crate::schema::{Location, LocationGen, Source};
+
+/// Number of parsed locations that may be waiting on the database before parsing pauses.
+const CHANNEL_SIZE: usize = 1024;
+
+/// # Records.json
+/// Google Takeout's Location History export is a single JSON object, often several gigabytes,
+/// with one entry per fix in the `locations` array:
+/// ```json
+/// {
+///   "locations": [
+///     {
+///       "latitudeE7": 417410869,
+///       "longitudeE7": -918449087,
+///       "accuracy": 14,
+///       "altitude": 1387,
+///       "verticalAccuracy": 3,
+///       "source": "WIFI",
+///       "deviceTag": 1234567890,
+///       "timestamp": "2025-01-16T03:54:51.123Z"
+///     }
+///   ]
+/// }
+/// ```
+/// Older exports use `timestampMs`, a string of Unix milliseconds, instead of `timestamp`.
+///
+/// Mapping of Location fields to Records.json keys:
+/// - time_utc: timestamp or timestampMs
+/// - time_local: same as time_utc, since no offset is recorded
+/// - latitude: latitudeE7 / 1e7
+/// - longitude: longitudeE7 / 1e7
+/// - altitude: altitude, or 0 if missing
+/// - accuracy: accuracy
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RawRecord {
+    /// Latitude in degrees multiplied by 1e7.
+    /// Example: 417410869
+    latitude_e7: Option<i64>,
+    /// Longitude in degrees multiplied by 1e7.
+    /// Example: -918449087
+    longitude_e7: Option<i64>,
+    /// Horizontal accuracy in meters.
+    /// Example: 14
+    accuracy: Option<f32>,
+    /// Altitude in meters.
+    /// Example: 1387
+    altitude: Option<f64>,
+    /// Time in ISO 8601 format.
+    /// Example: 2025-01-16T03:54:51.123Z
+    timestamp: Option<String>,
+    /// Unix timestamp in milliseconds, as a string. Only present in older exports.
+    /// Example: "1736999691123"
+    timestamp_ms: Option<String>,
+    /// Vertical accuracy in meters.
+    #[allow(dead_code)]
+    vertical_accuracy: Option<f32>,
+    /// How the fix was obtained, e.g. `GPS`, `WIFI`, `CELL`.
+    #[allow(dead_code)]
+    source: Option<String>,
+}
+
+/// A Records.json entry that has been checked for the fields needed to make a Location.
+#[derive(Debug)]
+struct Record {
+    time: DateTime<FixedOffset>,
+    latitude: f64,
+    longitude: f64,
+    altitude: f64,
+    accuracy: Option<f32>,
+}
+
+/// Convert a coordinate in E7 format to degrees. Some exports contain values that overflowed a
+/// signed 32 bit integer, which Google documents as needing 2^32 subtracted.
+/// # Arguments
+/// * `e7` - The coordinate multiplied by 1e7.
+/// * `limit` - 90 for latitudes, 180 for longitudes.
+/// # Return
+/// The coordinate in decimal degrees.
+fn from_e7(e7: i64, limit: i64) -> f64 {
+    let e7 = if e7 > limit * 10_000_000 {
+        e7 - 4_294_967_296
+    } else {
+        e7
+    };
+    e7 as f64 / 1e7
+}
+
+impl TryFrom<RawRecord> for Record {
+    type Error = color_eyre::eyre::Report;
+
+    fn try_from(raw: RawRecord) -> Result<Self> {
+        let time = match (&raw.timestamp, &raw.timestamp_ms) {
+            (Some(timestamp), _) => DateTime::parse_from_rfc3339(timestamp)
+                .map_err(|e| eyre!("Invalid timestamp {}: {}", timestamp, e))?,
+            (None, Some(timestamp_ms)) => timestamp_ms
+                .parse::<i64>()
+                .ok()
+                .and_then(DateTime::from_timestamp_millis)
+                .ok_or_else(|| eyre!("Invalid timestampMs: {}", timestamp_ms))?
+                .fixed_offset(),
+            (None, None) => return Err(eyre!("Record has no timestamp: {:?}", raw)),
+        };
+        let (latitude_e7, longitude_e7) = match (raw.latitude_e7, raw.longitude_e7) {
+            (Some(latitude_e7), Some(longitude_e7)) => (latitude_e7, longitude_e7),
+            _ => return Err(eyre!("Record has no position: {:?}", raw)),
+        };
+        Ok(Record {
+            time,
+            latitude: from_e7(latitude_e7, 90),
+            longitude: from_e7(longitude_e7, 180),
+            altitude: raw.altitude.unwrap_or(0.0),
+            accuracy: raw.accuracy,
+        })
+    }
+}
+
+impl LocationGen for Record {
+    fn to_location(&self, username: &str) -> Location {
+        Location {
+            username: username.to_string(),
+            time_utc: self.time.to_utc(),
+            time_local: self.time,
+            latitude: self.latitude,
+            longitude: self.longitude,
+            altitude: self.altitude,
+            accuracy: self.accuracy,
+            source: Source::GoogleRecords,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        }
+    }
+}
+
+/// Visits the top level object of Records.json, handing the `locations` array to
+/// `LocationsVisitor` and skipping everything else.
+struct RecordsVisitor<'a> {
+    tx: &'a SyncSender<Result<Location>>,
+    username: &'a str,
+}
+
+impl<'de> Visitor<'de> for RecordsVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an object with a `locations` array")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "locations" {
+                map.next_value_seed(LocationsVisitor {
+                    tx: self.tx,
+                    username: self.username,
+                })?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Visits the `locations` array one entry at a time, sending each converted entry down the
+/// channel so that the whole array is never held in memory.
+struct LocationsVisitor<'a> {
+    tx: &'a SyncSender<Result<Location>>,
+    username: &'a str,
+}
+
+impl<'de> DeserializeSeed<'de> for LocationsVisitor<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for LocationsVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of location records")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(raw) = seq.next_element::<RawRecord>()? {
+            let location = Record::try_from(raw).map(|record| record.to_location(self.username));
+            if self.tx.send(location).is_err() {
+                // the receiver is gone, so nobody wants the rest of the file
+                return Err(de::Error::custom("Location receiver hung up"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read a Records.json file and return an iterator of `Location` structs. The file is parsed on a
+/// background thread and never loaded into memory all at once.
+/// # Arguments
+/// * `path` - The path to the Records.json file.
+/// * `username` - The username to associate with the locations.
+/// # Return
+/// An iterator of `Location` structs. A parse error is returned as the last item.
+pub fn read_records(path: &Path, username: &str) -> Result<impl Iterator<Item = Result<Location>>> {
+    let username = username.to_string();
+    let file = File::open(path).map_err(|e| eyre!("Failed to open Records.json file: {}", e))?;
+    let (tx, rx) = sync_channel(CHANNEL_SIZE);
+    thread::spawn(move || {
+        let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(file));
+        let result = deserializer
+            .deserialize_map(RecordsVisitor {
+                tx: &tx,
+                username: &username,
+            })
+            .and_then(|_| deserializer.end());
+        if let Err(e) = result {
+            let _ = tx.send(Err(eyre!("Failed to parse Records.json: {}", e)));
+        }
+    });
+    Ok(rx.into_iter())
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    static RECORDS_DATA: &str = r#"{
+  "locations": [{
+    "latitudeE7": 417410869,
+    "longitudeE7": -918449087,
+    "accuracy": 14,
+    "altitude": 1387,
+    "verticalAccuracy": 3,
+    "activity": [{ "activity": [{ "type": "STILL", "confidence": 100 }], "timestamp": "2025-01-16T03:54:50Z" }],
+    "source": "WIFI",
+    "deviceTag": 1234567890,
+    "timestamp": "2025-01-16T03:54:51.123Z"
+  }, {
+    "latitudeE7": 4250000000,
+    "longitudeE7": 4200000000,
+    "accuracy": 20,
+    "timestampMs": "1369325466393"
+  }],
+  "settings": { "ignored": true }
+}"#;
+
+    static USERNAME: &str = "test_user";
+
+    fn create_records(data: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_read_records() {
+        let file = create_records(RECORDS_DATA);
+        let locations = read_records(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap();
+        assert_eq!(locations.len(), 2);
+
+        assert_eq!(locations[0].username, USERNAME);
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2025-01-16T03:54:51.123+00:00"
+        );
+        assert_eq!(
+            locations[0].time_local.to_rfc3339(),
+            "2025-01-16T03:54:51.123+00:00"
+        );
+        assert_eq!(locations[0].latitude, 41.7410869);
+        assert_eq!(locations[0].longitude, -91.8449087);
+        assert_eq!(locations[0].altitude, 1387.0);
+        assert_eq!(locations[0].accuracy, Some(14.0));
+        assert_eq!(locations[0].source, Source::GoogleRecords);
+
+        assert_eq!(
+            locations[1].time_utc.to_rfc3339(),
+            "2013-05-23T16:11:06.393+00:00"
+        );
+        // overflowed E7 values wrap back into range
+        assert_eq!(locations[1].latitude, -4.4967296);
+        assert_eq!(locations[1].longitude, -9.4967296);
+        assert_eq!(locations[1].altitude, 0.0);
+    }
+
+    #[test]
+    fn test_read_records_errors() {
+        // missing position
+        let file = create_records(r#"{"locations":[{"timestamp":"2025-01-16T03:54:51Z"}]}"#);
+        let locations = read_records(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(locations.len(), 1);
+        assert!(locations[0].is_err());
+        // truncated file yields the good record, then the parse error
+        let file = create_records(
+            r#"{"locations":[{"latitudeE7":1,"longitudeE7":1,"timestamp":"2025-01-16T03:54:51Z"},{"lat"#,
+        );
+        let locations = read_records(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(locations.len(), 2);
+        assert!(locations[0].is_ok());
+        assert!(locations[1].is_err());
+    }
+}
@@ -0,0 +1,683 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+
+use std::{collections::BTreeMap, fs::File, io::BufReader, path::Path};
+
+use crate::schema::{segment, Location, Segment, Source};
+
+/// # Timeline.json
+/// Since Google Timeline moved on-device, the only export is `Timeline.json`, produced from the
+/// Maps app settings. An abridged example:
+/// ```json
+/// {
+///   "semanticSegments": [
+///     {
+///       "startTime": "2024-06-01T08:00:00.000+02:00",
+///       "endTime": "2024-06-01T10:00:00.000+02:00",
+///       "timelinePath": [
+///         { "point": "48.1173000°, 11.5167000°", "time": "2024-06-01T08:05:00.000+02:00" }
+///       ]
+///     },
+///     {
+///       "startTime": "2024-06-01T10:00:00.000+02:00",
+///       "endTime": "2024-06-01T12:00:00.000+02:00",
+///       "visit": {
+///         "hierarchyLevel": 0,
+///         "probability": 0.9,
+///         "topCandidate": {
+///           "placeId": "ChIJ2V-Mo_l1nkcRfZixfUq4DAE",
+///           "semanticType": "HOME",
+///           "probability": 0.8,
+///           "placeLocation": { "latLng": "48.1372000°, 11.5756000°" }
+///         }
+///       }
+///     },
+///     {
+///       "startTime": "2024-06-01T12:00:00.000+02:00",
+///       "endTime": "2024-06-01T12:30:00.000+02:00",
+///       "activity": {
+///         "start": { "latLng": "48.1372000°, 11.5756000°" },
+///         "end": { "latLng": "48.1173000°, 11.5167000°" },
+///         "distanceMeters": 4500.0,
+///         "topCandidate": { "type": "IN_PASSENGER_VEHICLE", "probability": 0.7 }
+///       }
+///     }
+///   ],
+///   "rawSignals": [
+///     {
+///       "position": {
+///         "LatLng": "48.1173000°, 11.5167000°",
+///         "accuracyMeters": 13,
+///         "altitudeMeters": 545.4,
+///         "source": "WIFI",
+///         "timestamp": "2024-06-01T08:05:00.000+02:00",
+///         "speedMetersPerSecond": 0.0
+///       }
+///     },
+///     { "wifiScan": {} }
+///   ]
+/// }
+/// ```
+///
+/// `timelinePath` points and `rawSignals` positions become locations. Path points carry neither
+/// accuracy nor altitude, so a raw signal at the same time takes precedence. Visits and
+/// activities are kept as segments. Nested visits (`hierarchyLevel` above 0) and other segment
+/// kinds such as `timelineMemory` are ignored.
+///
+/// The iOS app exports the segments alone, as a top level array with a different layout: numbers
+/// are strings, positions are `geo:` URIs, and path points give their time in minutes after the
+/// start of their segment. There are no raw signals.
+/// ```json
+/// [
+///   {
+///     "startTime": "2024-06-01T08:00:00.000+02:00",
+///     "endTime": "2024-06-01T10:00:00.000+02:00",
+///     "timelinePath": [
+///       { "point": "geo:48.117300,11.516700", "durationMinutesOffsetFromStartTime": "5" }
+///     ]
+///   },
+///   {
+///     "startTime": "2024-06-01T10:00:00.000+02:00",
+///     "endTime": "2024-06-01T12:00:00.000+02:00",
+///     "visit": {
+///       "hierarchyLevel": "0",
+///       "probability": "0.9",
+///       "topCandidate": {
+///         "placeID": "ChIJ2V-Mo_l1nkcRfZixfUq4DAE",
+///         "semanticType": "Home",
+///         "probability": "0.8",
+///         "placeLocation": "geo:48.137200,11.575600"
+///       }
+///     }
+///   },
+///   {
+///     "startTime": "2024-06-01T12:00:00.000+02:00",
+///     "endTime": "2024-06-01T12:30:00.000+02:00",
+///     "activity": {
+///       "start": "geo:48.137200,11.575600",
+///       "end": "geo:48.117300,11.516700",
+///       "distanceMeters": "4500.0",
+///       "topCandidate": { "type": "in passenger vehicle", "probability": "0.7" }
+///     }
+///   }
+/// ]
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RawTimeline {
+    #[serde(default)]
+    semantic_segments: Vec<RawSegment>,
+    #[serde(default)]
+    raw_signals: Vec<RawSignal>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RawSegment {
+    start_time: DateTime<FixedOffset>,
+    end_time: DateTime<FixedOffset>,
+    timeline_path: Option<Vec<RawPathPoint>>,
+    visit: Option<RawVisit>,
+    activity: Option<RawActivity>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RawPathPoint {
+    point: String,
+    time: DateTime<FixedOffset>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RawVisit {
+    #[serde(default)]
+    hierarchy_level: u32,
+    top_candidate: RawVisitCandidate,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RawVisitCandidate {
+    place_id: Option<String>,
+    semantic_type: Option<String>,
+    probability: Option<f32>,
+    place_location: RawLatLng,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RawActivity {
+    start: RawLatLng,
+    end: RawLatLng,
+    distance_meters: Option<f32>,
+    top_candidate: Option<RawActivityCandidate>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RawActivityCandidate {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    probability: Option<f32>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RawLatLng {
+    lat_lng: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RawSignal {
+    position: Option<RawPosition>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RawPosition {
+    #[serde(rename = "LatLng")]
+    lat_lng: String,
+    accuracy_meters: Option<f32>,
+    altitude_meters: Option<f64>,
+    timestamp: DateTime<FixedOffset>,
+}
+
+/// A number in an iOS export, which is usually written as a string.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum IosNumber {
+    Number(f64),
+    Text(String),
+}
+
+impl IosNumber {
+    /// The number, or None if the string is not one.
+    fn value(&self) -> Option<f64> {
+        match self {
+            IosNumber::Number(n) => Some(*n),
+            IosNumber::Text(s) => s.trim().parse().ok(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct IosSegment {
+    start_time: DateTime<FixedOffset>,
+    end_time: DateTime<FixedOffset>,
+    timeline_path: Option<Vec<IosPathPoint>>,
+    visit: Option<IosVisit>,
+    activity: Option<IosActivity>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct IosPathPoint {
+    point: String,
+    duration_minutes_offset_from_start_time: IosNumber,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct IosVisit {
+    hierarchy_level: Option<IosNumber>,
+    top_candidate: IosVisitCandidate,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct IosVisitCandidate {
+    #[serde(rename = "placeID")]
+    place_id: Option<String>,
+    semantic_type: Option<String>,
+    probability: Option<IosNumber>,
+    place_location: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct IosActivity {
+    start: String,
+    end: String,
+    distance_meters: Option<IosNumber>,
+    top_candidate: Option<IosActivityCandidate>,
+}
+
+#[derive(Deserialize, Debug)]
+struct IosActivityCandidate {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    probability: Option<IosNumber>,
+}
+
+impl From<IosSegment> for RawSegment {
+    /// Convert an iOS segment to the layout of the Android export, which has the same contents.
+    fn from(ios: IosSegment) -> Self {
+        let start_time = ios.start_time;
+        let lat_lng = |lat_lng: String| RawLatLng { lat_lng };
+        let number = |n: Option<IosNumber>| n.and_then(|n| n.value());
+        RawSegment {
+            start_time,
+            end_time: ios.end_time,
+            timeline_path: ios.timeline_path.map(|path| {
+                path.into_iter()
+                    .map(|point| {
+                        let minutes = point
+                            .duration_minutes_offset_from_start_time
+                            .value()
+                            .unwrap_or(0.0);
+                        RawPathPoint {
+                            point: point.point,
+                            time: start_time
+                                + chrono::Duration::seconds((minutes * 60.0).round() as i64),
+                        }
+                    })
+                    .collect()
+            }),
+            visit: ios.visit.map(|visit| RawVisit {
+                hierarchy_level: number(visit.hierarchy_level).unwrap_or(0.0) as u32,
+                top_candidate: RawVisitCandidate {
+                    place_id: visit.top_candidate.place_id,
+                    semantic_type: visit.top_candidate.semantic_type,
+                    probability: number(visit.top_candidate.probability).map(|p| p as f32),
+                    place_location: lat_lng(visit.top_candidate.place_location),
+                },
+            }),
+            activity: ios.activity.map(|activity| RawActivity {
+                start: lat_lng(activity.start),
+                end: lat_lng(activity.end),
+                distance_meters: number(activity.distance_meters).map(|d| d as f32),
+                top_candidate: activity
+                    .top_candidate
+                    .map(|candidate| RawActivityCandidate {
+                        kind: candidate.kind,
+                        probability: number(candidate.probability).map(|p| p as f32),
+                    }),
+            }),
+        }
+    }
+}
+
+/// Contents of a Timeline.json file, converted to crataegus types.
+#[derive(Debug)]
+pub struct Timeline {
+    /// Path points and raw positions, in ascending order of time.
+    pub locations: Vec<Location>,
+    /// Visits and activities, in the order they appear in the file.
+    pub segments: Vec<Segment>,
+}
+
+/// Parse a position string. Android exports use `48.1173000°, 11.5167000°`, while iOS exports
+/// use `geo:48.117300,11.516700`.
+/// # Arguments
+/// * `s` - The position string.
+/// # Return
+/// The latitude and longitude in decimal degrees.
+fn parse_lat_lng(s: &str) -> Result<(f64, f64)> {
+    let parse = |part: &str| {
+        part.trim()
+            .trim_end_matches('°')
+            .parse::<f64>()
+            .wrap_err(format!("Invalid position: {}", s))
+    };
+    let (latitude, longitude) = s
+        .trim_start_matches("geo:")
+        .split_once(',')
+        .ok_or_else(|| eyre!("Invalid position: {}", s))?;
+    Ok((parse(latitude)?, parse(longitude)?))
+}
+
+/// Build a location with the parts that Timeline.json provides.
+fn make_location(
+    username: &str,
+    time: DateTime<FixedOffset>,
+    lat_lng: &str,
+    altitude: Option<f64>,
+    accuracy: Option<f32>,
+) -> Result<Location> {
+    let (latitude, longitude) = parse_lat_lng(lat_lng)?;
+    Ok(Location {
+        username: username.to_string(),
+        time_utc: time.to_utc(),
+        time_local: time,
+        latitude,
+        longitude,
+        altitude: altitude.unwrap_or(0.0),
+        accuracy,
+        source: Source::GoogleTimeline,
+        speed: None,
+        bearing: None,
+        satellites: None,
+        provider: None,
+        hdop: None,
+        vdop: None,
+        pdop: None,
+        battery: None,
+        charging: None,
+        device: String::new(),
+    })
+}
+
+/// Read a Timeline.json file. The file is loaded into memory, which is fine for the sizes Google
+/// produces for on-device exports.
+/// # Arguments
+/// * `path` - The path to the Timeline.json file.
+/// * `username` - The username to associate with the locations and segments.
+/// # Return
+/// The locations and segments in the file.
+pub fn read_timeline(path: &Path, username: &str) -> Result<Timeline> {
+    let file = File::open(path).map_err(|e| eyre!("Failed to open Timeline.json file: {}", e))?;
+    let value: serde_json::Value = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| eyre!("Failed to parse Timeline.json: {}", e))?;
+    // iOS exports are a bare array of segments
+    let raw = if value.is_array() {
+        let ios: Vec<IosSegment> = serde_json::from_value(value)
+            .map_err(|e| eyre!("Failed to parse iOS Timeline.json: {}", e))?;
+        RawTimeline {
+            semantic_segments: ios.into_iter().map(RawSegment::from).collect(),
+            raw_signals: Vec::new(),
+        }
+    } else {
+        serde_json::from_value::<RawTimeline>(value)
+            .map_err(|e| eyre!("Failed to parse Timeline.json: {}", e))?
+    };
+
+    let mut locations: BTreeMap<DateTime<Utc>, Location> = BTreeMap::new();
+    let mut segments = Vec::new();
+    for raw_segment in raw.semantic_segments {
+        for point in raw_segment.timeline_path.unwrap_or_default() {
+            let location = make_location(username, point.time, &point.point, None, None)?;
+            locations.insert(location.time_utc, location);
+        }
+        let mut seg = Segment {
+            username: username.to_string(),
+            start_utc: raw_segment.start_time.to_utc(),
+            end_utc: raw_segment.end_time.to_utc(),
+            start_local: raw_segment.start_time,
+            end_local: raw_segment.end_time,
+            kind: segment::Kind::Visit,
+            latitude: 0.0,
+            longitude: 0.0,
+            end_latitude: None,
+            end_longitude: None,
+            place_id: None,
+            semantic_type: None,
+            probability: None,
+            distance: None,
+        };
+        if let Some(visit) = raw_segment.visit {
+            if visit.hierarchy_level > 0 {
+                continue;
+            }
+            let candidate = visit.top_candidate;
+            (seg.latitude, seg.longitude) = parse_lat_lng(&candidate.place_location.lat_lng)?;
+            seg.place_id = candidate.place_id;
+            seg.semantic_type = candidate.semantic_type;
+            seg.probability = candidate.probability;
+            segments.push(seg);
+        } else if let Some(activity) = raw_segment.activity {
+            let (end_latitude, end_longitude) = parse_lat_lng(&activity.end.lat_lng)?;
+            seg.kind = segment::Kind::Activity;
+            (seg.latitude, seg.longitude) = parse_lat_lng(&activity.start.lat_lng)?;
+            seg.end_latitude = Some(end_latitude);
+            seg.end_longitude = Some(end_longitude);
+            seg.distance = activity.distance_meters;
+            if let Some(candidate) = activity.top_candidate {
+                seg.semantic_type = candidate.kind;
+                seg.probability = candidate.probability;
+            }
+            segments.push(seg);
+        }
+    }
+    // raw signals are inserted last so they replace path points at the same time
+    for position in raw
+        .raw_signals
+        .into_iter()
+        .filter_map(|signal| signal.position)
+    {
+        let location = make_location(
+            username,
+            position.timestamp,
+            &position.lat_lng,
+            position.altitude_meters,
+            position.accuracy_meters,
+        )?;
+        locations.insert(location.time_utc, location);
+    }
+    Ok(Timeline {
+        locations: locations.into_values().collect(),
+        segments,
+    })
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    static TIMELINE_DATA: &str = r#"{
+  "semanticSegments": [
+    {
+      "startTime": "2024-06-01T08:00:00.000+02:00",
+      "endTime": "2024-06-01T10:00:00.000+02:00",
+      "startTimeTimezoneUtcOffsetMinutes": 120,
+      "endTimeTimezoneUtcOffsetMinutes": 120,
+      "timelinePath": [
+        { "point": "48.1173000°, 11.5167000°", "time": "2024-06-01T08:05:00.000+02:00" },
+        { "point": "48.1175000°, 11.5166000°", "time": "2024-06-01T08:06:00.000+02:00" }
+      ]
+    },
+    {
+      "startTime": "2024-06-01T10:00:00.000+02:00",
+      "endTime": "2024-06-01T12:00:00.000+02:00",
+      "visit": {
+        "hierarchyLevel": 0,
+        "probability": 0.9,
+        "topCandidate": {
+          "placeId": "ChIJ2V-Mo_l1nkcRfZixfUq4DAE",
+          "semanticType": "HOME",
+          "probability": 0.8,
+          "placeLocation": { "latLng": "48.1372000°, 11.5756000°" }
+        }
+      }
+    },
+    {
+      "startTime": "2024-06-01T10:00:00.000+02:00",
+      "endTime": "2024-06-01T12:00:00.000+02:00",
+      "visit": {
+        "hierarchyLevel": 1,
+        "topCandidate": { "placeLocation": { "latLng": "48.0°, 11.0°" } }
+      }
+    },
+    {
+      "startTime": "2024-06-01T12:00:00.000+02:00",
+      "endTime": "2024-06-01T12:30:00.000+02:00",
+      "activity": {
+        "start": { "latLng": "48.1372000°, 11.5756000°" },
+        "end": { "latLng": "48.1173000°, 11.5167000°" },
+        "distanceMeters": 4500.0,
+        "topCandidate": { "type": "IN_PASSENGER_VEHICLE", "probability": 0.7 }
+      }
+    },
+    {
+      "startTime": "2024-06-01T00:00:00.000+02:00",
+      "endTime": "2024-06-02T00:00:00.000+02:00",
+      "timelineMemory": { "trip": {} }
+    }
+  ],
+  "rawSignals": [
+    {
+      "position": {
+        "LatLng": "48.1173100°, 11.5167100°",
+        "accuracyMeters": 13,
+        "altitudeMeters": 545.4,
+        "source": "WIFI",
+        "timestamp": "2024-06-01T08:05:00.000+02:00",
+        "speedMetersPerSecond": 0.0
+      }
+    },
+    { "wifiScan": { "deliveryTime": "2024-06-01T08:05:00.000+02:00" } }
+  ],
+  "userLocationProfile": {}
+}"#;
+
+    /// The same segments, as exported by the iOS app.
+    static IOS_TIMELINE_DATA: &str = r#"[
+  {
+    "endTime": "2024-06-01T10:00:00.000+02:00",
+    "startTime": "2024-06-01T08:00:00.000+02:00",
+    "timelinePath": [
+      { "point": "geo:48.117300,11.516700", "durationMinutesOffsetFromStartTime": "5" },
+      { "point": "geo:48.117500,11.516600", "durationMinutesOffsetFromStartTime": "6" }
+    ]
+  },
+  {
+    "endTime": "2024-06-01T12:00:00.000+02:00",
+    "startTime": "2024-06-01T10:00:00.000+02:00",
+    "visit": {
+      "hierarchyLevel": "0",
+      "topCandidate": {
+        "probability": "0.8",
+        "semanticType": "Home",
+        "placeID": "ChIJ2V-Mo_l1nkcRfZixfUq4DAE",
+        "placeLocation": "geo:48.137200,11.575600"
+      },
+      "probability": "0.9"
+    }
+  },
+  {
+    "endTime": "2024-06-01T12:00:00.000+02:00",
+    "startTime": "2024-06-01T10:00:00.000+02:00",
+    "visit": {
+      "hierarchyLevel": "1",
+      "topCandidate": { "placeLocation": "geo:48.0,11.0" }
+    }
+  },
+  {
+    "endTime": "2024-06-01T12:30:00.000+02:00",
+    "startTime": "2024-06-01T12:00:00.000+02:00",
+    "activity": {
+      "probability": "0.7",
+      "end": "geo:48.117300,11.516700",
+      "topCandidate": { "type": "in passenger vehicle", "probability": "0.7" },
+      "distanceMeters": "4500.0",
+      "start": "geo:48.137200,11.575600"
+    }
+  }
+]"#;
+
+    static USERNAME: &str = "test_user";
+
+    #[test]
+    fn test_parse_lat_lng() {
+        assert_eq!(
+            parse_lat_lng("48.1173000°, 11.5167000°").unwrap(),
+            (48.1173, 11.5167)
+        );
+        assert_eq!(
+            parse_lat_lng("geo:48.117300,-11.516700").unwrap(),
+            (48.1173, -11.5167)
+        );
+        assert!(parse_lat_lng("48.1173000°").is_err());
+        assert!(parse_lat_lng("north, east").is_err());
+    }
+
+    #[test]
+    fn test_read_timeline() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(TIMELINE_DATA.as_bytes()).unwrap();
+        let timeline = read_timeline(file.path(), USERNAME).unwrap();
+
+        assert_eq!(timeline.locations.len(), 2);
+        // the raw signal replaced the path point at the same time
+        let location = &timeline.locations[0];
+        assert_eq!(location.username, USERNAME);
+        assert_eq!(location.time_utc.to_rfc3339(), "2024-06-01T06:05:00+00:00");
+        assert_eq!(
+            location.time_local.to_rfc3339(),
+            "2024-06-01T08:05:00+02:00"
+        );
+        assert_eq!(location.latitude, 48.11731);
+        assert_eq!(location.longitude, 11.51671);
+        assert_eq!(location.altitude, 545.4);
+        assert_eq!(location.accuracy, Some(13.0));
+        assert_eq!(location.source, Source::GoogleTimeline);
+        let location = &timeline.locations[1];
+        assert_eq!(location.latitude, 48.1175);
+        assert_eq!(location.altitude, 0.0);
+        assert_eq!(location.accuracy, None);
+
+        assert_eq!(timeline.segments.len(), 2);
+        let visit = &timeline.segments[0];
+        assert_eq!(visit.kind, segment::Kind::Visit);
+        assert_eq!(visit.start_utc.to_rfc3339(), "2024-06-01T08:00:00+00:00");
+        assert_eq!(visit.end_utc.to_rfc3339(), "2024-06-01T10:00:00+00:00");
+        assert_eq!((visit.latitude, visit.longitude), (48.1372, 11.5756));
+        assert_eq!(visit.end_latitude, None);
+        assert_eq!(
+            visit.place_id.as_deref(),
+            Some("ChIJ2V-Mo_l1nkcRfZixfUq4DAE")
+        );
+        assert_eq!(visit.semantic_type.as_deref(), Some("HOME"));
+        assert_eq!(visit.probability, Some(0.8));
+        let activity = &timeline.segments[1];
+        assert_eq!(activity.kind, segment::Kind::Activity);
+        assert_eq!((activity.latitude, activity.longitude), (48.1372, 11.5756));
+        assert_eq!(activity.end_latitude, Some(48.1173));
+        assert_eq!(activity.end_longitude, Some(11.5167));
+        assert_eq!(
+            activity.semantic_type.as_deref(),
+            Some("IN_PASSENGER_VEHICLE")
+        );
+        assert_eq!(activity.distance, Some(4500.0));
+    }
+
+    #[test]
+    fn test_read_ios_timeline() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(IOS_TIMELINE_DATA.as_bytes()).unwrap();
+        let timeline = read_timeline(file.path(), USERNAME).unwrap();
+
+        assert_eq!(timeline.locations.len(), 2);
+        let location = &timeline.locations[0];
+        assert_eq!(location.username, USERNAME);
+        assert_eq!(location.time_utc.to_rfc3339(), "2024-06-01T06:05:00+00:00");
+        assert_eq!(
+            location.time_local.to_rfc3339(),
+            "2024-06-01T08:05:00+02:00"
+        );
+        assert_eq!((location.latitude, location.longitude), (48.1173, 11.5167));
+        assert_eq!(location.accuracy, None);
+        assert_eq!(location.source, Source::GoogleTimeline);
+        assert_eq!(
+            timeline.locations[1].time_utc.to_rfc3339(),
+            "2024-06-01T06:06:00+00:00"
+        );
+
+        assert_eq!(timeline.segments.len(), 2);
+        let visit = &timeline.segments[0];
+        assert_eq!(visit.kind, segment::Kind::Visit);
+        assert_eq!((visit.latitude, visit.longitude), (48.1372, 11.5756));
+        assert_eq!(
+            visit.place_id.as_deref(),
+            Some("ChIJ2V-Mo_l1nkcRfZixfUq4DAE")
+        );
+        assert_eq!(visit.semantic_type.as_deref(), Some("Home"));
+        assert_eq!(visit.probability, Some(0.8));
+        let activity = &timeline.segments[1];
+        assert_eq!(activity.kind, segment::Kind::Activity);
+        assert_eq!(activity.end_latitude, Some(48.1173));
+        assert_eq!(
+            activity.semantic_type.as_deref(),
+            Some("in passenger vehicle")
+        );
+        assert_eq!(activity.distance, Some(4500.0));
+    }
+}
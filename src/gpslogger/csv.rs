@@ -28,7 +28,14 @@ use crate::schema::{Location, LocationGen, Source};
 /// - latitude: lat
 /// - longitude: lon
 /// - altitude: elevation
-/// - accuracy: accuracy    
+/// - accuracy: accuracy
+/// - speed: speed
+/// - bearing: bearing
+/// - satellites: satellites
+/// - provider: provider
+/// - hdop, vdop, pdop: hdop, vdop, pdop
+/// - battery: battery
+/// - charging: battery_charging
 ///
 #[derive(Deserialize, Debug)]
 struct Payload {
@@ -51,32 +58,25 @@ struct Payload {
     /// Direction of travel in degrees. Unclear whether this is north-referenced.
     /// Example: 45.0
     #[serde(deserialize_with = "deserialize_option_f64")]
-    #[allow(dead_code)]
     bearing: Option<f64>,
-    /// Speed in km/h.
+    /// Speed in meters per second.
     /// Example: 2.4
     #[serde(deserialize_with = "deserialize_option_f64")]
-    #[allow(dead_code)]
     speed: Option<f64>,
     /// Number of satellites used to determine location.
     /// Example: 4
-    #[allow(dead_code)]
     satellites: u32,
     /// Source of the location data. Known possible values are:
     /// - gps
-    #[allow(dead_code)]
     provider: String,
     /// Horizontal dilution of precision.
     #[serde(deserialize_with = "deserialize_option_f64")]
-    #[allow(dead_code)]
     hdop: Option<f64>,
     /// Vertical dilution of precision.
     #[serde(deserialize_with = "deserialize_option_f64")]
-    #[allow(dead_code)]
     vdop: Option<f64>,
     /// Position dilution of precision.
     #[serde(deserialize_with = "deserialize_option_f64")]
-    #[allow(dead_code)]
     pdop: Option<f64>,
     /// Height of geoid above WGS84 ellipsoid.
     #[serde(deserialize_with = "deserialize_option_f64")]
@@ -95,7 +95,6 @@ struct Payload {
     #[allow(dead_code)]
     activity: Option<String>,
     /// Battery level as a percentage.
-    #[allow(dead_code)]
     battery: u32,
     /// Annotation.
     #[serde(deserialize_with = "deserialize_option_string")]
@@ -122,7 +121,6 @@ struct Payload {
     #[allow(dead_code)]
     profile_name: String,
     /// Whether the battery is charging.
-    #[allow(dead_code)]
     battery_charging: bool,
 }
 
@@ -137,6 +135,16 @@ impl LocationGen for Payload {
             altitude: self.elevation,
             accuracy: Some(self.accuracy as f32),
             source: Source::GpsLogger,
+            speed: self.speed.map(|speed| speed as f32),
+            bearing: self.bearing.map(|bearing| bearing as f32),
+            satellites: Some(self.satellites as i32),
+            provider: Some(self.provider.clone()).filter(|provider| !provider.is_empty()),
+            hdop: self.hdop.map(|hdop| hdop as f32),
+            vdop: self.vdop.map(|vdop| vdop as f32),
+            pdop: self.pdop.map(|pdop| pdop as f32),
+            battery: Some(self.battery as f32),
+            charging: Some(self.battery_charging),
+            device: String::new(),
         }
     }
 }
@@ -210,6 +218,16 @@ mod tests {
         assert_eq!(locations[0].as_ref().unwrap().accuracy, Some(48.0));
         assert_eq!(locations[0].as_ref().unwrap().source, Source::GpsLogger);
         assert_eq!(locations[0].as_ref().unwrap().username, USERNAME);
+        assert_eq!(locations[0].as_ref().unwrap().speed, Some(0.0));
+        assert_eq!(locations[0].as_ref().unwrap().bearing, None);
+        assert_eq!(locations[0].as_ref().unwrap().satellites, Some(0));
+        assert_eq!(
+            locations[0].as_ref().unwrap().provider.as_deref(),
+            Some("gps")
+        );
+        assert_eq!(locations[0].as_ref().unwrap().hdop, None);
+        assert_eq!(locations[0].as_ref().unwrap().battery, Some(64.0));
+        assert_eq!(locations[0].as_ref().unwrap().charging, Some(false));
 
         assert_eq!(
             locations[5].as_ref().unwrap().time_utc.to_rfc3339(),
@@ -225,5 +243,7 @@ mod tests {
         assert_eq!(locations[5].as_ref().unwrap().accuracy, Some(7.7476687));
         assert_eq!(locations[5].as_ref().unwrap().source, Source::GpsLogger);
         assert_eq!(locations[5].as_ref().unwrap().username, USERNAME);
+        assert_eq!(locations[5].as_ref().unwrap().speed, None);
+        assert_eq!(locations[5].as_ref().unwrap().battery, Some(60.0));
     }
 }
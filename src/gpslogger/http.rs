@@ -5,7 +5,7 @@ use crate::gpslogger::deserializers::{
     deserialize_date_from_str, deserialize_date_time_fixed_offset_from_str,
     deserialize_date_time_utc_from_sec, deserialize_date_time_utc_from_str, deserialize_option_f32,
 };
-use crate::schema::{Location, LocationGen, Source};
+use crate::schema::{Device, Location, LocationGen, Source};
 
 /// # HTTP
 /// The body of the HTTP message is specified by a template that is configured in the GpsLogger app.
@@ -99,7 +99,6 @@ pub struct Payload {
     pub lon: f64,
     /// Number of satellites in use/visible (unclear).
     /// Example: `0`.
-    #[allow(dead_code)]
     sat: u8,
     /// Description of the data collection event to which this data belongs.
     /// Example: `""`, `"Hiking"`.
@@ -116,19 +115,16 @@ pub struct Payload {
     /// presumably direction of travel (angle of velocity vector), but may be the fused estimate of
     /// phone orientation.
     /// Example: `170.8125`.
-    #[allow(dead_code)]
     dir: f32,
     /// Provider of the location data. Known possible values are:
     /// - `"gps"`: GPS location data
-    #[allow(dead_code)]
     prov: String,
     /// Speed in kilometers per hour.
     /// Example: `0.0`.
     #[allow(dead_code)]
     spd_kph: f32,
-    /// Speed in (meters per second?).
+    /// Speed in meters per second.
     /// Example: `0.0`.
-    #[allow(dead_code)]
     spd: f32,
     /// Unix timestamp of the data, second-precision.
     /// Example: `1736999691`.
@@ -155,19 +151,15 @@ pub struct Payload {
     date: NaiveDate,
     /// Battery percentage.
     /// Example: `27.0`.
-    #[allow(dead_code)]
     batt: f32,
     /// Whether the device is charging.
     /// Example: `false`.
-    #[allow(dead_code)]
     ischarging: bool,
     /// Android ID
     /// Example: `4ca9e1da592aca9b`.
-    #[allow(dead_code)]
     aid: String,
     /// Serial number
     /// Example: `4ca9e1da592aca9b`.
-    #[allow(dead_code)]
     ser: String,
     /// File name of the data collection event on the phone.
     /// Example: `20250115`.
@@ -175,22 +167,18 @@ pub struct Payload {
     filename: String,
     /// Profile name of the data collection event on the phone.
     /// Example: `Default Profile`.
-    #[allow(dead_code)]
     profile: String,
     /// Horizontal dilution of precision. May not be present.
     /// Example: ``, `1.0`.
     #[serde(deserialize_with = "deserialize_option_f32")]
-    #[allow(dead_code)]
     hdop: Option<f32>,
     /// Vertical dilution of precision. May not be present.
     /// Example: ``, `1.0`.
     #[serde(deserialize_with = "deserialize_option_f32")]
-    #[allow(dead_code)]
     vdop: Option<f32>,
     /// Position dilution of precision. May not be present.
     /// Example: ``, `1.0`.
     #[serde(deserialize_with = "deserialize_option_f32")]
-    #[allow(dead_code)]
     pdop: Option<f32>,
     /// Distance traveled. Unclear whether this is distance from last data point, distance from
     /// last sent point, or distance since start of data collection event.
@@ -199,6 +187,31 @@ pub struct Payload {
     dist: f32,
 }
 
+impl Payload {
+    /// The id the device is known by: its Android ID, or its serial number if the ID is empty.
+    fn device_id(&self) -> &str {
+        match self.aid.is_empty() {
+            true => &self.ser,
+            false => &self.aid,
+        }
+    }
+
+    /// Describe the device that sent the payload, to register it with the user.
+    /// # Arguments
+    /// * `username` - The username of the device's owner.
+    /// # Return
+    /// The device, or None if it sent neither an Android ID nor a serial number.
+    pub fn to_device(&self, username: &str) -> Option<Device> {
+        let non_empty = |value: &str| Some(value.to_string()).filter(|value| !value.is_empty());
+        Some(Device {
+            username: username.to_string(),
+            id: non_empty(self.device_id())?,
+            serial: non_empty(&self.ser),
+            profile: non_empty(&self.profile),
+        })
+    }
+}
+
 impl LocationGen for Payload {
     /// Convert the Payload struct to a Location struct.
     /// # Arguments
@@ -215,6 +228,16 @@ impl LocationGen for Payload {
             altitude: self.alt,
             accuracy: Some(self.acc),
             source: Source::GpsLogger,
+            speed: Some(self.spd),
+            bearing: Some(self.dir),
+            satellites: Some(self.sat as i32),
+            provider: Some(self.prov.clone()).filter(|prov| !prov.is_empty()),
+            hdop: self.hdop,
+            vdop: self.vdop,
+            pdop: self.pdop,
+            battery: Some(self.batt),
+            charging: Some(self.ischarging),
+            device: self.device_id().to_string(),
         }
     }
 }
@@ -279,5 +302,40 @@ mod tests {
         assert_eq!(location.altitude, payload.alt);
         assert_eq!(location.accuracy, Some(payload.acc));
         assert_eq!(location.source, Source::GpsLogger);
+        assert_eq!(location.speed, Some(0.0));
+        assert_eq!(location.bearing, Some(170.8125));
+        assert_eq!(location.satellites, Some(0));
+        assert_eq!(location.provider.as_deref(), Some("gps"));
+        assert_eq!(location.hdop, None);
+        assert_eq!(location.battery, Some(27.0));
+        assert_eq!(location.charging, Some(false));
+        assert_eq!(location.device, "4ca9e1da592aca9b");
+    }
+
+    /// The Android ID identifies the device, falling back to the serial number.
+    #[test]
+    fn test_to_device() {
+        let payload: Payload = serde_urlencoded::from_str(BODY_STR).unwrap();
+        let device = payload.to_device("testuser").unwrap();
+        assert_eq!(device.username, "testuser");
+        assert_eq!(device.id, "4ca9e1da592aca9b");
+        assert_eq!(device.serial.as_deref(), Some("4ca9e1da592aca9b"));
+        assert_eq!(device.profile.as_deref(), Some("Default Profile"));
+
+        let body = BODY_STR.replace("aid=4ca9e1da592aca9b", "aid=");
+        let payload: Payload = serde_urlencoded::from_str(&body).unwrap();
+        assert_eq!(
+            payload.to_device("testuser").unwrap().id,
+            "4ca9e1da592aca9b"
+        );
+        assert_eq!(
+            LocationGen::to_location(&payload, "testuser").device,
+            "4ca9e1da592aca9b"
+        );
+
+        let body = body.replace("ser=4ca9e1da592aca9b", "ser=");
+        let payload: Payload = serde_urlencoded::from_str(&body).unwrap();
+        assert_eq!(payload.to_device("testuser"), None);
+        assert_eq!(LocationGen::to_location(&payload, "testuser").device, "");
     }
 }
@@ -0,0 +1,364 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
+use color_eyre::eyre::{eyre, Result};
+use exif::{Exif, In, Reader, Tag, Value};
+use log::{debug, warn};
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use crate::schema::{Location, LocationGen, Source};
+
+/// File extensions, in lower case, of the image containers that carry EXIF metadata.
+const EXTENSIONS: [&str; 6] = ["jpg", "jpeg", "heic", "heif", "tif", "tiff"];
+
+/// The geotag of a single photo.
+#[derive(Debug, PartialEq)]
+struct Geotag {
+    latitude: f64,
+    longitude: f64,
+    altitude: Option<f64>,
+    accuracy: Option<f32>,
+    time: DateTime<FixedOffset>,
+}
+
+impl LocationGen for Geotag {
+    /// Convert the geotag to a Location struct. A missing altitude is recorded as 0.
+    fn to_location(&self, username: &str) -> Location {
+        Location {
+            username: username.to_string(),
+            time_utc: self.time.to_utc(),
+            time_local: self.time,
+            latitude: self.latitude,
+            longitude: self.longitude,
+            altitude: self.altitude.unwrap_or(0.0),
+            accuracy: self.accuracy,
+            source: Source::Exif,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        }
+    }
+}
+
+/// Get the rational values of a field, converted to floats.
+fn rationals(exif: &Exif, tag: Tag) -> Option<Vec<f64>> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) => Some(values.iter().map(|value| value.to_f64()).collect()),
+        _ => None,
+    }
+}
+
+/// Get the first string of an ASCII field.
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|value| String::from_utf8_lossy(value).trim().to_string()),
+        _ => None,
+    }
+}
+
+/// Read a latitude or longitude stored as degrees, minutes and seconds, with its N/S or E/W
+/// reference.
+fn coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative: &str) -> Option<f64> {
+    let dms = rationals(exif, tag)?;
+    let degrees =
+        dms.first()? + dms.get(1).unwrap_or(&0.0) / 60.0 + dms.get(2).unwrap_or(&0.0) / 3600.0;
+    match ascii(exif, ref_tag) {
+        Some(reference) if reference.eq_ignore_ascii_case(negative) => Some(-degrees),
+        _ => Some(degrees),
+    }
+}
+
+/// Read the altitude, which is stored as a magnitude plus a byte that is 1 below sea level.
+fn altitude(exif: &Exif) -> Option<f64> {
+    let altitude = *rationals(exif, Tag::GPSAltitude)?.first()?;
+    match exif
+        .get_field(Tag::GPSAltitudeRef, In::PRIMARY)
+        .map(|f| &f.value)
+    {
+        Some(Value::Byte(bytes)) if bytes.first() == Some(&1) => Some(-altitude),
+        _ => Some(altitude),
+    }
+}
+
+/// Determine when the photo was taken. `DateTimeOriginal` is the local time on the camera, so it is
+/// only used when an `OffsetTimeOriginal` (or `OffsetTime`) says which zone that was. Otherwise the
+/// GPS date and time, which are always UTC, are used.
+fn time(exif: &Exif) -> Option<DateTime<FixedOffset>> {
+    let original = ascii(exif, Tag::DateTimeOriginal)
+        .and_then(|s| NaiveDateTime::parse_from_str(&s, "%Y:%m:%d %H:%M:%S").ok());
+    let offset = ascii(exif, Tag::OffsetTimeOriginal)
+        .or_else(|| ascii(exif, Tag::OffsetTime))
+        .and_then(|s| {
+            DateTime::parse_from_str(
+                &format!("2000-01-01 00:00:00 {}", s),
+                "%Y-%m-%d %H:%M:%S %:z",
+            )
+            .ok()
+        })
+        .map(|time| *time.offset());
+    if let (Some(original), Some(offset)) = (original, offset) {
+        return original.and_local_timezone(offset).single();
+    }
+    let date = ascii(exif, Tag::GPSDateStamp)
+        .and_then(|s| NaiveDate::parse_from_str(&s, "%Y:%m:%d").ok())?;
+    let hms = rationals(exif, Tag::GPSTimeStamp)?;
+    let seconds =
+        hms.first()? * 3600.0 + hms.get(1).unwrap_or(&0.0) * 60.0 + hms.get(2).unwrap_or(&0.0);
+    let time = NaiveTime::from_num_seconds_from_midnight_opt(
+        seconds.trunc() as u32,
+        (seconds.fract() * 1e9) as u32,
+    )?;
+    Some(date.and_time(time).and_utc().fixed_offset())
+}
+
+/// Read the geotag from parsed EXIF metadata.
+/// # Arguments
+/// * `exif` - The metadata of one photo.
+/// # Return
+/// The geotag, or None if the photo has no position or no usable time.
+fn geotag(exif: &Exif) -> Option<Geotag> {
+    Some(Geotag {
+        latitude: coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")?,
+        longitude: coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")?,
+        altitude: altitude(exif),
+        accuracy: rationals(exif, Tag::GPSHPositioningError)
+            .and_then(|values| values.first().map(|value| *value as f32)),
+        time: time(exif)?,
+    })
+}
+
+/// Read the geotag of a single image file.
+fn read_file(path: &Path) -> Result<Option<Geotag>> {
+    let file = File::open(path).map_err(|e| eyre!("Failed to open {}: {}", path.display(), e))?;
+    match Reader::new().read_from_container(&mut BufReader::new(file)) {
+        Ok(exif) => Ok(geotag(&exif)),
+        Err(exif::Error::NotFound(_)) => Ok(None),
+        Err(e) => Err(eyre!("Failed to read EXIF from {}: {}", path.display(), e)),
+    }
+}
+
+/// Whether a path looks like an image that may carry EXIF metadata.
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+/// Find every image below a directory, in a stable order. Symbolic links to images are followed,
+/// but not those to directories, which may form a cycle.
+fn find_images(path: &Path, images: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_file() {
+        if is_image(path) {
+            images.push(path.to_path_buf());
+        }
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(path)
+        .map_err(|e| eyre!("Failed to read directory {}: {}", path.display(), e))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()
+        .map_err(|e| eyre!("Failed to read directory {}: {}", path.display(), e))?;
+    entries.sort();
+    for entry in entries {
+        let metadata = std::fs::symlink_metadata(&entry)
+            .map_err(|e| eyre!("Failed to read metadata of {}: {}", entry.display(), e))?;
+        if metadata.file_type().is_symlink() && !entry.is_file() {
+            debug!("Skipping symbolic link {}", entry.display());
+            continue;
+        }
+        find_images(&entry, images)?;
+    }
+    Ok(())
+}
+
+/// Walk a directory of JPEG, HEIC and TIFF photos and return an iterator of the `Location`s they
+/// were taken at. Photos without a GPS position or time are skipped, as are files whose metadata
+/// cannot be read, since one damaged photo should not stop a whole library from being imported.
+/// # Arguments
+/// * `path` - A directory to search recursively, or a single image.
+/// * `username` - The username to associate with the locations.
+/// # Return
+/// An iterator of `Location` structs, in path order.
+pub fn read_exif(path: &Path, username: &str) -> Result<impl Iterator<Item = Result<Location>>> {
+    let mut images = Vec::new();
+    find_images(path, &mut images)?;
+    debug!("Found {} images in {}", images.len(), path.display());
+    let username = username.to_string();
+    Ok(images
+        .into_iter()
+        .filter_map(move |image| match read_file(&image) {
+            Ok(Some(geotag)) => Some(Ok(geotag.to_location(&username))),
+            Ok(None) => {
+                debug!("No geotag in {}", image.display());
+                None
+            }
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        }))
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::{experimental::Writer, Field, Rational};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    static USERNAME: &str = "test_user";
+
+    fn ascii_field(tag: Tag, value: &str) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![value.as_bytes().to_vec()]),
+        }
+    }
+
+    fn rational_field(tag: Tag, values: &[(u32, u32)]) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Rational(
+                values
+                    .iter()
+                    .map(|&(num, denom)| Rational { num, denom })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Write a TIFF file holding only the given EXIF fields.
+    fn write_tiff(path: &Path, fields: &[Field]) {
+        let mut writer = Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut buf = Cursor::new(Vec::new());
+        writer.write(&mut buf, false).unwrap();
+        std::fs::write(path, buf.into_inner()).unwrap();
+    }
+
+    /// Position fields for 48°7'2.28"N 11°31'0.12"W, 545.5 m below sea level.
+    fn position_fields() -> Vec<Field> {
+        vec![
+            rational_field(Tag::GPSLatitude, &[(48, 1), (7, 1), (228, 100)]),
+            ascii_field(Tag::GPSLatitudeRef, "N"),
+            rational_field(Tag::GPSLongitude, &[(11, 1), (31, 1), (12, 100)]),
+            ascii_field(Tag::GPSLongitudeRef, "W"),
+            rational_field(Tag::GPSAltitude, &[(5455, 10)]),
+            Field {
+                tag: Tag::GPSAltitudeRef,
+                ifd_num: In::PRIMARY,
+                value: Value::Byte(vec![1]),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_read_exif() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("2023")).unwrap();
+
+        // Local time with an offset
+        let mut fields = position_fields();
+        fields.push(ascii_field(Tag::DateTimeOriginal, "2023:10:07 12:35:19"));
+        fields.push(ascii_field(Tag::OffsetTimeOriginal, "+02:00"));
+        fields.push(rational_field(Tag::GPSHPositioningError, &[(9, 2)]));
+        write_tiff(&dir.path().join("2023/a.TIF"), &fields);
+
+        // Local time without an offset falls back to GPS time
+        let mut fields = position_fields();
+        fields.push(ascii_field(Tag::DateTimeOriginal, "2023:10:07 12:35:29"));
+        fields.push(ascii_field(Tag::GPSDateStamp, "2023:10:07"));
+        fields.push(rational_field(
+            Tag::GPSTimeStamp,
+            &[(10, 1), (35, 1), (29, 1)],
+        ));
+        write_tiff(&dir.path().join("2023/b.tiff"), &fields);
+
+        // No time at all
+        write_tiff(&dir.path().join("c.tif"), &position_fields());
+        // No position
+        write_tiff(
+            &dir.path().join("d.tif"),
+            &[ascii_field(Tag::DateTimeOriginal, "2023:10:07 12:35:39")],
+        );
+        // Not an image, or not a readable one
+        std::fs::write(dir.path().join("e.txt"), "not an image").unwrap();
+        std::fs::write(dir.path().join("f.jpg"), "not an image").unwrap();
+        // A link back up the tree is not followed round in circles
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("2023/loop")).unwrap();
+
+        let locations = read_exif(dir.path(), USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap();
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[0].username, USERNAME);
+        assert_eq!(
+            locations[0].time_local.to_rfc3339(),
+            "2023-10-07T12:35:19+02:00"
+        );
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2023-10-07T10:35:19+00:00"
+        );
+        assert!((locations[0].latitude - 48.1173).abs() < 1e-9);
+        assert!((locations[0].longitude + 11.5167).abs() < 1e-9);
+        assert_eq!(locations[0].altitude, -545.5);
+        assert_eq!(locations[0].accuracy, Some(4.5));
+        assert_eq!(locations[0].source, Source::Exif);
+        assert_eq!(
+            locations[1].time_local.to_rfc3339(),
+            "2023-10-07T10:35:29+00:00"
+        );
+        assert_eq!(locations[1].accuracy, None);
+    }
+
+    #[test]
+    fn test_read_exif_single_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.tif");
+        let mut fields = position_fields();
+        fields.push(ascii_field(Tag::GPSDateStamp, "2023:10:07"));
+        fields.push(rational_field(
+            Tag::GPSTimeStamp,
+            &[(10, 1), (35, 1), (295, 10)],
+        ));
+        write_tiff(&path, &fields);
+        let locations = read_exif(&path, USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap();
+        assert_eq!(locations.len(), 1);
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2023-10-07T10:35:29.500+00:00"
+        );
+    }
+
+    #[test]
+    fn test_read_exif_missing() {
+        assert!(read_exif(Path::new("/does/not/exist"), USERNAME).is_err());
+    }
+}
@@ -0,0 +1,557 @@
+use chrono::{DateTime, FixedOffset};
+use color_eyre::eyre::{ensure, eyre, Result};
+use log::debug;
+
+use std::{collections::HashMap, path::Path};
+
+use crate::schema::{Location, Source};
+
+/// Seconds from the Unix epoch to the FIT epoch.
+const FIT_EPOCH: i64 = 631_065_600;
+
+/// Timestamps below this are seconds since the device powered on, not since the epoch.
+const MIN_TIMESTAMP: u32 = 0x1000_0000;
+
+/// Global message numbers.
+const MESG_RECORD: u16 = 20;
+const MESG_ACTIVITY: u16 = 34;
+
+/// Field numbers.
+const FIELD_TIMESTAMP: u8 = 253;
+const FIELD_POSITION_LAT: u8 = 0;
+const FIELD_POSITION_LONG: u8 = 1;
+const FIELD_ALTITUDE: u8 = 2;
+const FIELD_ENHANCED_ALTITUDE: u8 = 78;
+const FIELD_LOCAL_TIMESTAMP: u8 = 5;
+
+/// Nibble table of the FIT CRC-16.
+const CRC_TABLE: [u16; 16] = [
+    0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800, 0xB401,
+    0x5000, 0x9C01, 0x8801, 0x4400,
+];
+
+/// Compute the FIT CRC-16 of some bytes.
+fn crc(data: &[u8]) -> u16 {
+    data.iter().fold(0, |mut crc, byte| {
+        for nibble in [byte & 0xF, byte >> 4] {
+            let tmp = CRC_TABLE[(crc & 0xF) as usize];
+            crc = (crc >> 4) & 0x0FFF;
+            crc = crc ^ tmp ^ CRC_TABLE[nibble as usize];
+        }
+        crc
+    })
+}
+
+/// One field of a definition message.
+#[derive(Debug, Clone, Copy)]
+struct FieldDef {
+    number: u8,
+    size: usize,
+}
+
+/// The layout of a local message type, from its definition message.
+#[derive(Debug, Clone)]
+struct Definition {
+    global: u16,
+    big_endian: bool,
+    fields: Vec<FieldDef>,
+    /// Total size of the developer fields, which are skipped.
+    developer_size: usize,
+}
+
+/// A `record` message with a position.
+#[derive(Debug, Clone, PartialEq)]
+struct Record {
+    timestamp: u32,
+    latitude: f64,
+    longitude: f64,
+    altitude: Option<f64>,
+}
+
+impl Record {
+    /// Convert the record to a Location struct. A missing altitude is recorded as 0.
+    fn to_location(&self, username: &str, offset: FixedOffset) -> Result<Location> {
+        let time_utc = DateTime::from_timestamp(self.timestamp as i64 + FIT_EPOCH, 0)
+            .ok_or_else(|| eyre!("Invalid FIT timestamp: {}", self.timestamp))?;
+        Ok(Location {
+            username: username.to_string(),
+            time_utc,
+            time_local: time_utc.with_timezone(&offset),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            altitude: self.altitude.unwrap_or(0.0),
+            accuracy: None,
+            source: Source::Fit,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        })
+    }
+}
+
+/// Everything of interest decoded from a FIT file.
+#[derive(Debug, Default)]
+struct Activity {
+    records: Vec<Record>,
+    /// The UTC offset of the device, in seconds, from the `activity` message.
+    utc_offset: Option<i32>,
+}
+
+/// Read an unsigned integer field, returning None for the all ones invalid value.
+fn uint(bytes: &[u8], big_endian: bool) -> Option<u64> {
+    let value = match (bytes.len(), big_endian) {
+        (1, _) => bytes[0] as u64,
+        (2, false) => u16::from_le_bytes(bytes.try_into().ok()?) as u64,
+        (2, true) => u16::from_be_bytes(bytes.try_into().ok()?) as u64,
+        (4, false) => u32::from_le_bytes(bytes.try_into().ok()?) as u64,
+        (4, true) => u32::from_be_bytes(bytes.try_into().ok()?) as u64,
+        _ => return None,
+    };
+    let invalid = u64::MAX >> (64 - 8 * bytes.len());
+    (value != invalid).then_some(value)
+}
+
+/// Read a sint32 field, returning None for the invalid value.
+fn sint32(bytes: &[u8], big_endian: bool) -> Option<i32> {
+    let bytes: [u8; 4] = bytes.try_into().ok()?;
+    let value = match big_endian {
+        false => i32::from_le_bytes(bytes),
+        true => i32::from_be_bytes(bytes),
+    };
+    (value != i32::MAX).then_some(value)
+}
+
+/// Convert semicircles, 2^31 per 180 degrees, to degrees.
+fn semicircles_to_degrees(semicircles: i32) -> f64 {
+    semicircles as f64 * 180.0 / 2f64.powi(31)
+}
+
+/// Decodes the records of one FIT file, which may be one of several chained together.
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    definitions: HashMap<u8, Definition>,
+    /// The last full timestamp seen, for compressed timestamp headers.
+    last_timestamp: Option<u32>,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| eyre!("FIT file is truncated at byte {}", self.pos))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_definition(&mut self, header: u8) -> Result<()> {
+        let fixed = self.take(5)?;
+        let big_endian = fixed[1] == 1;
+        let global = match big_endian {
+            false => u16::from_le_bytes([fixed[2], fixed[3]]),
+            true => u16::from_be_bytes([fixed[2], fixed[3]]),
+        };
+        let fields = self
+            .take(3 * fixed[4] as usize)?
+            .chunks_exact(3)
+            .map(|field| FieldDef {
+                number: field[0],
+                size: field[1] as usize,
+            })
+            .collect();
+        let mut developer_size = 0;
+        if header & 0x20 != 0 {
+            let count = self.take(1)?[0] as usize;
+            developer_size = self
+                .take(3 * count)?
+                .chunks_exact(3)
+                .map(|field| field[1] as usize)
+                .sum();
+        }
+        self.definitions.insert(
+            header & 0x0F,
+            Definition {
+                global,
+                big_endian,
+                fields,
+                developer_size,
+            },
+        );
+        Ok(())
+    }
+
+    fn read_data(
+        &mut self,
+        local: u8,
+        compressed_offset: Option<u8>,
+        activity: &mut Activity,
+    ) -> Result<()> {
+        let definition = self
+            .definitions
+            .get(&local)
+            .cloned()
+            .ok_or_else(|| eyre!("FIT data message uses undefined local type {}", local))?;
+        let mut timestamp = match (compressed_offset, self.last_timestamp) {
+            (Some(offset), Some(last)) => {
+                let offset = offset as u32;
+                // the offset rolls over every 32 s, which must not carry past the largest time
+                let rollover = if offset < last & 0x1F { 0x20 } else { 0 };
+                let timestamp = (last & !0x1F)
+                    .checked_add(offset + rollover)
+                    .ok_or_else(|| eyre!("FIT compressed timestamp is out of range"))?;
+                Some(timestamp)
+            }
+            (Some(_), None) => return Err(eyre!("FIT compressed timestamp before any timestamp")),
+            (None, _) => None,
+        };
+        let mut latitude = None;
+        let mut longitude = None;
+        let mut altitude = None;
+        let mut enhanced_altitude = None;
+        let mut local_timestamp = None;
+        for field in definition.fields.iter() {
+            let bytes = self.take(field.size)?;
+            let big_endian = definition.big_endian;
+            match (definition.global, field.number) {
+                (_, FIELD_TIMESTAMP) => timestamp = uint(bytes, big_endian).map(|t| t as u32),
+                (MESG_RECORD, FIELD_POSITION_LAT) => latitude = sint32(bytes, big_endian),
+                (MESG_RECORD, FIELD_POSITION_LONG) => longitude = sint32(bytes, big_endian),
+                (MESG_RECORD, FIELD_ALTITUDE) => altitude = uint(bytes, big_endian),
+                (MESG_RECORD, FIELD_ENHANCED_ALTITUDE) => {
+                    enhanced_altitude = uint(bytes, big_endian)
+                }
+                (MESG_ACTIVITY, FIELD_LOCAL_TIMESTAMP) => local_timestamp = uint(bytes, big_endian),
+                _ => {}
+            }
+        }
+        self.take(definition.developer_size)?;
+        if let Some(timestamp) = timestamp.filter(|t| *t >= MIN_TIMESTAMP) {
+            self.last_timestamp = Some(timestamp);
+        }
+
+        match definition.global {
+            MESG_RECORD => match (timestamp, latitude, longitude) {
+                (Some(timestamp), Some(latitude), Some(longitude))
+                    if timestamp >= MIN_TIMESTAMP =>
+                {
+                    activity.records.push(Record {
+                        timestamp,
+                        latitude: semicircles_to_degrees(latitude),
+                        longitude: semicircles_to_degrees(longitude),
+                        altitude: enhanced_altitude
+                            .or(altitude)
+                            .map(|altitude| altitude as f64 / 5.0 - 500.0),
+                    })
+                }
+                _ => debug!("Skipping FIT record without a time or position"),
+            },
+            MESG_ACTIVITY => {
+                if let (Some(timestamp), Some(local_timestamp)) = (timestamp, local_timestamp) {
+                    let utc_offset = (local_timestamp as i64)
+                        .checked_sub(timestamp as i64)
+                        .and_then(|offset| i32::try_from(offset).ok())
+                        .ok_or_else(|| eyre!("FIT activity local timestamp is out of range"))?;
+                    activity.utc_offset = Some(utc_offset);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Decode every message of the file.
+    fn decode(mut self, activity: &mut Activity) -> Result<()> {
+        while self.pos < self.data.len() {
+            let header = self.take(1)?[0];
+            if header & 0x80 != 0 {
+                self.read_data((header >> 5) & 0x03, Some(header & 0x1F), activity)?;
+            } else if header & 0x40 != 0 {
+                self.read_definition(header)?;
+            } else {
+                self.read_data(header & 0x0F, None, activity)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decode a whole FIT file, verifying the CRC of each file in the chain.
+fn decode(data: &[u8]) -> Result<Activity> {
+    let mut activity = Activity::default();
+    let mut rest = data;
+    while !rest.is_empty() {
+        ensure!(rest.len() >= 12, "FIT file is too short");
+        let header_size = rest[0] as usize;
+        ensure!(
+            rest.len() >= header_size && header_size >= 12 && &rest[8..12] == b".FIT",
+            "Not a FIT file"
+        );
+        let data_size = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+        let end = header_size + data_size;
+        let stored = rest
+            .get(end..end + 2)
+            .ok_or_else(|| eyre!("FIT file is truncated"))?;
+        let stored = u16::from_le_bytes([stored[0], stored[1]]);
+        let computed = crc(&rest[..end]);
+        ensure!(
+            stored == 0 || stored == computed,
+            format!(
+                "FIT file CRC mismatch: stored {:04X}, computed {:04X}",
+                stored, computed
+            )
+        );
+        Decoder {
+            data: &rest[header_size..end],
+            pos: 0,
+            definitions: HashMap::new(),
+            last_timestamp: None,
+        }
+        .decode(&mut activity)?;
+        rest = &rest[end + 2..];
+    }
+    Ok(activity)
+}
+
+/// Read a FIT activity and return an iterator of `Location` structs, one per `record` message with
+/// a position. The file is loaded into memory. Local times use the UTC offset the device recorded
+/// for the activity, or UTC if it recorded none.
+///
+/// The Flexible and Interoperable Data Transfer protocol is the binary format written by Garmin
+/// and most other sport devices. A file is a header, a stream of records and a CRC. Each record is
+/// either a definition message, which describes the layout of a local message type, or a data
+/// message in a previously defined layout. Only a few global messages are of interest:
+/// - `record` (20): `timestamp` (253), `position_lat` (0) and `position_long` (1) in semicircles,
+///   and `altitude` (2) or `enhanced_altitude` (78), both scaled by 5 with an offset of 500 m.
+/// - `activity` (34): `timestamp` (253) and `local_timestamp` (5), whose difference is the UTC
+///   offset of the device.
+///
+/// Times are seconds since the FIT epoch, 1989-12-31T00:00:00Z. Records may also use a compressed
+/// header carrying only the low 5 bits of the time, relative to the last full timestamp.
+/// # Arguments
+/// * `path` - The path to the FIT file.
+/// * `username` - The username to associate with the locations.
+/// # Return
+/// An iterator of `Location` structs, in the order they were recorded.
+pub fn read_fit(path: &Path, username: &str) -> Result<impl Iterator<Item = Result<Location>>> {
+    let data = std::fs::read(path).map_err(|e| eyre!("Failed to read FIT file: {}", e))?;
+    let activity = decode(&data)?;
+    let offset = activity
+        .utc_offset
+        .and_then(FixedOffset::east_opt)
+        .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+    debug!(
+        "Found {} FIT records, UTC offset {}",
+        activity.records.len(),
+        offset
+    );
+    let username = username.to_string();
+    Ok(activity
+        .records
+        .into_iter()
+        .map(move |record| record.to_location(&username, offset)))
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    static USERNAME: &str = "test_user";
+
+    fn fit_time(s: &str) -> u32 {
+        (DateTime::parse_from_rfc3339(s).unwrap().timestamp() - FIT_EPOCH) as u32
+    }
+
+    fn semicircles(degrees: f64) -> i32 {
+        (degrees * 2f64.powi(31) / 180.0).round() as i32
+    }
+
+    /// Wrap records in a FIT header and CRC.
+    fn fit_file(records: &[u8]) -> Vec<u8> {
+        let mut header = vec![14, 0x20];
+        header.extend_from_slice(&2132u16.to_le_bytes());
+        header.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        header.extend_from_slice(b".FIT");
+        let header_crc = crc(&header);
+        header.extend_from_slice(&header_crc.to_le_bytes());
+        let mut data = [header, records.to_vec()].concat();
+        let file_crc = crc(&data);
+        data.extend_from_slice(&file_crc.to_le_bytes());
+        data
+    }
+
+    /// A little endian `record` definition for local type 0, and a big endian `activity`
+    /// definition, with a developer field, for local type 1.
+    fn definitions() -> Vec<u8> {
+        let mut data = vec![0x40, 0, 0];
+        data.extend_from_slice(&MESG_RECORD.to_le_bytes());
+        data.extend_from_slice(&[
+            4,
+            FIELD_TIMESTAMP,
+            4,
+            0x86,
+            FIELD_POSITION_LAT,
+            4,
+            0x85,
+            FIELD_POSITION_LONG,
+            4,
+            0x85,
+            FIELD_ALTITUDE,
+            2,
+            0x84,
+        ]);
+        data.extend_from_slice(&[0x61, 0, 1]);
+        data.extend_from_slice(&MESG_ACTIVITY.to_be_bytes());
+        data.extend_from_slice(&[2, FIELD_TIMESTAMP, 4, 0x86, FIELD_LOCAL_TIMESTAMP, 4, 0x86]);
+        data.extend_from_slice(&[1, 0, 2, 0]);
+        data
+    }
+
+    fn record(header: u8, time: Option<u32>, lat: i32, lon: i32, altitude: u16) -> Vec<u8> {
+        let mut data = vec![header];
+        if let Some(time) = time {
+            data.extend_from_slice(&time.to_le_bytes());
+        }
+        data.extend_from_slice(&lat.to_le_bytes());
+        data.extend_from_slice(&lon.to_le_bytes());
+        data.extend_from_slice(&altitude.to_le_bytes());
+        data
+    }
+
+    fn fit_data() -> Vec<u8> {
+        let start = fit_time("2023-10-07T12:35:19Z");
+        let mut records = definitions();
+        records.extend(record(
+            0,
+            Some(start),
+            semicircles(48.1173),
+            semicircles(11.5167),
+            (545 + 500) * 5,
+        ));
+        // No position
+        records.extend(record(0, Some(start + 5), i32::MAX, i32::MAX, 0xFFFF));
+        // No timestamp field, so a compressed header with a time 10 s after the start
+        let compressed = record(
+            0x80 | ((start + 10) & 0x1F) as u8,
+            None,
+            semicircles(-48.1172),
+            semicircles(-11.5168),
+            0xFFFF,
+        );
+        let compressed_definition = [
+            vec![0x40, 0, 0],
+            MESG_RECORD.to_le_bytes().to_vec(),
+            vec![3, FIELD_POSITION_LAT, 4, 0x85, FIELD_POSITION_LONG, 4, 0x85],
+            vec![FIELD_ALTITUDE, 2, 0x84],
+        ]
+        .concat();
+        records.extend(compressed_definition);
+        records.extend(compressed);
+        // The activity, in local time two hours ahead
+        records.push(1);
+        records.extend_from_slice(&(start + 20).to_be_bytes());
+        records.extend_from_slice(&(start + 20 + 7200).to_be_bytes());
+        records.extend_from_slice(&[0xAB, 0xCD]);
+        fit_file(&records)
+    }
+
+    fn create_fit(data: &[u8]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(data).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_crc() {
+        assert_eq!(crc(b""), 0);
+        assert_eq!(crc(b"123456789"), 0xBB3D);
+    }
+
+    #[test]
+    fn test_read_fit() {
+        let file = create_fit(&fit_data());
+        let locations = read_fit(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap();
+        assert_eq!(locations.len(), 2);
+
+        assert_eq!(locations[0].username, USERNAME);
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2023-10-07T12:35:19+00:00"
+        );
+        assert_eq!(
+            locations[0].time_local.to_rfc3339(),
+            "2023-10-07T14:35:19+02:00"
+        );
+        assert!((locations[0].latitude - 48.1173).abs() < 1e-6);
+        assert!((locations[0].longitude - 11.5167).abs() < 1e-6);
+        assert_eq!(locations[0].altitude, 545.0);
+        assert_eq!(locations[0].accuracy, None);
+        assert_eq!(locations[0].source, Source::Fit);
+
+        assert_eq!(
+            locations[1].time_utc.to_rfc3339(),
+            "2023-10-07T12:35:29+00:00"
+        );
+        assert!((locations[1].latitude + 48.1172).abs() < 1e-6);
+        assert!((locations[1].longitude + 11.5168).abs() < 1e-6);
+        assert_eq!(locations[1].altitude, 0.0);
+    }
+
+    #[test]
+    fn test_read_fit_chained() {
+        let data = [fit_data(), fit_data()].concat();
+        let file = create_fit(&data);
+        let count = read_fit(file.path(), USERNAME).unwrap().count();
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn test_read_fit_errors() {
+        // Corrupt a byte of the data
+        let mut data = fit_data();
+        data[20] ^= 0xFF;
+        let file = create_fit(&data);
+        assert!(read_fit(file.path(), USERNAME).is_err());
+        // Truncated
+        let data = fit_data();
+        let file = create_fit(&data[..data.len() - 10]);
+        assert!(read_fit(file.path(), USERNAME).is_err());
+        // Not a FIT file
+        let file = create_fit(b"<TrainingCenterDatabase/>");
+        assert!(read_fit(file.path(), USERNAME).is_err());
+        // Data before any definition
+        let file = create_fit(&fit_file(&[0, 1, 2, 3]));
+        assert!(read_fit(file.path(), USERNAME).is_err());
+        // A compressed timestamp past the largest time
+        let mut records = definitions();
+        records.extend(record(0, Some(u32::MAX - 1), 0, 0, 0));
+        records.extend(record(0x80, None, 0, 0, 0));
+        let file = create_fit(&fit_file(&records));
+        assert!(read_fit(file.path(), USERNAME).is_err());
+        // A local time too far from the UTC time for an offset
+        let mut records = definitions();
+        records.push(1);
+        records.extend_from_slice(&0u32.to_be_bytes());
+        records.extend_from_slice(&(u32::MAX - 1).to_be_bytes());
+        records.extend_from_slice(&[0xAB, 0xCD]);
+        let file = create_fit(&fit_file(&records));
+        assert!(read_fit(file.path(), USERNAME).is_err());
+    }
+}
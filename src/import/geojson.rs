@@ -0,0 +1,386 @@
+use chrono::{DateTime, FixedOffset};
+use color_eyre::eyre::{ensure, eyre, Result};
+use log::debug;
+use sea_orm::ActiveEnum;
+use serde::Deserialize;
+
+use std::{fs::File, io::BufReader, path::Path};
+
+use crate::schema::{Location, Source};
+
+/// # GeoJSON
+/// Any FeatureCollection with timestamped features can be imported, including both formats
+/// written by the GeoJSON exporters:
+/// - `Point` features with a `time` (or `timestamp`) property, and optional `accuracy`, `source`,
+///   `speed`, `bearing`, `satellites`, `provider`, `hdop`, `vdop`, `pdop`, `battery` and
+///   `charging` properties.
+/// - `LineString` and `MultiPoint` features with a `times` array property holding one time per
+///   position, and optional `accuracies`, `sources`, `speeds`, `bearings`, `satellite_counts`,
+///   `providers`, `hdops`, `vdops`, `pdops`, `batteries` and `charging_states` arrays.
+///
+/// Positions are `[longitude, latitude]` or `[longitude, latitude, altitude]`. A `source` that
+/// names a known source is kept, so exported data round-trips unchanged; otherwise the source is
+/// recorded as GeoJSON. Features without times are skipped.
+#[derive(Deserialize, Debug)]
+struct FeatureCollection {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Feature {
+    geometry: Option<Geometry>,
+    properties: Option<Properties>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+enum Geometry {
+    Point {
+        coordinates: Vec<f64>,
+    },
+    LineString {
+        coordinates: Vec<Vec<f64>>,
+    },
+    MultiPoint {
+        coordinates: Vec<Vec<f64>>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Properties {
+    #[serde(alias = "timestamp")]
+    time: Option<DateTime<FixedOffset>>,
+    accuracy: Option<f32>,
+    source: Option<String>,
+    #[serde(flatten)]
+    details: Details,
+    times: Option<Vec<DateTime<FixedOffset>>>,
+    accuracies: Option<Vec<Option<f32>>>,
+    sources: Option<Vec<String>>,
+    speeds: Option<Vec<Option<f32>>>,
+    bearings: Option<Vec<Option<f32>>>,
+    satellite_counts: Option<Vec<Option<i32>>>,
+    providers: Option<Vec<Option<String>>>,
+    hdops: Option<Vec<Option<f32>>>,
+    vdops: Option<Vec<Option<f32>>>,
+    pdops: Option<Vec<Option<f32>>>,
+    batteries: Option<Vec<Option<f32>>>,
+    charging_states: Option<Vec<Option<bool>>>,
+}
+
+/// The optional values of a location, as written by the exporters.
+#[derive(Deserialize, Debug, Default, Clone)]
+struct Details {
+    speed: Option<f32>,
+    bearing: Option<f32>,
+    satellites: Option<i32>,
+    provider: Option<String>,
+    hdop: Option<f32>,
+    vdop: Option<f32>,
+    pdop: Option<f32>,
+    battery: Option<f32>,
+    charging: Option<bool>,
+}
+
+impl Properties {
+    /// The optional values of the `i`th position of a LineString or MultiPoint.
+    fn details_at(&self, i: usize) -> Details {
+        fn at<T: Clone>(values: &Option<Vec<Option<T>>>, i: usize) -> Option<T> {
+            values
+                .as_ref()
+                .and_then(|values| values.get(i).cloned().flatten())
+        }
+        Details {
+            speed: at(&self.speeds, i),
+            bearing: at(&self.bearings, i),
+            satellites: at(&self.satellite_counts, i),
+            provider: at(&self.providers, i),
+            hdop: at(&self.hdops, i),
+            vdop: at(&self.vdops, i),
+            pdop: at(&self.pdops, i),
+            battery: at(&self.batteries, i),
+            charging: at(&self.charging_states, i),
+        }
+    }
+}
+
+/// Build a location from a GeoJSON position and its properties.
+fn make_location(
+    username: &str,
+    position: &[f64],
+    time: DateTime<FixedOffset>,
+    accuracy: Option<f32>,
+    source: Option<&str>,
+    details: Details,
+) -> Result<Location> {
+    ensure!(
+        position.len() >= 2,
+        format!("Position has too few coordinates: {:?}", position)
+    );
+    Ok(Location {
+        username: username.to_string(),
+        time_utc: time.to_utc(),
+        time_local: time,
+        latitude: position[1],
+        longitude: position[0],
+        altitude: position.get(2).copied().unwrap_or(0.0),
+        accuracy,
+        source: source
+            .and_then(|source| Source::try_from_value(&source.to_string()).ok())
+            .unwrap_or(Source::GeoJson),
+        speed: details.speed,
+        bearing: details.bearing,
+        satellites: details.satellites,
+        provider: details.provider,
+        hdop: details.hdop,
+        vdop: details.vdop,
+        pdop: details.pdop,
+        battery: details.battery,
+        charging: details.charging,
+        device: String::new(),
+    })
+}
+
+/// Convert one feature into locations.
+fn feature_locations(username: &str, feature: Feature) -> Result<Vec<Location>> {
+    let properties = feature.properties.unwrap_or_default();
+    match feature.geometry {
+        Some(Geometry::Point { coordinates }) => match properties.time {
+            Some(time) => Ok(vec![make_location(
+                username,
+                &coordinates,
+                time,
+                properties.accuracy,
+                properties.source.as_deref(),
+                properties.details.clone(),
+            )?]),
+            None => {
+                debug!("Skipping GeoJSON Point without a time: {:?}", coordinates);
+                Ok(vec![])
+            }
+        },
+        Some(Geometry::LineString { coordinates } | Geometry::MultiPoint { coordinates }) => {
+            let Some(times) = &properties.times else {
+                debug!("Skipping GeoJSON feature without times");
+                return Ok(vec![]);
+            };
+            ensure!(
+                times.len() == coordinates.len(),
+                format!(
+                    "Feature has {} positions but {} times",
+                    coordinates.len(),
+                    times.len()
+                )
+            );
+            coordinates
+                .iter()
+                .zip(times.iter().copied())
+                .enumerate()
+                .map(|(i, (position, time))| {
+                    let accuracy = properties
+                        .accuracies
+                        .as_ref()
+                        .and_then(|accuracies| accuracies.get(i).copied().flatten());
+                    let source = properties
+                        .sources
+                        .as_ref()
+                        .and_then(|sources| sources.get(i))
+                        .map(String::as_str);
+                    let details = properties.details_at(i);
+                    make_location(username, position, time, accuracy, source, details)
+                })
+                .collect()
+        }
+        Some(Geometry::Other) | None => {
+            debug!("Skipping GeoJSON feature with unsupported geometry");
+            Ok(vec![])
+        }
+    }
+}
+
+/// Read a GeoJSON FeatureCollection and return an iterator of `Location` structs. The file is
+/// loaded into memory.
+/// # Arguments
+/// * `path` - The path to the GeoJSON file.
+/// * `username` - The username to associate with the locations.
+/// # Return
+/// An iterator of `Location` structs, feature by feature.
+pub fn read_geojson(path: &Path, username: &str) -> Result<impl Iterator<Item = Result<Location>>> {
+    let file = File::open(path).map_err(|e| eyre!("Failed to open GeoJSON file: {}", e))?;
+    let collection: FeatureCollection = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| eyre!("Failed to parse GeoJSON: {}", e))?;
+    let username = username.to_string();
+    Ok(collection
+        .features
+        .into_iter()
+        .map(move |feature| feature_locations(&username, feature))
+        .flat_map(|result| match result {
+            Ok(locations) => locations.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        }))
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{create_exporter, Format as ExportFormat};
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    static USERNAME: &str = "test_user";
+
+    /// A collection as written by QGIS, with extra properties and an untimed polygon.
+    static GEOJSON_DATA: &str = r#"{
+"type": "FeatureCollection",
+"crs": { "type": "name", "properties": { "name": "urn:ogc:def:crs:OGC:1.3:CRS84" } },
+"features": [
+{ "type": "Feature", "properties": { "timestamp": "2023-10-07T12:35:19Z", "name": "a" }, "geometry": { "type": "Point", "coordinates": [ 11.5167, 48.1173 ] } },
+{ "type": "Feature", "properties": { "time": "2023-10-07T12:35:29+02:00", "accuracy": 5, "source": "Somewhere" }, "geometry": { "type": "Point", "coordinates": [ 11.5168, 48.1172, 546.0 ] } },
+{ "type": "Feature", "properties": null, "geometry": { "type": "Point", "coordinates": [ 1.0, 2.0 ] } },
+{ "type": "Feature", "properties": {}, "geometry": { "type": "Polygon", "coordinates": [ [ [ 0, 0 ], [ 1, 0 ], [ 1, 1 ], [ 0, 0 ] ] ] } },
+{ "type": "Feature", "properties": {}, "geometry": null }
+]
+}"#;
+
+    fn create_geojson(data: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_read_geojson() {
+        let file = create_geojson(GEOJSON_DATA);
+        let locations = read_geojson(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap();
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[0].username, USERNAME);
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2023-10-07T12:35:19+00:00"
+        );
+        assert_eq!(locations[0].latitude, 48.1173);
+        assert_eq!(locations[0].longitude, 11.5167);
+        assert_eq!(locations[0].altitude, 0.0);
+        assert_eq!(locations[0].accuracy, None);
+        assert_eq!(locations[0].source, Source::GeoJson);
+        assert_eq!(
+            locations[1].time_local.to_rfc3339(),
+            "2023-10-07T12:35:29+02:00"
+        );
+        assert_eq!(locations[1].altitude, 546.0);
+        assert_eq!(locations[1].accuracy, Some(5.0));
+        assert_eq!(locations[1].source, Source::GeoJson);
+    }
+
+    #[test]
+    fn test_read_geojson_errors() {
+        let file = create_geojson(
+            r#"{"type":"FeatureCollection","features":[{"type":"Feature","properties":{"times":["2023-10-07T12:35:19Z"]},"geometry":{"type":"LineString","coordinates":[[0,0],[1,1]]}}]}"#,
+        );
+        let mut iter = read_geojson(file.path(), USERNAME).unwrap();
+        assert!(iter.next().unwrap().is_err());
+        let file = create_geojson(
+            r#"{"type":"FeatureCollection","features":[{"type":"Feature","properties":{"time":"2023-10-07T12:35:19Z"},"geometry":{"type":"Point","coordinates":[0]}}]}"#,
+        );
+        let mut iter = read_geojson(file.path(), USERNAME).unwrap();
+        assert!(iter.next().unwrap().is_err());
+    }
+
+    /// Whatever the GeoJSON exporters write must come back unchanged.
+    #[test]
+    fn test_round_trip() {
+        let original = vec![
+            Location {
+                username: USERNAME.to_string(),
+                time_utc: DateTime::parse_from_rfc3339("2023-10-07T10:35:19Z")
+                    .unwrap()
+                    .into(),
+                time_local: DateTime::parse_from_rfc3339("2023-10-07T12:35:19+02:00").unwrap(),
+                latitude: 48.1173,
+                longitude: 11.5167,
+                altitude: 545.4,
+                accuracy: Some(4.5),
+                source: Source::GpsLogger,
+                speed: Some(1.5),
+                bearing: Some(270.0),
+                satellites: Some(9),
+                provider: Some("gps".to_string()),
+                hdop: Some(0.8),
+                vdop: Some(1.25),
+                pdop: Some(1.5),
+                battery: Some(80.0),
+                charging: Some(true),
+                device: String::new(),
+            },
+            Location {
+                username: USERNAME.to_string(),
+                time_utc: DateTime::parse_from_rfc3339("2023-10-07T10:35:29Z")
+                    .unwrap()
+                    .into(),
+                time_local: DateTime::parse_from_rfc3339("2023-10-07T12:35:29+02:00").unwrap(),
+                latitude: 48.1172,
+                longitude: 11.5168,
+                altitude: 546.0,
+                accuracy: None,
+                source: Source::OwnTracks,
+                speed: None,
+                bearing: None,
+                satellites: None,
+                provider: None,
+                hdop: None,
+                vdop: None,
+                pdop: None,
+                battery: None,
+                charging: None,
+                device: String::new(),
+            },
+            Location {
+                username: USERNAME.to_string(),
+                time_utc: DateTime::parse_from_rfc3339("2023-10-08T10:35:39Z")
+                    .unwrap()
+                    .into(),
+                time_local: DateTime::parse_from_rfc3339("2023-10-08T12:35:39+02:00").unwrap(),
+                latitude: 48.1175,
+                longitude: 11.5166,
+                altitude: 547.5,
+                accuracy: None,
+                source: Source::Gpx,
+                speed: None,
+                bearing: None,
+                satellites: None,
+                provider: None,
+                hdop: None,
+                vdop: None,
+                pdop: None,
+                battery: None,
+                charging: None,
+                device: String::new(),
+            },
+        ];
+        for format in [ExportFormat::GeoJson, ExportFormat::GeoJsonDaily] {
+            let file = NamedTempFile::new().unwrap();
+            {
+                let mut exporter = create_exporter(format, "test", file.path()).unwrap();
+                for location in original.iter() {
+                    exporter.write_location(location).unwrap();
+                }
+                exporter.finish().unwrap();
+            }
+            let locations = read_geojson(file.path(), USERNAME)
+                .unwrap()
+                .collect::<Result<Vec<Location>>>()
+                .unwrap();
+            assert_eq!(locations, original);
+        }
+    }
+}
@@ -0,0 +1,487 @@
+use chrono::{DateTime, FixedOffset};
+use color_eyre::eyre::{eyre, Result};
+use log::debug;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use sea_orm::ActiveEnum;
+
+use std::{fs::File, io::BufReader, path::Path};
+
+use crate::import::parse_time;
+use crate::schema::{Location, LocationGen, Source};
+
+/// Which child element of a point is currently being read.
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Ele,
+    Time,
+    Sat,
+    Hdop,
+    Vdop,
+    Pdop,
+    Speed,
+    Course,
+    Battery,
+    Charging,
+    Provider,
+    Accuracy,
+    Source,
+    Device,
+}
+
+impl Field {
+    /// The field read from an element, by its local name. Speed and course are standard in GPX
+    /// 1.0 and usually found in a TrackPointExtension in GPX 1.1; accuracy, battery, charging,
+    /// provider, source and device are written by `GpxExporter`.
+    fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"ele" => Some(Field::Ele),
+            b"time" => Some(Field::Time),
+            b"sat" => Some(Field::Sat),
+            b"hdop" => Some(Field::Hdop),
+            b"vdop" => Some(Field::Vdop),
+            b"pdop" => Some(Field::Pdop),
+            b"speed" => Some(Field::Speed),
+            b"course" => Some(Field::Course),
+            b"battery" => Some(Field::Battery),
+            b"charging" => Some(Field::Charging),
+            b"provider" => Some(Field::Provider),
+            b"accuracy" => Some(Field::Accuracy),
+            b"source" => Some(Field::Source),
+            b"device" => Some(Field::Device),
+            _ => None,
+        }
+    }
+}
+
+/// A `trkpt`, `rtept` or `wpt` element, filled in as its children are read.
+#[derive(Debug)]
+struct Point {
+    latitude: f64,
+    longitude: f64,
+    elevation: Option<f64>,
+    time: Option<DateTime<FixedOffset>>,
+    satellites: Option<i32>,
+    hdop: Option<f32>,
+    vdop: Option<f32>,
+    pdop: Option<f32>,
+    speed: Option<f32>,
+    bearing: Option<f32>,
+    battery: Option<f32>,
+    charging: Option<bool>,
+    provider: Option<String>,
+    accuracy: Option<f32>,
+    /// The source of an exported location, if it names a known one
+    source: Option<Source>,
+    device: String,
+}
+
+impl Point {
+    /// Start a point from the `lat` and `lon` attributes of its element.
+    fn from_element(element: &BytesStart) -> Result<Self> {
+        let attribute = |name: &str| -> Result<f64> {
+            let value = element
+                .try_get_attribute(name)
+                .map_err(|e| eyre!("Invalid attribute on point: {}", e))?
+                .ok_or_else(|| eyre!("Point is missing `{}`", name))?
+                .unescape_value()
+                .map_err(|e| eyre!("Invalid `{}` on point: {}", name, e))?
+                .into_owned();
+            value
+                .trim()
+                .parse::<f64>()
+                .map_err(|e| eyre!("Invalid `{}` on point: {}: {}", name, value, e))
+        };
+        Ok(Point {
+            latitude: attribute("lat")?,
+            longitude: attribute("lon")?,
+            elevation: None,
+            time: None,
+            satellites: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            speed: None,
+            bearing: None,
+            battery: None,
+            charging: None,
+            provider: None,
+            accuracy: None,
+            source: None,
+            device: String::new(),
+        })
+    }
+
+    /// Set a field of the point from the text of its element.
+    /// # Arguments
+    /// * `field`: The field to set
+    /// * `text`: The trimmed, unescaped text of the element
+    fn set(&mut self, field: Field, text: &str) -> Result<()> {
+        fn parse<T: std::str::FromStr>(name: &str, text: &str) -> Result<Option<T>>
+        where
+            T::Err: std::fmt::Display,
+        {
+            text.parse::<T>()
+                .map(Some)
+                .map_err(|e| eyre!("Invalid {}: {}: {}", name, text, e))
+        }
+        match field {
+            Field::Ele => self.elevation = parse("elevation", text)?,
+            Field::Time => self.time = Some(parse_time(text)?),
+            Field::Sat => self.satellites = parse("satellite count", text)?,
+            Field::Hdop => self.hdop = parse("HDOP", text)?,
+            Field::Vdop => self.vdop = parse("VDOP", text)?,
+            Field::Pdop => self.pdop = parse("PDOP", text)?,
+            Field::Speed => self.speed = parse("speed", text)?,
+            Field::Course => self.bearing = parse("course", text)?,
+            Field::Battery => self.battery = parse("battery level", text)?,
+            Field::Charging => self.charging = parse("charging state", text)?,
+            Field::Provider => self.provider = Some(text.to_string()),
+            Field::Accuracy => self.accuracy = parse("accuracy", text)?,
+            Field::Source => self.source = Source::try_from_value(&text.to_string()).ok(),
+            Field::Device => self.device = text.to_string(),
+        }
+        Ok(())
+    }
+}
+
+/// A point that has a time, and can therefore be recorded.
+struct TimedPoint {
+    point: Point,
+    time: DateTime<FixedOffset>,
+}
+
+impl LocationGen for TimedPoint {
+    /// Convert the point to a Location struct. A missing elevation is recorded as 0. Points
+    /// without a known source, such as those of other apps, are recorded as from a GPX file.
+    fn to_location(&self, username: &str) -> Location {
+        Location {
+            username: username.to_string(),
+            time_utc: self.time.to_utc(),
+            time_local: self.time,
+            latitude: self.point.latitude,
+            longitude: self.point.longitude,
+            altitude: self.point.elevation.unwrap_or(0.0),
+            accuracy: self.point.accuracy,
+            source: self.point.source.clone().unwrap_or(Source::Gpx),
+            speed: self.point.speed,
+            bearing: self.point.bearing,
+            satellites: self.point.satellites,
+            provider: self.point.provider.clone(),
+            hdop: self.point.hdop,
+            vdop: self.point.vdop,
+            pdop: self.point.pdop,
+            battery: self.point.battery,
+            charging: self.point.charging,
+            device: self.point.device.clone(),
+        }
+    }
+}
+
+/// Streams points out of a GPX 1.0 or 1.1 file. Track points, route points and waypoints are all
+/// treated the same. Points without a `<time>` cannot be placed in the history and are skipped.
+struct GpxReader {
+    reader: Reader<BufReader<File>>,
+    buf: Vec<u8>,
+    username: String,
+    /// The point being read, if inside a point element.
+    point: Option<Point>,
+    /// The child element of the point being read, if any.
+    field: Option<Field>,
+    /// Set after the end of the file or an error.
+    done: bool,
+}
+
+impl Iterator for GpxReader {
+    type Item = Result<Location>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            self.buf.clear();
+            let event = match self.reader.read_event_into(&mut self.buf) {
+                Ok(event) => event,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(eyre!(
+                        "Failed to parse GPX at byte {}: {}",
+                        self.reader.buffer_position(),
+                        e
+                    )));
+                }
+            };
+            match event {
+                Event::Start(element) => match element.local_name().as_ref() {
+                    b"trkpt" | b"rtept" | b"wpt" => match Point::from_element(&element) {
+                        Ok(point) => self.point = Some(point),
+                        Err(e) => {
+                            self.done = true;
+                            return Some(Err(e));
+                        }
+                    },
+                    name if self.point.is_some() => self.field = Field::from_name(name),
+                    _ => {}
+                },
+                Event::Empty(element) => {
+                    if let b"trkpt" | b"rtept" | b"wpt" = element.local_name().as_ref() {
+                        debug!("Skipping GPX point without a time");
+                    }
+                }
+                Event::Text(text) => {
+                    if let (Some(field), Some(point)) = (self.field, self.point.as_mut()) {
+                        let text = match text.unescape() {
+                            Ok(text) => text,
+                            Err(e) => {
+                                self.done = true;
+                                return Some(Err(eyre!("Invalid text in GPX point: {}", e)));
+                            }
+                        };
+                        if let Err(e) = point.set(field, text.trim()) {
+                            self.done = true;
+                            return Some(Err(e));
+                        }
+                    }
+                }
+                Event::End(element) => match element.local_name().as_ref() {
+                    b"trkpt" | b"rtept" | b"wpt" => {
+                        if let Some(point) = self.point.take() {
+                            match point.time {
+                                Some(time) => {
+                                    let timed = TimedPoint { point, time };
+                                    return Some(Ok(timed.to_location(&self.username)));
+                                }
+                                None => debug!("Skipping GPX point without a time: {:?}", point),
+                            }
+                        }
+                    }
+                    _ => self.field = None,
+                },
+                Event::Eof => {
+                    self.done = true;
+                    return None;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Read a GPX file and return an iterator of `Location` structs. Does not load the entire file
+/// into memory.
+/// # Arguments
+/// * `path` - The path to the GPX file.
+/// * `username` - The username to associate with the locations.
+/// # Return
+/// An iterator of `Location` structs, in the order the points appear in the file.
+pub fn read_gpx(path: &Path, username: &str) -> Result<impl Iterator<Item = Result<Location>>> {
+    let file = File::open(path).map_err(|e| eyre!("Failed to open GPX file: {}", e))?;
+    let mut reader = Reader::from_reader(BufReader::new(file));
+    reader.config_mut().trim_text(true);
+    Ok(GpxReader {
+        reader,
+        buf: Vec::new(),
+        username: username.to_string(),
+        point: None,
+        field: None,
+        done: false,
+    })
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{create_exporter, Format as ExportFormat};
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    static GPX_DATA: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no" ?>
+<gpx xmlns="http://www.topografix.com/GPX/1/1" version="1.1" creator="Garmin">
+  <metadata>
+    <time>2023-10-07T00:00:00Z</time>
+  </metadata>
+  <wpt lat="48.2" lon="11.6">
+    <ele>500</ele>
+    <time>2023-10-07T12:00:00Z</time>
+    <name>Start</name>
+  </wpt>
+  <wpt lat="48.3" lon="11.7">
+    <name>No time</name>
+  </wpt>
+  <rte>
+    <rtept lat="48.4" lon="11.8"/>
+  </rte>
+  <trk>
+    <name>Track</name>
+    <trkseg>
+      <trkpt lat="48.1173" lon="11.5167">
+        <ele>545.4</ele>
+        <time>2023-10-07T12:35:19.500Z</time>
+        <extensions><speed>1.0</speed></extensions>
+      </trkpt>
+    </trkseg>
+    <trkseg>
+      <trkpt lat="-48.1172" lon="-11.5168">
+        <time>2023-10-07T12:35:29</time>
+      </trkpt>
+    </trkseg>
+  </trk>
+</gpx>
+"#;
+
+    static GPX_10_DATA: &str = r#"<?xml version="1.0"?>
+<gpx version="1.0" creator="GPSBabel" xmlns="http://www.topografix.com/GPX/1/0">
+  <time>2023-10-07T00:00:00Z</time>
+  <trk><trkseg>
+    <trkpt lat="48.1173" lon="11.5167"><ele>545.4</ele><time>2023-10-07T12:35:19Z</time><course>90</course></trkpt>
+  </trkseg></trk>
+</gpx>
+"#;
+
+    static USERNAME: &str = "test_user";
+
+    fn create_gpx(data: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_read_gpx() {
+        let file = create_gpx(GPX_DATA);
+        let locations = read_gpx(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap();
+        assert_eq!(locations.len(), 3);
+
+        assert_eq!(locations[0].username, USERNAME);
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2023-10-07T12:00:00+00:00"
+        );
+        assert_eq!(locations[0].latitude, 48.2);
+        assert_eq!(locations[0].longitude, 11.6);
+        assert_eq!(locations[0].altitude, 500.0);
+        assert_eq!(locations[0].accuracy, None);
+        assert_eq!(locations[0].source, Source::Gpx);
+
+        assert_eq!(
+            locations[1].time_utc.to_rfc3339(),
+            "2023-10-07T12:35:19.500+00:00"
+        );
+        assert_eq!(locations[1].latitude, 48.1173);
+        assert_eq!(locations[1].longitude, 11.5167);
+        assert_eq!(locations[1].altitude, 545.4);
+        assert_eq!(locations[1].speed, Some(1.0));
+
+        // no zone and no elevation
+        assert_eq!(
+            locations[2].time_utc.to_rfc3339(),
+            "2023-10-07T12:35:29+00:00"
+        );
+        assert_eq!(locations[2].latitude, -48.1172);
+        assert_eq!(locations[2].longitude, -11.5168);
+        assert_eq!(locations[2].altitude, 0.0);
+    }
+
+    #[test]
+    fn test_read_gpx_10() {
+        let file = create_gpx(GPX_10_DATA);
+        let locations = read_gpx(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap();
+        assert_eq!(locations.len(), 1);
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2023-10-07T12:35:19+00:00"
+        );
+        assert_eq!(locations[0].altitude, 545.4);
+        assert_eq!(locations[0].bearing, Some(90.0));
+    }
+
+    #[test]
+    fn test_read_gpx_errors() {
+        let file =
+            create_gpx(r#"<gpx><trk><trkseg><trkpt lon="1.0"></trkpt></trkseg></trk></gpx>"#);
+        let mut iter = read_gpx(file.path(), USERNAME).unwrap();
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+        let file = create_gpx(
+            r#"<gpx><trk><trkseg><trkpt lat="1.0" lon="1.0"><time>noon</time></trkpt></trkseg></trk></gpx>"#,
+        );
+        let mut iter = read_gpx(file.path(), USERNAME).unwrap();
+        assert!(iter.next().unwrap().is_err());
+    }
+
+    /// Whatever `GpxExporter` writes must come back unchanged.
+    #[test]
+    fn test_round_trip() {
+        let original = vec![
+            Location {
+                username: USERNAME.to_string(),
+                time_utc: DateTime::parse_from_rfc3339("2023-10-07T12:35:19Z")
+                    .unwrap()
+                    .into(),
+                time_local: DateTime::parse_from_rfc3339("2023-10-07T14:35:19+02:00").unwrap(),
+                latitude: 48.1173,
+                longitude: 11.5167,
+                altitude: 545.4,
+                accuracy: None,
+                source: Source::GpsLogger,
+                speed: None,
+                bearing: None,
+                satellites: None,
+                provider: None,
+                hdop: None,
+                vdop: None,
+                pdop: None,
+                battery: None,
+                charging: None,
+                device: String::new(),
+            },
+            Location {
+                username: USERNAME.to_string(),
+                time_utc: DateTime::parse_from_rfc3339("2023-10-07T12:35:29Z")
+                    .unwrap()
+                    .into(),
+                time_local: DateTime::parse_from_rfc3339("2023-10-07T14:35:29+02:00").unwrap(),
+                latitude: 48.1172,
+                longitude: 11.5168,
+                altitude: 546.0,
+                accuracy: Some(4.0),
+                source: Source::GpsLogger,
+                speed: Some(1.5),
+                bearing: Some(90.0),
+                satellites: Some(7),
+                provider: Some("gps & network".to_string()),
+                hdop: Some(0.8),
+                vdop: None,
+                pdop: Some(1.25),
+                battery: Some(80.0),
+                charging: Some(true),
+                device: "phone <1>".to_string(),
+            },
+        ];
+        let file = NamedTempFile::new().unwrap();
+        {
+            let mut exporter = create_exporter(ExportFormat::Gpx, "test", file.path()).unwrap();
+            for location in original.iter() {
+                exporter.write_location(location).unwrap();
+            }
+            exporter.finish().unwrap();
+        }
+        let locations = read_gpx(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap();
+        assert_eq!(locations, original);
+    }
+}
@@ -0,0 +1,446 @@
+use chrono::{DateTime, FixedOffset};
+use color_eyre::eyre::{ensure, eyre, Result};
+use log::debug;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use zip::ZipArchive;
+
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use crate::import::parse_time;
+use crate::schema::{Location, Source};
+
+/// A single KML coordinate tuple.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Coord {
+    longitude: f64,
+    latitude: f64,
+    altitude: Option<f64>,
+}
+
+impl Coord {
+    /// Parse a coordinate from its parts, in KML's longitude, latitude, altitude order.
+    fn from_parts<'a>(mut parts: impl Iterator<Item = &'a str>, s: &str) -> Result<Self> {
+        let mut next = || -> Result<Option<f64>> {
+            parts
+                .next()
+                .map(|part| {
+                    part.parse::<f64>()
+                        .map_err(|e| eyre!("Invalid coordinate: {}: {}", s, e))
+                })
+                .transpose()
+        };
+        let longitude = next()?.ok_or_else(|| eyre!("Coordinate has no longitude: {}", s))?;
+        let latitude = next()?.ok_or_else(|| eyre!("Coordinate has no latitude: {}", s))?;
+        let altitude = next()?;
+        Ok(Coord {
+            longitude,
+            latitude,
+            altitude,
+        })
+    }
+}
+
+/// Parse the contents of a `<coordinates>` element, a whitespace separated list of
+/// `lon,lat[,alt]` tuples.
+fn parse_coordinates(s: &str) -> Result<Vec<Coord>> {
+    s.split_whitespace()
+        .map(|tuple| Coord::from_parts(tuple.split(','), tuple))
+        .collect()
+}
+
+/// Parse the contents of a `<gx:coord>` element, a space separated `lon lat [alt]` triple.
+fn parse_gx_coord(s: &str) -> Result<Coord> {
+    Coord::from_parts(s.split_whitespace(), s)
+}
+
+/// Everything of interest inside one `<Placemark>`.
+#[derive(Debug, Default)]
+struct Placemark {
+    /// `<TimeStamp><when>`
+    when: Option<DateTime<FixedOffset>>,
+    /// `<TimeSpan><begin>`
+    begin: Option<DateTime<FixedOffset>>,
+    /// `<TimeSpan><end>`
+    end: Option<DateTime<FixedOffset>>,
+    /// Coordinates of every `<Point>`.
+    points: Vec<Coord>,
+    /// Coordinates of every `<LineString>`.
+    lines: Vec<Vec<Coord>>,
+    /// Completed `<gx:Track>` elements.
+    tracks: Vec<Vec<(DateTime<FixedOffset>, Coord)>>,
+    /// `<when>` elements of the `<gx:Track>` being read.
+    track_whens: Vec<DateTime<FixedOffset>>,
+    /// `<gx:coord>` elements of the `<gx:Track>` being read.
+    track_coords: Vec<Coord>,
+}
+
+impl Placemark {
+    /// Pair up the `<when>` and `<gx:coord>` elements of the track that just ended.
+    fn finish_track(&mut self) -> Result<()> {
+        ensure!(
+            self.track_whens.len() == self.track_coords.len(),
+            format!(
+                "gx:Track has {} <when> but {} <gx:coord> elements",
+                self.track_whens.len(),
+                self.track_coords.len()
+            )
+        );
+        let whens = std::mem::take(&mut self.track_whens);
+        let coords = std::mem::take(&mut self.track_coords);
+        self.tracks.push(whens.into_iter().zip(coords).collect());
+        Ok(())
+    }
+
+    /// Convert everything in the placemark to locations.
+    /// - `gx:Track` points use their own `<when>`.
+    /// - `Point`s use the `TimeStamp`, or the start of the `TimeSpan`.
+    /// - `LineString`s only have their first and last vertex recorded, at the start and end of the
+    ///   `TimeSpan`, since KML has no per-vertex times and any for the vertices between would be
+    ///   made up. A single vertex may use the `TimeStamp` instead.
+    ///
+    /// Geometry that cannot be placed in time is skipped.
+    fn into_locations(self, username: &str) -> Vec<Location> {
+        let make = |time: DateTime<FixedOffset>, coord: Coord| Location {
+            username: username.to_string(),
+            time_utc: time.to_utc(),
+            time_local: time,
+            latitude: coord.latitude,
+            longitude: coord.longitude,
+            altitude: coord.altitude.unwrap_or(0.0),
+            accuracy: None,
+            source: Source::Kml,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        };
+        let mut locations = Vec::new();
+        for track in self.tracks {
+            locations.extend(track.into_iter().map(|(time, coord)| make(time, coord)));
+        }
+        let time = self.when.or(self.begin);
+        for coord in self.points {
+            match time {
+                Some(time) => locations.push(make(time, coord)),
+                None => debug!("Skipping KML Point without a time: {:?}", coord),
+            }
+        }
+        for line in self.lines {
+            match (self.begin, self.end, time) {
+                (Some(begin), Some(end), _) if line.len() > 1 && end > begin => {
+                    debug!(
+                        "Skipping {} untimed vertices between the ends of a KML LineString",
+                        line.len() - 2
+                    );
+                    locations.push(make(begin, line[0]));
+                    locations.push(make(end, line[line.len() - 1]));
+                }
+                (_, _, Some(time)) if line.len() == 1 => locations.push(make(time, line[0])),
+                _ => debug!("Skipping KML LineString without a time span: {:?}", line),
+            }
+        }
+        locations
+    }
+}
+
+/// Parse a KML document.
+/// # Arguments
+/// * `data` - The KML document.
+/// * `username` - The username to associate with the locations.
+/// # Return
+/// The locations, placemark by placemark in the order they appear in the document.
+fn parse_kml(data: &[u8], username: &str) -> Result<Vec<Location>> {
+    let mut reader = Reader::from_reader(data);
+    reader.config_mut().trim_text(true);
+    let mut buf = Vec::new();
+    // local names of the open elements
+    let mut stack: Vec<Vec<u8>> = Vec::new();
+    let mut placemark: Option<Placemark> = None;
+    let mut locations = Vec::new();
+    loop {
+        buf.clear();
+        let event = reader.read_event_into(&mut buf).map_err(|e| {
+            eyre!(
+                "Failed to parse KML at byte {}: {}",
+                reader.buffer_position(),
+                e
+            )
+        })?;
+        match event {
+            Event::Start(element) => {
+                let name = element.local_name().as_ref().to_vec();
+                if name == b"Placemark" {
+                    placemark = Some(Placemark::default());
+                }
+                stack.push(name);
+            }
+            Event::End(_) => {
+                let name = stack.pop().unwrap_or_default();
+                match name.as_slice() {
+                    b"Track" => {
+                        if let Some(placemark) = placemark.as_mut() {
+                            placemark.finish_track()?;
+                        }
+                    }
+                    b"Placemark" => {
+                        if let Some(placemark) = placemark.take() {
+                            locations.extend(placemark.into_locations(username));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Text(text) => {
+                let Some(placemark) = placemark.as_mut() else {
+                    continue;
+                };
+                let text = text
+                    .unescape()
+                    .map_err(|e| eyre!("Invalid text in KML: {}", e))?;
+                let text = text.trim();
+                let parent = stack.iter().rev().nth(1).map(Vec::as_slice);
+                match stack.last().map(Vec::as_slice) {
+                    Some(b"when") if matches!(parent, Some(b"Track")) => {
+                        placemark.track_whens.push(parse_time(text)?)
+                    }
+                    Some(b"when") => placemark.when = Some(parse_time(text)?),
+                    Some(b"begin") => placemark.begin = Some(parse_time(text)?),
+                    Some(b"end") => placemark.end = Some(parse_time(text)?),
+                    Some(b"coord") => placemark.track_coords.push(parse_gx_coord(text)?),
+                    Some(b"coordinates") => match parent {
+                        Some(b"Point") => placemark.points.extend(parse_coordinates(text)?),
+                        Some(b"LineString") => placemark.lines.push(parse_coordinates(text)?),
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(locations)
+}
+
+/// Most bytes the KML document inside a KMZ archive may extract to. The document is kept in
+/// memory, so a small archive must not be able to unpack to more than that.
+const MAX_KMZ_DOCUMENT_BYTES: u64 = 256 << 20;
+
+/// Read a KML file, or the first KML document inside a KMZ archive.
+/// # Arguments
+/// * `path` - The path to the KML or KMZ file.
+/// * `max_bytes` - Most bytes the document in a KMZ archive may extract to
+/// # Return
+/// The contents of the KML document.
+fn read_document(path: &Path, max_bytes: u64) -> Result<Vec<u8>> {
+    let data = std::fs::read(path).map_err(|e| eyre!("Failed to read KML file: {}", e))?;
+    // KMZ files are zip archives, which always start with a local file header
+    if !data.starts_with(b"PK\x03\x04") {
+        return Ok(data);
+    }
+    let mut archive =
+        ZipArchive::new(Cursor::new(data)).map_err(|e| eyre!("Failed to open KMZ file: {}", e))?;
+    for i in 0..archive.len() {
+        let file = archive
+            .by_index(i)
+            .map_err(|e| eyre!("Failed to read KMZ entry: {}", e))?;
+        if file.name().to_lowercase().ends_with(".kml") {
+            let name = file.name().to_string();
+            let mut kml = Vec::new();
+            // one byte over the limit is enough to tell that it is exceeded
+            file.take(max_bytes + 1)
+                .read_to_end(&mut kml)
+                .map_err(|e| eyre!("Failed to extract {} from KMZ: {}", name, e))?;
+            ensure!(
+                kml.len() as u64 <= max_bytes,
+                format!("{} in KMZ extracts to more than {} bytes", name, max_bytes)
+            );
+            return Ok(kml);
+        }
+    }
+    Err(eyre!("KMZ file contains no KML document"))
+}
+
+/// Read a KML or KMZ file and return an iterator of `Location` structs. The document is parsed up
+/// front, since timestamps may appear after the geometry they apply to.
+/// # Arguments
+/// * `path` - The path to the KML or KMZ file.
+/// * `username` - The username to associate with the locations.
+/// # Return
+/// An iterator of `Location` structs.
+pub fn read_kml(path: &Path, username: &str) -> Result<impl Iterator<Item = Result<Location>>> {
+    let data = read_document(path, MAX_KMZ_DOCUMENT_BYTES)?;
+    let locations = parse_kml(&data, username)?;
+    Ok(locations.into_iter().map(Ok))
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    static KML_DATA: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
+  <Document>
+    <name>Location history</name>
+    <Placemark>
+      <name>Home</name>
+      <TimeSpan>
+        <begin>2023-10-07T10:00:00+02:00</begin>
+        <end>2023-10-07T12:00:00+02:00</end>
+      </TimeSpan>
+      <Point><coordinates>11.5756,48.1372,0</coordinates></Point>
+    </Placemark>
+    <Placemark>
+      <name>Driving</name>
+      <TimeSpan>
+        <begin>2023-10-07T12:00:00Z</begin>
+        <end>2023-10-07T12:30:00Z</end>
+      </TimeSpan>
+      <LineString>
+        <coordinates>
+          11.5756,48.1372,520 11.5500,48.1300,530
+          11.5167,48.1173,545
+        </coordinates>
+      </LineString>
+    </Placemark>
+    <Placemark>
+      <gx:Track>
+        <when>2023-10-07T13:00:00Z</when>
+        <when>2023-10-07T13:00:10Z</when>
+        <gx:coord>11.5167 48.1173 545.4</gx:coord>
+        <gx:coord>11.5168 48.1172 546</gx:coord>
+      </gx:Track>
+    </Placemark>
+    <Placemark>
+      <name>No time</name>
+      <Point><coordinates>1,2</coordinates></Point>
+    </Placemark>
+  </Document>
+</kml>
+"#;
+
+    static USERNAME: &str = "test_user";
+
+    #[test]
+    fn test_parse_coordinates() {
+        assert_eq!(
+            parse_coordinates(" 11.5,48.1,520\n11.6,48.2 ").unwrap(),
+            vec![
+                Coord {
+                    longitude: 11.5,
+                    latitude: 48.1,
+                    altitude: Some(520.0)
+                },
+                Coord {
+                    longitude: 11.6,
+                    latitude: 48.2,
+                    altitude: None
+                },
+            ]
+        );
+        assert_eq!(
+            parse_gx_coord("11.5 48.1 520").unwrap(),
+            Coord {
+                longitude: 11.5,
+                latitude: 48.1,
+                altitude: Some(520.0)
+            }
+        );
+        assert!(parse_coordinates("11.5").is_err());
+        assert!(parse_gx_coord("east north").is_err());
+    }
+
+    #[test]
+    fn test_read_kml() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(KML_DATA.as_bytes()).unwrap();
+        let locations = read_kml(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap();
+        assert_eq!(locations.len(), 5);
+
+        // point at the start of its span
+        assert_eq!(locations[0].username, USERNAME);
+        assert_eq!(
+            locations[0].time_local.to_rfc3339(),
+            "2023-10-07T10:00:00+02:00"
+        );
+        assert_eq!(locations[0].latitude, 48.1372);
+        assert_eq!(locations[0].longitude, 11.5756);
+        assert_eq!(locations[0].source, Source::Kml);
+
+        // line string only at the ends of its span
+        assert_eq!(
+            locations[1].time_utc.to_rfc3339(),
+            "2023-10-07T12:00:00+00:00"
+        );
+        assert_eq!(locations[1].altitude, 520.0);
+        assert_eq!(
+            locations[2].time_utc.to_rfc3339(),
+            "2023-10-07T12:30:00+00:00"
+        );
+        assert_eq!(locations[2].latitude, 48.1173);
+        assert_eq!(locations[2].altitude, 545.0);
+
+        // gx:Track
+        assert_eq!(
+            locations[3].time_utc.to_rfc3339(),
+            "2023-10-07T13:00:00+00:00"
+        );
+        assert_eq!(locations[3].latitude, 48.1173);
+        assert_eq!(locations[3].longitude, 11.5167);
+        assert_eq!(locations[3].altitude, 545.4);
+        assert_eq!(
+            locations[4].time_utc.to_rfc3339(),
+            "2023-10-07T13:00:10+00:00"
+        );
+        assert_eq!(locations[4].altitude, 546.0);
+    }
+
+    #[test]
+    fn test_read_kmz() {
+        let file = NamedTempFile::new().unwrap();
+        {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(file.path()).unwrap());
+            zip.start_file("files/icon.png", zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(b"not a png").unwrap();
+            zip.start_file("doc.kml", zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(KML_DATA.as_bytes()).unwrap();
+            zip.finish().unwrap();
+        }
+        let locations = read_kml(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap();
+        assert_eq!(locations.len(), 5);
+        // the document may only extract to so much, however small the archive is
+        assert!(read_document(file.path(), KML_DATA.len() as u64).is_ok());
+        assert!(read_document(file.path(), KML_DATA.len() as u64 - 1).is_err());
+    }
+
+    #[test]
+    fn test_mismatched_track() {
+        let kml = r#"<kml><Placemark><gx:Track><when>2023-10-07T13:00:00Z</when></gx:Track></Placemark></kml>"#;
+        assert!(parse_kml(kml.as_bytes(), USERNAME).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use color_eyre::eyre::{ensure, eyre, Result};
+use log::debug;
+use tempfile::TempDir;
+use zip::ZipArchive;
+
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+pub mod exif;
+pub mod fit;
+pub mod geojson;
+pub mod gpx;
+pub mod kml;
+pub mod nmea;
+pub mod tcx;
+pub mod video;
+
+/// Parse an XML schema `dateTime`, as used by GPX and KML. Offsets are kept when present, and times
+/// with no zone at all are assumed to be UTC.
+/// # Arguments
+/// * `s` - The contents of a time element.
+/// # Return
+/// The time, with the offset as written in the file.
+pub(crate) fn parse_time(s: &str) -> Result<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(s).or_else(|_| {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f")
+            .map(|time| time.and_utc().fixed_offset())
+            .map_err(|e| eyre!("Invalid time: {}: {}", s, e))
+    })
+}
+
+/// A file found by `find_files`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoundFile {
+    /// Where the file is on disk, possibly in a temporary directory.
+    pub path: PathBuf,
+    /// The name to show for the file, relative to the searched path, with archives treated as
+    /// directories.
+    pub name: PathBuf,
+}
+
+/// Whether a path is a ZIP archive, by its extension. KMZ files are ZIP archives too, but are read
+/// by `kml::read_kml` as a whole.
+fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("zip"))
+}
+
+/// Most entries the ZIP archives found by one `find_files` may hold, all together.
+const MAX_ZIP_ENTRIES: usize = 100_000;
+/// Most bytes the ZIP archives found by one `find_files` may extract to, all together, unless
+/// configured otherwise.
+pub const DEFAULT_MAX_ZIP_BYTES: u64 = 1 << 30;
+
+/// What is left of the limits on extracting ZIP archives. Shared by every archive found by one
+/// `find_files`, nested ones included, so that a ZIP bomb cannot fill the disk.
+#[derive(Debug, Clone, Copy)]
+struct ZipBudget {
+    entries: usize,
+    bytes: u64,
+}
+
+impl ZipBudget {
+    fn new(max_bytes: u64) -> Self {
+        ZipBudget {
+            entries: MAX_ZIP_ENTRIES,
+            bytes: max_bytes,
+        }
+    }
+}
+
+/// Extract a ZIP archive into a directory, within the budget. Entries are written one by one and
+/// counted as they are decompressed, since the sizes an archive declares may be forged. Entries
+/// whose names would escape the directory are skipped.
+fn extract_zip(path: &Path, dir: &Path, budget: &mut ZipBudget) -> Result<()> {
+    let file = File::open(path).map_err(|e| eyre!("Failed to open {}: {}", path.display(), e))?;
+    let mut archive = ZipArchive::new(file)
+        .map_err(|e| eyre!("Failed to read ZIP archive {}: {}", path.display(), e))?;
+    ensure!(
+        archive.len() <= budget.entries,
+        format!(
+            "ZIP archive {} has too many entries: {}",
+            path.display(),
+            archive.len()
+        )
+    );
+    budget.entries -= archive.len();
+    let error = |e: &dyn std::fmt::Display| {
+        eyre!("Failed to extract ZIP archive {}: {}", path.display(), e)
+    };
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| error(&e))?;
+        let Some(name) = entry.enclosed_name() else {
+            debug!("Skipping unsafe ZIP entry {}", entry.name());
+            continue;
+        };
+        let dest = dir.join(name);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&dest).map_err(|e| error(&e))?;
+            continue;
+        }
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| error(&e))?;
+        }
+        let mut out = File::create(&dest).map_err(|e| error(&e))?;
+        // one byte over the budget is enough to tell that it is exceeded
+        let written = std::io::copy(&mut (&mut entry).take(budget.bytes + 1), &mut out)
+            .map_err(|e| error(&e))?;
+        ensure!(
+            written <= budget.bytes,
+            format!(
+                "ZIP archive {} extracts to more than the {} bytes left of the limit",
+                path.display(),
+                budget.bytes
+            )
+        );
+        budget.bytes -= written;
+    }
+    Ok(())
+}
+
+/// Find every file below a path, so they can be imported one by one. Directories are searched
+/// recursively in name order, skipping hidden entries, and ZIP archives are extracted to temporary
+/// directories and searched in turn, up to `MAX_ZIP_ENTRIES` entries and `max_zip_bytes` bytes
+/// in all.
+/// # Arguments
+/// * `path` - A file, directory, or ZIP archive.
+/// * `max_zip_bytes` - Most bytes the archives may extract to, all together.
+/// * `temp_dirs` - Holds the directories archives are extracted to, which must outlive the import.
+/// # Return
+/// Every file found, in name order.
+pub fn find_files(
+    path: &Path,
+    max_zip_bytes: u64,
+    temp_dirs: &mut Vec<TempDir>,
+) -> Result<Vec<FoundFile>> {
+    let mut files = Vec::new();
+    let name = PathBuf::from(path.file_name().unwrap_or(path.as_os_str()));
+    let mut budget = ZipBudget::new(max_zip_bytes);
+    find_files_into(path, name, temp_dirs, &mut budget, &mut files)?;
+    Ok(files)
+}
+
+fn find_files_into(
+    path: &Path,
+    name: PathBuf,
+    temp_dirs: &mut Vec<TempDir>,
+    budget: &mut ZipBudget,
+    files: &mut Vec<FoundFile>,
+) -> Result<()> {
+    if path.is_dir() {
+        find_files_in_dir(path, &name, temp_dirs, budget, files)
+    } else if is_zip(path) {
+        let dir =
+            TempDir::new().map_err(|e| eyre!("Failed to create temporary directory: {}", e))?;
+        let dir_path = dir.path().to_path_buf();
+        // kept even if extraction fails, so that it is cleaned up along with the others
+        temp_dirs.push(dir);
+        extract_zip(path, &dir_path, budget)?;
+        find_files_in_dir(&dir_path, &name, temp_dirs, budget, files)
+    } else if path.is_file() {
+        files.push(FoundFile {
+            path: path.to_path_buf(),
+            name,
+        });
+        Ok(())
+    } else {
+        Err(eyre!("No such file or directory: {}", path.display()))
+    }
+}
+
+fn find_files_in_dir(
+    dir: &Path,
+    name: &Path,
+    temp_dirs: &mut Vec<TempDir>,
+    budget: &mut ZipBudget,
+    files: &mut Vec<FoundFile>,
+) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(|e| eyre!("Failed to read directory {}: {}", dir.display(), e))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()
+        .map_err(|e| eyre!("Failed to read directory {}: {}", dir.display(), e))?;
+    entries.sort();
+    for entry in entries {
+        let Some(file_name) = entry.file_name() else {
+            continue;
+        };
+        // Hidden files, and the resource forks macOS adds to archives
+        if file_name.to_string_lossy().starts_with('.') || file_name == "__MACOSX" {
+            continue;
+        }
+        let metadata = std::fs::symlink_metadata(&entry)
+            .map_err(|e| eyre!("Failed to read metadata of {}: {}", entry.display(), e))?;
+        if metadata.file_type().is_symlink() && !entry.is_file() {
+            debug!("Skipping symbolic link {}", entry.display());
+            continue;
+        }
+        find_files_into(&entry, name.join(file_name), temp_dirs, budget, files)?;
+    }
+    Ok(())
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("2023-10-07T12:35:19+02:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2023-10-07T12:35:19+02:00"
+        );
+        assert_eq!(
+            parse_time("2023-10-07T12:35:19.5").unwrap().to_rfc3339(),
+            "2023-10-07T12:35:19.500+00:00"
+        );
+        assert!(parse_time("noon").is_err());
+    }
+
+    #[test]
+    fn test_find_files() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("gpslogger");
+        std::fs::create_dir_all(root.join("2025/.thumbnails")).unwrap();
+        std::fs::write(root.join("20250124.csv"), "a").unwrap();
+        std::fs::write(root.join(".hidden.csv"), "b").unwrap();
+        std::fs::write(root.join("2025/.thumbnails/x.jpg"), "c").unwrap();
+        std::fs::write(root.join("2025/track.gpx"), "d").unwrap();
+        {
+            let mut zip = zip::ZipWriter::new(File::create(root.join("2025/sent.zip")).unwrap());
+            let options = zip::write::SimpleFileOptions::default();
+            zip.start_file("20250125.csv", options).unwrap();
+            zip.write_all(b"e").unwrap();
+            zip.start_file("__MACOSX/._20250125.csv", options).unwrap();
+            zip.write_all(b"f").unwrap();
+            zip.start_file("nested/20250126.csv", options).unwrap();
+            zip.write_all(b"g").unwrap();
+            zip.finish().unwrap();
+        }
+        // A link back up the tree is not followed round in circles
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&root, root.join("2025/loop")).unwrap();
+
+        let mut temp_dirs = Vec::new();
+        let files = find_files(&root, DEFAULT_MAX_ZIP_BYTES, &mut temp_dirs).unwrap();
+        let names: Vec<PathBuf> = files.iter().map(|file| file.name.clone()).collect();
+        assert_eq!(
+            names,
+            vec![
+                PathBuf::from("gpslogger/2025/sent.zip/20250125.csv"),
+                PathBuf::from("gpslogger/2025/sent.zip/nested/20250126.csv"),
+                PathBuf::from("gpslogger/2025/track.gpx"),
+                PathBuf::from("gpslogger/20250124.csv"),
+            ]
+        );
+        assert_eq!(temp_dirs.len(), 1);
+        assert_eq!(std::fs::read_to_string(&files[1].path).unwrap(), "g");
+        assert_eq!(files[3].path, root.join("20250124.csv"));
+
+        // A single file is returned as is
+        let files = find_files(
+            &root.join("20250124.csv"),
+            DEFAULT_MAX_ZIP_BYTES,
+            &mut temp_dirs,
+        )
+        .unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, PathBuf::from("20250124.csv"));
+
+        assert!(find_files(&root.join("missing"), DEFAULT_MAX_ZIP_BYTES, &mut temp_dirs).is_err());
+
+        // Archives may only extract to so much, however small they are themselves
+        assert!(find_files(&root, 2, &mut temp_dirs).is_err());
+        let zip_path = root.join("2025/sent.zip");
+        let mut budget = ZipBudget {
+            entries: 3,
+            bytes: 1,
+        };
+        assert!(find_files_into(
+            &zip_path,
+            PathBuf::new(),
+            &mut temp_dirs,
+            &mut budget,
+            &mut Vec::new()
+        )
+        .is_err());
+        let mut budget = ZipBudget {
+            entries: 2,
+            bytes: 100,
+        };
+        assert!(find_files_into(
+            &zip_path,
+            PathBuf::new(),
+            &mut temp_dirs,
+            &mut budget,
+            &mut Vec::new()
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,525 @@
+use chrono::{Days, NaiveDate, NaiveTime, TimeDelta};
+use color_eyre::eyre::{ensure, eyre, Result};
+use log::{debug, warn};
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use crate::schema::{Location, Source};
+
+/// A line of the log that could not be used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorruptLine {
+    /// 1-based line number in the file.
+    pub line: usize,
+    /// Why the line was rejected.
+    pub reason: String,
+}
+
+/// A position fix, from GGA or RMC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Position {
+    latitude: f64,
+    longitude: f64,
+}
+
+/// The sentences of one fix, all sharing the same UTC time of day.
+#[derive(Debug, Clone, PartialEq)]
+struct Epoch {
+    time: NaiveTime,
+    /// The date from RMC, if it was part of this epoch.
+    date: Option<NaiveDate>,
+    /// Position, altitude and HDOP from GGA.
+    gga: Option<(Position, Option<f64>, Option<f32>)>,
+    /// Position from RMC.
+    rmc: Option<Position>,
+    /// PDOP, HDOP and VDOP from GSA. Its HDOP is used if GGA had none.
+    gsa_dops: (Option<f32>, Option<f32>, Option<f32>),
+    /// Whether any sentence reported there was no fix.
+    no_fix: bool,
+}
+
+impl Epoch {
+    fn new(time: NaiveTime) -> Self {
+        Epoch {
+            time,
+            date: None,
+            gga: None,
+            rmc: None,
+            gsa_dops: (None, None, None),
+            no_fix: false,
+        }
+    }
+
+    /// Convert the epoch to a Location struct, if it has a fix. NMEA times are always UTC.
+    fn to_location(&self, username: &str, date: NaiveDate) -> Option<Location> {
+        if self.no_fix {
+            return None;
+        }
+        let (position, altitude, hdop) = match (self.gga, self.rmc) {
+            (Some((position, altitude, hdop)), _) => (position, altitude, hdop),
+            (None, Some(position)) => (position, None, None),
+            (None, None) => return None,
+        };
+        let (pdop, gsa_hdop, vdop) = self.gsa_dops;
+        let hdop = hdop.or(gsa_hdop);
+        let time_utc = date.and_time(self.time).and_utc();
+        Some(Location {
+            username: username.to_string(),
+            time_utc,
+            time_local: time_utc.fixed_offset(),
+            latitude: position.latitude,
+            longitude: position.longitude,
+            altitude: altitude.unwrap_or(0.0),
+            accuracy: hdop,
+            source: Source::Nmea,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop,
+            vdop,
+            pdop,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        })
+    }
+}
+
+/// Verify the checksum of a sentence and split it into its fields, the first being the address,
+/// such as `GPGGA`. Anything before the `$` is ignored, since some loggers prefix each line with
+/// their own timestamp.
+fn parse_sentence(line: &str) -> Result<Vec<&str>> {
+    let start = line
+        .find('$')
+        .ok_or_else(|| eyre!("Not an NMEA sentence"))?;
+    let (body, checksum) = line[start + 1..]
+        .trim_end()
+        .split_once('*')
+        .ok_or_else(|| eyre!("Missing checksum"))?;
+    let expected = u8::from_str_radix(checksum, 16)
+        .map_err(|e| eyre!("Invalid checksum: {}: {}", checksum, e))?;
+    let actual = body.bytes().fold(0, |acc, byte| acc ^ byte);
+    ensure!(
+        actual == expected,
+        format!(
+            "Checksum mismatch: expected {:02X}, computed {:02X}",
+            expected, actual
+        )
+    );
+    Ok(body.split(',').collect())
+}
+
+/// Get a field, which may be missing or empty.
+fn field<'a>(fields: &[&'a str], i: usize) -> Option<&'a str> {
+    fields.get(i).copied().filter(|s| !s.is_empty())
+}
+
+/// Parse an optional numeric field.
+fn number(fields: &[&str], i: usize) -> Result<Option<f64>> {
+    field(fields, i)
+        .map(|s| {
+            s.parse::<f64>()
+                .map_err(|e| eyre!("Invalid number: {}: {}", s, e))
+        })
+        .transpose()
+}
+
+/// Parse a `hhmmss.sss` time field.
+fn parse_time(s: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H%M%S%.f").map_err(|e| eyre!("Invalid time: {}: {}", s, e))
+}
+
+/// Parse a `ddmmyy` date field.
+fn parse_date(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%d%m%y").map_err(|e| eyre!("Invalid date: {}: {}", s, e))
+}
+
+/// Parse a `(d)ddmm.mmmm` coordinate and its hemisphere.
+fn parse_coordinate(value: &str, hemisphere: &str) -> Result<f64> {
+    let value = value
+        .parse::<f64>()
+        .map_err(|e| eyre!("Invalid coordinate: {}: {}", value, e))?;
+    let degrees = (value / 100.0).trunc();
+    let coordinate = degrees + (value - degrees * 100.0) / 60.0;
+    match hemisphere {
+        "N" | "E" => Ok(coordinate),
+        "S" | "W" => Ok(-coordinate),
+        _ => Err(eyre!("Invalid hemisphere: {}", hemisphere)),
+    }
+}
+
+/// Parse the position at fields `i` to `i + 3`, if present.
+fn parse_position(fields: &[&str], i: usize) -> Result<Option<Position>> {
+    match (
+        field(fields, i),
+        field(fields, i + 1),
+        field(fields, i + 2),
+        field(fields, i + 3),
+    ) {
+        (Some(lat), Some(ns), Some(lon), Some(ew)) => Ok(Some(Position {
+            latitude: parse_coordinate(lat, ns)?,
+            longitude: parse_coordinate(lon, ew)?,
+        })),
+        _ => Ok(None),
+    }
+}
+
+/// Streams fixes out of an NMEA 0183 log. GGA sentences give the position, altitude and HDOP, RMC
+/// the date (and a position, if there is no GGA), and GSA the PDOP, HDOP and VDOP and whether
+/// there is a fix at all. Sentences are grouped into epochs by their time of day, and an epoch becomes a location
+/// once a sentence with a different time arrives.
+///
+/// Lines that fail their checksum, are not valid UTF-8 or cannot be parsed are skipped and
+/// recorded, rather than aborting the import, since loggers routinely write a truncated sentence
+/// when they lose power.
+pub struct NmeaReader {
+    reader: BufReader<File>,
+    /// The raw bytes of the line being read, reused between lines.
+    buffer: Vec<u8>,
+    line: usize,
+    username: String,
+    /// The epoch being assembled.
+    epoch: Option<Epoch>,
+    /// Completed epochs waiting for the first date in the file.
+    undated: Vec<Epoch>,
+    /// The date and time of the most recent epoch with a date, to carry the date forward.
+    last_dated: Option<(NaiveDate, NaiveTime)>,
+    /// Locations ready to be returned.
+    ready: VecDeque<Location>,
+    corrupt: Vec<CorruptLine>,
+    /// Set after the end of the file or an error.
+    done: bool,
+}
+
+impl NmeaReader {
+    /// The lines skipped so far because they were corrupt.
+    pub fn corrupt_lines(&self) -> &[CorruptLine] {
+        &self.corrupt
+    }
+
+    /// Handle one line of the log.
+    fn read_line(&mut self, line: &str) -> Result<()> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        let fields = parse_sentence(line)?;
+        let address = fields[0];
+        ensure!(
+            address.len() == 5 && address.is_ascii(),
+            format!("Invalid sentence address: {}", address)
+        );
+        match &address[2..] {
+            "GGA" => {
+                let time = parse_time(field(&fields, 1).ok_or_else(|| eyre!("GGA has no time"))?)?;
+                let position = parse_position(&fields, 2)?;
+                let quality = number(&fields, 6)?;
+                let hdop = number(&fields, 8)?.map(|hdop| hdop as f32);
+                let altitude = number(&fields, 9)?;
+                let epoch = self.epoch_at(time);
+                match (position, quality) {
+                    (Some(position), Some(quality)) if quality > 0.0 => {
+                        epoch.gga = Some((position, altitude, hdop))
+                    }
+                    _ => epoch.no_fix = true,
+                }
+            }
+            "RMC" => {
+                let time = parse_time(field(&fields, 1).ok_or_else(|| eyre!("RMC has no time"))?)?;
+                let position = parse_position(&fields, 3)?;
+                let date = field(&fields, 9).map(parse_date).transpose()?;
+                let valid = field(&fields, 2) == Some("A");
+                let epoch = self.epoch_at(time);
+                epoch.date = date;
+                match position {
+                    Some(position) if valid => epoch.rmc = Some(position),
+                    _ => epoch.no_fix = true,
+                }
+            }
+            "GSA" => {
+                // GSA has no time, so belongs to the epoch being assembled
+                if let Some(epoch) = self.epoch.as_mut() {
+                    if number(&fields, 2)? == Some(1.0) {
+                        epoch.no_fix = true;
+                    }
+                    let dop = |i| number(&fields, i).map(|dop| dop.map(|dop| dop as f32));
+                    epoch.gsa_dops = (dop(15)?, dop(16)?, dop(17)?);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Get the epoch for a time of day, completing the current one if the time differs.
+    fn epoch_at(&mut self, time: NaiveTime) -> &mut Epoch {
+        if self.epoch.as_ref().is_some_and(|epoch| epoch.time != time) {
+            let epoch = self.epoch.take().unwrap();
+            self.complete(epoch);
+        }
+        self.epoch.get_or_insert_with(|| Epoch::new(time))
+    }
+
+    /// Date a finished epoch and queue its location. Epochs without an RMC take the date of the
+    /// previous one, rolling over to the next day if the time of day went backwards.
+    fn complete(&mut self, epoch: Epoch) {
+        let date = match (epoch.date, self.last_dated) {
+            (Some(date), _) => date,
+            (None, Some((date, time))) if epoch.time < time => {
+                date.checked_add_days(Days::new(1)).unwrap_or(date)
+            }
+            (None, Some((date, _))) => date,
+            (None, None) => {
+                self.undated.push(epoch);
+                return;
+            }
+        };
+        self.last_dated = Some((date, epoch.time));
+        for undated in std::mem::take(&mut self.undated) {
+            // Shortly before the first date, possibly on the previous day
+            let undated_date =
+                if undated.time > epoch.time && undated.time - epoch.time > TimeDelta::hours(12) {
+                    date.checked_sub_days(Days::new(1)).unwrap_or(date)
+                } else {
+                    date
+                };
+            self.queue(&undated, undated_date);
+        }
+        self.queue(&epoch, date);
+    }
+
+    fn queue(&mut self, epoch: &Epoch, date: NaiveDate) {
+        match epoch.to_location(&self.username, date) {
+            Some(location) => self.ready.push_back(location),
+            None => debug!("Skipping NMEA epoch without a fix at {}", epoch.time),
+        }
+    }
+}
+
+impl Iterator for NmeaReader {
+    type Item = Result<Location>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(location) = self.ready.pop_front() {
+                return Some(Ok(location));
+            }
+            if self.done {
+                return None;
+            }
+            let mut buffer = std::mem::take(&mut self.buffer);
+            buffer.clear();
+            match self.reader.read_until(b'\n', &mut buffer) {
+                Ok(0) => {
+                    self.done = true;
+                    if let Some(epoch) = self.epoch.take() {
+                        self.complete(epoch);
+                    }
+                    if !self.undated.is_empty() {
+                        warn!(
+                            "Skipping {} NMEA fixes, since the log has no RMC date",
+                            self.undated.len()
+                        );
+                    }
+                }
+                Ok(_) => {
+                    self.line += 1;
+                    let result = std::str::from_utf8(&buffer)
+                        .map_err(|e| eyre!("Line is not valid UTF-8: {}", e))
+                        .and_then(|line| self.read_line(line.trim_end_matches(['\r', '\n'])));
+                    if let Err(e) = result {
+                        warn!("Skipping corrupt NMEA line {}: {}", self.line, e);
+                        self.corrupt.push(CorruptLine {
+                            line: self.line,
+                            reason: e.to_string(),
+                        });
+                    }
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(eyre!(
+                        "Failed to read NMEA line {}: {}",
+                        self.line + 1,
+                        e
+                    )));
+                }
+            }
+            self.buffer = buffer;
+        }
+    }
+}
+
+/// Read an NMEA 0183 log and return an iterator of `Location` structs. Does not load the entire
+/// file into memory.
+/// # Arguments
+/// * `path` - The path to the NMEA log.
+/// * `username` - The username to associate with the locations.
+/// # Return
+/// A reader yielding `Location` structs in the order they were logged, which also keeps track of
+/// the corrupt lines it skipped.
+pub fn read_nmea(path: &Path, username: &str) -> Result<NmeaReader> {
+    let file = File::open(path).map_err(|e| eyre!("Failed to open NMEA log: {}", e))?;
+    Ok(NmeaReader {
+        reader: BufReader::new(file),
+        buffer: Vec::new(),
+        line: 0,
+        username: username.to_string(),
+        epoch: None,
+        undated: Vec::new(),
+        last_dated: None,
+        ready: VecDeque::new(),
+        corrupt: Vec::new(),
+        done: false,
+    })
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    static USERNAME: &str = "test_user";
+
+    /// Add the checksum to a sentence.
+    fn sentence(body: &str) -> String {
+        let checksum = body.bytes().fold(0, |acc, byte| acc ^ byte);
+        format!("${}*{:02X}", body, checksum)
+    }
+
+    fn create_nmea(lines: &[String]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(lines.join("\r\n").as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_parse_sentence() {
+        // A real sentence
+        let fields =
+            parse_sentence("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47")
+                .unwrap();
+        assert_eq!(fields[0], "GPGGA");
+        assert_eq!(fields[9], "545.4");
+        // GPSLogger prefixes its own timestamp
+        assert!(parse_sentence(&format!("1696682119000,{}", sentence("GPGSA,A,3"))).is_ok());
+        assert!(parse_sentence(
+            "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*48"
+        )
+        .is_err());
+        assert!(parse_sentence("$GPGGA,123519,4807.038,N,01131.000,E,1,08").is_err());
+        assert!(parse_sentence("garbage").is_err());
+    }
+
+    #[test]
+    fn test_parse_coordinate() {
+        assert!((parse_coordinate("4807.038", "N").unwrap() - 48.1173).abs() < 1e-9);
+        assert!((parse_coordinate("01131.000", "W").unwrap() + 11.516_666_666).abs() < 1e-6);
+        assert!(parse_coordinate("4807.038", "X").is_err());
+    }
+
+    #[test]
+    fn test_read_nmea() {
+        let lines = vec![
+            // GGA before the first RMC, so its date comes later
+            sentence("GPGGA,235959.000,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"),
+            sentence("GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1"),
+            sentence("GPRMC,235959.000,A,4807.038,N,01131.000,E,022.4,084.4,071023,003.1,W"),
+            // A truncated line
+            "$GPGGA,000000.000,4807.0".to_string(),
+            // Past midnight, without RMC, and with HDOP only from GSA
+            sentence("GNGGA,000001.000,4807.039,S,01131.001,W,1,08,,545.5,M,46.9,M,,"),
+            sentence("GNGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.4,2.1"),
+            // No fix
+            sentence("GPGGA,000002.000,,,,,0,00,,,M,,M,,"),
+            sentence("GPGSA,A,1,,,,,,,,,,,,,,,"),
+            // A bad checksum
+            sentence("GPGGA,000003.000,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,")
+                .replace("4807.038", "4807.039"),
+            // RMC only
+            String::new(),
+            sentence("GPRMC,000004.500,A,4807.040,N,01131.002,E,022.4,084.4,081023,003.1,W"),
+            sentence("GPVTG,084.4,T,,M,022.4,N,041.5,K"),
+        ];
+        let file = create_nmea(&lines);
+        let mut reader = read_nmea(file.path(), USERNAME).unwrap();
+        let locations = reader.by_ref().collect::<Result<Vec<Location>>>().unwrap();
+        assert_eq!(locations.len(), 3);
+
+        assert_eq!(locations[0].username, USERNAME);
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2023-10-07T23:59:59+00:00"
+        );
+        assert!((locations[0].latitude - 48.1173).abs() < 1e-9);
+        assert!((locations[0].longitude - 11.516_666_666).abs() < 1e-6);
+        assert_eq!(locations[0].altitude, 545.4);
+        assert_eq!(locations[0].accuracy, Some(0.9));
+        assert_eq!(locations[0].hdop, Some(0.9));
+        assert_eq!(locations[0].pdop, Some(2.5));
+        assert_eq!(locations[0].vdop, Some(2.1));
+        assert_eq!(locations[0].source, Source::Nmea);
+
+        assert_eq!(
+            locations[1].time_utc.to_rfc3339(),
+            "2023-10-08T00:00:01+00:00"
+        );
+        assert!(locations[1].latitude < 0.0);
+        assert!(locations[1].longitude < 0.0);
+        assert_eq!(locations[1].accuracy, Some(1.4));
+        assert_eq!(locations[1].hdop, Some(1.4));
+
+        assert_eq!(
+            locations[2].time_utc.to_rfc3339(),
+            "2023-10-08T00:00:04.500+00:00"
+        );
+        assert_eq!(locations[2].altitude, 0.0);
+        assert_eq!(locations[2].accuracy, None);
+        assert_eq!(locations[2].pdop, None);
+
+        let corrupt = reader.corrupt_lines();
+        assert_eq!(corrupt.len(), 2);
+        assert_eq!(corrupt[0].line, 4);
+        assert_eq!(corrupt[1].line, 9);
+        assert!(corrupt[1].reason.contains("Checksum"));
+    }
+
+    #[test]
+    fn test_read_nmea_invalid_utf8() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"\xFF\xFE$GPGGA,garbled\r\n").unwrap();
+        file.write_all(
+            sentence("GPRMC,235959.000,A,4807.038,N,01131.000,E,022.4,084.4,071023,003.1,W")
+                .as_bytes(),
+        )
+        .unwrap();
+        let mut reader = read_nmea(file.path(), USERNAME).unwrap();
+        let locations = reader.by_ref().collect::<Result<Vec<Location>>>().unwrap();
+        assert_eq!(locations.len(), 1);
+        let corrupt = reader.corrupt_lines();
+        assert_eq!(corrupt.len(), 1);
+        assert_eq!(corrupt[0].line, 1);
+        assert!(corrupt[0].reason.contains("UTF-8"));
+    }
+
+    #[test]
+    fn test_read_nmea_no_date() {
+        let lines = vec![sentence(
+            "GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,",
+        )];
+        let file = create_nmea(&lines);
+        let mut reader = read_nmea(file.path(), USERNAME).unwrap();
+        assert!(reader.next().is_none());
+        assert!(reader.corrupt_lines().is_empty());
+    }
+}
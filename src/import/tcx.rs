@@ -0,0 +1,276 @@
+use chrono::{DateTime, FixedOffset};
+use color_eyre::eyre::{eyre, Result};
+use log::debug;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use std::{fs::File, io::BufReader, path::Path};
+
+use crate::import::parse_time;
+use crate::schema::{Location, Source};
+
+/// Which child element of a trackpoint is currently being read.
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Time,
+    Altitude,
+    Latitude,
+    Longitude,
+}
+
+/// A `<Trackpoint>`, filled in as its children are read.
+#[derive(Debug, Default)]
+struct Trackpoint {
+    time: Option<DateTime<FixedOffset>>,
+    altitude: Option<f64>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+impl Trackpoint {
+    /// Convert the trackpoint to a Location struct, if it has a time and a position. A missing
+    /// altitude is recorded as 0.
+    fn to_location(&self, username: &str) -> Option<Location> {
+        let time = self.time?;
+        Some(Location {
+            username: username.to_string(),
+            time_utc: time.to_utc(),
+            time_local: time,
+            latitude: self.latitude?,
+            longitude: self.longitude?,
+            altitude: self.altitude.unwrap_or(0.0),
+            accuracy: None,
+            source: Source::Tcx,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        })
+    }
+}
+
+/// Streams trackpoints out of a Garmin Training Center XML file, from both activities and
+/// courses. Trackpoints without a `<Position>`, as recorded indoors or while the GPS is searching,
+/// are skipped.
+struct TcxReader {
+    reader: Reader<BufReader<File>>,
+    buf: Vec<u8>,
+    username: String,
+    /// The trackpoint being read, if inside a `<Trackpoint>`.
+    point: Option<Trackpoint>,
+    /// The child element of the trackpoint being read, if any.
+    field: Option<Field>,
+    /// Set after the end of the file or an error.
+    done: bool,
+}
+
+impl TcxReader {
+    /// Store the text of a trackpoint child element.
+    fn set_field(&mut self, field: Field, text: &str) -> Result<()> {
+        let Some(point) = self.point.as_mut() else {
+            return Ok(());
+        };
+        let number = || {
+            text.parse::<f64>()
+                .map_err(|e| eyre!("Invalid number in TCX trackpoint: {}: {}", text, e))
+        };
+        match field {
+            Field::Time => point.time = Some(parse_time(text)?),
+            Field::Altitude => point.altitude = Some(number()?),
+            Field::Latitude => point.latitude = Some(number()?),
+            Field::Longitude => point.longitude = Some(number()?),
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for TcxReader {
+    type Item = Result<Location>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            self.buf.clear();
+            let event = match self.reader.read_event_into(&mut self.buf) {
+                Ok(event) => event.into_owned(),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(eyre!(
+                        "Failed to parse TCX at byte {}: {}",
+                        self.reader.buffer_position(),
+                        e
+                    )));
+                }
+            };
+            match event {
+                Event::Start(element) => match element.local_name().as_ref() {
+                    b"Trackpoint" => self.point = Some(Trackpoint::default()),
+                    b"Time" => self.field = Some(Field::Time),
+                    b"AltitudeMeters" => self.field = Some(Field::Altitude),
+                    b"LatitudeDegrees" => self.field = Some(Field::Latitude),
+                    b"LongitudeDegrees" => self.field = Some(Field::Longitude),
+                    _ => {}
+                },
+                Event::Text(text) => {
+                    if let Some(field) = self.field {
+                        let result = text
+                            .unescape()
+                            .map_err(|e| eyre!("Invalid text in TCX trackpoint: {}", e))
+                            .and_then(|text| self.set_field(field, text.trim()));
+                        if let Err(e) = result {
+                            self.done = true;
+                            return Some(Err(e));
+                        }
+                    }
+                }
+                Event::End(element) => match element.local_name().as_ref() {
+                    b"Time" | b"AltitudeMeters" | b"LatitudeDegrees" | b"LongitudeDegrees" => {
+                        self.field = None
+                    }
+                    b"Trackpoint" => {
+                        if let Some(point) = self.point.take() {
+                            match point.to_location(&self.username) {
+                                Some(location) => return Some(Ok(location)),
+                                None => debug!("Skipping TCX trackpoint: {:?}", point),
+                            }
+                        }
+                    }
+                    _ => {}
+                },
+                Event::Eof => {
+                    self.done = true;
+                    return None;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Read a TCX file and return an iterator of `Location` structs. Does not load the entire file
+/// into memory.
+/// # Arguments
+/// * `path` - The path to the TCX file.
+/// * `username` - The username to associate with the locations.
+/// # Return
+/// An iterator of `Location` structs, in the order the trackpoints appear in the file.
+pub fn read_tcx(path: &Path, username: &str) -> Result<impl Iterator<Item = Result<Location>>> {
+    let file = File::open(path).map_err(|e| eyre!("Failed to open TCX file: {}", e))?;
+    let mut reader = Reader::from_reader(BufReader::new(file));
+    reader.config_mut().trim_text(true);
+    Ok(TcxReader {
+        reader,
+        buf: Vec::new(),
+        username: username.to_string(),
+        point: None,
+        field: None,
+        done: false,
+    })
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    static TCX_DATA: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Activities>
+    <Activity Sport="Biking">
+      <Id>2023-10-07T12:35:19Z</Id>
+      <Lap StartTime="2023-10-07T12:35:19Z">
+        <TotalTimeSeconds>20</TotalTimeSeconds>
+        <Track>
+          <Trackpoint>
+            <Time>2023-10-07T12:35:19Z</Time>
+            <Position>
+              <LatitudeDegrees>48.1173</LatitudeDegrees>
+              <LongitudeDegrees>11.5167</LongitudeDegrees>
+            </Position>
+            <AltitudeMeters>545.4</AltitudeMeters>
+            <HeartRateBpm><Value>120</Value></HeartRateBpm>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>2023-10-07T12:35:24Z</Time>
+            <HeartRateBpm><Value>121</Value></HeartRateBpm>
+          </Trackpoint>
+          <Trackpoint>
+            <Time>2023-10-07T14:35:29+02:00</Time>
+            <Position>
+              <LatitudeDegrees>-48.1172</LatitudeDegrees>
+              <LongitudeDegrees>-11.5168</LongitudeDegrees>
+            </Position>
+          </Trackpoint>
+        </Track>
+      </Lap>
+    </Activity>
+  </Activities>
+</TrainingCenterDatabase>
+"#;
+
+    static USERNAME: &str = "test_user";
+
+    fn create_tcx(data: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(data.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_read_tcx() {
+        let file = create_tcx(TCX_DATA);
+        let locations = read_tcx(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap();
+        assert_eq!(locations.len(), 2);
+
+        assert_eq!(locations[0].username, USERNAME);
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2023-10-07T12:35:19+00:00"
+        );
+        assert_eq!(locations[0].latitude, 48.1173);
+        assert_eq!(locations[0].longitude, 11.5167);
+        assert_eq!(locations[0].altitude, 545.4);
+        assert_eq!(locations[0].accuracy, None);
+        assert_eq!(locations[0].source, Source::Tcx);
+
+        assert_eq!(
+            locations[1].time_local.to_rfc3339(),
+            "2023-10-07T14:35:29+02:00"
+        );
+        assert_eq!(locations[1].latitude, -48.1172);
+        assert_eq!(locations[1].longitude, -11.5168);
+        assert_eq!(locations[1].altitude, 0.0);
+    }
+
+    #[test]
+    fn test_read_tcx_errors() {
+        let file = create_tcx(
+            r#"<TrainingCenterDatabase><Trackpoint><Time>noon</Time></Trackpoint></TrainingCenterDatabase>"#,
+        );
+        let mut iter = read_tcx(file.path(), USERNAME).unwrap();
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+        let file = create_tcx(
+            r#"<TrainingCenterDatabase><Trackpoint><Time>2023-10-07T12:35:19Z</Time><Position><LatitudeDegrees>north</LatitudeDegrees></Position></Trackpoint></TrainingCenterDatabase>"#,
+        );
+        let mut iter = read_tcx(file.path(), USERNAME).unwrap();
+        assert!(iter.next().unwrap().is_err());
+    }
+}
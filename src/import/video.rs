@@ -0,0 +1,924 @@
+use chrono::{DateTime, Duration, FixedOffset, NaiveDateTime, Utc};
+use color_eyre::eyre::{ensure, eyre, Result};
+use log::debug;
+
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::schema::{Location, LocationGen, Source};
+
+/// A four character code naming a box or GPMF key.
+type FourCC = [u8; 4];
+
+/// The user data box holding a phone's ISO 6709 location.
+const XYZ: FourCC = [0xa9, b'x', b'y', b'z'];
+
+/// Metadata keys written by iOS.
+const KEY_LOCATION: &str = "com.apple.quicktime.location.ISO6709";
+const KEY_CREATION_DATE: &str = "com.apple.quicktime.creationdate";
+
+/// Seconds from the QuickTime epoch, 1904-01-01, to the Unix epoch.
+const QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// A position recovered from a video.
+#[derive(Debug, Clone, PartialEq)]
+struct Fix {
+    time: DateTime<FixedOffset>,
+    latitude: f64,
+    longitude: f64,
+    altitude: Option<f64>,
+    pdop: Option<f32>,
+}
+
+impl LocationGen for Fix {
+    /// Convert the fix to a Location struct. A missing altitude is recorded as 0.
+    fn to_location(&self, username: &str) -> Location {
+        Location {
+            username: username.to_string(),
+            time_utc: self.time.to_utc(),
+            time_local: self.time,
+            latitude: self.latitude,
+            longitude: self.longitude,
+            altitude: self.altitude.unwrap_or(0.0),
+            accuracy: None,
+            source: Source::Video,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: self.pdop,
+            battery: None,
+            charging: None,
+            device: String::new(),
+        }
+    }
+}
+
+fn fourcc_str(kind: &FourCC) -> String {
+    String::from_utf8_lossy(kind).into_owned()
+}
+
+fn bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N]> {
+    data.get(offset..offset + N)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| eyre!("Unexpected end of data at offset {}", offset))
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Result<u16> {
+    Ok(u16::from_be_bytes(bytes(data, offset)?))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Result<u32> {
+    Ok(u32::from_be_bytes(bytes(data, offset)?))
+}
+
+fn be_u64(data: &[u8], offset: usize) -> Result<u64> {
+    Ok(u64::from_be_bytes(bytes(data, offset)?))
+}
+
+/// Iterates over the boxes directly inside a buffer, yielding the type and payload of each.
+struct Boxes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = Result<(FourCC, &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < 8 {
+            return None;
+        }
+        match split_box(self.data) {
+            Ok((kind, payload, rest)) => {
+                self.data = rest;
+                Some(Ok((kind, payload)))
+            }
+            Err(e) => {
+                self.data = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Split the first box off a buffer.
+/// # Return
+/// The type of the box, its payload, and the remainder of the buffer.
+fn split_box(data: &[u8]) -> Result<(FourCC, &[u8], &[u8])> {
+    let kind: FourCC = bytes(data, 4)?;
+    let (header, size) = match be_u32(data, 0)? {
+        0 => (8, data.len() as u64),
+        1 => (16, be_u64(data, 8)?),
+        size => (8, size as u64),
+    };
+    ensure!(
+        size >= header && size <= data.len() as u64,
+        format!("Invalid size {} of box {}", size, fourcc_str(&kind))
+    );
+    Ok((
+        kind,
+        &data[header as usize..size as usize],
+        &data[size as usize..],
+    ))
+}
+
+/// Find every box of a type directly inside a buffer.
+fn children<'a>(data: &'a [u8], kind: &FourCC) -> Result<Vec<&'a [u8]>> {
+    let mut found = Vec::new();
+    for item in (Boxes { data }) {
+        let (child_kind, payload) = item?;
+        if child_kind == *kind {
+            found.push(payload);
+        }
+    }
+    Ok(found)
+}
+
+/// Find the first box of a type directly inside a buffer.
+fn child<'a>(data: &'a [u8], kind: &FourCC) -> Result<Option<&'a [u8]>> {
+    Ok(children(data, kind)?.into_iter().next())
+}
+
+/// Follow a path of box types down from a buffer.
+fn descend<'a>(data: &'a [u8], path: &[&FourCC]) -> Result<Option<&'a [u8]>> {
+    let mut data = data;
+    for kind in path {
+        match child(data, kind)? {
+            Some(payload) => data = payload,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(data))
+}
+
+/// Read the payload of the top level `moov` box, skipping over everything else, including the
+/// media data.
+fn read_moov<R: Read + Seek>(reader: &mut R) -> Result<Vec<u8>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let mut pos = 0;
+    while pos + 8 <= len {
+        reader.seek(SeekFrom::Start(pos))?;
+        let mut header = [0u8; 16];
+        reader.read_exact(&mut header[..8])?;
+        let kind: FourCC = bytes(&header, 4)?;
+        let (header_len, size) = match be_u32(&header, 0)? {
+            0 => (8, len - pos),
+            1 => {
+                reader.read_exact(&mut header[8..])?;
+                (16, be_u64(&header, 8)?)
+            }
+            size => (8, size as u64),
+        };
+        ensure!(
+            size >= header_len && size <= len - pos,
+            format!("Invalid size {} of box {}", size, fourcc_str(&kind))
+        );
+        if kind == *b"moov" {
+            let mut moov = vec![0; (size - header_len) as usize];
+            reader.read_exact(&mut moov)?;
+            return Ok(moov);
+        }
+        pos += size;
+    }
+    Err(eyre!("No moov box found, this is not an MP4/MOV file"))
+}
+
+/// Parse an ISO 6709 location string in decimal degrees, such as `+48.1173+011.5167+545.4/`.
+fn parse_iso6709(s: &str) -> Result<(f64, f64, Option<f64>)> {
+    let s = s.trim().trim_end_matches('/');
+    let mut numbers = Vec::new();
+    let mut start = 0;
+    for (i, c) in s.char_indices().skip(1) {
+        if c == '+' || c == '-' {
+            numbers.push(&s[start..i]);
+            start = i;
+        }
+    }
+    numbers.push(&s[start..]);
+    let numbers = numbers
+        .into_iter()
+        .map(|n| {
+            n.parse::<f64>()
+                .map_err(|e| eyre!("Invalid ISO 6709 location: {}: {}", s, e))
+        })
+        .collect::<Result<Vec<f64>>>()?;
+    ensure!(
+        numbers.len() == 2 || numbers.len() == 3,
+        format!("Invalid ISO 6709 location: {}", s)
+    );
+    Ok((numbers[0], numbers[1], numbers.get(2).copied()))
+}
+
+/// The creation time of the movie, from the `mvhd` box. This is UTC, and 0 when unset.
+fn movie_creation_time(moov: &[u8]) -> Result<Option<DateTime<FixedOffset>>> {
+    let Some(mvhd) = child(moov, b"mvhd")? else {
+        return Ok(None);
+    };
+    let seconds = match mvhd.first() {
+        Some(1) => be_u64(mvhd, 4)?,
+        _ => be_u32(mvhd, 4)? as u64,
+    };
+    if seconds == 0 {
+        return Ok(None);
+    }
+    Ok(
+        DateTime::from_timestamp(seconds as i64 - QUICKTIME_EPOCH_OFFSET, 0)
+            .map(|time| time.fixed_offset()),
+    )
+}
+
+/// Read the string values of the QuickTime metadata in a `meta` box, keyed by name.
+fn metadata_strings(meta: &[u8]) -> Result<Vec<(String, String)>> {
+    // QuickTime `meta` boxes start directly with their children, MP4 ones with a version and flags
+    let meta = match meta.get(4..8) {
+        Some(b"hdlr") => meta,
+        _ => meta.get(4..).unwrap_or_default(),
+    };
+    let (Some(keys), Some(ilst)) = (child(meta, b"keys")?, child(meta, b"ilst")?) else {
+        return Ok(vec![]);
+    };
+    let count = be_u32(keys, 4)? as usize;
+    // every key takes at least 8 bytes, so a corrupt count cannot reserve more than the box holds
+    let mut names = Vec::with_capacity(count.min(keys.len() / 8));
+    let mut pos = 8;
+    for _ in 0..count {
+        let size = be_u32(keys, pos)? as usize;
+        let name = keys
+            .get(pos + 8..pos + size)
+            .ok_or_else(|| eyre!("Invalid metadata key"))?;
+        names.push(String::from_utf8_lossy(name).into_owned());
+        pos += size;
+    }
+    let mut strings = Vec::new();
+    for item in (Boxes { data: ilst }) {
+        let (index, payload) = item?;
+        let index = u32::from_be_bytes(index) as usize;
+        let (Some(name), Some(data)) = (names.get(index.wrapping_sub(1)), child(payload, b"data")?)
+        else {
+            continue;
+        };
+        // Type 1 is UTF-8
+        if be_u32(data, 0)? == 1 {
+            let value = data.get(8..).unwrap_or_default();
+            strings.push((name.clone(), String::from_utf8_lossy(value).into_owned()));
+        }
+    }
+    Ok(strings)
+}
+
+/// Find where a phone recorded a video.
+/// # Return
+/// The fix, or None if the video has no location or no time.
+fn phone_fix(moov: &[u8]) -> Result<Option<Fix>> {
+    let mut location = None;
+    let mut creation_date = None;
+    if let Some(xyz) = descend(moov, &[b"udta", &XYZ])? {
+        let len = be_u16(xyz, 0)? as usize;
+        let s = xyz
+            .get(4..4 + len)
+            .ok_or_else(|| eyre!("Invalid ©xyz box"))?;
+        location = Some(parse_iso6709(&String::from_utf8_lossy(s))?);
+    }
+    let metas = [child(moov, b"meta")?, descend(moov, &[b"udta", b"meta"])?];
+    for meta in metas.into_iter().flatten() {
+        for (key, value) in metadata_strings(meta)? {
+            match key.as_str() {
+                KEY_LOCATION if location.is_none() => location = Some(parse_iso6709(&value)?),
+                KEY_CREATION_DATE => {
+                    creation_date = DateTime::parse_from_str(value.trim(), "%Y-%m-%dT%H:%M:%S%z")
+                        .map_err(|e| debug!("Invalid creation date: {}: {}", value, e))
+                        .ok()
+                }
+                _ => {}
+            }
+        }
+    }
+    let Some((latitude, longitude, altitude)) = location else {
+        return Ok(None);
+    };
+    let Some(time) = creation_date.or(movie_creation_time(moov)?) else {
+        debug!("Video has a location but no creation time");
+        return Ok(None);
+    };
+    Ok(Some(Fix {
+        time,
+        latitude,
+        longitude,
+        altitude,
+        pdop: None,
+    }))
+}
+
+/// Where the samples of a track are, and how long each lasts.
+#[derive(Debug, Default)]
+struct SampleTable {
+    timescale: u32,
+    offsets: Vec<u64>,
+    sizes: Vec<u32>,
+    durations: Vec<u32>,
+}
+
+impl SampleTable {
+    /// Read the sample table of a track from its `mdia` box. `len` is the length of the file,
+    /// which the samples must fit into.
+    fn from_mdia(mdia: &[u8], stbl: &[u8], len: u64) -> Result<Self> {
+        let mdhd = child(mdia, b"mdhd")?.ok_or_else(|| eyre!("Track has no mdhd"))?;
+        let timescale = match mdhd.first() {
+            Some(1) => be_u32(mdhd, 20)?,
+            _ => be_u32(mdhd, 12)?,
+        };
+
+        let stsz = child(stbl, b"stsz")?.ok_or_else(|| eyre!("Track has no stsz"))?;
+        let sample_size = be_u32(stsz, 4)?;
+        let count = be_u32(stsz, 8)? as usize;
+        let sizes = match sample_size {
+            0 => (0..count)
+                .map(|i| be_u32(stsz, 12 + 4 * i))
+                .collect::<Result<Vec<u32>>>()?,
+            size => {
+                ensure!(
+                    (count as u64).saturating_mul(size as u64) <= len,
+                    format!("{} samples of {} bytes do not fit in the file", count, size)
+                );
+                vec![size; count]
+            }
+        };
+
+        let chunk_offsets = if let Some(stco) = child(stbl, b"stco")? {
+            (0..be_u32(stco, 4)? as usize)
+                .map(|i| be_u32(stco, 8 + 4 * i).map(u64::from))
+                .collect::<Result<Vec<u64>>>()?
+        } else if let Some(co64) = child(stbl, b"co64")? {
+            (0..be_u32(co64, 4)? as usize)
+                .map(|i| be_u64(co64, 8 + 8 * i))
+                .collect::<Result<Vec<u64>>>()?
+        } else {
+            return Err(eyre!("Track has no chunk offsets"));
+        };
+
+        let stsc = child(stbl, b"stsc")?.ok_or_else(|| eyre!("Track has no stsc"))?;
+        let chunk_runs = (0..be_u32(stsc, 4)? as usize)
+            .map(|i| Ok((be_u32(stsc, 8 + 12 * i)?, be_u32(stsc, 12 + 12 * i)?)))
+            .collect::<Result<Vec<(u32, u32)>>>()?;
+
+        let mut offsets = Vec::with_capacity(sizes.len());
+        for (i, chunk_offset) in chunk_offsets.iter().enumerate() {
+            let chunk = i as u32 + 1;
+            let per_chunk = chunk_runs
+                .iter()
+                .rev()
+                .find(|(first_chunk, _)| *first_chunk <= chunk)
+                .map(|(_, samples)| *samples)
+                .unwrap_or(1);
+            let mut offset = *chunk_offset;
+            for _ in 0..per_chunk {
+                let Some(size) = sizes.get(offsets.len()) else {
+                    break;
+                };
+                offsets.push(offset);
+                offset += *size as u64;
+            }
+        }
+
+        let mut durations = Vec::with_capacity(sizes.len());
+        if let Some(stts) = child(stbl, b"stts")? {
+            for i in 0..be_u32(stts, 4)? as usize {
+                let count = be_u32(stts, 8 + 8 * i)? as usize;
+                let delta = be_u32(stts, 12 + 8 * i)?;
+                // no more durations than samples, however many a corrupt table claims
+                let count = count.min(sizes.len() - durations.len());
+                durations.extend(std::iter::repeat_n(delta, count));
+            }
+        }
+
+        Ok(SampleTable {
+            timescale,
+            offsets,
+            sizes,
+            durations,
+        })
+    }
+
+    /// The duration of a sample.
+    fn duration(&self, sample: usize) -> Duration {
+        match (self.durations.get(sample), self.timescale) {
+            (Some(duration), timescale) if timescale > 0 => {
+                Duration::microseconds(*duration as i64 * 1_000_000 / timescale as i64)
+            }
+            _ => Duration::zero(),
+        }
+    }
+}
+
+/// Find the GoPro telemetry track, the one with `gpmd` samples, in a file of `len` bytes.
+fn gpmf_track(moov: &[u8], len: u64) -> Result<Option<SampleTable>> {
+    for trak in children(moov, b"trak")? {
+        let Some(mdia) = child(trak, b"mdia")? else {
+            continue;
+        };
+        let Some(stbl) = descend(mdia, &[b"minf", b"stbl"])? else {
+            continue;
+        };
+        let Some(stsd) = child(stbl, b"stsd")? else {
+            continue;
+        };
+        // Version and flags, entry count, then the first sample entry box
+        if stsd.get(12..16) == Some(b"gpmd".as_slice()) {
+            return Ok(Some(SampleTable::from_mdia(mdia, stbl, len)?));
+        }
+    }
+    Ok(None)
+}
+
+/// The GPS readings of one GPMF stream.
+#[derive(Debug, Default)]
+struct GpsStream {
+    scale: Vec<f64>,
+    time: Option<DateTime<Utc>>,
+    fix: Option<u32>,
+    precision: Option<f64>,
+    points: Vec<Vec<f64>>,
+}
+
+impl GpsStream {
+    /// Apply the scale to the `i`th value of a point.
+    fn scaled(&self, point: &[f64], i: usize) -> f64 {
+        let scale = match self.scale.len() {
+            0 => 1.0,
+            1 => self.scale[0],
+            _ => self.scale.get(i).copied().unwrap_or(1.0),
+        };
+        if scale == 0.0 {
+            point[i]
+        } else {
+            point[i] / scale
+        }
+    }
+
+    /// Convert the readings to fixes, spread evenly over the duration of their sample. Readings
+    /// without a time or without a satellite fix are dropped.
+    fn to_fixes(&self, duration: Duration) -> Vec<Fix> {
+        let Some(time) = self.time else {
+            debug!("Skipping GPMF stream without GPSU");
+            return vec![];
+        };
+        if self.fix.is_some_and(|fix| fix < 2) {
+            debug!("Skipping GPMF stream without a GPS fix");
+            return vec![];
+        }
+        let count = self.points.len() as i32;
+        self.points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| Fix {
+                time: (time + duration * i as i32 / count).fixed_offset(),
+                latitude: self.scaled(point, 0),
+                longitude: self.scaled(point, 1),
+                // A 2D fix has no meaningful altitude
+                altitude: (self.fix != Some(2)).then(|| self.scaled(point, 2)),
+                // GPSP is the dilution of precision, times 100
+                pdop: self.precision.map(|dop| (dop / 100.0) as f32),
+            })
+            .collect()
+    }
+}
+
+/// Decode the values of a GPMF entry, of any numeric type, as floats.
+fn gpmf_numbers(kind: u8, value: &[u8]) -> Result<Vec<f64>> {
+    fn decode<const N: usize>(value: &[u8], f: impl Fn([u8; N]) -> f64) -> Vec<f64> {
+        value
+            .chunks_exact(N)
+            .map(|chunk| f(chunk.try_into().unwrap()))
+            .collect()
+    }
+    Ok(match kind {
+        b'b' => decode(value, |b: [u8; 1]| i8::from_be_bytes(b) as f64),
+        b'B' => decode(value, |b: [u8; 1]| u8::from_be_bytes(b) as f64),
+        b's' => decode(value, |b: [u8; 2]| i16::from_be_bytes(b) as f64),
+        b'S' => decode(value, |b: [u8; 2]| u16::from_be_bytes(b) as f64),
+        b'l' => decode(value, |b: [u8; 4]| i32::from_be_bytes(b) as f64),
+        b'L' => decode(value, |b: [u8; 4]| u32::from_be_bytes(b) as f64),
+        b'f' => decode(value, |b: [u8; 4]| f32::from_be_bytes(b) as f64),
+        b'd' => decode(value, f64::from_be_bytes),
+        _ => return Err(eyre!("Unsupported GPMF value type: {}", kind as char)),
+    })
+}
+
+/// Parse a GPSU time, `yymmddhhmmss.sss` in UTC.
+fn parse_gpsu(value: &[u8]) -> Option<DateTime<Utc>> {
+    let s = String::from_utf8_lossy(value);
+    NaiveDateTime::parse_from_str(
+        &format!("20{}", s.trim_end_matches('\0')),
+        "%Y%m%d%H%M%S%.f",
+    )
+    .map_err(|e| debug!("Invalid GPSU: {}: {}", s, e))
+    .ok()
+    .map(|time| time.and_utc())
+}
+
+/// Walk a GPMF payload, a tree of key-length-value entries, collecting the streams that hold GPS5
+/// readings.
+fn walk_gpmf(data: &[u8], stream: &mut GpsStream, streams: &mut Vec<GpsStream>) -> Result<()> {
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let key: FourCC = bytes(data, pos)?;
+        let kind = data[pos + 4];
+        let size = data[pos + 5] as usize;
+        let repeat = be_u16(data, pos + 6)? as usize;
+        let start = pos + 8;
+        let end = start + size * repeat;
+        ensure!(
+            end <= data.len(),
+            format!("GPMF entry {} overruns its parent", fourcc_str(&key))
+        );
+        let value = &data[start..end];
+        match (&key, kind) {
+            (b"STRM", 0) => {
+                let mut inner = GpsStream::default();
+                walk_gpmf(value, &mut inner, streams)?;
+                if !inner.points.is_empty() {
+                    streams.push(inner);
+                }
+            }
+            (_, 0) => walk_gpmf(value, stream, streams)?,
+            (b"SCAL", _) => stream.scale = gpmf_numbers(kind, value)?,
+            (b"GPSU", b'U') => stream.time = parse_gpsu(value),
+            (b"GPSF", _) => stream.fix = gpmf_numbers(kind, value)?.first().map(|f| *f as u32),
+            (b"GPSP", _) => stream.precision = gpmf_numbers(kind, value)?.first().copied(),
+            (b"GPS5", _) => {
+                let values = gpmf_numbers(kind, value)?;
+                stream
+                    .points
+                    .extend(values.chunks_exact(5).map(|point| point.to_vec()));
+            }
+            _ => {}
+        }
+        // Values are padded to 32 bits
+        pos = start + (size * repeat).div_ceil(4) * 4;
+    }
+    Ok(())
+}
+
+/// Read every fix in a GoPro telemetry track.
+fn gpmf_fixes<R: Read + Seek>(reader: &mut R, track: &SampleTable) -> Result<Vec<Fix>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let mut fixes = Vec::new();
+    for (sample, (offset, size)) in track.offsets.iter().zip(track.sizes.iter()).enumerate() {
+        ensure!(
+            *offset <= len && *size as u64 <= len - offset,
+            format!("Telemetry sample {} lies outside the file", sample)
+        );
+        reader.seek(SeekFrom::Start(*offset))?;
+        let mut data = vec![0; *size as usize];
+        reader
+            .read_exact(&mut data)
+            .map_err(|e| eyre!("Failed to read telemetry sample {}: {}", sample, e))?;
+        let mut streams = Vec::new();
+        walk_gpmf(&data, &mut GpsStream::default(), &mut streams)
+            .map_err(|e| eyre!("Invalid telemetry sample {}: {}", sample, e))?;
+        for stream in streams {
+            fixes.extend(stream.to_fixes(track.duration(sample)));
+        }
+    }
+    Ok(fixes)
+}
+
+/// Read the positions recorded in an MP4 or MOV video and return an iterator of `Location`
+/// structs.
+///
+/// Videos are ISO base media files, a tree of boxes. Positions are recovered from two places:
+/// - Phones record where a video was taken as an ISO 6709 string, either in a `©xyz` box in the
+///   user data (Android, older iOS), or as the `com.apple.quicktime.location.ISO6709` key in the
+///   QuickTime metadata (iOS). The time is the `com.apple.quicktime.creationdate` key when present,
+///   which includes the local offset, and otherwise the creation time of the movie.
+/// - GoPro cameras record a GPMF telemetry track, a `gpmd` sample per second or so. Each sample
+///   holds a `GPS5` stream of latitude, longitude, altitude, and speeds, along with the UTC time of
+///   its first fix in `GPSU`. The fixes are spread evenly over the duration of the sample.
+///
+/// Only the `moov` box and the telemetry samples are read, so large videos are cheap to import.
+/// # Arguments
+/// * `path` - The path to the video.
+/// * `username` - The username to associate with the locations.
+/// # Return
+/// An iterator of `Location` structs: the phone location if there is one, followed by the GoPro
+/// telemetry in order of time.
+pub fn read_video(path: &Path, username: &str) -> Result<impl Iterator<Item = Result<Location>>> {
+    let file = File::open(path).map_err(|e| eyre!("Failed to open video: {}", e))?;
+    let mut reader = BufReader::new(file);
+    let moov = read_moov(&mut reader)?;
+    let mut fixes = Vec::new();
+    if let Some(fix) = phone_fix(&moov)? {
+        fixes.push(fix);
+    }
+    let len = reader.seek(SeekFrom::End(0))?;
+    if let Some(track) = gpmf_track(&moov, len)? {
+        fixes.extend(gpmf_fixes(&mut reader, &track)?);
+    }
+    debug!("Found {} positions in {}", fixes.len(), path.display());
+    let username = username.to_string();
+    Ok(fixes
+        .into_iter()
+        .map(move |fix| Ok(fix.to_location(&username))))
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    static USERNAME: &str = "test_user";
+
+    fn mp4_box(kind: &FourCC, payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    /// A box with a zero version and flags before its payload.
+    fn full_box(kind: &FourCC, payload: &[u8]) -> Vec<u8> {
+        mp4_box(kind, &[[0u8; 4].as_slice(), payload].concat())
+    }
+
+    fn mvhd(time: &str) -> Vec<u8> {
+        let seconds =
+            DateTime::parse_from_rfc3339(time).unwrap().timestamp() + QUICKTIME_EPOCH_OFFSET;
+        full_box(
+            b"mvhd",
+            &[(seconds as u32).to_be_bytes().as_slice(), &[0u8; 92]].concat(),
+        )
+    }
+
+    fn write_video(boxes: &[Vec<u8>]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&boxes.concat()).unwrap();
+        file
+    }
+
+    fn read(file: &NamedTempFile) -> Vec<Location> {
+        read_video(file.path(), USERNAME)
+            .unwrap()
+            .collect::<Result<Vec<Location>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_iso6709() {
+        assert_eq!(
+            parse_iso6709("+48.1173+011.5167+545.400/").unwrap(),
+            (48.1173, 11.5167, Some(545.4))
+        );
+        assert_eq!(
+            parse_iso6709("-33.8688+151.2093/").unwrap(),
+            (-33.8688, 151.2093, None)
+        );
+        assert_eq!(
+            parse_iso6709("+48.1173-011.5167-10.5/").unwrap(),
+            (48.1173, -11.5167, Some(-10.5))
+        );
+        assert!(parse_iso6709("+48.1173/").is_err());
+        assert!(parse_iso6709("garbage").is_err());
+    }
+
+    #[test]
+    fn test_read_video_xyz() {
+        let location = b"+48.1173+011.5167+545.400/";
+        let xyz = mp4_box(
+            &XYZ,
+            &[
+                (location.len() as u16).to_be_bytes().as_slice(),
+                &[0x15, 0xc7],
+                location,
+            ]
+            .concat(),
+        );
+        let moov = mp4_box(
+            b"moov",
+            &[mvhd("2023-10-07T10:35:19Z"), mp4_box(b"udta", &xyz)].concat(),
+        );
+        let file = write_video(&[
+            mp4_box(b"ftyp", b"mp42\0\0\0\0"),
+            mp4_box(b"mdat", &[0u8; 64]),
+            moov,
+        ]);
+        let locations = read(&file);
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].username, USERNAME);
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2023-10-07T10:35:19+00:00"
+        );
+        assert_eq!(locations[0].latitude, 48.1173);
+        assert_eq!(locations[0].longitude, 11.5167);
+        assert_eq!(locations[0].altitude, 545.4);
+        assert_eq!(locations[0].accuracy, None);
+        assert_eq!(locations[0].source, Source::Video);
+    }
+
+    #[test]
+    fn test_read_video_quicktime_keys() {
+        let key = |name: &str| mp4_box(b"mdta", name.as_bytes());
+        let item = |index: u32, value: &str| {
+            mp4_box(
+                &index.to_be_bytes(),
+                &mp4_box(
+                    b"data",
+                    &[1u32.to_be_bytes().as_slice(), &[0u8; 4], value.as_bytes()].concat(),
+                ),
+            )
+        };
+        let meta = mp4_box(
+            b"meta",
+            &[
+                full_box(b"hdlr", &[0u8; 24]),
+                full_box(
+                    b"keys",
+                    &[
+                        2u32.to_be_bytes().to_vec(),
+                        key(KEY_LOCATION),
+                        key(KEY_CREATION_DATE),
+                    ]
+                    .concat(),
+                ),
+                mp4_box(
+                    b"ilst",
+                    &[
+                        item(1, "+48.1173-011.5167/"),
+                        item(2, "2023-10-07T12:35:19+0200"),
+                    ]
+                    .concat(),
+                ),
+            ]
+            .concat(),
+        );
+        let moov = mp4_box(b"moov", &[mvhd("2023-10-07T10:35:00Z"), meta].concat());
+        let file = write_video(&[mp4_box(b"ftyp", b"qt  \0\0\0\0"), moov]);
+        let locations = read(&file);
+        assert_eq!(locations.len(), 1);
+        assert_eq!(
+            locations[0].time_local.to_rfc3339(),
+            "2023-10-07T12:35:19+02:00"
+        );
+        assert_eq!(locations[0].latitude, 48.1173);
+        assert_eq!(locations[0].longitude, -11.5167);
+        assert_eq!(locations[0].altitude, 0.0);
+    }
+
+    /// A GPMF entry, padded to 32 bits.
+    fn klv(key: &FourCC, kind: u8, size: u8, repeat: u16, value: &[u8]) -> Vec<u8> {
+        let mut data = key.to_vec();
+        data.push(kind);
+        data.push(size);
+        data.extend_from_slice(&repeat.to_be_bytes());
+        data.extend_from_slice(value);
+        data.resize(data.len().div_ceil(4) * 4, 0);
+        data
+    }
+
+    /// A telemetry sample with one GPS stream.
+    fn gpmf_sample(time: &str, fix: u32, points: &[[i32; 5]]) -> Vec<u8> {
+        let scale: Vec<u8> = [10_000_000i32, 10_000_000, 1000, 1000, 100]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let values: Vec<u8> = points
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let strm = [
+            klv(b"STNM", b'c', 3, 1, b"GPS"),
+            klv(b"GPSF", b'L', 4, 1, &fix.to_be_bytes()),
+            klv(b"GPSU", b'U', 16, 1, time.as_bytes()),
+            klv(b"GPSP", b'S', 2, 1, &150u16.to_be_bytes()),
+            klv(b"SCAL", b'l', 4, 5, &scale),
+            klv(b"GPS5", b'l', 20, points.len() as u16, &values),
+        ]
+        .concat();
+        let strm = klv(b"STRM", 0, 1, strm.len() as u16, &strm);
+        let devc = [klv(b"DVID", b'L', 4, 1, &1u32.to_be_bytes()), strm].concat();
+        klv(b"DEVC", 0, 1, devc.len() as u16, &devc)
+    }
+
+    #[test]
+    fn test_read_video_gpmf() {
+        let samples = [
+            gpmf_sample(
+                "231007103519.000",
+                3,
+                &[
+                    [481173000, 115167000, 545400, 1000, 1000],
+                    [481172000, 115168000, 546000, 1000, 1000],
+                ],
+            ),
+            gpmf_sample("231007103520.000", 0, &[[0, 0, 0, 0, 0]]),
+            gpmf_sample(
+                "231007103521.000",
+                2,
+                &[[481175000, 115166000, 547500, 1000, 1000]],
+            ),
+        ];
+        let ftyp = mp4_box(b"ftyp", b"mp41\0\0\0\0");
+        let first_offset = (ftyp.len() + 8) as u32;
+        let mdat = mp4_box(b"mdat", &samples.concat());
+
+        let sizes: Vec<u8> = samples
+            .iter()
+            .flat_map(|s| (s.len() as u32).to_be_bytes())
+            .collect();
+        let stbl = mp4_box(
+            b"stbl",
+            &[
+                full_box(
+                    b"stsd",
+                    &[1u32.to_be_bytes().as_slice(), &mp4_box(b"gpmd", &[0u8; 8])].concat(),
+                ),
+                full_box(b"stts", &[1u32, 3, 1001].map(u32::to_be_bytes).concat()),
+                full_box(b"stsc", &[1u32, 1, 3, 1].map(u32::to_be_bytes).concat()),
+                full_box(
+                    b"stsz",
+                    &[0u32.to_be_bytes().as_slice(), &3u32.to_be_bytes(), &sizes].concat(),
+                ),
+                full_box(
+                    b"stco",
+                    &[1u32, first_offset].map(u32::to_be_bytes).concat(),
+                ),
+            ]
+            .concat(),
+        );
+        let mdhd = full_box(
+            b"mdhd",
+            &[[0u8; 8].as_slice(), &1000u32.to_be_bytes(), &[0u8; 8]].concat(),
+        );
+        let mdia = mp4_box(b"mdia", &[mdhd, mp4_box(b"minf", &stbl)].concat());
+        let moov = mp4_box(
+            b"moov",
+            &[mvhd("2023-10-07T10:35:19Z"), mp4_box(b"trak", &mdia)].concat(),
+        );
+        let file = write_video(&[ftyp, mdat, moov]);
+
+        let locations = read(&file);
+        assert_eq!(locations.len(), 3);
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2023-10-07T10:35:19+00:00"
+        );
+        assert_eq!(locations[0].latitude, 48.1173);
+        assert_eq!(locations[0].longitude, 11.5167);
+        assert_eq!(locations[0].altitude, 545.4);
+        assert_eq!(locations[0].accuracy, None);
+        assert_eq!(locations[0].pdop, Some(1.5));
+        assert_eq!(locations[0].source, Source::Video);
+        assert_eq!(
+            locations[1].time_utc.to_rfc3339(),
+            "2023-10-07T10:35:19.500500+00:00"
+        );
+        assert_eq!(locations[1].latitude, 48.1172);
+        assert_eq!(
+            locations[2].time_utc.to_rfc3339(),
+            "2023-10-07T10:35:21+00:00"
+        );
+        assert_eq!(locations[2].altitude, 0.0);
+    }
+
+    #[test]
+    fn test_read_video_invalid() {
+        let file = write_video(&[b"not a video at all".to_vec()]);
+        assert!(read_video(file.path(), USERNAME).is_err());
+        let file = write_video(&[mp4_box(b"ftyp", b"mp42\0\0\0\0")]);
+        assert!(read_video(file.path(), USERNAME).is_err());
+        // a 64-bit box size far beyond the end of the file
+        let huge = [
+            1u32.to_be_bytes().as_slice(),
+            b"free",
+            &u64::MAX.to_be_bytes(),
+        ]
+        .concat();
+        let file = write_video(&[huge]);
+        assert!(read_video(file.path(), USERNAME).is_err());
+        // more metadata keys than the box could hold
+        let meta = mp4_box(
+            b"meta",
+            &[
+                full_box(b"hdlr", &[0u8; 24]),
+                full_box(b"keys", &u32::MAX.to_be_bytes()),
+                mp4_box(b"ilst", &[]),
+            ]
+            .concat(),
+        );
+        let moov = mp4_box(b"moov", &[mvhd("2023-10-07T10:35:00Z"), meta].concat());
+        let file = write_video(&[mp4_box(b"ftyp", b"qt  \0\0\0\0"), moov]);
+        assert!(read_video(file.path(), USERNAME).is_err());
+    }
+}
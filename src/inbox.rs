@@ -0,0 +1,426 @@
+use chrono::Utc;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use log::{error, info, warn};
+use serde::Deserialize;
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use crate::cli::{import_file, plan_import, FileImport, ImportFormat};
+use crate::db::{ConflictPolicy, Db, InsertCounts};
+use crate::import::{find_files, DEFAULT_MAX_ZIP_BYTES};
+
+/// Imported files are moved here, inside the user's directory.
+const DONE_DIR: &str = "done";
+/// Files that failed to import are moved here, next to a report of the error.
+const FAILED_DIR: &str = "failed";
+
+fn default_interval() -> u64 {
+    60
+}
+
+fn default_settle() -> u64 {
+    30
+}
+
+fn default_max_zip_bytes() -> u64 {
+    DEFAULT_MAX_ZIP_BYTES
+}
+
+/// Configuration for the inbox, the `[inbox]` section of the config file
+#[derive(Debug, Deserialize, Clone)]
+pub struct Config {
+    /// Directory with one subdirectory per user, named after them, to drop files into
+    pub path: PathBuf,
+    /// Seconds between scans of the inbox
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    /// Seconds a file must go unmodified before it is imported, so that files still being synced
+    /// or uploaded are left alone
+    #[serde(default = "default_settle")]
+    pub settle: u64,
+    /// What to do with dropped locations that conflict with stored ones
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+    /// Most bytes the ZIP archives in one dropped entry may extract to, all together
+    #[serde(default = "default_max_zip_bytes")]
+    pub max_zip_bytes: u64,
+}
+
+/// Watches a drop directory and imports whatever appears in it. Each user has a subdirectory,
+/// `<path>/<username>/`, and every file, directory or ZIP archive placed in it is imported for
+/// that user with the format detected per file. Afterwards it is moved to `done/`, or to
+/// `failed/` along with a `<name>.error.txt` report, in the same directory. Corrupt lines that
+/// were skipped are listed in a `<name>.corrupt.txt` report next to an imported entry.
+pub struct Inbox {
+    config: Config,
+    db: Arc<Db>,
+}
+
+impl Inbox {
+    pub fn new(config: Config, db: Arc<Db>) -> Self {
+        Inbox { config, db }
+    }
+
+    /// Scan the inbox forever, at the configured interval. Errors are logged, and the next scan
+    /// goes ahead regardless.
+    pub async fn run(self) {
+        info!("Watching inbox: {}", self.config.path.display());
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval.max(1)));
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.scan().await {
+                error!("Inbox scan failed: {:?}", e);
+            }
+        }
+    }
+
+    /// Import everything currently waiting in the inbox. A user whose directory cannot be scanned
+    /// is logged and skipped, so the others still get their files imported.
+    /// # Returns
+    /// The number of entries processed, whether they imported successfully or not
+    pub async fn scan(&self) -> Result<usize> {
+        let users = self.db.user_vec().await.wrap_err("Failed to list users")?;
+        let mut count = 0;
+        for dir in list_entries(&self.config.path)? {
+            if !dir.is_dir() {
+                continue;
+            }
+            let Some(username) = dir.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if !users.iter().any(|user| user == username) {
+                warn!("Inbox directory for unknown user: {}", dir.display());
+                continue;
+            }
+            match self.scan_user(username, &dir).await {
+                Ok(user_count) => count += user_count,
+                Err(e) => error!("Inbox scan for {} failed: {:?}", username, e),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Import everything waiting in one user's directory. An entry that cannot be moved aside
+    /// afterwards is logged, and the next one goes ahead regardless.
+    async fn scan_user(&self, username: &str, dir: &Path) -> Result<usize> {
+        let mut count = 0;
+        for entry in list_entries(dir)? {
+            let name = entry.file_name().unwrap_or_default();
+            if name == DONE_DIR || name == FAILED_DIR || !self.is_settled(&entry) {
+                continue;
+            }
+            if let Err(e) = self.process_entry(username, dir, &entry).await {
+                error!(
+                    "Failed to process {} for {}: {:?}",
+                    entry.display(),
+                    username,
+                    e
+                );
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Import a single entry of a user's directory, then move it to `done/` or `failed/` with its
+    /// reports.
+    async fn process_entry(&self, username: &str, dir: &Path, entry: &Path) -> Result<()> {
+        // reading the files is blocking work, kept off the threads serving requests
+        let handle = tokio::runtime::Handle::current();
+        let db = self.db.clone();
+        let policy = self.config.conflict_policy;
+        let max_zip_bytes = self.config.max_zip_bytes;
+        let (task_username, task_entry) = (username.to_string(), entry.to_path_buf());
+        let result = tokio::task::spawn_blocking(move || {
+            handle.block_on(import_entry(
+                db,
+                policy,
+                max_zip_bytes,
+                &task_username,
+                &task_entry,
+            ))
+        })
+        .await
+        .map_err(|e| eyre!("Import task failed: {}", e))
+        .and_then(|result| result);
+        match result {
+            Ok((counts, corrupt)) => {
+                info!("Imported {} for {}: {}", entry.display(), username, counts);
+                let moved = move_into(entry, &dir.join(DONE_DIR))?;
+                if !corrupt.is_empty() {
+                    warn!(
+                        "Skipped {} corrupt lines in {} for {}",
+                        corrupt.len(),
+                        entry.display(),
+                        username
+                    );
+                    let mut report = moved.into_os_string();
+                    report.push(".corrupt.txt");
+                    std::fs::write(
+                        &report,
+                        format!("{}\n{}\n", Utc::now().to_rfc3339(), corrupt.join("\n")),
+                    )
+                    .wrap_err("Failed to write corrupt line report")?;
+                }
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to import {} for {}: {}",
+                    entry.display(),
+                    username,
+                    e
+                );
+                let moved = move_into(entry, &dir.join(FAILED_DIR))?;
+                let mut report = moved.into_os_string();
+                report.push(".error.txt");
+                std::fs::write(&report, format!("{}\n{}\n", Utc::now().to_rfc3339(), e))
+                    .wrap_err("Failed to write error report")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether an entry, and everything in it if it is a directory, has gone unmodified for long
+    /// enough to be imported.
+    fn is_settled(&self, path: &Path) -> bool {
+        last_modified(path)
+            .ok()
+            // a modification time in the future counts as just now
+            .map(|modified| modified.elapsed().unwrap_or_default())
+            .is_some_and(|age| age >= Duration::from_secs(self.config.settle))
+    }
+}
+
+/// Import a single entry of a user's directory, which may be a file, directory or archive.
+/// # Returns
+/// The number of locations that met each outcome and the corrupt lines that were skipped, or
+/// an error listing every file that failed
+async fn import_entry(
+    db: Arc<Db>,
+    policy: ConflictPolicy,
+    max_zip_bytes: u64,
+    username: &str,
+    entry: &Path,
+) -> Result<(InsertCounts, Vec<String>)> {
+    let mut temp_dirs = Vec::new();
+    let files = find_files(entry, max_zip_bytes, &mut temp_dirs)?;
+    let plan = plan_import(ImportFormat::Auto, entry, files);
+    if plan.is_empty() {
+        return Err(eyre!("No files in a supported format"));
+    }
+    let mut total = InsertCounts::default();
+    let mut corrupt = Vec::new();
+    let mut errors = Vec::new();
+    for (file, format) in plan {
+        match import_file(db.clone(), format, &file.path, username, policy).await {
+            Ok(FileImport {
+                counts,
+                corrupt: lines,
+            }) => {
+                total += counts;
+                corrupt.extend(lines.iter().map(|line| {
+                    format!(
+                        "{} line {}: {}",
+                        file.name.display(),
+                        line.line,
+                        line.reason
+                    )
+                }));
+            }
+            Err(e) => errors.push(format!("{} ({:?}): {}", file.name.display(), format, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(eyre!("{}", errors.join("\n")));
+    }
+    Ok((total, corrupt))
+}
+
+/// The newest modification time of an entry and, if it is a directory, of everything below it.
+/// A directory's own time only changes when entries are added or removed, not when a file
+/// further down is still being written. Symbolic links are not followed.
+fn last_modified(path: &Path) -> std::io::Result<SystemTime> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    let mut modified = metadata.modified()?;
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            modified = modified.max(last_modified(&entry?.path())?);
+        }
+    }
+    Ok(modified)
+}
+
+/// List the entries of a directory in name order, skipping hidden ones, such as the temporary
+/// files Syncthing writes while syncing.
+fn list_entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = std::fs::read_dir(dir)
+        .map_err(|e| eyre!("Failed to read directory {}: {}", dir.display(), e))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()
+        .map_err(|e| eyre!("Failed to read directory {}: {}", dir.display(), e))?;
+    entries.retain(|entry| {
+        entry
+            .file_name()
+            .is_some_and(|name| !name.to_string_lossy().starts_with('.'))
+    });
+    entries.sort();
+    Ok(entries)
+}
+
+/// Move an entry into a directory, creating it if needed. If the name is taken, the time is
+/// appended to it.
+/// # Returns
+/// The new path of the entry
+fn move_into(entry: &Path, dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| eyre!("Failed to create directory {}: {}", dir.display(), e))?;
+    let name = entry
+        .file_name()
+        .ok_or_else(|| eyre!("Invalid path: {}", entry.display()))?;
+    let mut dest = dir.join(name);
+    if dest.exists() {
+        let mut unique = name.to_os_string();
+        unique.push(format!(".{}", Utc::now().format("%Y%m%dT%H%M%S%.f")));
+        dest = dir.join(unique);
+    }
+    std::fs::rename(entry, &dest).map_err(|e| {
+        eyre!(
+            "Failed to move {} to {}: {}",
+            entry.display(),
+            dest.display(),
+            e
+        )
+    })?;
+    Ok(dest)
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Config as DbConfig;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    static CSV_DATA: &str = r#"time,lat,lon,elevation,accuracy,bearing,speed,satellites,provider,hdop,vdop,pdop,geoidheight,ageofdgpsdata,dgpsid,activity,battery,annotation,timestamp_ms,time_offset,distance,starttimestamp_ms,profile_name,battery_charging
+2025-01-24T07:02:29.168Z,24.240779519081116,-11.84485614299774,1476.0,48.0,,0.0,0,gps,,,,,,,,64,,1737702149168,2025-01-24T00:02:29.168-07:00,14780.376051140634,1737686054899,Default Profile,false
+2025-01-24T07:23:55.551Z,24.241143584251404,-11.84490287303925,1411.0,48.0,,0.0,0,gps,,,,,,,,63,,1737703435551,2025-01-24T00:23:55.551-07:00,14821.04923758446,1737686054899,Default Profile,false
+"#;
+
+    #[tokio::test]
+    async fn test_scan() {
+        let dir = tempdir().unwrap();
+        let db = Arc::new(
+            Db::new(&DbConfig {
+                path: dir.path().join("test.db"),
+                backups: 0,
+                bounds: Default::default(),
+            })
+            .await
+            .unwrap(),
+        );
+        db.user_insert("alice".to_string(), "password".to_string())
+            .await
+            .unwrap();
+        let inbox_path = dir.path().join("inbox");
+        let alice = inbox_path.join("alice");
+        let bob = inbox_path.join("bob");
+        std::fs::create_dir_all(alice.join(DONE_DIR)).unwrap();
+        std::fs::create_dir_all(&bob).unwrap();
+        std::fs::write(alice.join("20250124.csv"), CSV_DATA).unwrap();
+        std::fs::write(alice.join(DONE_DIR).join("20250124.csv"), "older").unwrap();
+        std::fs::write(alice.join("broken.gpx"), "<gpx><trkpt lat=").unwrap();
+        std::fs::write(
+            alice.join("track.nmea"),
+            "$GPGGA,0000\r\n$GPRMC,235959.000,A,4807.038,N,01131.000,E,022.4,084.4,071023,003.1,W*70\r\n",
+        )
+        .unwrap();
+        std::fs::write(alice.join("notes.txt"), "hello").unwrap();
+        std::fs::write(alice.join(".syncthing.x.csv.tmp"), "partial").unwrap();
+        std::fs::write(bob.join("20250124.csv"), CSV_DATA).unwrap();
+
+        let inbox = Inbox::new(
+            Config {
+                path: inbox_path.clone(),
+                interval: 1,
+                settle: 0,
+                conflict_policy: ConflictPolicy::Error,
+                max_zip_bytes: DEFAULT_MAX_ZIP_BYTES,
+            },
+            db.clone(),
+        );
+        assert_eq!(inbox.scan().await.unwrap(), 4);
+
+        // imported, and moved aside without clobbering the earlier file of the same name
+        assert_eq!(db.location_count(Some("alice"), None).await.unwrap(), 3);
+        assert!(!alice.join("20250124.csv").exists());
+        let done = list_entries(&alice.join(DONE_DIR)).unwrap();
+        assert_eq!(done.len(), 4);
+        // imported despite a corrupt line, which is reported
+        let report =
+            std::fs::read_to_string(alice.join(DONE_DIR).join("track.nmea.corrupt.txt")).unwrap();
+        assert!(report.contains("track.nmea line 1: "));
+        // failed, with reports
+        for name in ["broken.gpx", "notes.txt"] {
+            assert!(!alice.join(name).exists());
+            assert!(alice.join(FAILED_DIR).join(name).exists());
+            let report =
+                std::fs::read_to_string(alice.join(FAILED_DIR).join(format!("{}.error.txt", name)))
+                    .unwrap();
+            assert!(!report.is_empty());
+        }
+        // hidden files and unknown users are left alone
+        assert!(alice.join(".syncthing.x.csv.tmp").exists());
+        assert!(bob.join("20250124.csv").exists());
+
+        // nothing left to do
+        assert_eq!(inbox.scan().await.unwrap(), 0);
+
+        // files are left alone until they settle
+        std::fs::write(alice.join("20250125.csv"), CSV_DATA).unwrap();
+        let inbox = Inbox::new(
+            Config {
+                path: inbox_path,
+                interval: 1,
+                settle: 3600,
+                conflict_policy: ConflictPolicy::Error,
+                max_zip_bytes: DEFAULT_MAX_ZIP_BYTES,
+            },
+            db,
+        );
+        assert_eq!(inbox.scan().await.unwrap(), 0);
+        assert!(alice.join("20250125.csv").exists());
+    }
+
+    #[test]
+    fn test_last_modified() {
+        let dir = tempdir().unwrap();
+        let nested = dir.path().join("a").join("b");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(nested.join("c.csv"), CSV_DATA).unwrap();
+        let old = SystemTime::now() - Duration::from_secs(7200);
+        let newer = old + Duration::from_secs(3600);
+        for path in [dir.path(), &dir.path().join("a"), &nested] {
+            std::fs::File::open(path)
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+        std::fs::File::options()
+            .write(true)
+            .open(nested.join("c.csv"))
+            .unwrap()
+            .set_modified(newer)
+            .unwrap();
+        // a file still being written deep down keeps the whole directory unsettled
+        assert_eq!(last_modified(dir.path()).unwrap(), newer);
+        assert_eq!(last_modified(&nested.join("c.csv")).unwrap(), newer);
+    }
+}
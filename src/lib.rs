@@ -1,6 +1,15 @@
+pub mod auth;
 pub mod cli;
 pub mod db;
 pub mod export;
+pub mod google;
 pub mod gpslogger;
+pub mod import;
+pub mod inbox;
+pub mod lockout;
+pub mod migrate;
+pub mod osmand;
+pub mod overland;
+pub mod owntracks;
 pub mod schema;
 pub mod server;
@@ -0,0 +1,431 @@
+use chrono::Utc;
+use color_eyre::eyre::{Result, WrapErr};
+use log::warn;
+use serde::Deserialize;
+
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    hash::Hash,
+    io::Write,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Past this many IP addresses or usernames with failures, those that have been forgotten are
+/// dropped whenever a failure is recorded, so that the counters cannot grow without bound.
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// Limits on failed logins, the `[https.lockout]` section of the config file
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    /// Failures from one IP address before it is locked out
+    pub max_ip_failures: u32,
+    /// Failures for one username from one IP address before they are locked out
+    pub max_user_failures: u32,
+    /// Failures for one username from all IP addresses together before it is locked out from
+    /// every address, so that guesses spread over many addresses are throttled too
+    pub max_user_failures_any_ip: u32,
+    /// Seconds of the first lockout. Each further failure doubles it.
+    pub lockout: u64,
+    /// Longest lockout in seconds
+    pub max_lockout: u64,
+    /// Seconds without a failure after which earlier failures are forgotten
+    pub forget_after: u64,
+    /// File to append a line to for every failure, with the time, IP address, username and reason
+    pub audit_log: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_ip_failures: 20,
+            max_user_failures: 5,
+            max_user_failures_any_ip: 50,
+            lockout: 60,
+            max_lockout: 3600,
+            forget_after: 3600,
+            audit_log: None,
+        }
+    }
+}
+
+/// Failures recorded for one IP address or username.
+#[derive(Debug, Clone)]
+struct Failures {
+    /// Failures since the count was last forgotten
+    count: u32,
+    /// Time of the latest failure
+    last: Instant,
+    /// Attempts are refused until this time
+    locked_until: Option<Instant>,
+}
+
+/// Failures per IP address or username, with a limit after which they are locked out.
+#[derive(Debug)]
+struct Counter<K> {
+    limit: u32,
+    entries: HashMap<K, Failures>,
+}
+
+impl<K: Eq + Hash> Counter<K> {
+    fn new(limit: u32) -> Self {
+        Counter {
+            limit,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// How long the key remains locked out, if it is.
+    fn locked(&self, key: &K, now: Instant) -> Option<Duration> {
+        self.entries
+            .get(key)
+            .and_then(|failures| failures.locked_until)
+            .and_then(|until| until.checked_duration_since(now))
+            .filter(|wait| !wait.is_zero())
+    }
+
+    /// Record a failure of the key. Once the limit is reached, every failure locks the key out,
+    /// for twice as long as the last.
+    /// # Returns
+    /// How long the key is now locked out, if it is
+    fn fail(&mut self, key: K, now: Instant, config: &Config) -> Option<Duration> {
+        let forget_after = Duration::from_secs(config.forget_after);
+        if self.entries.len() > PRUNE_THRESHOLD {
+            self.entries.retain(|_, failures| {
+                now.duration_since(failures.last) < forget_after
+                    || failures.locked_until.is_some_and(|until| until > now)
+            });
+        }
+        let failures = self.entries.entry(key).or_insert(Failures {
+            count: 0,
+            last: now,
+            locked_until: None,
+        });
+        if now.duration_since(failures.last) >= forget_after {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last = now;
+        if failures.count < self.limit {
+            return None;
+        }
+        let doublings = (failures.count - self.limit).min(32);
+        let lockout = Duration::from_secs(
+            config
+                .lockout
+                .saturating_mul(1 << doublings)
+                .min(config.max_lockout),
+        );
+        failures.locked_until = Some(now + lockout);
+        Some(lockout)
+    }
+
+    /// Forget the failures of the key.
+    fn clear(&mut self, key: &K) {
+        self.entries.remove(key);
+    }
+}
+
+/// Tracks failed logins per IP address, per username from each IP address, and per username from
+/// all addresses, and locks each out for a while once it fails too often. A username is locked out
+/// at the address that failed well before it is locked out everywhere, so that anyone who knows a
+/// username can only lock its owner out with many more failures, while guesses spread over many
+/// addresses are still throttled. Successful logins clear the failures of the username at that
+/// address, but not of the address itself, so that an attacker with one account cannot use it to
+/// keep guessing at others, nor of the username everywhere, so that the owner logging in does not
+/// reset the count of guesses from elsewhere.
+#[derive(Debug)]
+pub struct Lockout {
+    config: Config,
+    ips: Mutex<Counter<IpAddr>>,
+    users: Mutex<Counter<(IpAddr, String)>>,
+    users_any_ip: Mutex<Counter<String>>,
+    audit_log: Option<Mutex<File>>,
+}
+
+impl Lockout {
+    /// Create the lockout, opening the audit log if one is configured.
+    pub fn new(config: Config) -> Result<Self> {
+        let audit_log = match &config.audit_log {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .wrap_err(format!("Failed to open audit log: {}", path.display()))?,
+            )),
+            None => None,
+        };
+        Ok(Lockout {
+            ips: Mutex::new(Counter::new(config.max_ip_failures)),
+            users: Mutex::new(Counter::new(config.max_user_failures)),
+            users_any_ip: Mutex::new(Counter::new(config.max_user_failures_any_ip)),
+            config,
+            audit_log,
+        })
+    }
+
+    /// Check whether an attempt may go ahead.
+    /// # Arguments
+    /// * `ip` - The address the attempt comes from
+    /// * `username` - The user the attempt is for, if known
+    /// # Returns
+    /// How long until the IP address, or the username from it, may try again, if either is locked
+    /// out, the username possibly from every address
+    pub fn check(&self, ip: IpAddr, username: Option<&str>) -> Option<Duration> {
+        self.check_at(ip, username, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, username: Option<&str>, now: Instant) -> Option<Duration> {
+        let ip_wait = self
+            .ips
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .locked(&ip, now);
+        let user_wait = username.and_then(|username| {
+            self.users
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .locked(&(ip, username.to_string()), now)
+        });
+        let any_ip_wait = username.and_then(|username| {
+            self.users_any_ip
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .locked(&username.to_string(), now)
+        });
+        ip_wait.max(user_wait).max(any_ip_wait)
+    }
+
+    /// Record a failed attempt, and write it to the audit log.
+    /// # Arguments
+    /// * `ip` - The address the attempt came from
+    /// * `username` - The user the attempt was for, if known
+    /// * `reason` - Why it failed
+    pub fn fail(&self, ip: IpAddr, username: Option<&str>, reason: &str) {
+        self.fail_at(ip, username, reason, Instant::now())
+    }
+
+    fn fail_at(&self, ip: IpAddr, username: Option<&str>, reason: &str, now: Instant) {
+        warn!(
+            "Authentication failed from {} for {}: {}",
+            ip,
+            username.unwrap_or("-"),
+            reason
+        );
+        self.audit(ip, username, reason);
+        let ip_lockout = self
+            .ips
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .fail(ip, now, &self.config);
+        if let Some(lockout) = ip_lockout {
+            warn!("Locked out {} for {}s", ip, lockout.as_secs());
+            self.audit(ip, username, &format!("{} locked out", ip));
+        }
+        if let Some(username) = username {
+            let user_lockout = self
+                .users
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .fail((ip, username.to_string()), now, &self.config);
+            if let Some(lockout) = user_lockout {
+                warn!(
+                    "Locked out user {} from {} for {}s",
+                    username,
+                    ip,
+                    lockout.as_secs()
+                );
+                self.audit(ip, Some(username), &format!("{} locked out", username));
+            }
+            let any_ip_lockout = self
+                .users_any_ip
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .fail(username.to_string(), now, &self.config);
+            if let Some(lockout) = any_ip_lockout {
+                warn!(
+                    "Locked out user {} from every address for {}s",
+                    username,
+                    lockout.as_secs()
+                );
+                self.audit(
+                    ip,
+                    Some(username),
+                    &format!("{} locked out from every address", username),
+                );
+            }
+        }
+    }
+
+    /// Record a successful login, which forgets the failures of the username from the IP address.
+    pub fn succeed(&self, ip: IpAddr, username: &str) {
+        self.users
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear(&(ip, username.to_string()));
+    }
+
+    /// Append a line to the audit log, if there is one. The username and reason are escaped, since
+    /// usernames come from the request and may hold tabs or line breaks. Errors are only logged,
+    /// so that a full disk does not stop logins.
+    fn audit(&self, ip: IpAddr, username: Option<&str>, reason: &str) {
+        let Some(file) = &self.audit_log else {
+            return;
+        };
+        let line = format!(
+            "{}\t{}\t{}\t{}\n",
+            Utc::now().to_rfc3339(),
+            ip,
+            username.unwrap_or("-").escape_debug(),
+            reason.escape_debug()
+        );
+        let mut file = file.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            warn!("Failed to write to audit log: {}", e);
+        }
+    }
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_user_lockout() {
+        let lockout = Lockout::new(Config::default()).unwrap();
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        let now = Instant::now();
+        for _ in 0..4 {
+            lockout.fail_at(ip, Some("user"), "wrong password", now);
+            assert_eq!(lockout.check_at(ip, Some("user"), now), None);
+        }
+        lockout.fail_at(ip, Some("user"), "wrong password", now);
+        assert_eq!(
+            lockout.check_at(ip, Some("user"), now),
+            Some(Duration::from_secs(60))
+        );
+        // other users are not affected, nor is the user from other addresses
+        assert_eq!(lockout.check_at(ip, Some("other"), now), None);
+        let other_ip = IpAddr::from([192, 0, 2, 2]);
+        assert_eq!(lockout.check_at(other_ip, Some("user"), now), None);
+        // the lockout doubles with every further failure
+        let later = now + Duration::from_secs(60);
+        assert_eq!(lockout.check_at(ip, Some("user"), later), None);
+        lockout.fail_at(ip, Some("user"), "wrong password", later);
+        assert_eq!(
+            lockout.check_at(ip, Some("user"), later),
+            Some(Duration::from_secs(120))
+        );
+        // success forgets the failures
+        lockout.succeed(ip, "user");
+        assert_eq!(lockout.check_at(ip, Some("user"), later), None);
+    }
+
+    #[test]
+    fn test_user_lockout_any_ip() {
+        let lockout = Lockout::new(Config {
+            max_user_failures_any_ip: 4,
+            ..Default::default()
+        })
+        .unwrap();
+        let now = Instant::now();
+        // guesses spread over addresses still lock the username out, from every address
+        for i in 1..=4 {
+            let ip = IpAddr::from([192, 0, 2, i]);
+            assert_eq!(lockout.check_at(ip, Some("user"), now), None);
+            lockout.fail_at(ip, Some("user"), "wrong password", now);
+        }
+        let other_ip = IpAddr::from([198, 51, 100, 1]);
+        assert_eq!(
+            lockout.check_at(other_ip, Some("user"), now),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(lockout.check_at(other_ip, Some("other"), now), None);
+        assert_eq!(lockout.check_at(other_ip, None, now), None);
+        // and the owner logging in does not reset the count
+        lockout.succeed(other_ip, "user");
+        let later = now + Duration::from_secs(60);
+        lockout.fail_at(other_ip, Some("user"), "wrong password", later);
+        assert_eq!(
+            lockout.check_at(other_ip, Some("user"), later),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn test_ip_lockout() {
+        let lockout = Lockout::new(Config {
+            max_ip_failures: 3,
+            max_lockout: 100,
+            ..Default::default()
+        })
+        .unwrap();
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        let other_ip = IpAddr::from([192, 0, 2, 2]);
+        let now = Instant::now();
+        // spreading guesses over usernames does not avoid the lockout
+        for username in ["a", "b", "c"] {
+            lockout.fail_at(ip, Some(username), "wrong password", now);
+        }
+        assert_eq!(
+            lockout.check_at(ip, None, now),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(lockout.check_at(other_ip, Some("d"), now), None);
+        // capped
+        let later = now + Duration::from_secs(60);
+        lockout.fail_at(ip, None, "unknown token", later);
+        lockout.fail_at(ip, None, "unknown token", later);
+        assert_eq!(
+            lockout.check_at(ip, None, later),
+            Some(Duration::from_secs(100))
+        );
+        // forgotten after a while without failures
+        let much_later = later + Duration::from_secs(3600);
+        lockout.fail_at(ip, None, "unknown token", much_later);
+        assert_eq!(lockout.check_at(ip, None, much_later), None);
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let lockout = Lockout::new(Config {
+            max_user_failures: 2,
+            audit_log: Some(path.clone()),
+            ..Default::default()
+        })
+        .unwrap();
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        lockout.fail(ip, Some("user"), "wrong password");
+        lockout.fail(ip, None, "unknown token");
+        lockout.fail(ip, Some("user"), "wrong password");
+        lockout.fail(ip, Some("forged\tname\n"), "unknown user");
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines = log
+            .lines()
+            .map(|line| line.split('\t').skip(1).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                vec!["192.0.2.1", "user", "wrong password"],
+                vec!["192.0.2.1", "-", "unknown token"],
+                vec!["192.0.2.1", "user", "wrong password"],
+                vec!["192.0.2.1", "user", "user locked out"],
+                vec!["192.0.2.1", "forged\\tname\\n", "unknown user"],
+            ]
+        );
+    }
+}
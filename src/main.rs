@@ -1,13 +1,21 @@
 use std::io::Write;
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{Args as ClapArgs, Parser, Subcommand};
 use color_eyre::eyre::Result;
 use env_logger::{Builder as LogBuilder, Env as LogEnv};
 use log::info;
 
-use crataegus::cli::{backup, export, import, info, serve, useradd, Config, ImportFormat};
+use crataegus::cli::{
+    backup, export, import, info, migrate, migrate_status, quarantine_clear, quarantine_list,
+    quarantine_promote, quarantine_purge, quarantine_show, serve, token_create, token_list,
+    token_revoke, user_del, user_list, user_passwd, user_rename, useradd, Config, ImportFormat,
+    PasswordSource,
+};
+use crataegus::db::ConflictPolicy;
 use crataegus::export::Format as ExportFormat;
+use crataegus::import::DEFAULT_MAX_ZIP_BYTES;
+use crataegus::schema::Scope;
 
 /// Command line arguments
 #[derive(Parser, Debug)]
@@ -26,10 +34,36 @@ struct Args {
 enum Cmd {
     /// Start the server
     Serve,
-    /// Add a user to the database
-    Useradd,
+    /// Add a user to the database, the same as `user add`
+    Useradd {
+        /// Prompted for if not given
+        username: Option<String>,
+
+        #[clap(flatten)]
+        password: PasswordArgs,
+    },
+    /// Manage users
+    User {
+        #[clap(subcommand)]
+        action: UserCmd,
+    },
+    /// Manage API tokens, which apps may use instead of a user's password
+    Token {
+        #[clap(subcommand)]
+        action: TokenCmd,
+    },
     /// Backup the database. May be called while server is running.
     Backup,
+    /// Bring the database schema up to date with this version, backing it up first
+    Migrate {
+        /// Only show the schema version and the pending migrations
+        #[clap(long)]
+        status: bool,
+
+        /// Print the statements that would run, without running them
+        #[clap(long, conflicts_with = "status")]
+        dry_run: bool,
+    },
     /// Export the database to a file
     Export {
         // all these values are required
@@ -44,23 +78,186 @@ enum Cmd {
         start_str: String,
 
         stop_str: String,
+
+        /// Only export locations recorded by this device
+        #[clap(short, long)]
+        device: Option<String>,
     },
     Import {
-        /// The format of the file to import
+        /// The format of the file to import, or `auto` to detect it for each file
         #[arg(value_enum)]
         format: ImportFormat,
 
-        /// The path to the file to import
-        #[clap(value_hint = clap::ValueHint::FilePath)]
+        /// The path to the file, directory or ZIP archive to import
+        #[clap(value_hint = clap::ValueHint::AnyPath)]
         path: PathBuf,
 
         /// The username to associate with the imported data
         username: String,
+
+        /// What to do with locations that share their time with a stored location, but differ
+        /// from it
+        #[clap(long, value_enum, default_value_t = ConflictPolicy::Error)]
+        conflicts: ConflictPolicy,
+
+        /// Check every record against the database and report what would happen, without
+        /// writing anything
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Most bytes the ZIP archives found may extract to, all together
+        #[clap(long, default_value_t = DEFAULT_MAX_ZIP_BYTES)]
+        max_zip_bytes: u64,
     },
     Info {
         /// Optionally specify a username to get info for
         #[clap(short, long)]
         username: Option<String>,
+
+        /// Optionally count only the locations of this device
+        #[clap(short, long)]
+        device: Option<String>,
+    },
+    /// Review locations that failed the sanity check and were quarantined
+    Quarantine {
+        #[clap(subcommand)]
+        action: QuarantineCmd,
+    },
+}
+
+/// User subcommands
+#[derive(Subcommand, Debug)]
+enum UserCmd {
+    /// Add a user
+    Add {
+        /// Prompted for if not given
+        username: Option<String>,
+
+        #[clap(flatten)]
+        password: PasswordArgs,
+    },
+    /// List the usernames, one per line
+    List,
+    /// Delete a user, with all of their locations
+    Del {
+        username: String,
+
+        /// Do not ask for confirmation
+        #[clap(short, long)]
+        yes: bool,
+
+        /// Back up the database before deleting
+        #[clap(long)]
+        backup: bool,
+    },
+    /// Rename a user, with all of their locations
+    Rename {
+        username: String,
+
+        new_username: String,
+    },
+    /// Change a user's password
+    Passwd {
+        username: String,
+
+        #[clap(flatten)]
+        password: PasswordArgs,
+    },
+}
+
+/// API token subcommands
+#[derive(Subcommand, Debug)]
+enum TokenCmd {
+    /// Create a token and print it, which is the only time it is shown
+    Create {
+        /// The user the token authenticates as
+        username: String,
+
+        /// Name of the token, such as the device it is for
+        name: String,
+
+        /// What the token may be used for
+        #[clap(short, long, value_enum, default_value_t = Scope::Ingest)]
+        scope: Scope,
+
+        /// When the token stops working, such as `2026-12-31` or `in 90 days`
+        #[clap(short, long)]
+        expires: Option<String>,
+    },
+    /// List tokens, without the tokens themselves
+    List {
+        /// Only list this user's
+        #[clap(short, long)]
+        username: Option<String>,
+    },
+    /// Revoke a token
+    Revoke { username: String, name: String },
+}
+
+/// Ways to give a password without being prompted for it. It is prompted for if neither is used.
+#[derive(ClapArgs)]
+struct PasswordArgs {
+    /// The password. Prefer the environment variable, since arguments are visible to other users.
+    #[clap(long, env = "CRATAEGUS_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// Read the password from the first line of stdin, ignoring `--password`
+    #[clap(long)]
+    password_stdin: bool,
+}
+
+impl PasswordArgs {
+    fn source(self) -> PasswordSource {
+        match (self.password_stdin, self.password) {
+            (true, _) => PasswordSource::Stdin,
+            (false, Some(password)) => PasswordSource::Value(password),
+            (false, None) => PasswordSource::Prompt,
+        }
+    }
+}
+
+/// The arguments are logged, so the password is left out.
+impl std::fmt::Debug for PasswordArgs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PasswordArgs")
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("password_stdin", &self.password_stdin)
+            .finish()
+    }
+}
+
+/// Quarantine subcommands
+#[derive(Subcommand, Debug)]
+enum QuarantineCmd {
+    /// List quarantined locations, with the rule each failed
+    List {
+        /// Only list this user's
+        #[clap(short, long)]
+        username: Option<String>,
+    },
+    /// Show every field of a quarantined location
+    Show { id: i64 },
+    /// Record quarantined locations despite the altitude and accuracy limits
+    Promote {
+        #[clap(required = true)]
+        ids: Vec<i64>,
+
+        /// What to do if a location conflicts with a stored one
+        #[clap(long, value_enum, default_value_t = ConflictPolicy::Error)]
+        conflicts: ConflictPolicy,
+    },
+    /// Delete quarantined locations
+    Purge {
+        #[clap(required_unless_present = "all")]
+        ids: Vec<i64>,
+
+        /// Delete every quarantined location
+        #[clap(long, conflicts_with = "ids")]
+        all: bool,
+
+        /// With `--all`, only delete this user's
+        #[clap(short, long, requires = "all")]
+        username: Option<String>,
     },
 }
 
@@ -98,21 +295,94 @@ async fn main() -> Result<()> {
 
     match args.cmd {
         Cmd::Serve => serve(config).await?,
-        Cmd::Useradd => useradd(config).await?,
+        Cmd::Useradd { username, password } => useradd(config, username, password.source()).await?,
+        Cmd::User { action } => match action {
+            UserCmd::Add { username, password } => {
+                useradd(config, username, password.source()).await?
+            }
+            UserCmd::List => user_list(config).await?,
+            UserCmd::Del {
+                username,
+                yes,
+                backup,
+            } => user_del(config, &username, yes, backup).await?,
+            UserCmd::Rename {
+                username,
+                new_username,
+            } => user_rename(config, &username, &new_username).await?,
+            UserCmd::Passwd { username, password } => {
+                user_passwd(config, &username, password.source()).await?
+            }
+        },
         Cmd::Backup => backup(config).await?,
+        Cmd::Migrate { status, dry_run } => match status {
+            true => migrate_status(config).await?,
+            false => migrate(config, dry_run).await?,
+        },
         Cmd::Export {
             format,
             path,
             username,
             start_str,
             stop_str,
-        } => export(config, format, &path, &username, &start_str, &stop_str).await?,
+            device,
+        } => {
+            export(
+                config,
+                format,
+                &path,
+                &username,
+                device.as_deref(),
+                &start_str,
+                &stop_str,
+            )
+            .await?
+        }
         Cmd::Import {
             format,
             path,
             username,
-        } => import(config, format, &path, &username).await?,
-        Cmd::Info { username } => info(config, username.as_deref()).await?,
+            conflicts,
+            dry_run,
+            max_zip_bytes,
+        } => {
+            import(
+                config,
+                format,
+                &path,
+                &username,
+                conflicts,
+                dry_run,
+                max_zip_bytes,
+            )
+            .await?
+        }
+        Cmd::Info { username, device } => {
+            info(config, username.as_deref(), device.as_deref()).await?
+        }
+        Cmd::Token { action } => match action {
+            TokenCmd::Create {
+                username,
+                name,
+                scope,
+                expires,
+            } => token_create(config, &username, &name, scope, expires.as_deref()).await?,
+            TokenCmd::List { username } => token_list(config, username.as_deref()).await?,
+            TokenCmd::Revoke { username, name } => token_revoke(config, &username, &name).await?,
+        },
+        Cmd::Quarantine { action } => match action {
+            QuarantineCmd::List { username } => {
+                quarantine_list(config, username.as_deref()).await?
+            }
+            QuarantineCmd::Show { id } => quarantine_show(config, id).await?,
+            QuarantineCmd::Promote { ids, conflicts } => {
+                quarantine_promote(config, &ids, conflicts).await?
+            }
+            QuarantineCmd::Purge { ids, all, username } => match all {
+                true => quarantine_clear(config, username.as_deref()).await?,
+                false => quarantine_purge(config, &ids).await?,
+            },
+        },
     }
 
     Ok(())
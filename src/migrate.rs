@@ -0,0 +1,582 @@
+use chrono::Utc;
+use color_eyre::eyre::{Result, WrapErr};
+use log::{debug, info};
+use sea_orm::{ConnectionTrait, Statement, TransactionTrait};
+
+/// Table recording which migrations have been applied, one row per migration.
+const VERSION_TABLE: &str = "schema_version";
+
+/// One statement of a migration.
+#[derive(Debug, Clone)]
+pub struct Step {
+    /// Query that returns a row if the statement has already taken effect, in which case it is
+    /// skipped. Databases created before migrations were versioned may be anywhere between the
+    /// first schema and the one before versioning, so every step must be safe to skip.
+    pub unless: Option<String>,
+    /// The statement
+    pub sql: String,
+}
+
+/// A numbered change to the schema. Migrations are applied in order, each in its own
+/// transaction, and are never edited once released: changes to the schema need a new migration.
+#[derive(Debug, Clone)]
+pub struct Migration {
+    /// Schema version after the migration
+    pub version: i64,
+    /// What the migration does
+    pub description: &'static str,
+    /// The statements, in order
+    pub steps: Vec<Step>,
+}
+
+/// A statement that creates a table, if it does not exist.
+fn create_table(sql: &str) -> Step {
+    Step {
+        unless: None,
+        sql: sql.replacen("CREATE TABLE", "CREATE TABLE IF NOT EXISTS", 1),
+    }
+}
+
+/// A statement that adds a column to a table, if it does not have it.
+fn add_column(table: &str, column: &str, definition: &str) -> Step {
+    Step {
+        unless: Some(has_column(table, column)),
+        sql: format!(r#"ALTER TABLE "{table}" ADD COLUMN "{column}" {definition}"#),
+    }
+}
+
+/// A query that returns a row if the table has the column.
+fn has_column(table: &str, column: &str) -> String {
+    format!("SELECT 1 FROM pragma_table_info('{table}') WHERE name = '{column}'")
+}
+
+/// Columns added to locations, conflicts and quarantine in version 5.
+const DETAIL_COLUMNS: [(&str, &str); 9] = [
+    ("speed", "float"),
+    ("bearing", "float"),
+    ("satellites", "integer"),
+    ("provider", "varchar"),
+    ("hdop", "float"),
+    ("vdop", "float"),
+    ("pdop", "float"),
+    ("battery", "float"),
+    ("charging", "boolean"),
+];
+
+/// Every migration, in order. The schema they build must match the entities in `schema`.
+pub fn migrations() -> Vec<Migration> {
+    vec![
+        Migration {
+            version: 1,
+            description: "Create the users and locations tables",
+            steps: vec![
+                create_table(
+                    r#"CREATE TABLE "users" (
+                        "username" varchar NOT NULL PRIMARY KEY,
+                        "password" varchar NOT NULL
+                    )"#,
+                ),
+                create_table(
+                    r#"CREATE TABLE "locations" (
+                        "username" varchar NOT NULL,
+                        "time_utc" timestamp_with_timezone_text NOT NULL,
+                        "time_local" timestamp_with_timezone_text NOT NULL,
+                        "latitude" double NOT NULL,
+                        "longitude" double NOT NULL,
+                        "altitude" double NOT NULL,
+                        "accuracy" float,
+                        "source" varchar(32) NOT NULL,
+                        PRIMARY KEY ("username", "time_utc"),
+                        FOREIGN KEY ("username") REFERENCES "users" ("username")
+                            ON DELETE CASCADE ON UPDATE CASCADE
+                    )"#,
+                ),
+            ],
+        },
+        Migration {
+            version: 2,
+            description: "Create the segments table for visits and activities",
+            steps: vec![create_table(
+                r#"CREATE TABLE "segments" (
+                    "username" varchar NOT NULL,
+                    "start_utc" timestamp_with_timezone_text NOT NULL,
+                    "end_utc" timestamp_with_timezone_text NOT NULL,
+                    "start_local" timestamp_with_timezone_text NOT NULL,
+                    "end_local" timestamp_with_timezone_text NOT NULL,
+                    "kind" varchar(16) NOT NULL,
+                    "latitude" double NOT NULL,
+                    "longitude" double NOT NULL,
+                    "end_latitude" double,
+                    "end_longitude" double,
+                    "place_id" varchar,
+                    "semantic_type" varchar,
+                    "probability" float,
+                    "distance" float,
+                    PRIMARY KEY ("username", "start_utc", "end_utc"),
+                    FOREIGN KEY ("username") REFERENCES "users" ("username")
+                        ON DELETE CASCADE ON UPDATE CASCADE
+                )"#,
+            )],
+        },
+        Migration {
+            version: 3,
+            description: "Create the conflicts table for locations kept aside",
+            steps: vec![create_table(
+                r#"CREATE TABLE "conflicts" (
+                    "id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    "username" varchar NOT NULL,
+                    "time_utc" timestamp_with_timezone_text NOT NULL,
+                    "time_local" timestamp_with_timezone_text NOT NULL,
+                    "latitude" double NOT NULL,
+                    "longitude" double NOT NULL,
+                    "altitude" double NOT NULL,
+                    "accuracy" float,
+                    "source" varchar(32) NOT NULL,
+                    FOREIGN KEY ("username") REFERENCES "users" ("username")
+                        ON DELETE CASCADE ON UPDATE CASCADE
+                )"#,
+            )],
+        },
+        Migration {
+            version: 4,
+            description: "Create the quarantine table for locations that fail the sanity check",
+            steps: vec![create_table(
+                r#"CREATE TABLE "quarantine" (
+                    "id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    "username" varchar NOT NULL,
+                    "time_utc" timestamp_with_timezone_text NOT NULL,
+                    "time_local" timestamp_with_timezone_text NOT NULL,
+                    "latitude" double,
+                    "longitude" double,
+                    "altitude" double,
+                    "accuracy" float,
+                    "source" varchar(32) NOT NULL,
+                    "rule" varchar NOT NULL,
+                    "quarantined_utc" timestamp_with_timezone_text NOT NULL,
+                    FOREIGN KEY ("username") REFERENCES "users" ("username")
+                        ON DELETE CASCADE ON UPDATE CASCADE
+                )"#,
+            )],
+        },
+        Migration {
+            version: 5,
+            description: "Add speed, bearing, satellites, provider, DOPs and battery to locations",
+            steps: ["locations", "conflicts", "quarantine"]
+                .into_iter()
+                .flat_map(|table| {
+                    DETAIL_COLUMNS
+                        .iter()
+                        .map(move |(column, definition)| add_column(table, column, definition))
+                })
+                .collect(),
+        },
+        Migration {
+            version: 6,
+            description: "Create the devices table and add the device to the key of locations",
+            steps: vec![
+                create_table(
+                    r#"CREATE TABLE "devices" (
+                        "username" varchar NOT NULL,
+                        "id" varchar NOT NULL,
+                        "serial" varchar,
+                        "profile" varchar,
+                        PRIMARY KEY ("username", "id"),
+                        FOREIGN KEY ("username") REFERENCES "users" ("username")
+                            ON DELETE CASCADE ON UPDATE CASCADE
+                    )"#,
+                ),
+                add_column("conflicts", "device", "varchar NOT NULL DEFAULT ''"),
+                add_column("quarantine", "device", "varchar NOT NULL DEFAULT ''"),
+                // SQLite cannot change a primary key, so the table is rebuilt
+                Step {
+                    unless: Some(has_column("locations", "device")),
+                    sql: r#"CREATE TABLE "locations_new" (
+                        "username" varchar NOT NULL,
+                        "time_utc" timestamp_with_timezone_text NOT NULL,
+                        "time_local" timestamp_with_timezone_text NOT NULL,
+                        "latitude" double NOT NULL,
+                        "longitude" double NOT NULL,
+                        "altitude" double NOT NULL,
+                        "accuracy" float,
+                        "source" varchar(32) NOT NULL,
+                        "speed" float,
+                        "bearing" float,
+                        "satellites" integer,
+                        "provider" varchar,
+                        "hdop" float,
+                        "vdop" float,
+                        "pdop" float,
+                        "battery" float,
+                        "charging" boolean,
+                        "device" varchar NOT NULL,
+                        PRIMARY KEY ("username", "time_utc", "device"),
+                        FOREIGN KEY ("username") REFERENCES "users" ("username")
+                            ON DELETE CASCADE ON UPDATE CASCADE
+                    )"#
+                    .to_string(),
+                },
+                Step {
+                    unless: Some(has_column("locations", "device")),
+                    sql: r#"INSERT INTO "locations_new" SELECT
+                        "username", "time_utc", "time_local", "latitude", "longitude", "altitude",
+                        "accuracy", "source", "speed", "bearing", "satellites", "provider",
+                        "hdop", "vdop", "pdop", "battery", "charging", ''
+                        FROM "locations""#
+                        .to_string(),
+                },
+                Step {
+                    unless: Some(has_column("locations", "device")),
+                    sql: r#"DROP TABLE "locations""#.to_string(),
+                },
+                Step {
+                    unless: Some(has_column("locations", "device")),
+                    sql: r#"ALTER TABLE "locations_new" RENAME TO "locations""#.to_string(),
+                },
+            ],
+        },
+        Migration {
+            version: 7,
+            description: "Create the api_tokens table",
+            steps: vec![create_table(
+                r#"CREATE TABLE "api_tokens" (
+                    "id" integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+                    "username" varchar NOT NULL,
+                    "name" varchar NOT NULL,
+                    "scope" varchar(16) NOT NULL,
+                    "hash" varchar NOT NULL UNIQUE,
+                    "created_utc" timestamp_with_timezone_text NOT NULL,
+                    "expires_utc" timestamp_with_timezone_text,
+                    UNIQUE ("username", "name"),
+                    FOREIGN KEY ("username") REFERENCES "users" ("username")
+                        ON DELETE CASCADE ON UPDATE CASCADE
+                )"#,
+            )],
+        },
+        Migration {
+            version: 8,
+            description: "Index the quarantine by user and time",
+            steps: vec![Step {
+                unless: None,
+                sql: r#"CREATE INDEX IF NOT EXISTS "idx-quarantine-username-time_utc"
+                    ON "quarantine" ("username", "time_utc")"#
+                    .to_string(),
+            }],
+        },
+    ]
+}
+
+/// The schema version this build of crataegus works with.
+pub fn latest_version() -> i64 {
+    migrations().last().map_or(0, |migration| migration.version)
+}
+
+/// Whether a table exists.
+async fn table_exists<C: ConnectionTrait>(conn: &C, table: &str) -> Result<bool> {
+    let row = conn
+        .query_one(Statement::from_sql_and_values(
+            conn.get_database_backend(),
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?",
+            [table.into()],
+        ))
+        .await
+        .wrap_err(format!("Failed to check whether table `{}` exists", table))?;
+    Ok(row.is_some())
+}
+
+/// Find the version of the schema.
+/// # Arguments
+/// * `conn` - The database connection
+/// # Returns
+/// The version of the last migration applied, or None if migrations have never been applied. In
+/// that case, the database is empty if it has no users table, and otherwise predates versioning.
+pub async fn current_version<C: ConnectionTrait>(conn: &C) -> Result<Option<i64>> {
+    if !table_exists(conn, VERSION_TABLE).await? {
+        return Ok(None);
+    }
+    let row = conn
+        .query_one(Statement::from_string(
+            conn.get_database_backend(),
+            format!(r#"SELECT MAX("version") AS "version" FROM "{VERSION_TABLE}""#),
+        ))
+        .await
+        .wrap_err("Failed to query the schema version")?;
+    let version: Option<i64> = match row {
+        Some(row) => row.try_get("", "version")?,
+        None => None,
+    };
+    Ok(Some(version.unwrap_or(0)))
+}
+
+/// Whether the database has no tables of crataegus yet.
+pub async fn is_empty<C: ConnectionTrait>(conn: &C) -> Result<bool> {
+    Ok(!table_exists(conn, VERSION_TABLE).await? && !table_exists(conn, "users").await?)
+}
+
+/// Whether a step would run, or be skipped because it has already taken effect.
+pub async fn step_pending<C: ConnectionTrait>(conn: &C, step: &Step) -> Result<bool> {
+    let Some(unless) = &step.unless else {
+        return Ok(true);
+    };
+    let row = conn
+        .query_one(Statement::from_string(
+            conn.get_database_backend(),
+            unless.clone(),
+        ))
+        .await
+        .wrap_err(format!("Failed to check migration step: {}", unless))?;
+    Ok(row.is_none())
+}
+
+/// Apply a migration in a transaction, and record it in the version table. If any step fails,
+/// nothing is changed.
+/// # Arguments
+/// * `conn` - The database connection
+/// * `migration` - The migration to apply
+pub async fn apply<C: ConnectionTrait + TransactionTrait>(
+    conn: &C,
+    migration: &Migration,
+) -> Result<()> {
+    info!(
+        "Migrating database to version {}: {}",
+        migration.version, migration.description
+    );
+    let txn = conn
+        .begin()
+        .await
+        .wrap_err("Failed to begin migration transaction")?;
+    txn.execute_unprepared(&format!(
+        r#"CREATE TABLE IF NOT EXISTS "{VERSION_TABLE}" (
+            "version" integer NOT NULL PRIMARY KEY,
+            "description" varchar NOT NULL,
+            "applied_utc" timestamp_with_timezone_text NOT NULL
+        )"#
+    ))
+    .await
+    .wrap_err("Failed to create the schema version table")?;
+    for step in migration.steps.iter() {
+        if !step_pending(&txn, step).await? {
+            debug!("Skipping migration step already in effect: {}", step.sql);
+            continue;
+        }
+        txn.execute_unprepared(&step.sql).await.wrap_err(format!(
+            "Migration to version {} failed at: {}",
+            migration.version, step.sql
+        ))?;
+    }
+    txn.execute(Statement::from_sql_and_values(
+        txn.get_database_backend(),
+        format!(
+            r#"INSERT INTO "{VERSION_TABLE}" ("version", "description", "applied_utc") VALUES (?, ?, ?)"#
+        ),
+        [
+            migration.version.into(),
+            migration.description.into(),
+            Utc::now().to_rfc3339().into(),
+        ],
+    ))
+    .await
+    .wrap_err("Failed to record the schema version")?;
+    // returning early drops the transaction, which rolls it back
+    txn.commit().await.wrap_err(format!(
+        "Failed to commit migration to version {}",
+        migration.version
+    ))
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Config, ConflictPolicy, Db};
+    use crate::gpslogger::csv::read_csv;
+    use crate::schema::{api_token, conflict, device, location, quarantine, segment, user};
+    use pretty_assertions::assert_eq;
+    use sea_orm::{Database, EntityTrait};
+    use tempfile::tempdir;
+
+    /// Query every entity, which fails if a column it expects is missing.
+    async fn check_entities(conn: &impl ConnectionTrait) {
+        user::Entity::find().all(conn).await.unwrap();
+        location::Entity::find().all(conn).await.unwrap();
+        segment::Entity::find().all(conn).await.unwrap();
+        conflict::Entity::find().all(conn).await.unwrap();
+        quarantine::Entity::find().all(conn).await.unwrap();
+        device::Entity::find().all(conn).await.unwrap();
+        api_token::Entity::find().all(conn).await.unwrap();
+    }
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        let migrations = migrations();
+        for (i, migration) in migrations.iter().enumerate() {
+            assert_eq!(migration.version, i as i64 + 1);
+        }
+        assert_eq!(latest_version(), migrations.len() as i64);
+    }
+
+    /// A new database is created at the latest version.
+    #[tokio::test]
+    async fn test_new_database() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        let conn = Database::connect(format!("sqlite://{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+        assert!(is_empty(&conn).await.unwrap());
+        assert_eq!(current_version(&conn).await.unwrap(), None);
+        for migration in migrations() {
+            apply(&conn, &migration).await.unwrap();
+        }
+        assert!(!is_empty(&conn).await.unwrap());
+        assert_eq!(
+            current_version(&conn).await.unwrap(),
+            Some(latest_version())
+        );
+        check_entities(&conn).await;
+        // every step has taken effect, so applying again changes nothing
+        for migration in migrations() {
+            for step in migration.steps.iter().filter(|step| step.unless.is_some()) {
+                assert!(!step_pending(&conn, step).await.unwrap(), "{}", step.sql);
+            }
+        }
+    }
+
+    /// The statements crataegus ran to create its tables before migrations were versioned, as
+    /// sea-orm built them from the entities.
+    const UNVERSIONED_SCHEMA: [&str; 2] = [
+        r#"CREATE TABLE IF NOT EXISTS "users" ( "username" varchar NOT NULL PRIMARY KEY, "password" varchar NOT NULL )"#,
+        r#"CREATE TABLE IF NOT EXISTS "locations" ( "username" varchar NOT NULL, "time_utc" timestamp_with_timezone_text NOT NULL, "time_local" timestamp_with_timezone_text NOT NULL, "latitude" double NOT NULL, "longitude" double NOT NULL, "altitude" double NOT NULL, "accuracy" float, "source" varchar(32) NOT NULL, CONSTRAINT "pk-locations" PRIMARY KEY ("username", "time_utc"), FOREIGN KEY ("username") REFERENCES "users" ("username") ON DELETE CASCADE ON UPDATE CASCADE )"#,
+    ];
+
+    /// A database from before versioning is migrated, keeping its locations.
+    #[tokio::test]
+    async fn test_unversioned_database() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        {
+            let conn = Database::connect(format!("sqlite://{}?mode=rwc", path.display()))
+                .await
+                .unwrap();
+            for sql in UNVERSIONED_SCHEMA {
+                conn.execute_unprepared(sql).await.unwrap();
+            }
+            conn.execute_unprepared(r#"INSERT INTO "users" VALUES ('test', 'pass')"#)
+                .await
+                .unwrap();
+            conn.execute_unprepared(
+                r#"INSERT INTO "locations" VALUES ('test', '2025-01-16T03:54:51+00:00',
+                    '2025-01-15T20:54:51-07:00', 41.7, -91.8, 1387.0, 6.0, 'GPSLogger')"#,
+            )
+            .await
+            .unwrap();
+        }
+        let config = Config {
+            path: path.clone(),
+            backups: 1,
+            bounds: Default::default(),
+        };
+        // the database must be migrated explicitly
+        assert!(Db::new(&config).await.is_err());
+        let db = Db::open(&config).await.unwrap();
+        let status = db.migration_status().await.unwrap();
+        assert_eq!(status.current, None);
+        assert_eq!(status.pending.len(), migrations().len());
+        assert_eq!(db.migrate().await.unwrap(), latest_version() as usize);
+        let status = db.migration_status().await.unwrap();
+        assert_eq!(status.current, Some(latest_version()));
+        assert!(status.pending.is_empty());
+        // a backup of the old schema was made first
+        let backups = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".bak"))
+            .collect::<Vec<_>>();
+        assert_eq!(backups.len(), 1);
+        drop(db);
+
+        let db = Db::new(&config).await.unwrap();
+        let locations = db
+            .location_vec(
+                "test",
+                "2025-01-16T00:00:00Z".parse().unwrap(),
+                "2025-01-17T00:00:00Z".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].latitude, 41.7);
+        assert_eq!(locations[0].device, "");
+        assert_eq!(locations[0].speed, None);
+        assert_eq!(db.migrate().await.unwrap(), 0);
+    }
+
+    /// Locations stored before the details were have them filled in by importing them again,
+    /// rather than conflicting.
+    #[tokio::test]
+    async fn test_reimport_after_details() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        {
+            let conn = Database::connect(format!("sqlite://{}?mode=rwc", path.display()))
+                .await
+                .unwrap();
+            for migration in &migrations()[..4] {
+                apply(&conn, migration).await.unwrap();
+            }
+            conn.execute_unprepared(r#"INSERT INTO "users" VALUES ('test', 'pass')"#)
+                .await
+                .unwrap();
+            conn.execute_unprepared(
+                r#"INSERT INTO "locations" VALUES ('test', '2025-01-24T07:02:29.168+00:00',
+                    '2025-01-24T00:02:29.168-07:00', 24.240779519081116, -11.84485614299774,
+                    1476.0, 48.0, 'GPSLogger')"#,
+            )
+            .await
+            .unwrap();
+        }
+        let config = Config {
+            path,
+            backups: 0,
+            bounds: Default::default(),
+        };
+        let db = Db::open(&config).await.unwrap();
+        assert_eq!(db.migrate().await.unwrap(), migrations().len() - 4);
+        drop(db);
+
+        let csv = dir.path().join("20250124.csv");
+        std::fs::write(
+            &csv,
+            "time,lat,lon,elevation,accuracy,bearing,speed,satellites,provider,hdop,vdop,pdop,geoidheight,ageofdgpsdata,dgpsid,activity,battery,annotation,timestamp_ms,time_offset,distance,starttimestamp_ms,profile_name,battery_charging
+2025-01-24T07:02:29.168Z,24.240779519081116,-11.84485614299774,1476.0,48.0,,0.0,0,gps,,,,,,,,64,,1737702149168,2025-01-24T00:02:29.168-07:00,14780.376051140634,1737686054899,Default Profile,false
+",
+        )
+        .unwrap();
+        let db = Db::new(&config).await.unwrap();
+        let counts = db
+            .location_insert_many(read_csv(&csv, "test").unwrap(), ConflictPolicy::Error)
+            .await
+            .unwrap();
+        assert_eq!((counts.skipped, counts.added), (1, 0));
+        let locations = db
+            .location_vec(
+                "test",
+                "2025-01-24T00:00:00Z".parse().unwrap(),
+                "2025-01-25T00:00:00Z".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].provider.as_deref(), Some("gps"));
+        assert_eq!(locations[0].battery, Some(64.0));
+        assert_eq!(locations[0].charging, Some(false));
+        // and once filled in, importing again skips it as before
+        let counts = db
+            .location_insert_many(read_csv(&csv, "test").unwrap(), ConflictPolicy::Error)
+            .await
+            .unwrap();
+        assert_eq!(counts.skipped, 1);
+    }
+}
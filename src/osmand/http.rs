@@ -0,0 +1,208 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+use serde::{de, Deserialize, Deserializer};
+
+use crate::gpslogger::deserializers::{deserialize_option_f32, deserialize_option_f64};
+use crate::schema::{Location, LocationGen, Source};
+
+/// # HTTP
+/// The OsmAnd protocol, as spoken by OsmAnd live tracking, Traccar Client and many hardware
+/// trackers, sends every fix as query parameters on a GET or POST request:
+/// ```txt
+/// https://<host>:<port>/osmand?id=123456&lat=41.74108695983887&lon=-91.84490871429443&timestamp=1736999691&hdop=6.0&altitude=1387.0&speed=0.0
+/// ```
+///
+/// Many of these clients cannot send basic auth, so the device `id` is mapped to a crataegus user
+/// in the server configuration instead.
+///
+/// The timestamp may be Unix seconds, Unix milliseconds, an ISO 8601 string, or
+/// `yyyy-MM-dd HH:mm:ss` in UTC, depending on the client. Only ISO 8601 strings carry a local
+/// offset; the others are recorded with a zero offset.
+///
+/// OsmAnd fills `hdop` with its own accuracy estimate in meters, while Traccar Client sends a
+/// separate `accuracy` parameter. `accuracy` is preferred when both are present.
+#[derive(Deserialize, Debug)]
+pub struct Payload {
+    /// Device identifier, configured in the app.
+    /// Example: `123456`.
+    pub id: String,
+    /// Latitude in decimal degrees.
+    /// Example: `41.74108695983887`.
+    pub lat: f64,
+    /// Longitude in decimal degrees.
+    /// Example: `-91.84490871429443`.
+    pub lon: f64,
+    /// Time of the fix.
+    /// Example: `1736999691`, `2025-01-15T20:54:51-07:00`.
+    #[serde(deserialize_with = "deserialize_timestamp")]
+    pub timestamp: DateTime<FixedOffset>,
+    /// Horizontal dilution of precision, or the accuracy in meters for OsmAnd.
+    /// Example: `6.0`.
+    #[serde(default, deserialize_with = "deserialize_option_f32")]
+    pub hdop: Option<f32>,
+    /// Horizontal accuracy in meters.
+    /// Example: `6.0`.
+    #[serde(default, deserialize_with = "deserialize_option_f32")]
+    pub accuracy: Option<f32>,
+    /// Altitude in meters.
+    /// Example: `1387.0`.
+    #[serde(default, deserialize_with = "deserialize_option_f64")]
+    pub altitude: Option<f64>,
+    /// Speed, in knots according to Traccar.
+    /// Example: `0.0`.
+    #[serde(default, deserialize_with = "deserialize_option_f32")]
+    #[allow(dead_code)]
+    speed: Option<f32>,
+    /// Direction of travel in degrees.
+    /// Example: `170.8`.
+    #[serde(default, deserialize_with = "deserialize_option_f32")]
+    #[allow(dead_code)]
+    bearing: Option<f32>,
+    /// Battery percentage.
+    /// Example: `27.0`.
+    #[serde(default, deserialize_with = "deserialize_option_f32")]
+    #[allow(dead_code)]
+    batt: Option<f32>,
+}
+
+/// Deserializer for the many timestamp formats used by OsmAnd protocol clients.
+/// # Arguments
+/// * `deserializer` - The serde deserializer.
+/// # Return
+/// A DateTime<FixedOffset> if the string is parseable, or an error if it is not.
+fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<DateTime<FixedOffset>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_timestamp(&s).ok_or_else(|| de::Error::custom(format!("Invalid timestamp: {}", s)))
+}
+
+/// Parse a timestamp in any of the formats accepted by Traccar's OsmAnd protocol decoder.
+/// # Arguments
+/// * `s` - The timestamp string.
+/// # Return
+/// The time, or None if the format is not recognized.
+fn parse_timestamp(s: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(ts) = s.parse::<i64>() {
+        // anything past the year 5138 in seconds is assumed to be milliseconds
+        let time = if ts > 100_000_000_000 {
+            DateTime::from_timestamp_millis(ts)
+        } else {
+            DateTime::from_timestamp(ts, 0)
+        };
+        return time.map(|time| time.fixed_offset());
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Some(time);
+    }
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .map(|time| time.and_utc().fixed_offset())
+}
+
+impl LocationGen for Payload {
+    /// Convert the Payload struct to a Location struct. A missing altitude is recorded as 0.
+    /// # Arguments
+    /// * `username` - The username to associate with the location.
+    /// # Return
+    /// A Location struct with the data from the Payload struct.
+    fn to_location(&self, username: &str) -> Location {
+        Location {
+            username: username.to_string(),
+            time_utc: self.timestamp.to_utc(),
+            time_local: self.timestamp,
+            latitude: self.lat,
+            longitude: self.lon,
+            altitude: self.altitude.unwrap_or(0.0),
+            accuracy: self.accuracy.or(self.hdop),
+            source: Source::OsmAnd,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: self.id.clone(),
+        }
+    }
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// A query string as sent by OsmAnd live tracking.
+    const OSMAND_STR: &str = "id=123456&lat=41.74108695983887&lon=-91.84490871429443&timestamp=1736999691&hdop=6.0&altitude=1387.0&speed=0.0";
+
+    /// A query string as sent by Traccar Client.
+    const TRACCAR_STR: &str = "id=phone&timestamp=1736999691000&lat=41.7&lon=-91.8&speed=0.0&bearing=170.8&altitude=&accuracy=12.5&batt=27.0";
+
+    #[test]
+    fn test_from_osmand_query() {
+        let payload: Payload = serde_urlencoded::from_str(OSMAND_STR).unwrap();
+        assert_eq!(payload.id, "123456");
+        assert_eq!(payload.lat, 41.74108695983887);
+        assert_eq!(payload.lon, -91.84490871429443);
+        assert_eq!(payload.timestamp.timestamp(), 1736999691);
+        assert_eq!(payload.hdop, Some(6.0));
+        assert_eq!(payload.accuracy, None);
+        assert_eq!(payload.altitude, Some(1387.0));
+        assert_eq!(payload.speed, Some(0.0));
+        assert_eq!(payload.bearing, None);
+
+        let location = payload.to_location("testuser");
+        assert_eq!(location.username, "testuser");
+        assert_eq!(location.time_utc.to_rfc3339(), "2025-01-16T03:54:51+00:00");
+        assert_eq!(
+            location.time_local.to_rfc3339(),
+            "2025-01-16T03:54:51+00:00"
+        );
+        assert_eq!(location.altitude, 1387.0);
+        assert_eq!(location.accuracy, Some(6.0));
+        assert_eq!(location.source, Source::OsmAnd);
+        assert_eq!(location.device, "123456");
+    }
+
+    #[test]
+    fn test_from_traccar_query() {
+        let payload: Payload = serde_urlencoded::from_str(TRACCAR_STR).unwrap();
+        assert_eq!(payload.id, "phone");
+        assert_eq!(payload.timestamp.timestamp(), 1736999691);
+        assert_eq!(payload.altitude, None);
+        assert_eq!(payload.batt, Some(27.0));
+        let location = payload.to_location("testuser");
+        assert_eq!(location.altitude, 0.0);
+        assert_eq!(location.accuracy, Some(12.5));
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(
+            parse_timestamp("1736999691").unwrap().to_rfc3339(),
+            "2025-01-16T03:54:51+00:00"
+        );
+        assert_eq!(
+            parse_timestamp("1736999691500").unwrap().to_rfc3339(),
+            "2025-01-16T03:54:51.500+00:00"
+        );
+        assert_eq!(
+            parse_timestamp("2025-01-15T20:54:51-07:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2025-01-15T20:54:51-07:00"
+        );
+        assert_eq!(
+            parse_timestamp("2025-01-16 03:54:51").unwrap().to_rfc3339(),
+            "2025-01-16T03:54:51+00:00"
+        );
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+}
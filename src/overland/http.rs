@@ -0,0 +1,326 @@
+use chrono::{DateTime, FixedOffset};
+use color_eyre::eyre::{eyre, Report};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::schema::{Location, LocationGen, Source};
+
+/// # HTTP
+/// Overland is configured with the receiver endpoint `https://<host>:<port>/overland` and the
+/// username/password of a crataegus user in the URL. It POSTs batches of points as a GeoJSON
+/// FeatureCollection under the `locations` key:
+/// ```json
+/// {
+///   "locations": [
+///     {
+///       "type": "Feature",
+///       "geometry": { "type": "Point", "coordinates": [-91.84490871429443, 41.74108695983887] },
+///       "properties": {
+///         "timestamp": "2025-01-15T20:54:51-0700",
+///         "altitude": 1387,
+///         "speed": 0,
+///         "horizontal_accuracy": 6,
+///         "vertical_accuracy": 3,
+///         "motion": ["stationary"],
+///         "battery_state": "unplugged",
+///         "battery_level": 0.27,
+///         "device_id": "phone",
+///         "wifi": "home"
+///       }
+///     }
+///   ]
+/// }
+/// ```
+///
+/// The app keeps a batch queued until it receives `{"result":"ok"}`, so that reply must only be
+/// sent once every point in the batch is committed. Other top level keys (`current`, `trip`) are
+/// ignored.
+#[derive(Deserialize, Debug)]
+pub struct Payload {
+    /// The batch of points, oldest first. Each is only parsed into a `Feature` by
+    /// `Payload::to_locations`, so that one malformed point cannot fail the whole batch.
+    pub locations: Vec<serde_json::Value>,
+}
+
+/// A single GeoJSON point feature in the batch.
+#[derive(Deserialize, Debug)]
+pub struct Feature {
+    /// Position of the point.
+    pub geometry: Geometry,
+    /// Everything else about the point.
+    pub properties: Properties,
+}
+
+/// GeoJSON geometry. Overland only sends points.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum Geometry {
+    /// `[longitude, latitude]` in decimal degrees.
+    Point { coordinates: Vec<f64> },
+}
+
+/// Properties of a point. Overland omits or nulls fields that the device does not report.
+#[derive(Deserialize, Debug)]
+pub struct Properties {
+    /// ISO 8601 time of the fix with the device's local offset.
+    /// Example: `2025-01-15T20:54:51-0700`.
+    #[serde(deserialize_with = "deserialize_date_time_fixed_offset_from_iso8601")]
+    pub timestamp: DateTime<FixedOffset>,
+    /// Altitude in meters.
+    /// Example: `1387`.
+    pub altitude: Option<f64>,
+    /// Horizontal accuracy in meters. Negative values mean the accuracy is unknown.
+    /// Example: `6`.
+    pub horizontal_accuracy: Option<f32>,
+    /// Vertical accuracy in meters. Negative values mean the accuracy is unknown.
+    /// Example: `3`.
+    #[allow(dead_code)]
+    vertical_accuracy: Option<f32>,
+    /// Speed in meters per second. Negative values mean the speed is unknown.
+    /// Example: `0`.
+    #[allow(dead_code)]
+    speed: Option<f32>,
+    /// Motion states reported by the device.
+    /// Example: `["driving", "stationary"]`.
+    #[allow(dead_code)]
+    motion: Option<Vec<String>>,
+    /// One of `unknown`, `charging`, `full`, `unplugged`.
+    #[allow(dead_code)]
+    battery_state: Option<String>,
+    /// Battery level as a fraction.
+    /// Example: `0.27`.
+    #[allow(dead_code)]
+    battery_level: Option<f32>,
+    /// Device ID configured in the app.
+    device_id: Option<String>,
+    /// SSID of the connected wifi network.
+    #[allow(dead_code)]
+    wifi: Option<String>,
+}
+
+/// Reply sent once a batch is committed.
+#[derive(Serialize, Debug)]
+pub struct Reply {
+    result: &'static str,
+}
+
+impl Reply {
+    /// The reply that tells Overland to drop the batch from its queue.
+    pub fn ok() -> Self {
+        Reply { result: "ok" }
+    }
+}
+
+/// Overland emits ISO 8601 times with or without a colon in the offset. Accept both.
+/// # Arguments
+/// * `deserializer` - The serde deserializer.
+/// # Return
+/// A DateTime<FixedOffset> if the string is parseable, or an error if it is not.
+fn deserialize_date_time_fixed_offset_from_iso8601<'de, D>(
+    deserializer: D,
+) -> Result<DateTime<FixedOffset>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    DateTime::parse_from_rfc3339(&s)
+        .or_else(|_| DateTime::parse_from_str(&s, "%Y-%m-%dT%H:%M:%S%.f%z"))
+        .map_err(de::Error::custom)
+}
+
+impl LocationGen for Feature {
+    /// Convert the feature to a Location struct. A missing altitude is recorded as 0. Assumes the
+    /// coordinates have been checked by `Payload::to_locations`.
+    /// # Arguments
+    /// * `username` - The username to associate with the location.
+    /// # Return
+    /// A Location struct with the data from the feature.
+    fn to_location(&self, username: &str) -> Location {
+        let Geometry::Point { coordinates } = &self.geometry;
+        Location {
+            username: username.to_string(),
+            time_utc: self.properties.timestamp.to_utc(),
+            time_local: self.properties.timestamp,
+            latitude: coordinates[1],
+            longitude: coordinates[0],
+            altitude: self.properties.altitude.unwrap_or(0.0),
+            accuracy: self
+                .properties
+                .horizontal_accuracy
+                .filter(|acc| *acc >= 0.0),
+            source: Source::Overland,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: self.properties.device_id.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl Payload {
+    /// Convert every well-formed feature in the batch to a Location struct. Malformed features are
+    /// reported rather than failing the batch, since Overland resends a batch until it is
+    /// accepted, and one bad point would otherwise block its queue for good.
+    /// # Arguments
+    /// * `username` - The username to associate with the locations.
+    /// # Return
+    /// The locations in the order they were received, and an error for each malformed feature.
+    pub fn to_locations(&self, username: &str) -> (Vec<Location>, Vec<Report>) {
+        let mut locations = Vec::with_capacity(self.locations.len());
+        let mut errors = Vec::new();
+        for (i, value) in self.locations.iter().enumerate() {
+            let feature = match Feature::deserialize(value) {
+                Ok(feature) => feature,
+                Err(e) => {
+                    errors.push(eyre!("Feature {} is malformed: {}", i, e));
+                    continue;
+                }
+            };
+            let Geometry::Point { coordinates } = &feature.geometry;
+            if coordinates.len() < 2 {
+                errors.push(eyre!(
+                    "Feature {} has too few coordinates: {:?}",
+                    i,
+                    coordinates
+                ));
+                continue;
+            }
+            locations.push(feature.to_location(username));
+        }
+        (locations, errors)
+    }
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// A two point batch as sent by the app, with and without a colon in the offset.
+    const BODY_STR: &str = r#"{
+        "locations": [
+            {
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [-91.84490871429443, 41.74108695983887] },
+                "properties": {
+                    "timestamp": "2025-01-15T20:54:51-0700",
+                    "altitude": 1387,
+                    "speed": 0,
+                    "horizontal_accuracy": 6,
+                    "vertical_accuracy": 3,
+                    "motion": ["stationary"],
+                    "pauses": false,
+                    "battery_state": "unplugged",
+                    "battery_level": 0.27,
+                    "device_id": "phone",
+                    "wifi": "home"
+                }
+            },
+            {
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [-91.8, 41.7] },
+                "properties": {
+                    "timestamp": "2025-01-15T20:55:51.500-07:00",
+                    "horizontal_accuracy": -1
+                }
+            }
+        ],
+        "current": { "type": "Feature" }
+    }"#;
+
+    #[test]
+    fn test_from_http_body() {
+        let payload: Payload = serde_json::from_str(BODY_STR).unwrap();
+        assert_eq!(payload.locations.len(), 2);
+        let features = payload
+            .locations
+            .iter()
+            .map(|value| Feature::deserialize(value).unwrap())
+            .collect::<Vec<_>>();
+        let properties = &features[0].properties;
+        assert_eq!(
+            properties.timestamp.to_rfc3339(),
+            "2025-01-15T20:54:51-07:00"
+        );
+        assert_eq!(properties.altitude, Some(1387.0));
+        assert_eq!(properties.horizontal_accuracy, Some(6.0));
+        assert_eq!(properties.battery_level, Some(0.27));
+        assert_eq!(properties.device_id.as_deref(), Some("phone"));
+        let properties = &features[1].properties;
+        assert_eq!(
+            properties.timestamp.to_rfc3339(),
+            "2025-01-15T20:55:51.500-07:00"
+        );
+        assert_eq!(properties.altitude, None);
+    }
+
+    #[test]
+    fn test_to_locations() {
+        let payload: Payload = serde_json::from_str(BODY_STR).unwrap();
+        let (locations, errors) = payload.to_locations("testuser");
+        assert!(errors.is_empty());
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[0].username, "testuser");
+        assert_eq!(
+            locations[0].time_utc.to_rfc3339(),
+            "2025-01-16T03:54:51+00:00"
+        );
+        assert_eq!(locations[0].latitude, 41.74108695983887);
+        assert_eq!(locations[0].longitude, -91.84490871429443);
+        assert_eq!(locations[0].altitude, 1387.0);
+        assert_eq!(locations[0].accuracy, Some(6.0));
+        assert_eq!(locations[0].source, Source::Overland);
+        assert_eq!(locations[0].device, "phone");
+        assert_eq!(locations[1].altitude, 0.0);
+        assert_eq!(locations[1].accuracy, None);
+        assert_eq!(locations[1].device, "");
+    }
+
+    #[test]
+    fn test_bad_coordinates() {
+        let payload: Payload = serde_json::from_str(
+            r#"{"locations":[{"type":"Feature","geometry":{"type":"Point","coordinates":[1.0]},"properties":{"timestamp":"2025-01-15T20:54:51Z"}},{"type":"Feature","geometry":{"type":"Point","coordinates":[1.0,2.0]},"properties":{"timestamp":"2025-01-15T20:55:51Z"}}]}"#,
+        )
+        .unwrap();
+        // the malformed feature is reported, and the rest of the batch kept
+        let (locations, errors) = payload.to_locations("testuser");
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].latitude, 2.0);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_malformed_features() {
+        let payload: Payload = serde_json::from_str(
+            r#"{"locations":[
+                {"type":"Feature","geometry":{"type":"Point","coordinates":[1.0,2.0]},"properties":{"timestamp":"yesterday"}},
+                {"type":"Feature","geometry":{"type":"Point","coordinates":[null,2.0]},"properties":{"timestamp":"2025-01-15T20:54:51Z"}},
+                {"type":"Feature","geometry":{"type":"LineString","coordinates":[[1.0,2.0]]},"properties":{"timestamp":"2025-01-15T20:54:52Z"}},
+                {"type":"Feature","geometry":{"type":"Point","coordinates":[3.0,4.0]},"properties":{"timestamp":"2025-01-15T20:55:51Z"}}
+            ]}"#,
+        )
+        .unwrap();
+        // a bad timestamp, a null coordinate or other geometry only drops that feature
+        let (locations, errors) = payload.to_locations("testuser");
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].latitude, 4.0);
+        assert_eq!(errors.len(), 3);
+    }
+
+    #[test]
+    fn test_reply() {
+        assert_eq!(
+            serde_json::to_string(&Reply::ok()).unwrap(),
+            r#"{"result":"ok"}"#
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::schema::{Location, LocationGen, Source};
+
+/// # HTTP
+/// OwnTracks is configured in HTTP mode with the URL `https://<host>:<port>/owntracks` and the
+/// username/password of a crataegus user. The app POSTs a single JSON object per message, with
+/// the message type given by the `_type` key. A `location` message looks like this:
+/// ```json
+/// {
+///   "_type": "location",
+///   "acc": 12,
+///   "alt": 1387,
+///   "batt": 27,
+///   "bs": 1,
+///   "conn": "w",
+///   "lat": 41.74108695983887,
+///   "lon": -91.84490871429443,
+///   "t": "u",
+///   "tid": "jd",
+///   "tst": 1736999691,
+///   "vac": 3,
+///   "vel": 0
+/// }
+/// ```
+///
+/// `transition` messages are sent when entering or leaving a region and also carry a position, so
+/// they are recorded as locations too. `waypoint` messages describe the regions themselves and
+/// carry no fix, so they are accepted but not stored. Any other message type is acknowledged and
+/// ignored, since the app will otherwise keep retrying it.
+///
+/// OwnTracks only sends UTC epoch timestamps, so `time_local` is recorded with a zero offset.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "_type", rename_all = "lowercase")]
+pub enum Payload {
+    Location(LocationPayload),
+    Transition(TransitionPayload),
+    Waypoint(WaypointPayload),
+    #[serde(other)]
+    Other,
+}
+
+/// Body of a `_type: location` message.
+#[derive(Deserialize, Debug)]
+pub struct LocationPayload {
+    /// Latitude in decimal degrees.
+    /// Example: `41.74108695983887`.
+    pub lat: f64,
+    /// Longitude in decimal degrees.
+    /// Example: `-91.84490871429443`.
+    pub lon: f64,
+    /// Unix timestamp of the fix, second-precision.
+    /// Example: `1736999691`.
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub tst: DateTime<Utc>,
+    /// Horizontal accuracy in meters.
+    /// Example: `12`.
+    pub acc: Option<f32>,
+    /// Altitude above sea level in meters.
+    /// Example: `1387`.
+    pub alt: Option<f64>,
+    /// Vertical accuracy in meters.
+    /// Example: `3`.
+    #[allow(dead_code)]
+    vac: Option<f32>,
+    /// Battery percentage.
+    /// Example: `27`.
+    #[allow(dead_code)]
+    batt: Option<u8>,
+    /// Battery status. 0 unknown, 1 unplugged, 2 charging, 3 full.
+    /// Example: `1`.
+    #[allow(dead_code)]
+    bs: Option<u8>,
+    /// Velocity in km/h.
+    /// Example: `0`.
+    #[allow(dead_code)]
+    vel: Option<f32>,
+    /// Course over ground in degrees.
+    /// Example: `170`.
+    #[allow(dead_code)]
+    cog: Option<f32>,
+    /// Tracker ID, a two character label shown on the map. Recorded as the device, since it is
+    /// set per device.
+    /// Example: `jd`.
+    tid: Option<String>,
+    /// Trigger for the report, e.g. `p` ping, `u` manual, `t` timer.
+    /// Example: `u`.
+    #[allow(dead_code)]
+    t: Option<String>,
+    /// Connectivity status, `w` wifi, `o` offline, `m` mobile data.
+    /// Example: `w`.
+    #[allow(dead_code)]
+    conn: Option<String>,
+}
+
+/// Body of a `_type: transition` message.
+#[derive(Deserialize, Debug)]
+pub struct TransitionPayload {
+    /// Latitude in decimal degrees.
+    pub lat: f64,
+    /// Longitude in decimal degrees.
+    pub lon: f64,
+    /// Unix timestamp of the fix that triggered the transition.
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub tst: DateTime<Utc>,
+    /// Horizontal accuracy in meters.
+    pub acc: Option<f32>,
+    /// Unix timestamp of the region definition.
+    #[serde(with = "chrono::serde::ts_seconds")]
+    #[allow(dead_code)]
+    wtst: DateTime<Utc>,
+    /// Either `enter` or `leave`.
+    #[allow(dead_code)]
+    event: String,
+    /// Name of the region.
+    #[allow(dead_code)]
+    desc: Option<String>,
+    /// Tracker ID.
+    tid: Option<String>,
+}
+
+/// Body of a `_type: waypoint` message, which defines a region on the device.
+#[derive(Deserialize, Debug)]
+pub struct WaypointPayload {
+    /// Name of the region.
+    pub desc: String,
+    /// Latitude of the region center in decimal degrees.
+    pub lat: f64,
+    /// Longitude of the region center in decimal degrees.
+    pub lon: f64,
+    /// Radius of the region in meters.
+    pub rad: Option<u32>,
+    /// Unix timestamp of the region definition.
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub tst: DateTime<Utc>,
+}
+
+impl LocationGen for LocationPayload {
+    /// Convert the location message to a Location struct. A missing altitude is recorded as 0.
+    /// # Arguments
+    /// * `username` - The username to associate with the location.
+    /// # Return
+    /// A Location struct with the data from the message.
+    fn to_location(&self, username: &str) -> Location {
+        Location {
+            username: username.to_string(),
+            time_utc: self.tst,
+            time_local: self.tst.fixed_offset(),
+            latitude: self.lat,
+            longitude: self.lon,
+            altitude: self.alt.unwrap_or(0.0),
+            accuracy: self.acc,
+            source: Source::OwnTracks,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: self.tid.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl LocationGen for TransitionPayload {
+    /// Convert the transition message to a Location struct. Transitions carry no altitude, so it
+    /// is recorded as 0.
+    /// # Arguments
+    /// * `username` - The username to associate with the location.
+    /// # Return
+    /// A Location struct with the data from the message.
+    fn to_location(&self, username: &str) -> Location {
+        Location {
+            username: username.to_string(),
+            time_utc: self.tst,
+            time_local: self.tst.fixed_offset(),
+            latitude: self.lat,
+            longitude: self.lon,
+            altitude: 0.0,
+            accuracy: self.acc,
+            source: Source::OwnTracks,
+            speed: None,
+            bearing: None,
+            satellites: None,
+            provider: None,
+            hdop: None,
+            vdop: None,
+            pdop: None,
+            battery: None,
+            charging: None,
+            device: self.tid.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// A location message sent back to the app in the response body. OwnTracks expects a JSON array
+/// of messages in reply to every POST, and shows any `location` messages in it as friends on the
+/// map, distinguished by `topic`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Friend {
+    /// Always `location`.
+    #[serde(rename = "_type")]
+    kind: &'static str,
+    /// Latitude in decimal degrees.
+    lat: f64,
+    /// Longitude in decimal degrees.
+    lon: f64,
+    /// Unix timestamp of the fix.
+    #[serde(with = "chrono::serde::ts_seconds")]
+    tst: DateTime<Utc>,
+    /// Horizontal accuracy in meters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    acc: Option<u32>,
+    /// Altitude in meters.
+    alt: i32,
+    /// Two character label shown on the map, taken from the username.
+    tid: String,
+    /// Pseudo MQTT topic used by the app to tell friends apart.
+    topic: String,
+}
+
+impl Friend {
+    /// Build the friend message for the most recent location of another user.
+    /// # Arguments
+    /// * `location` - The location to report.
+    /// # Return
+    /// The message to send back to the app.
+    pub fn from_location(location: &Location) -> Self {
+        Friend {
+            kind: "location",
+            lat: location.latitude,
+            lon: location.longitude,
+            tst: location.time_utc,
+            acc: location.accuracy.map(|acc| acc.round() as u32),
+            alt: location.altitude.round() as i32,
+            tid: location.username.chars().take(2).collect(),
+            topic: format!("owntracks/{}/crataegus", location.username),
+        }
+    }
+}
+
+////////////////
+// Unit Tests //
+////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// A location message as sent by the iOS app.
+    const LOCATION_STR: &str = r#"{"_type":"location","acc":12,"alt":1387,"batt":27,"bs":1,"conn":"w","lat":41.74108695983887,"lon":-91.84490871429443,"t":"u","tid":"jd","tst":1736999691,"vac":3,"vel":0,"BSSID":"aa:bb:cc:dd:ee:ff"}"#;
+
+    /// A transition message for entering a region.
+    const TRANSITION_STR: &str = r#"{"_type":"transition","wtst":1736000000,"lat":41.7,"lon":-91.8,"tst":1736999691,"acc":30,"tid":"jd","event":"enter","desc":"Home","t":"c"}"#;
+
+    /// A waypoint message defining a region.
+    const WAYPOINT_STR: &str =
+        r#"{"_type":"waypoint","desc":"Home","lat":41.7,"lon":-91.8,"rad":50,"tst":1736000000}"#;
+
+    #[test]
+    fn test_location_payload() {
+        let payload: Payload = serde_json::from_str(LOCATION_STR).unwrap();
+        let payload = match payload {
+            Payload::Location(p) => p,
+            other => panic!("Expected a location message, got {:?}", other),
+        };
+        assert_eq!(payload.lat, 41.74108695983887);
+        assert_eq!(payload.lon, -91.84490871429443);
+        assert_eq!(payload.tst.timestamp(), 1736999691);
+        assert_eq!(payload.acc, Some(12.0));
+        assert_eq!(payload.alt, Some(1387.0));
+        assert_eq!(payload.vac, Some(3.0));
+        assert_eq!(payload.batt, Some(27));
+        assert_eq!(payload.tid.as_deref(), Some("jd"));
+
+        let location = payload.to_location("testuser");
+        assert_eq!(location.username, "testuser");
+        assert_eq!(location.time_utc.to_rfc3339(), "2025-01-16T03:54:51+00:00");
+        assert_eq!(
+            location.time_local.to_rfc3339(),
+            "2025-01-16T03:54:51+00:00"
+        );
+        assert_eq!(location.latitude, 41.74108695983887);
+        assert_eq!(location.longitude, -91.84490871429443);
+        assert_eq!(location.altitude, 1387.0);
+        assert_eq!(location.accuracy, Some(12.0));
+        assert_eq!(location.source, Source::OwnTracks);
+        assert_eq!(location.device, "jd");
+    }
+
+    #[test]
+    fn test_transition_payload() {
+        let payload: Payload = serde_json::from_str(TRANSITION_STR).unwrap();
+        let payload = match payload {
+            Payload::Transition(p) => p,
+            other => panic!("Expected a transition message, got {:?}", other),
+        };
+        assert_eq!(payload.event, "enter");
+        assert_eq!(payload.desc.as_deref(), Some("Home"));
+        let location = payload.to_location("testuser");
+        assert_eq!(location.time_utc.timestamp(), 1736999691);
+        assert_eq!(location.latitude, 41.7);
+        assert_eq!(location.longitude, -91.8);
+        assert_eq!(location.altitude, 0.0);
+        assert_eq!(location.accuracy, Some(30.0));
+    }
+
+    #[test]
+    fn test_other_payloads() {
+        let payload: Payload = serde_json::from_str(WAYPOINT_STR).unwrap();
+        match payload {
+            Payload::Waypoint(p) => {
+                assert_eq!(p.desc, "Home");
+                assert_eq!(p.rad, Some(50));
+            }
+            other => panic!("Expected a waypoint message, got {:?}", other),
+        }
+        let payload: Payload = serde_json::from_str(r#"{"_type":"lwt","tst":1736999691}"#).unwrap();
+        assert!(matches!(payload, Payload::Other));
+    }
+
+    #[test]
+    fn test_friend() {
+        let payload: Payload = serde_json::from_str(LOCATION_STR).unwrap();
+        let location = match payload {
+            Payload::Location(p) => p.to_location("alice"),
+            other => panic!("Expected a location message, got {:?}", other),
+        };
+        let friend = Friend::from_location(&location);
+        assert_eq!(
+            serde_json::to_string(&friend).unwrap(),
+            r#"{"_type":"location","lat":41.74108695983887,"lon":-91.84490871429443,"tst":1736999691,"acc":12,"alt":1387,"tid":"al","topic":"owntracks/alice/crataegus"}"#
+        );
+    }
+}
@@ -1,7 +1,14 @@
 use color_eyre::eyre::{ensure, Result};
+use serde::Deserialize;
 
+pub use api_token::Model as ApiToken;
+pub use api_token::Scope;
+pub use conflict::Model as Conflict;
+pub use device::Model as Device;
 pub use location::Model as Location;
 pub use location::Source;
+pub use quarantine::Model as Quarantined;
+pub use segment::Model as Segment;
 pub use user::Model as User;
 
 /// Trait applied to all models to allow one-line validation.
@@ -31,6 +38,8 @@ pub mod user {
     pub struct Model {
         #[sea_orm(primary_key, auto_increment = false)]
         pub username: String,
+        /// Argon2id hash as a PHC string, or plaintext for users whose password predates hashing
+        /// and who have not logged in since
         pub password: String,
     }
 
@@ -40,17 +49,28 @@ pub mod user {
     impl ActiveModelBehavior for ActiveModel {}
 }
 
+/// Check that a username may be stored, whether for a new user or as a new name for one. It may not
+/// be empty, nor contain a colon, which HTTP basic authentication uses to end the username.
+/// # Arguments
+/// * `username` - The username to check
+/// # Returns
+/// `Ok(())` if the username is valid, an error otherwise
+pub fn check_username(username: &str) -> Result<()> {
+    ensure!(!username.is_empty(), "Username is empty");
+    ensure!(
+        !username.contains(':'),
+        format!("Username contains a colon: {}", username)
+    );
+    ensure!(
+        username.len() <= 32,
+        format!("Username too long: {}", username)
+    );
+    Ok(())
+}
+
 impl SanityCheck for User {
     fn sanity_check(&self) -> Result<()> {
-        ensure!(
-            self.username.len() <= 32,
-            format!("Username too long: {}", self.username)
-        );
-        ensure!(
-            self.password.len() <= 64,
-            format!("Password too long: {}", self.password)
-        );
-        Ok(())
+        check_username(&self.username)
     }
 }
 
@@ -65,6 +85,45 @@ pub mod location {
         /// crate::gpslogger::Payload
         #[sea_orm(string_value = "GPSLogger")]
         GpsLogger,
+        /// crate::owntracks::http::Payload
+        #[sea_orm(string_value = "OwnTracks")]
+        OwnTracks,
+        /// crate::overland::http::Payload
+        #[sea_orm(string_value = "Overland")]
+        Overland,
+        /// crate::osmand::http::Payload
+        #[sea_orm(string_value = "OsmAnd")]
+        OsmAnd,
+        /// crate::google::records::Record
+        #[sea_orm(string_value = "GoogleRecords")]
+        GoogleRecords,
+        /// crate::google::timeline::Timeline
+        #[sea_orm(string_value = "GoogleTimeline")]
+        GoogleTimeline,
+        /// crate::import::gpx::read_gpx
+        #[sea_orm(string_value = "GPX")]
+        Gpx,
+        /// crate::import::kml::read_kml
+        #[sea_orm(string_value = "KML")]
+        Kml,
+        /// crate::import::geojson::read_geojson
+        #[sea_orm(string_value = "GeoJSON")]
+        GeoJson,
+        /// crate::import::exif::read_exif
+        #[sea_orm(string_value = "EXIF")]
+        Exif,
+        /// crate::import::video::read_video
+        #[sea_orm(string_value = "Video")]
+        Video,
+        /// crate::import::fit::read_fit
+        #[sea_orm(string_value = "FIT")]
+        Fit,
+        /// crate::import::tcx::read_tcx
+        #[sea_orm(string_value = "TCX")]
+        Tcx,
+        /// crate::import::nmea::read_nmea
+        #[sea_orm(string_value = "NMEA")]
+        Nmea,
     }
 
     #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
@@ -80,6 +139,154 @@ pub mod location {
         pub altitude: f64,
         pub accuracy: Option<f32>,
         pub source: Source,
+        /// Speed over ground in meters per second
+        pub speed: Option<f32>,
+        /// Direction of travel in degrees clockwise from true north
+        pub bearing: Option<f32>,
+        /// Number of satellites used in the fix
+        pub satellites: Option<i32>,
+        /// What produced the fix on the device, such as `gps` or `network`
+        pub provider: Option<String>,
+        /// Horizontal dilution of precision
+        pub hdop: Option<f32>,
+        /// Vertical dilution of precision
+        pub vdop: Option<f32>,
+        /// Position dilution of precision
+        pub pdop: Option<f32>,
+        /// Battery charge of the device in percent
+        pub battery: Option<f32>,
+        /// Whether the device was charging
+        pub charging: Option<bool>,
+        /// Id of the device that recorded the location, or empty if it is not known. Part of the
+        /// key, so that two devices of a user may record the same second.
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub device: String,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {
+        #[sea_orm(
+            belongs_to = "super::user::Entity",
+            from = "Column::Username",
+            to = "super::user::Column::Username",
+            on_update = "Cascade",
+            on_delete = "Cascade"
+        )]
+        User,
+    }
+
+    impl Related<super::user::Entity> for Entity {
+        fn to() -> RelationDef {
+            Relation::User.def()
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod device {
+    use sea_orm::entity::prelude::*;
+
+    /// A device that a user records locations with, registered when its first location arrives.
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "devices")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub username: String,
+        /// Id the device identifies itself by, such as GPSLogger's Android ID or an Overland
+        /// `device_id`
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: String,
+        /// Serial number, as reported by GPSLogger
+        pub serial: Option<String>,
+        /// Name of the logging profile in use, as reported by GPSLogger
+        pub profile: Option<String>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {
+        #[sea_orm(
+            belongs_to = "super::user::Entity",
+            from = "Column::Username",
+            to = "super::user::Column::Username",
+            on_update = "Cascade",
+            on_delete = "Cascade"
+        )]
+        User,
+    }
+
+    impl Related<super::user::Entity> for Entity {
+        fn to() -> RelationDef {
+            Relation::User.def()
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+impl SanityCheck for Device {
+    fn sanity_check(&self) -> Result<()> {
+        ensure!(
+            !self.id.is_empty() && self.id.len() <= 64,
+            format!("Device id empty or too long: {}", self.id)
+        );
+        Ok(())
+    }
+}
+
+pub mod api_token {
+    use chrono::{DateTime, Utc};
+    use clap::ValueEnum;
+    use sea_orm::entity::prelude::*;
+
+    use std::fmt;
+
+    /// What an API token may be used for.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum, ValueEnum)]
+    #[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+    pub enum Scope {
+        /// Recording locations at the endpoints of the logging apps, and nothing else
+        #[sea_orm(string_value = "ingest")]
+        Ingest,
+        /// Reading recorded locations, such as the friends in replies to OwnTracks, as well as
+        /// recording them, since OwnTracks only gets its friends in reply to a location
+        #[sea_orm(string_value = "read")]
+        Read,
+    }
+
+    impl Scope {
+        /// Whether a token of this scope may be used for `scope`.
+        pub fn allows(self, scope: Scope) -> bool {
+            self == scope || self == Scope::Read
+        }
+    }
+
+    impl fmt::Display for Scope {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Scope::Ingest => f.pad("ingest"),
+                Scope::Read => f.pad("read"),
+            }
+        }
+    }
+
+    /// A named token that authenticates as a user with a limited scope, so that apps need not
+    /// store the user's password. Only a hash of the token is stored.
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "api_tokens")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i64,
+        pub username: String,
+        /// Name given when the token was created, unique per user, such as the device it is for
+        pub name: String,
+        pub scope: Scope,
+        /// SHA-256 of the token, in hex
+        #[sea_orm(unique)]
+        pub hash: String,
+        pub created_utc: DateTime<Utc>,
+        /// The token is refused from this time on, if set
+        pub expires_utc: Option<DateTime<Utc>>,
     }
 
     #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -101,12 +308,78 @@ pub mod location {
     }
 
     impl ActiveModelBehavior for ActiveModel {}
+
+    impl Model {
+        /// Whether the token has expired.
+        pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+            self.expires_utc.is_some_and(|expires| expires <= now)
+        }
+    }
+}
+
+impl SanityCheck for ApiToken {
+    fn sanity_check(&self) -> Result<()> {
+        ensure!(
+            !self.name.is_empty() && self.name.len() <= 64,
+            format!("Token name empty or too long: {}", self.name)
+        );
+        Ok(())
+    }
+}
+
+/// Limits on the values a location may have to be recorded. Locations outside them are put in
+/// quarantine rather than recorded, the `[db.bounds]` section of the config file.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Bounds {
+    /// Lowest altitude in meters
+    pub min_altitude: f64,
+    /// Highest altitude in meters
+    pub max_altitude: f64,
+    /// Largest accuracy radius in meters
+    pub max_accuracy: f32,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Bounds {
+            min_altitude: -1000.0,
+            max_altitude: 10000.0,
+            max_accuracy: 100.0,
+        }
+    }
+}
+
+impl Bounds {
+    /// No limits on altitude or accuracy, for locations that have been reviewed by hand.
+    pub fn unbounded() -> Self {
+        Bounds {
+            min_altitude: f64::NEG_INFINITY,
+            max_altitude: f64::INFINITY,
+            max_accuracy: f32::INFINITY,
+        }
+    }
 }
 
 impl SanityCheck for Location {
     fn sanity_check(&self) -> Result<()> {
+        self.sanity_check_bounds(&Bounds::default())
+    }
+}
+
+impl Location {
+    /// Perform the sanity check with the given limits on altitude and accuracy.
+    /// # Arguments
+    /// * `bounds` - The limits to check against.
+    /// # Returns
+    /// Result indicating success, or the first rule that failed.
+    pub fn sanity_check_bounds(&self, bounds: &Bounds) -> Result<()> {
         use chrono::Utc;
         // float nan/inf checks
+        ensure!(
+            self.device.len() <= 64,
+            format!("Device id too long: {}", self.device)
+        );
         ensure!(
             self.latitude.is_finite(),
             format!("Latitude is not finite: {}", self.latitude)
@@ -123,6 +396,19 @@ impl SanityCheck for Location {
             self.accuracy.is_none() || self.accuracy.unwrap().is_finite(),
             format!("Accuracy is not finite: {:?}", self.accuracy)
         );
+        for (name, value) in [
+            ("Speed", self.speed),
+            ("Bearing", self.bearing),
+            ("HDOP", self.hdop),
+            ("VDOP", self.vdop),
+            ("PDOP", self.pdop),
+            ("Battery", self.battery),
+        ] {
+            ensure!(
+                value.is_none_or(f32::is_finite),
+                format!("{} is not finite: {:?}", name, value)
+            );
+        }
         // Position value checks
         ensure!(
             -90.0 <= self.latitude && self.latitude <= 90.0,
@@ -133,12 +419,12 @@ impl SanityCheck for Location {
             format!("Longitude out of bounds: {}", self.longitude)
         );
         ensure!(
-            -1000.0 <= self.altitude && self.altitude <= 10000.0,
+            bounds.min_altitude <= self.altitude && self.altitude <= bounds.max_altitude,
             format!("Altitude out of bounds: {}", self.altitude)
         );
         ensure!(
             self.accuracy.is_none()
-                || (0.0 <= self.accuracy.unwrap() && self.accuracy.unwrap() <= 100.0),
+                || (0.0 <= self.accuracy.unwrap() && self.accuracy.unwrap() <= bounds.max_accuracy),
             format!("Accuracy out of bounds: {:?}", self.accuracy)
         );
         // utc and local time should be the same
@@ -152,3 +438,368 @@ impl SanityCheck for Location {
         Ok(())
     }
 }
+
+pub mod quarantine {
+    use chrono::{DateTime, FixedOffset, Utc};
+    use sea_orm::entity::prelude::*;
+    use sea_orm::ActiveValue::NotSet;
+
+    use super::location::Source;
+    use super::Location;
+
+    /// A location that failed the sanity check, kept with the rule it broke until it is promoted
+    /// to the locations table or purged. Values that are not finite are stored as empty.
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "quarantine")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i64,
+        pub username: String,
+        pub time_utc: DateTime<Utc>,
+        pub time_local: DateTime<FixedOffset>,
+        pub latitude: Option<f64>,
+        pub longitude: Option<f64>,
+        pub altitude: Option<f64>,
+        pub accuracy: Option<f32>,
+        pub source: Source,
+        pub speed: Option<f32>,
+        pub bearing: Option<f32>,
+        pub satellites: Option<i32>,
+        pub provider: Option<String>,
+        pub hdop: Option<f32>,
+        pub vdop: Option<f32>,
+        pub pdop: Option<f32>,
+        pub battery: Option<f32>,
+        pub charging: Option<bool>,
+        pub device: String,
+        /// The sanity check rule that failed
+        pub rule: String,
+        /// When the location was put in quarantine
+        pub quarantined_utc: DateTime<Utc>,
+    }
+
+    impl Model {
+        /// The quarantined location, or None if any of its values were not finite.
+        pub fn to_location(&self) -> Option<Location> {
+            Some(Location {
+                username: self.username.clone(),
+                time_utc: self.time_utc,
+                time_local: self.time_local,
+                latitude: self.latitude?,
+                longitude: self.longitude?,
+                altitude: self.altitude?,
+                accuracy: self.accuracy,
+                source: self.source.clone(),
+                speed: self.speed,
+                bearing: self.bearing,
+                satellites: self.satellites,
+                provider: self.provider.clone(),
+                hdop: self.hdop,
+                vdop: self.vdop,
+                pdop: self.pdop,
+                battery: self.battery,
+                charging: self.charging,
+                device: self.device.clone(),
+            })
+        }
+
+        /// The row for a location that failed the sanity check, before it is inserted and given
+        /// an id.
+        /// # Arguments
+        /// * `loc` - The location
+        /// * `rule` - The rule that failed
+        pub fn of(loc: Location, rule: String) -> Model {
+            Model {
+                id: 0,
+                username: loc.username,
+                time_utc: loc.time_utc,
+                time_local: loc.time_local,
+                latitude: finite(loc.latitude),
+                longitude: finite(loc.longitude),
+                altitude: finite(loc.altitude),
+                accuracy: loc.accuracy.filter(|v| v.is_finite()),
+                source: loc.source,
+                speed: loc.speed,
+                bearing: loc.bearing,
+                satellites: loc.satellites,
+                provider: loc.provider,
+                hdop: loc.hdop,
+                vdop: loc.vdop,
+                pdop: loc.pdop,
+                battery: loc.battery,
+                charging: loc.charging,
+                device: loc.device,
+                rule,
+                quarantined_utc: Utc::now(),
+            }
+        }
+
+        /// The row to insert, leaving the id to the database.
+        pub fn into_insert(self) -> ActiveModel {
+            ActiveModel {
+                id: NotSet,
+                ..ActiveModel::from(self).reset_all()
+            }
+        }
+
+        /// Whether this holds the given location, ignoring the id, rule and time of quarantine.
+        pub fn is(&self, loc: &Location) -> bool {
+            *self
+                == Model {
+                    id: self.id,
+                    rule: self.rule.clone(),
+                    quarantined_utc: self.quarantined_utc,
+                    ..Model::of(loc.clone(), String::new())
+                }
+        }
+    }
+
+    fn finite(value: f64) -> Option<f64> {
+        Some(value).filter(|v| v.is_finite())
+    }
+
+    /// Create a quarantine row for a location that failed the sanity check.
+    /// # Arguments
+    /// * `loc` - The location
+    /// * `rule` - The rule that failed
+    pub fn new(loc: Location, rule: String) -> ActiveModel {
+        Model::of(loc, rule).into_insert()
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {
+        #[sea_orm(
+            belongs_to = "super::user::Entity",
+            from = "Column::Username",
+            to = "super::user::Column::Username",
+            on_update = "Cascade",
+            on_delete = "Cascade"
+        )]
+        User,
+    }
+
+    impl Related<super::user::Entity> for Entity {
+        fn to() -> RelationDef {
+            Relation::User.def()
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod conflict {
+    use chrono::{DateTime, FixedOffset, Utc};
+    use sea_orm::entity::prelude::*;
+    use sea_orm::ActiveValue::{NotSet, Set};
+
+    use super::location::Source;
+    use super::Location;
+
+    /// A location that shares its user, time and device with a stored location but differs from
+    /// it, kept aside by `ConflictPolicy::StoreBoth` instead of being discarded.
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "conflicts")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i64,
+        pub username: String,
+        pub time_utc: DateTime<Utc>,
+        pub time_local: DateTime<FixedOffset>,
+        pub latitude: f64,
+        pub longitude: f64,
+        pub altitude: f64,
+        pub accuracy: Option<f32>,
+        pub source: Source,
+        pub speed: Option<f32>,
+        pub bearing: Option<f32>,
+        pub satellites: Option<i32>,
+        pub provider: Option<String>,
+        pub hdop: Option<f32>,
+        pub vdop: Option<f32>,
+        pub pdop: Option<f32>,
+        pub battery: Option<f32>,
+        pub charging: Option<bool>,
+        pub device: String,
+    }
+
+    impl Model {
+        /// Whether this is the given location, ignoring the id.
+        pub fn is(&self, loc: &Location) -> bool {
+            self.to_location() == *loc
+        }
+
+        /// The location that was kept aside.
+        pub fn to_location(&self) -> Location {
+            Location {
+                username: self.username.clone(),
+                time_utc: self.time_utc,
+                time_local: self.time_local,
+                latitude: self.latitude,
+                longitude: self.longitude,
+                altitude: self.altitude,
+                accuracy: self.accuracy,
+                source: self.source.clone(),
+                speed: self.speed,
+                bearing: self.bearing,
+                satellites: self.satellites,
+                provider: self.provider.clone(),
+                hdop: self.hdop,
+                vdop: self.vdop,
+                pdop: self.pdop,
+                battery: self.battery,
+                charging: self.charging,
+                device: self.device.clone(),
+            }
+        }
+    }
+
+    impl From<Location> for ActiveModel {
+        fn from(loc: Location) -> Self {
+            ActiveModel {
+                id: NotSet,
+                username: Set(loc.username),
+                time_utc: Set(loc.time_utc),
+                time_local: Set(loc.time_local),
+                latitude: Set(loc.latitude),
+                longitude: Set(loc.longitude),
+                altitude: Set(loc.altitude),
+                accuracy: Set(loc.accuracy),
+                source: Set(loc.source),
+                speed: Set(loc.speed),
+                bearing: Set(loc.bearing),
+                satellites: Set(loc.satellites),
+                provider: Set(loc.provider),
+                hdop: Set(loc.hdop),
+                vdop: Set(loc.vdop),
+                pdop: Set(loc.pdop),
+                battery: Set(loc.battery),
+                charging: Set(loc.charging),
+                device: Set(loc.device),
+            }
+        }
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {
+        #[sea_orm(
+            belongs_to = "super::user::Entity",
+            from = "Column::Username",
+            to = "super::user::Column::Username",
+            on_update = "Cascade",
+            on_delete = "Cascade"
+        )]
+        User,
+    }
+
+    impl Related<super::user::Entity> for Entity {
+        fn to() -> RelationDef {
+            Relation::User.def()
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod segment {
+    use chrono::{DateTime, FixedOffset, Utc};
+    use sea_orm::entity::prelude::*;
+
+    /// What the user was doing during the segment.
+    #[derive(Debug, Clone, PartialEq, EnumIter, DeriveActiveEnum)]
+    #[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+    pub enum Kind {
+        /// Stayed at a single place.
+        #[sea_orm(string_value = "Visit")]
+        Visit,
+        /// Moved from one place to another.
+        #[sea_orm(string_value = "Activity")]
+        Activity,
+    }
+
+    /// A span of time summarized as a visit or an activity, as inferred by Google Timeline.
+    /// Visits use `latitude`/`longitude` for the place and leave the `end_` position empty.
+    #[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+    #[sea_orm(table_name = "segments")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub username: String,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub start_utc: DateTime<Utc>,
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub end_utc: DateTime<Utc>,
+        pub start_local: DateTime<FixedOffset>,
+        pub end_local: DateTime<FixedOffset>,
+        pub kind: Kind,
+        /// Place of a visit, or start of an activity.
+        pub latitude: f64,
+        pub longitude: f64,
+        /// End of an activity.
+        pub end_latitude: Option<f64>,
+        pub end_longitude: Option<f64>,
+        /// Google place ID of a visit.
+        pub place_id: Option<String>,
+        /// Semantic type of a visit (e.g. `HOME`) or activity type (e.g. `WALKING`).
+        pub semantic_type: Option<String>,
+        /// Confidence in `semantic_type`, from 0 to 1.
+        pub probability: Option<f32>,
+        /// Distance covered by an activity in meters.
+        pub distance: Option<f32>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {
+        #[sea_orm(
+            belongs_to = "super::user::Entity",
+            from = "Column::Username",
+            to = "super::user::Column::Username",
+            on_update = "Cascade",
+            on_delete = "Cascade"
+        )]
+        User,
+    }
+
+    impl Related<super::user::Entity> for Entity {
+        fn to() -> RelationDef {
+            Relation::User.def()
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+impl SanityCheck for Segment {
+    fn sanity_check(&self) -> Result<()> {
+        use chrono::Utc;
+        let positions = [
+            Some((self.latitude, self.longitude)),
+            self.end_latitude.zip(self.end_longitude),
+        ];
+        for (latitude, longitude) in positions.into_iter().flatten() {
+            ensure!(
+                latitude.is_finite() && (-90.0..=90.0).contains(&latitude),
+                format!("Latitude out of bounds: {}", latitude)
+            );
+            ensure!(
+                longitude.is_finite() && (-180.0..=180.0).contains(&longitude),
+                format!("Longitude out of bounds: {}", longitude)
+            );
+        }
+        ensure!(
+            self.start_utc <= self.end_utc,
+            format!(
+                "Segment ends before it starts: {:?} > {:?}",
+                self.start_utc, self.end_utc
+            )
+        );
+        // utc and local time should be the same
+        ensure!(
+            self.start_utc == self.start_local.with_timezone(&Utc)
+                && self.end_utc == self.end_local.with_timezone(&Utc),
+            format!(
+                "Time UTC and Time Local are not the same: {:?} != {:?} or {:?} != {:?}",
+                self.start_utc, self.start_local, self.end_utc, self.end_local
+            )
+        );
+        Ok(())
+    }
+}
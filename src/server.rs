@@ -1,13 +1,15 @@
 use axum::{
     body::Body,
+    extract::ConnectInfo,
     extract::Extension,
+    extract::FromRequestParts,
     extract::Query,
     extract::State,
-    http::Request,
+    http::{header, request::Parts, Request, StatusCode},
     middleware::{self, Next},
-    response::Response,
-    routing::post,
-    Router,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
 };
 use axum_auth::AuthBasic;
 use axum_server::tls_rustls::RustlsConfig;
@@ -15,14 +17,18 @@ use color_eyre::eyre::{ensure, eyre, Result, WrapErr};
 use log::{debug, info, warn};
 use serde::Deserialize;
 
-use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
-use crate::db::Db;
+use crate::db::{ConflictPolicy, Db};
 use crate::gpslogger;
-use crate::schema::LocationGen;
+use crate::lockout::{Config as LockoutConfig, Lockout};
+use crate::osmand;
+use crate::overland;
+use crate::owntracks;
+use crate::schema::{LocationGen, Scope};
 
 /// Configuration for the server
-#[derive(Debug, Deserialize)]
+#[derive(Deserialize)]
 pub struct Config {
     /// Port to listen on  
     port: u16,
@@ -30,6 +36,38 @@ pub struct Config {
     cert: PathBuf,
     /// Path to the TLS private key
     key: PathBuf,
+    /// Map of OsmAnd protocol device IDs to the usernames they log for. These clients cannot send
+    /// basic auth, so the device ID is the only credential.
+    #[serde(default)]
+    osmand_devices: HashMap<String, String>,
+    /// What to do with received locations that conflict with stored ones
+    #[serde(default)]
+    conflict_policy: ConflictPolicy,
+    /// Users who share their latest position with each other as OwnTracks friends. Users not
+    /// listed neither see nor are seen by anyone.
+    #[serde(default)]
+    owntracks_friends: Vec<String>,
+    /// Limits on failed logins
+    #[serde(default)]
+    lockout: LockoutConfig,
+}
+
+/// The config is logged at startup, so the OsmAnd device IDs, which are credentials, are left out,
+/// leaving only the users they log for.
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut osmand_users = self.osmand_devices.values().collect::<Vec<_>>();
+        osmand_users.sort();
+        f.debug_struct("Config")
+            .field("port", &self.port)
+            .field("cert", &self.cert)
+            .field("key", &self.key)
+            .field("osmand_devices", &osmand_users)
+            .field("conflict_policy", &self.conflict_policy)
+            .field("owntracks_friends", &self.owntracks_friends)
+            .field("lockout", &self.lockout)
+            .finish()
+    }
 }
 
 /// The server struct
@@ -38,12 +76,24 @@ pub struct Server {
     config: Config,
     /// Database connection
     db: Arc<Db>,
+    /// Failed logins per IP address and username
+    lockout: Lockout,
 }
 
 /// Struct to hold the authenticated user as an extension for protected routes
 #[derive(Clone)]
 struct AuthenticatedUser {
     username: String,
+    /// Scope of the API token the user authenticated with, or None for their password, which
+    /// allows everything
+    scope: Option<Scope>,
+}
+
+impl AuthenticatedUser {
+    /// Whether the credentials the user authenticated with allow the scope.
+    fn allows(&self, scope: Scope) -> bool {
+        self.scope.is_none_or(|allowed| allowed.allows(scope))
+    }
 }
 
 impl Server {
@@ -51,7 +101,12 @@ impl Server {
         let _ = rustls::crypto::ring::default_provider()
             .install_default() // returns a Result<(), Arc(CryptoProvider)>
             .map_err(|_| eyre!("Failed to install default ring provider"));
-        Ok(Server { config, db })
+        let lockout = Lockout::new(config.lockout.clone())?;
+        Ok(Server {
+            config,
+            db,
+            lockout,
+        })
     }
 
     pub async fn serve(self) -> Result<()> {
@@ -62,9 +117,15 @@ impl Server {
         let server = Arc::new(self);
         let protected_routes = Router::new()
             .route("/gpslogger", post(Self::handle_gpslogger))
+            .route("/owntracks", post(Self::handle_owntracks))
+            .route("/overland", post(Self::handle_overland))
             .layer(middleware::from_fn_with_state(server.clone(), Self::auth));
         let router = Router::new()
             .merge(protected_routes)
+            .route(
+                "/osmand",
+                get(Self::handle_osmand).post(Self::handle_osmand),
+            )
             .fallback(Self::handle_fallback)
             .with_state(server.clone());
         let rustls_config =
@@ -76,59 +137,316 @@ impl Server {
         info!("Listening on {}", addr);
 
         axum_server::bind_rustls(addr, rustls_config)
-            .serve(router.into_make_service())
+            // the client address is needed to count failed logins per IP address
+            .serve(router.into_make_service_with_connect_info::<SocketAddr>())
             .await
             .wrap_err("Failed to start server")?;
 
         Ok(()) // reached after sever is stopped
     }
 
-    /// Middleware layer to check for HTTP basic auth, used for user auth
+    /// Middleware layer for the ingestion routes. Clients authenticate with an API token of the
+    /// ingest or read scope, as a Bearer token or as a `token` query parameter for clients that cannot set
+    /// headers, or with HTTP basic auth and the user's password. IP addresses and usernames that
+    /// fail too often are refused with 429 for a while.
     async fn auth(
         State(server): State<Arc<Server>>,
-        AuthBasic((username, password)): AuthBasic,
-        mut request: Request<Body>,
+        ConnectInfo(addr): ConnectInfo<SocketAddr>,
+        request: Request<Body>,
         next: Next,
     ) -> Response<Body> {
-        debug!("Authenticating user: {}", username);
-        let password = match password {
-            Some(p) => p,
+        let ip = addr.ip();
+        if let Some(wait) = server.lockout.check(ip, None) {
+            return Self::too_many_requests(wait);
+        }
+        let (mut parts, body) = request.into_parts();
+        let user = match Self::find_token(&parts) {
+            Some(token) => match server.db.token_check(&token).await {
+                Ok(Some(found)) => {
+                    debug!("Authenticated {} with token {}", found.username, found.name);
+                    AuthenticatedUser {
+                        username: found.username,
+                        scope: Some(found.scope),
+                    }
+                }
+                Ok(None) => {
+                    server.lockout.fail(ip, None, "unknown or expired token");
+                    return Response::builder().status(401).body(Body::empty()).unwrap();
+                }
+                Err(e) => {
+                    warn!("Failed to check API token: {:?}", e);
+                    return Response::builder().status(500).body(Body::empty()).unwrap();
+                }
+            },
             None => {
-                warn!("No password provided for user: {}", username);
-                return Response::builder()
-                    .status(401)
-                    .body(Body::from("No password provided"))
-                    .unwrap();
+                let AuthBasic((username, password)) =
+                    match AuthBasic::from_request_parts(&mut parts, &()).await {
+                        Ok(auth) => auth,
+                        Err(rejection) => return rejection.into_response(),
+                    };
+                debug!("Authenticating user: {}", username);
+                if let Some(wait) = server.lockout.check(ip, Some(&username)) {
+                    return Self::too_many_requests(wait);
+                }
+                let password = match password {
+                    Some(p) => p,
+                    None => {
+                        server
+                            .lockout
+                            .fail(ip, Some(&username), "no password provided");
+                        return Response::builder()
+                            .status(401)
+                            .body(Body::from("No password provided"))
+                            .unwrap();
+                    }
+                };
+                match server.db.user_check(&username, &password).await {
+                    Ok(true) => server.lockout.succeed(ip, &username),
+                    Ok(false) => {
+                        server
+                            .lockout
+                            .fail(ip, Some(&username), "wrong password or unknown user");
+                        return Response::builder().status(401).body(Body::empty()).unwrap();
+                    }
+                    Err(e) => {
+                        warn!("Failed to check password of {}: {:?}", username, e);
+                        return Response::builder().status(500).body(Body::empty()).unwrap();
+                    }
+                }
+                AuthenticatedUser {
+                    username,
+                    scope: None,
+                }
             }
         };
-        let good = server.db.user_check(&username, &password).await.unwrap();
-        if !good {
-            warn!("Failed to authenticate user: {}", username);
-            return Response::builder().status(401).body(Body::empty()).unwrap();
+        if !user.allows(Scope::Ingest) {
+            warn!("Token of {} lacks the ingest scope", user.username);
+            return Response::builder().status(403).body(Body::empty()).unwrap();
         }
         // Add the authenticated user to the request extensions
-        request
-            .extensions_mut()
-            .insert(AuthenticatedUser { username });
+        let mut request = Request::from_parts(parts, body);
+        request.extensions_mut().insert(user);
         next.run(request).await
     }
 
+    /// Response refusing a client that is locked out, telling it when to retry.
+    fn too_many_requests(wait: Duration) -> Response<Body> {
+        // round up, so that a retry at the given time is not refused again
+        let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(header::RETRY_AFTER, secs)
+            .body(Body::from("Too many failed logins, try again later"))
+            .unwrap()
+    }
+
+    /// Find the API token in a request, from the `Authorization: Bearer` header or else the
+    /// `token` query parameter.
+    fn find_token(parts: &Parts) -> Option<String> {
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        bearer.or_else(|| {
+            Query::<HashMap<String, String>>::try_from_uri(&parts.uri)
+                .ok()
+                .and_then(|Query(mut params)| params.remove("token"))
+        })
+    }
+
     async fn handle_gpslogger(
         State(server): State<Arc<Server>>,
-        Extension(AuthenticatedUser { username }): Extension<AuthenticatedUser>,
+        Extension(AuthenticatedUser { username, .. }): Extension<AuthenticatedUser>,
         Query(payload): Query<gpslogger::http::Payload>, // auto extracts query params from url
     ) -> Response<Body> {
         debug!("gpslogger url payload: {:?}", payload);
-        server
+        match server
+            .db
+            .location_insert_with_policy(
+                LocationGen::to_location(&payload, &username),
+                server.config.conflict_policy,
+            )
+            .await
+        {
+            Ok(_) => {
+                // the location is recorded either way, so a failure here is only logged
+                if let Some(device) = payload.to_device(&username) {
+                    if let Err(e) = server.db.device_insert(device).await {
+                        warn!(
+                            "Failed to register GPSLogger device for {}: {:?}",
+                            username, e
+                        );
+                    }
+                }
+                Response::new(Body::from("Request received"))
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to record GPSLogger location for {}: {:?}",
+                    username, e
+                );
+                Response::builder()
+                    .status(500)
+                    .body(Body::from("Failed to record location"))
+                    .unwrap()
+            }
+        }
+    }
+
+    /// Handler for OwnTracks in HTTP mode. Location and transition messages are recorded, and the
+    /// reply contains the most recent location of the other users who share theirs, so they show
+    /// up as friends.
+    /// Ingest tokens may not read locations, so they get no friends.
+    async fn handle_owntracks(
+        State(server): State<Arc<Server>>,
+        Extension(user): Extension<AuthenticatedUser>,
+        Json(payload): Json<owntracks::http::Payload>,
+    ) -> Response<Body> {
+        let username = &user.username;
+        debug!("owntracks json payload: {:?}", payload);
+        let location = match &payload {
+            owntracks::http::Payload::Location(p) => Some(p.to_location(username)),
+            owntracks::http::Payload::Transition(p) => Some(p.to_location(username)),
+            owntracks::http::Payload::Waypoint(_) | owntracks::http::Payload::Other => None,
+        };
+        if let Some(location) = location {
+            if let Err(e) = server
+                .db
+                .location_insert_with_policy(location, server.config.conflict_policy)
+                .await
+            {
+                warn!(
+                    "Failed to record OwnTracks location for {}: {:?}",
+                    username, e
+                );
+                return Response::builder()
+                    .status(500)
+                    .body(Body::from("Failed to record location"))
+                    .unwrap();
+            }
+        }
+        if !user.allows(Scope::Read) {
+            return Json(Vec::<owntracks::http::Friend>::new()).into_response();
+        }
+        match server.owntracks_friends(username).await {
+            Ok(friends) => Json(friends).into_response(),
+            Err(e) => {
+                warn!(
+                    "Failed to look up OwnTracks friends for {}: {:?}",
+                    username, e
+                );
+                Json(Vec::<owntracks::http::Friend>::new()).into_response()
+            }
+        }
+    }
+
+    /// Collect the most recent location of every user in `owntracks_friends` other than
+    /// `username`, if `username` is one of them.
+    /// # Arguments
+    /// * `username` - The user making the request, who is excluded from the result.
+    /// # Returns
+    /// One OwnTracks friend message per sharing user that has recorded at least one location.
+    async fn owntracks_friends(&self, username: &str) -> Result<Vec<owntracks::http::Friend>> {
+        let sharing = &self.config.owntracks_friends;
+        if !sharing.iter().any(|friend| friend == username) {
+            return Ok(Vec::new());
+        }
+        let now = chrono::Utc::now();
+        let mut friends = Vec::new();
+        for other in sharing.iter() {
+            if other == username {
+                continue;
+            }
+            if let Some(location) = self.db.location_at(other, &now).await? {
+                friends.push(owntracks::http::Friend::from_location(&location));
+            }
+        }
+        Ok(friends)
+    }
+
+    /// Handler for Overland batches. The whole batch is committed in one transaction, and only then
+    /// is the app told it may discard the batch. Overland resends a batch until it is accepted, so
+    /// nothing in it may fail the batch for good: malformed points are logged and dropped, and
+    /// under `ConflictPolicy::Error` conflicting points are set aside in the conflicts table, as
+    /// `ConflictPolicy::StoreBoth` does, instead of failing the batch.
+    async fn handle_overland(
+        State(server): State<Arc<Server>>,
+        Extension(AuthenticatedUser { username, .. }): Extension<AuthenticatedUser>,
+        Json(payload): Json<overland::http::Payload>,
+    ) -> Response<Body> {
+        debug!("overland batch of {} locations", payload.locations.len());
+        let (locations, errors) = payload.to_locations(&username);
+        for e in errors {
+            warn!("Dropped malformed Overland point for {}: {}", username, e);
+        }
+        let policy = match server.config.conflict_policy {
+            ConflictPolicy::Error => ConflictPolicy::StoreBoth,
+            policy => policy,
+        };
+        match server.db.location_insert_all(locations, policy).await {
+            Ok(counts) => {
+                debug!("overland batch for {}: {}", username, counts);
+                Json(overland::http::Reply::ok()).into_response()
+            }
+            Err(e) => {
+                warn!("Failed to record Overland batch for {}: {:?}", username, e);
+                Response::builder()
+                    .status(500)
+                    .body(Body::from("Failed to record batch"))
+                    .unwrap()
+            }
+        }
+    }
+
+    /// Handler for the OsmAnd protocol. The user is looked up from the device ID, since these
+    /// clients are not behind the basic auth middleware. Unknown device IDs count as failed
+    /// logins, so that they cannot be guessed any faster than passwords.
+    async fn handle_osmand(
+        State(server): State<Arc<Server>>,
+        ConnectInfo(addr): ConnectInfo<SocketAddr>,
+        Query(payload): Query<osmand::http::Payload>,
+    ) -> Response<Body> {
+        let ip = addr.ip();
+        if let Some(wait) = server.lockout.check(ip, None) {
+            return Self::too_many_requests(wait);
+        }
+        debug!("osmand url payload: {:?}", payload);
+        let username = match server.config.osmand_devices.get(&payload.id) {
+            Some(username) => username,
+            None => {
+                server.lockout.fail(ip, None, "unknown OsmAnd device id");
+                return Response::builder().status(401).body(Body::empty()).unwrap();
+            }
+        };
+        match server
             .db
-            .location_insert(LocationGen::to_location(&payload, &username))
+            .location_insert_with_policy(
+                LocationGen::to_location(&payload, username),
+                server.config.conflict_policy,
+            )
             .await
-            .unwrap();
-        Response::new(Body::from("Request received"))
+        {
+            Ok(_) => Response::new(Body::from("Request received")),
+            Err(e) => {
+                warn!("Failed to record OsmAnd location for {}: {:?}", username, e);
+                Response::builder()
+                    .status(500)
+                    .body(Body::from("Failed to record location"))
+                    .unwrap()
+            }
+        }
     }
 
+    /// Handler for unknown routes. Only the method and path are logged, since the query string
+    /// and headers may hold a token or password.
     async fn handle_fallback(request: Request<Body>) -> Response<Body> {
-        warn!("Fallback handler triggered. Request:\n{:#?}", request);
+        warn!(
+            "Fallback handler triggered: {} {}",
+            request.method(),
+            request.uri().path()
+        );
         Response::builder()
             .status(404)
             .body(Body::from("Not found"))